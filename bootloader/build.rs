@@ -0,0 +1,20 @@
+//! This build script copies the `memory.x` file of the bootloader into a directory where the linker can always find
+//! it, so the one of the firmware in the parent directory is not picked up by mistake.
+
+#![allow(clippy::unwrap_used)]
+
+use std::{env, fs::File, io::Write, path::PathBuf};
+
+fn main() {
+    let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    File::create(out.join("memory.x"))
+        .unwrap()
+        .write_all(include_bytes!("memory.x"))
+        .unwrap();
+    println!("cargo:rustc-link-search={}", out.display());
+    println!("cargo:rerun-if-changed=memory.x");
+
+    println!("cargo:rustc-link-arg-bins=--nmagic");
+    println!("cargo:rustc-link-arg-bins=-Tlink.x");
+    println!("cargo:rustc-link-arg-bins=-Tlink-rp.x");
+}
@@ -0,0 +1,59 @@
+//! # Bootloader
+//! Starts the firmware of the alarm clock from the active slot. When the firmware marked an update in the update slot,
+//! the bootloader swaps the two slots first. The updated firmware then runs on trial: if it restarts before marking
+//! itself as booted, the bootloader swaps the slots back and the previous firmware runs again.
+//!
+//! The bootloader is flashed once, firmware updates leave it alone. The flash layout is in `memory.x`, the slots must
+//! match the `memory.x` of the firmware.
+
+#![no_std]
+#![no_main]
+
+use core::cell::RefCell;
+
+use cortex_m_rt::{entry, exception};
+use embassy_boot_rp::{BootLoader, BootLoaderConfig, WatchdogFlash};
+use embassy_sync::blocking_mutex::{Mutex, raw::NoopRawMutex};
+use embassy_time::Duration;
+
+/// Size of the flash of the Pico W
+const FLASH_SIZE: usize = 2 * 1024 * 1024;
+
+/// The hardware watchdog restarts the bootloader if it hangs while swapping, the swap goes on where it stopped.
+/// The watchdog keeps running when the firmware starts, the firmware feeds it until it is confirmed and stops it then.
+const WATCHDOG_TIMEOUT: Duration = Duration::from_secs(8);
+
+/// Swap the slots if needed and start the firmware
+#[entry]
+fn main() -> ! {
+    let p = embassy_rp::init(embassy_rp::config::Config::default());
+
+    let flash = WatchdogFlash::<FLASH_SIZE>::start(p.FLASH, p.WATCHDOG, WATCHDOG_TIMEOUT);
+    let flash = Mutex::<NoopRawMutex, _>::new(RefCell::new(flash));
+
+    let config = BootLoaderConfig::from_linkerfile_blocking(&flash, &flash, &flash);
+    let active_offset = config.active.offset();
+    let bootloader: BootLoader = BootLoader::prepare(config);
+
+    // SAFETY: the active slot holds a firmware linked to start there, see `memory.x`
+    unsafe { bootloader.load(embassy_rp::flash::FLASH_BASE as u32 + active_offset) }
+}
+
+/// Restart on a hard fault, a swap that was interrupted goes on after the restart
+#[unsafe(no_mangle)]
+#[cfg_attr(target_os = "none", unsafe(link_section = ".HardFault.user"))]
+unsafe extern "C" fn HardFault() {
+    cortex_m::peripheral::SCB::sys_reset();
+}
+
+/// Restart on an unexpected interrupt
+#[exception]
+unsafe fn DefaultHandler(_: i16) -> ! {
+    cortex_m::peripheral::SCB::sys_reset();
+}
+
+/// Restart on a panic, the hardware watchdog would do the same a little later
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    cortex_m::peripheral::SCB::sys_reset();
+}
@@ -11,6 +11,7 @@
 #![allow(clippy::expect_used)]
 #![allow(clippy::unwrap_used)]
 #![allow(clippy::print_stdout)]
+#![allow(clippy::panic)]
 
 use std::{
     env, fs,
@@ -20,11 +21,19 @@ use std::{
     path::{Path, PathBuf},
 };
 
+use flate2::{Compression, write::GzEncoder};
+
 fn main() {
     println!("in build.rs");
     memory_x();
     wifi_secrets().unwrap();
     time_api_config().unwrap();
+    webhook_config().unwrap();
+    weather_config().unwrap();
+    calendar_config().unwrap();
+    ota_config().unwrap();
+    night_config().unwrap();
+    web_ui().unwrap();
 }
 
 /// Generate `wifi_secrets.rs` from `wifi_config.json`
@@ -60,13 +69,205 @@ fn wifi_secrets() -> io::Result<()> {
         .as_str()
         .expect("password not found in wifi_config.json file");
 
+    // The connectivity policy is optional, without it WiFi is only up while the time is fetched
+    let policy = match config["connectivity"].as_str().unwrap_or("on_demand") {
+        "on_demand" => "OnDemand",
+        "usb_power" => "WhileOnUsbPower",
+        "always" => "Always",
+        other => panic!("wifi_config.json: unknown connectivity {other}, expected on_demand, usb_power or always"),
+    };
+
     // Write the SSID and password to wifi_secrets.rs
     println!("in wifi_secrets, before writing ssid and password to output file");
     writeln!(f, "pub const SSID: &str = \"{ssid}\";")?;
     writeln!(f, "pub const PASSWORD: &str = \"{password}\";")?;
+    writeln!(
+        f,
+        "pub const CONNECTIVITY_POLICY: crate::task::network::ConnectivityPolicy = crate::task::network::ConnectivityPolicy::{policy};"
+    )?;
+    let hostname = network_config(&mut f, &config["network"])?;
+    mqtt_config(&mut f, &config["mqtt"], &hostname)?;
+    syslog_config(&mut f, &config["syslog"])?;
+    peer_sync_config(&mut f, &config["peer_sync"])?;
+    println!("cargo:rerun-if-changed=config/wifi_config.json");
     Ok(())
 }
 
+/// Write the network configuration from the optional `network` section of `wifi_config.json`, e.g.
+/// `{"hostname": "alarmclock-bedroom", "ipv4": {"address": "192.168.1.50/24", "gateway": "192.168.1.1",
+/// "dns": ["192.168.1.1"]}, "ipv6": "slaac"}`. Without `ipv4` the address is taken from DHCP.
+/// Returns the hostname.
+fn network_config(f: &mut File, network: &serde_json::Value) -> io::Result<String> {
+    let hostname = network["hostname"].as_str().unwrap_or("alarmclck");
+    assert!(
+        (1..=32).contains(&hostname.len())
+            && hostname.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            && !hostname.starts_with('-')
+            && !hostname.ends_with('-'),
+        "wifi_config.json: hostname must be 1 to 32 letters, digits or inner hyphens"
+    );
+
+    let ipv4 = &network["ipv4"];
+    let static_ipv4 = if ipv4.is_null() {
+        "None".to_string()
+    } else {
+        let address = ipv4["address"]
+            .as_str()
+            .expect("address not found in ipv4 section of wifi_config.json file");
+        let (address, prefix_len) = address
+            .split_once('/')
+            .expect("wifi_config.json: the ipv4 address needs a prefix length, e.g. 192.168.1.50/24");
+        let prefix_len: u8 = prefix_len
+            .parse()
+            .ok()
+            .filter(|len| *len <= 32)
+            .expect("wifi_config.json: the prefix length must be 0 to 32");
+        let gateway = ipv4["gateway"].as_str().map_or_else(
+            || "None".to_string(),
+            |gateway| format!("Some({})", ipv4_octets(gateway)),
+        );
+        let dns_servers: Vec<String> = ipv4["dns"]
+            .as_array()
+            .map(|servers| {
+                servers
+                    .iter()
+                    .map(|server| ipv4_octets(server.as_str().expect("wifi_config.json: dns servers must be strings")))
+                    .collect()
+            })
+            .unwrap_or_default();
+        assert!(dns_servers.len() <= 3, "wifi_config.json: at most 3 dns servers");
+        format!(
+            "Some(crate::task::network::StaticIpv4 {{ address: {}, prefix_len: {prefix_len}, gateway: {gateway}, dns_servers: &[{}] }})",
+            ipv4_octets(address),
+            dns_servers.join(", ")
+        )
+    };
+
+    let ipv6_slaac = match network["ipv6"].as_str() {
+        None => false,
+        Some("slaac") => true,
+        Some(other) => panic!("wifi_config.json: unknown ipv6 {other}, expected slaac"),
+    };
+
+    writeln!(f, "pub const HOSTNAME: &str = \"{hostname}\";")?;
+    writeln!(
+        f,
+        "pub const STATIC_IPV4: Option<crate::task::network::StaticIpv4> = {static_ipv4};"
+    )?;
+    writeln!(f, "pub const IPV6_SLAAC: bool = {ipv6_slaac};")?;
+    Ok(hostname.to_string())
+}
+
+/// Write the MQTT broker from the optional `mqtt` section of `wifi_config.json`, e.g.
+/// `{"host": "192.168.1.10", "port": 1883, "username": "clock", "password": "secret", "topic_prefix": "bedroom"}`.
+/// Only the host is required, the topic prefix defaults to `alarmclock/<hostname>`. Without the section there is no MQTT.
+fn mqtt_config(f: &mut File, mqtt: &serde_json::Value, hostname: &str) -> io::Result<()> {
+    let broker = if mqtt.is_null() {
+        "None".to_string()
+    } else {
+        let host = mqtt["host"]
+            .as_str()
+            .expect("host not found in mqtt section of wifi_config.json file");
+        let port = mqtt["port"].as_u64().map_or(1883, |port| {
+            u16::try_from(port).expect("wifi_config.json: the mqtt port must be 0 to 65535")
+        });
+        let optional = |key: &str| {
+            mqtt[key]
+                .as_str()
+                .map_or_else(|| "None".to_string(), |value| format!("Some({value:?})"))
+        };
+        let topic_prefix = mqtt["topic_prefix"]
+            .as_str()
+            .map_or_else(|| format!("alarmclock/{hostname}"), ToString::to_string);
+        assert!(
+            !topic_prefix.is_empty() && !topic_prefix.ends_with('/') && !topic_prefix.contains(['+', '#']),
+            "wifi_config.json: the mqtt topic prefix must not be empty, end with / or contain wildcards"
+        );
+        format!(
+            "Some(crate::task::mqtt::MqttBroker {{ host: {host:?}, port: {port}, username: {}, password: {}, topic_prefix: {topic_prefix:?} }})",
+            optional("username"),
+            optional("password")
+        )
+    };
+
+    writeln!(
+        f,
+        "pub const MQTT_BROKER: Option<crate::task::mqtt::MqttBroker> = {broker};"
+    )?;
+    Ok(())
+}
+
+/// Write the syslog collector from the optional `syslog` section of `wifi_config.json`, e.g.
+/// `{"host": "192.168.1.10", "port": 514}`. Only the host is required. Without the section nothing is logged remotely.
+fn syslog_config(f: &mut File, syslog: &serde_json::Value) -> io::Result<()> {
+    let server = if syslog.is_null() {
+        "None".to_string()
+    } else {
+        let host = syslog["host"]
+            .as_str()
+            .expect("host not found in syslog section of wifi_config.json file");
+        let port = syslog["port"].as_u64().map_or(514, |port| {
+            u16::try_from(port).expect("wifi_config.json: the syslog port must be 0 to 65535")
+        });
+        format!("Some(crate::task::syslog::SyslogServer {{ host: {host:?}, port: {port} }})")
+    };
+
+    writeln!(
+        f,
+        "pub const SYSLOG_SERVER: Option<crate::task::syslog::SyslogServer> = {server};"
+    )?;
+    Ok(())
+}
+
+/// Write the alarm sharing from the optional `peer_sync` section of `wifi_config.json`, e.g.
+/// `{"group": "bedrooms", "share": ["time", "enabled", "days"]}`. The clocks of a group share the listed fields of
+/// their alarm, `share` defaults to time, enabled and days. Without the section the alarm is not shared.
+fn peer_sync_config(f: &mut File, peer_sync: &serde_json::Value) -> io::Result<()> {
+    let config = if peer_sync.is_null() {
+        "None".to_string()
+    } else {
+        let group = peer_sync["group"]
+            .as_str()
+            .expect("group not found in peer_sync section of wifi_config.json file");
+        assert!(!group.is_empty(), "wifi_config.json: the peer_sync group is empty");
+        let share = match peer_sync["share"].as_array() {
+            Some(fields) => fields
+                .iter()
+                .map(|field| match field.as_str() {
+                    Some("time") => 0x01,
+                    Some("enabled") => 0x02,
+                    Some("days") => 0x04,
+                    Some("sunrise") => 0x08,
+                    Some("volume") => 0x10,
+                    _ => panic!(
+                        "wifi_config.json: unknown peer_sync field {field}, expected time, enabled, days, sunrise or volume"
+                    ),
+                })
+                .fold(0u8, |bits, bit| bits | bit),
+            None => 0x07,
+        };
+        assert!(share != 0, "wifi_config.json: peer_sync shares no field");
+        format!(
+            "Some(crate::task::peer_sync::PeerSyncConfig {{ group: {group:?}, \
+             shared: crate::utility::peer_sync::SharedFields::from_bits({share:#04x}) }})"
+        )
+    };
+
+    writeln!(
+        f,
+        "pub const PEER_SYNC: Option<crate::task::peer_sync::PeerSyncConfig> = {config};"
+    )?;
+    Ok(())
+}
+
+/// Parse an IPv4 address and return its octets as Rust source, e.g. `[192, 168, 1, 50]`
+fn ipv4_octets(address: &str) -> String {
+    let address: std::net::Ipv4Addr = address
+        .parse()
+        .unwrap_or_else(|_| panic!("wifi_config.json: {address} is not an IPv4 address"));
+    format!("{:?}", address.octets())
+}
+
 /// Generate `time_api_config.rs` from `time_api.json`
 fn time_api_config() -> io::Result<()> {
     println!("in time_api_config");
@@ -102,8 +303,355 @@ fn time_api_config() -> io::Result<()> {
     // Combine baseurl and timezone into a single string for TIME_SERVER_URL
     let combined_url = format!("{baseurl}{timezone}");
 
+    // An https server must be pinned, there is no other way for us to tell the real server from a spoofed one
+    let pin = time_server_pin(&config["time api by zone"]["tls"]);
+    let https = combined_url.starts_with("https://");
+    assert!(
+        !https || pin.is_some(),
+        "time_api.json: an https baseurl needs a \"tls\" section with the pin of the server"
+    );
+    assert!(
+        https || pin.is_none(),
+        "time_api.json: a \"tls\" section needs an https baseurl"
+    );
+
     // Write the baseurl and timezone to time_api_secrets.rs
     writeln!(f, "pub const TIME_SERVER_URL: &str = \"{combined_url}\";")?;
+    writeln!(
+        f,
+        "pub const TIME_SERVER_PIN: Option<crate::utility::tls_pin::Pin> = {};",
+        pin.unwrap_or_else(|| "None".to_string())
+    )?;
+    println!("cargo:rerun-if-changed=config/time_api.json");
+    Ok(())
+}
+
+/// Read the optional `tls` section of `time_api.json` and return the pin as Rust source, e.g.
+/// `{"pin": "public_key", "sha256": "<64 hex digits>"}`. The pin is either `public_key` or `certificate`.
+fn time_server_pin(tls: &serde_json::Value) -> Option<String> {
+    if tls.is_null() {
+        return None;
+    }
+
+    let variant = match tls["pin"]
+        .as_str()
+        .expect("pin not found in tls section of time_api.json file")
+    {
+        "public_key" => "PublicKey",
+        "certificate" => "Certificate",
+        other => panic!("time_api.json: unknown pin {other}, expected public_key or certificate"),
+    };
+    let sha256 = tls["sha256"]
+        .as_str()
+        .expect("sha256 not found in tls section of time_api.json file")
+        .replace(':', "");
+    assert!(
+        sha256.len() == 64 && sha256.chars().all(|c| c.is_ascii_hexdigit()),
+        "time_api.json: sha256 must be 32 bytes in hex"
+    );
+
+    let bytes: Vec<String> = (0..64).step_by(2).map(|i| format!("0x{}", &sha256[i..i + 2])).collect();
+    Some(format!(
+        "Some(crate::utility::tls_pin::Pin::{variant}([{}]))",
+        bytes.join(", ")
+    ))
+}
+
+/// Generate `webhook_config.rs` from the optional `webhooks.json`, e.g.
+/// `{"webhooks": [{"event": "alarm", "url": "http://192.168.1.20/coffee", "method": "POST", "body": {"on": true}}]}`.
+/// The body is a JSON value, sent as JSON, or a string, sent as plain text, both as is apart from their placeholders.
+/// Without the file no webhooks are fired.
+fn webhook_config() -> io::Result<()> {
+    println!("in webhook_config");
+    let out_dir = env::var("OUT_DIR").expect("OUT_DIR environment variable not set");
+    let mut f =
+        File::create(Path::new(&out_dir).join("webhook_config.rs")).expect("Could not create webhook_config.rs file");
+
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR environment variable not set");
+    let config_path = Path::new(&manifest_dir).join("config/webhooks.json");
+    println!("cargo:rerun-if-changed=config/webhooks.json");
+    let webhooks = if config_path.exists() {
+        let config_contents = fs::read_to_string(config_path).expect("Could not read webhooks.json file");
+        let config: serde_json::Value =
+            serde_json::from_str(&config_contents).expect("Could not parse webhooks.json file");
+        config["webhooks"]
+            .as_array()
+            .expect("webhooks not found in webhooks.json file")
+            .iter()
+            .map(webhook)
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    writeln!(
+        f,
+        "pub const WEBHOOKS: &[crate::task::webhook::Webhook] = &[{}];",
+        webhooks.join(", ")
+    )?;
+    Ok(())
+}
+
+/// Return a webhook of `webhooks.json` as Rust source
+fn webhook(webhook: &serde_json::Value) -> String {
+    let event = match webhook["event"].as_str().expect("webhooks.json: event not found") {
+        "alarm" => "Alarm",
+        "sunrise_finished" => "SunriseFinished",
+        "alarm_stop" => "AlarmStop",
+        "alarm_expired" => "AlarmExpired",
+        other => panic!(
+            "webhooks.json: unknown event {other}, expected alarm, sunrise_finished, alarm_stop or alarm_expired"
+        ),
+    };
+    let url = webhook["url"].as_str().expect("webhooks.json: url not found");
+    assert!(
+        url.starts_with("http://"),
+        "webhooks.json: {url} is not an http URL, webhooks are sent over plain HTTP"
+    );
+    let method = match webhook["method"].as_str().unwrap_or("POST") {
+        method @ ("GET" | "POST" | "PUT") => method,
+        other => panic!("webhooks.json: unknown method {other}, expected GET, POST or PUT"),
+    };
+    let (body, body_format) = match &webhook["body"] {
+        serde_json::Value::Null => ("None".to_string(), "Json"),
+        serde_json::Value::String(body) => (format!("Some({body:?})"), "Text"),
+        body => (format!("Some({:?})", body.to_string()), "Json"),
+    };
+    format!(
+        "crate::task::webhook::Webhook {{ event: crate::utility::webhook::WebhookEvent::{event}, method: reqwless::request::Method::{method}, url: {url:?}, body: {body}, body_format: crate::utility::webhook::BodyFormat::{body_format} }}"
+    )
+}
+
+/// Generate `weather_config.rs` from the optional `weather.json`, e.g. `{"latitude": 52.52, "longitude": 13.41}`.
+/// The forecast is fetched from Open-Meteo, a `baseurl` points it to another server with the same API, e.g. a local
+/// stand-in. Without the file no forecast is fetched.
+fn weather_config() -> io::Result<()> {
+    println!("in weather_config");
+    let out_dir = env::var("OUT_DIR").expect("OUT_DIR environment variable not set");
+    let mut f =
+        File::create(Path::new(&out_dir).join("weather_config.rs")).expect("Could not create weather_config.rs file");
+
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR environment variable not set");
+    let config_path = Path::new(&manifest_dir).join("config/weather.json");
+    println!("cargo:rerun-if-changed=config/weather.json");
+    let url = if config_path.exists() {
+        let config_contents = fs::read_to_string(config_path).expect("Could not read weather.json file");
+        let config: serde_json::Value =
+            serde_json::from_str(&config_contents).expect("Could not parse weather.json file");
+        let latitude = config["latitude"]
+            .as_f64()
+            .expect("latitude not found in weather.json file");
+        let longitude = config["longitude"]
+            .as_f64()
+            .expect("longitude not found in weather.json file");
+        assert!(
+            (-90.0..=90.0).contains(&latitude) && (-180.0..=180.0).contains(&longitude),
+            "weather.json: latitude or longitude out of range"
+        );
+        let baseurl = config["baseurl"].as_str().unwrap_or("http://api.open-meteo.com");
+        assert!(
+            baseurl.starts_with("http://"),
+            "weather.json: {baseurl} is not an http URL, the forecast is fetched over plain HTTP"
+        );
+        format!(
+            "Some(\"{baseurl}/v1/forecast?latitude={latitude}&longitude={longitude}\
+             &daily=weather_code,temperature_2m_max,temperature_2m_min&timezone=auto&forecast_days=1\")"
+        )
+    } else {
+        "None".to_string()
+    };
+
+    writeln!(f, "pub const FORECAST_URL: Option<&str> = {url};")?;
+    Ok(())
+}
+
+/// Generate `calendar_config.rs` from the optional `calendar.json`, e.g.
+/// `{"url": "http://192.168.1.20/shifts.ics", "skip_keyword": "OFF", "alarm_before_first_event_minutes": 90}`.
+/// Both rules are optional. Without the file no calendar is fetched.
+fn calendar_config() -> io::Result<()> {
+    println!("in calendar_config");
+    let out_dir = env::var("OUT_DIR").expect("OUT_DIR environment variable not set");
+    let mut f =
+        File::create(Path::new(&out_dir).join("calendar_config.rs")).expect("Could not create calendar_config.rs file");
+
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR environment variable not set");
+    let config_path = Path::new(&manifest_dir).join("config/calendar.json");
+    println!("cargo:rerun-if-changed=config/calendar.json");
+    let (url, skip_keyword, lead_minutes) = if config_path.exists() {
+        let config_contents = fs::read_to_string(config_path).expect("Could not read calendar.json file");
+        let config: serde_json::Value =
+            serde_json::from_str(&config_contents).expect("Could not parse calendar.json file");
+        let url = config["url"].as_str().expect("url not found in calendar.json file");
+        assert!(
+            url.starts_with("http://"),
+            "calendar.json: {url} is not an http URL, the calendar is fetched over plain HTTP"
+        );
+        let skip_keyword = match config["skip_keyword"].as_str() {
+            Some(keyword) => {
+                assert!(!keyword.is_empty(), "calendar.json: skip_keyword is empty");
+                format!("Some({keyword:?})")
+            }
+            None => "None".to_string(),
+        };
+        let lead_minutes = match config["alarm_before_first_event_minutes"].as_u64() {
+            Some(minutes) => {
+                assert!(
+                    minutes <= 720,
+                    "calendar.json: alarm_before_first_event_minutes is {minutes}, at most 720 are allowed"
+                );
+                format!("Some({minutes})")
+            }
+            None => "None".to_string(),
+        };
+        (format!("Some({url:?})"), skip_keyword, lead_minutes)
+    } else {
+        ("None".to_string(), "None".to_string(), "None".to_string())
+    };
+
+    writeln!(f, "pub const CALENDAR_URL: Option<&str> = {url};")?;
+    writeln!(
+        f,
+        "pub const CALENDAR_RULES: crate::utility::alarm_plan::CalendarRules = \
+         crate::utility::alarm_plan::CalendarRules {{ skip_keyword: {skip_keyword}, lead_minutes: {lead_minutes} }};"
+    )?;
+    Ok(())
+}
+
+/// Generate `ota_config.rs` from the optional `ota.json`, e.g.
+/// `{"url": "http://192.168.1.20/alarmclock.img", "public_key": "<64 hex digits>"}`.
+/// The public key is required with the `ota-signature` feature and not allowed without, so a key is never ignored
+/// silently. Without the file no updates are looked for.
+fn ota_config() -> io::Result<()> {
+    println!("in ota_config");
+    let out_dir = env::var("OUT_DIR").expect("OUT_DIR environment variable not set");
+    let mut f = File::create(Path::new(&out_dir).join("ota_config.rs")).expect("Could not create ota_config.rs file");
+
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR environment variable not set");
+    let config_path = Path::new(&manifest_dir).join("config/ota.json");
+    println!("cargo:rerun-if-changed=config/ota.json");
+    let signed = env::var_os("CARGO_FEATURE_OTA_SIGNATURE").is_some();
+    let (url, public_key) = if config_path.exists() {
+        let config_contents = fs::read_to_string(config_path).expect("Could not read ota.json file");
+        let config: serde_json::Value = serde_json::from_str(&config_contents).expect("Could not parse ota.json file");
+        let url = config["url"].as_str().expect("url not found in ota.json file");
+        assert!(
+            url.starts_with("http://"),
+            "ota.json: {url} is not an http URL, the firmware is fetched over plain HTTP"
+        );
+        let public_key = config["public_key"].as_str().map(|key| {
+            assert!(
+                signed,
+                "ota.json: a public_key needs the ota-signature feature, build with --features ota-signature"
+            );
+            assert!(
+                key.len() == 64 && key.chars().all(|c| c.is_ascii_hexdigit()),
+                "ota.json: public_key must be 32 bytes in hex"
+            );
+            let bytes: Vec<String> = (0..64).step_by(2).map(|i| format!("0x{}", &key[i..i + 2])).collect();
+            format!("[{}]", bytes.join(", "))
+        });
+        (format!("Some({url:?})"), public_key)
+    } else {
+        ("None".to_string(), None)
+    };
+
+    writeln!(f, "pub const FIRMWARE_URL: Option<&str> = {url};")?;
+    if signed {
+        let public_key = public_key.expect("ota.json: the ota-signature feature needs a public_key");
+        writeln!(f, "pub const FIRMWARE_PUBLIC_KEY: [u8; 32] = {public_key};")?;
+    }
+    Ok(())
+}
+
+/// Generate `night_config.rs` from the optional `night.json`, e.g.
+/// `{"start": "22:30", "end": "06:30", "display": "clock", "wake_seconds": 10, "day_brightness": "normal"}`.
+/// Only the start and the end are required. Without the file there is no night and the display stays dimmest.
+fn night_config() -> io::Result<()> {
+    println!("in night_config");
+    let out_dir = env::var("OUT_DIR").expect("OUT_DIR environment variable not set");
+    let mut f =
+        File::create(Path::new(&out_dir).join("night_config.rs")).expect("Could not create night_config.rs file");
+
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR environment variable not set");
+    let config_path = Path::new(&manifest_dir).join("config/night.json");
+    println!("cargo:rerun-if-changed=config/night.json");
+    let schedule = if config_path.exists() {
+        let config_contents = fs::read_to_string(config_path).expect("Could not read night.json file");
+        let config: serde_json::Value =
+            serde_json::from_str(&config_contents).expect("Could not parse night.json file");
+        let start_minutes = minute_of_day(config["start"].as_str().expect("start not found in night.json file"));
+        let end_minutes = minute_of_day(config["end"].as_str().expect("end not found in night.json file"));
+        assert!(
+            start_minutes != end_minutes,
+            "night.json: start and end are the same, the night would last no or all day"
+        );
+        let display = match config["display"].as_str().unwrap_or("clock") {
+            "dim" => "Dim",
+            "clock" => "Clock",
+            "off" => "Off",
+            display => panic!("night.json: display {display} is none of dim, clock and off"),
+        };
+        let wake_secs = config["wake_seconds"].as_u64().unwrap_or(10);
+        assert!(
+            (1..=300).contains(&wake_secs),
+            "night.json: wake_seconds is {wake_secs}, 1 to 300 are allowed"
+        );
+        let day_brightness = match config["day_brightness"].as_str().unwrap_or("dimmest") {
+            "dimmest" => "Dimmest",
+            "dim" => "Dim",
+            "normal" => "Normal",
+            "bright" => "Bright",
+            "brightest" => "Brightest",
+            brightness => {
+                panic!("night.json: day_brightness {brightness} is none of dimmest, dim, normal, bright and brightest")
+            }
+        };
+        format!(
+            "Some(crate::utility::night::NightSchedule {{ start_minutes: {start_minutes}, end_minutes: {end_minutes}, \
+             display: crate::utility::night::NightDisplay::{display}, wake_secs: {wake_secs}, \
+             day_brightness: crate::utility::night::DayBrightness::{day_brightness} }})"
+        )
+    } else {
+        "None".to_string()
+    };
+
+    writeln!(
+        f,
+        "pub const NIGHT_SCHEDULE: Option<crate::utility::night::NightSchedule> = {schedule};"
+    )?;
+    Ok(())
+}
+
+/// The minute of the day of a time like `22:30` in `night.json`
+fn minute_of_day(time: &str) -> u16 {
+    let parsed = time
+        .split_once(':')
+        .and_then(|(hour, minute)| Some((hour.parse::<u16>().ok()?, minute.parse::<u16>().ok()?)));
+    match parsed {
+        Some((hour, minute)) if hour < 24 && minute < 60 => hour * 60 + minute,
+        _ => panic!("night.json: {time} is not a time like 22:30"),
+    }
+}
+
+/// Compress the page of the web UI, `web/index.html`, into `index.html.gz`.
+/// The page is served as is with `Content-Encoding: gzip`, so the firmware never has to compress anything.
+fn web_ui() -> io::Result<()> {
+    println!("in web_ui");
+    let out_dir = env::var("OUT_DIR").expect("OUT_DIR environment variable not set");
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR environment variable not set");
+    let page = fs::read(Path::new(&manifest_dir).join("web/index.html")).expect("Could not read web/index.html file");
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(&page)?;
+    let compressed = encoder.finish()?;
+    println!(
+        "web UI page: {} bytes, {} bytes compressed",
+        page.len(),
+        compressed.len()
+    );
+    fs::write(Path::new(&out_dir).join("index.html.gz"), compressed)?;
+
+    println!("cargo:rerun-if-changed=web/index.html");
     Ok(())
 }
 
@@ -127,6 +675,6 @@ fn memory_x() {
 
     println!("cargo:rustc-link-arg-bins=--nmagic");
     println!("cargo:rustc-link-arg-bins=-Tlink.x");
-    println!("cargo:rustc-link-arg-bins=-Tlink-rp.x");
+    // no `link-rp.x`: the boot stage 2 belongs to the bootloader, see `bootloader/`
     println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
 }
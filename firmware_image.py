@@ -0,0 +1,143 @@
+#!/usr/bin/env python3
+"""Makes a firmware image for the over-the-air update from the ELF file of a release build.
+
+The image is the firmware as flat binary, starting at the active slot, behind a header with the version, the length,
+the SHA-256 hash and optionally an Ed25519 signature, see src/utility/firmware_image.rs for the layout. Put the image
+on a web server and its URL into config/ota.json, the clock installs it if its version is newer than the running one.
+
+    cargo build --release
+    python3 firmware_image.py target/thumbv6m-none-eabi/release/pi-pico-alarmclock -o alarmclock.img
+
+The version is taken from Cargo.toml unless given with --version, so bump it for every update.
+
+Signed images, for firmware built with the ota-signature feature:
+    python3 firmware_image.py --generate-key ota_key.pem
+        writes a new private key and prints the public key to put into "public_key" in config/ota.json
+    python3 firmware_image.py target/thumbv6m-none-eabi/release/pi-pico-alarmclock --key ota_key.pem -o alarmclock.img
+        signs the SHA-512 hash of the firmware, as checked by embassy-boot
+
+Signing needs the cryptography package (pip install cryptography), keep the private key out of the repository.
+"""
+
+import argparse
+import hashlib
+import re
+import struct
+import sys
+import zlib
+from pathlib import Path
+
+# start and size of the active slot, see memory.x
+ACTIVE_START = 0x10007000
+ACTIVE_SIZE = 992 * 1024
+
+MAGIC = b"ACFW"
+PT_LOAD = 1
+
+
+def load_segments(elf):
+    """Physical address and content of the loadable segments of a 32-bit little endian ELF file"""
+    if elf[:4] != b"\x7fELF" or elf[4] != 1 or elf[5] != 1:
+        sys.exit("not a 32-bit little endian ELF file")
+    phoff, = struct.unpack_from("<I", elf, 28)
+    phentsize, phnum = struct.unpack_from("<HH", elf, 42)
+    segments = []
+    for index in range(phnum):
+        p_type, p_offset, _vaddr, p_paddr, p_filesz, _memsz, _flags, _align = struct.unpack_from(
+            "<8I", elf, phoff + index * phentsize
+        )
+        if p_type == PT_LOAD and p_filesz > 0:
+            segments.append((p_paddr, elf[p_offset:p_offset + p_filesz]))
+    return segments
+
+
+def flat_firmware(segments):
+    """The segments in the active slot as one binary, gaps filled with 0xFF like erased flash"""
+    inside = [(address, data) for address, data in segments if ACTIVE_START <= address < ACTIVE_START + ACTIVE_SIZE]
+    outside = [hex(address) for address, _ in segments if not ACTIVE_START <= address < ACTIVE_START + ACTIVE_SIZE]
+    if outside:
+        sys.exit(f"segments outside of the active slot at {', '.join(outside)}, is the firmware linked for the bootloader?")
+    if not inside:
+        sys.exit("no loadable segments in the active slot")
+    end = max(address + len(data) for address, data in inside)
+    if end > ACTIVE_START + ACTIVE_SIZE:
+        sys.exit(f"the firmware is {end - ACTIVE_START} bytes, the active slot holds {ACTIVE_SIZE}")
+    firmware = bytearray(b"\xff" * (end - ACTIVE_START))
+    for address, data in inside:
+        firmware[address - ACTIVE_START:address - ACTIVE_START + len(data)] = data
+    return bytes(firmware)
+
+
+def package_version():
+    """Version of the package in Cargo.toml next to this script"""
+    cargo_toml = (Path(__file__).parent / "Cargo.toml").read_text()
+    match = re.search(r'^version\s*=\s*"([^"]+)"', cargo_toml, re.MULTILINE)
+    if not match:
+        sys.exit("no version in Cargo.toml, give it with --version")
+    return match.group(1)
+
+
+def parse_version(text):
+    """Major, minor and patch of a version like 0.6.1, anything after the patch number is ignored"""
+    match = re.match(r"(\d+)\.(\d+)\.(\d+)", text)
+    if not match:
+        sys.exit(f"invalid version {text}")
+    return tuple(int(number) for number in match.groups())
+
+
+def sign(firmware, key_path):
+    """Ed25519 signature of the SHA-512 hash of the firmware"""
+    from cryptography.hazmat.primitives.serialization import load_pem_private_key
+
+    key = load_pem_private_key(Path(key_path).read_bytes(), password=None)
+    return key.sign(hashlib.sha512(firmware).digest())
+
+
+def generate_key(key_path):
+    """Write a new Ed25519 private key and print its public key in hex"""
+    from cryptography.hazmat.primitives import serialization
+    from cryptography.hazmat.primitives.asymmetric.ed25519 import Ed25519PrivateKey
+
+    key = Ed25519PrivateKey.generate()
+    pem = key.private_bytes(
+        serialization.Encoding.PEM, serialization.PrivateFormat.PKCS8, serialization.NoEncryption()
+    )
+    Path(key_path).write_bytes(pem)
+    public = key.public_key().public_bytes(serialization.Encoding.Raw, serialization.PublicFormat.Raw)
+    print(f"Private key written to {key_path}, the public key for config/ota.json is")
+    print(public.hex())
+
+
+def image(firmware, version, signature):
+    """The header followed by the firmware"""
+    header = MAGIC + struct.pack("<3HHI", *version, 0, len(firmware))
+    header += hashlib.sha256(firmware).digest() + signature
+    header += struct.pack("<I", zlib.crc32(header))
+    return header + firmware
+
+
+def main():
+    parser = argparse.ArgumentParser(description="Makes a firmware image for the over-the-air update")
+    parser.add_argument("elf", nargs="?", help="ELF file of the release build")
+    parser.add_argument("-o", "--output", default="alarmclock.img")
+    parser.add_argument("--version", help="version of the firmware, defaults to the one in Cargo.toml")
+    parser.add_argument("--key", help="PEM file of the Ed25519 private key to sign the image with")
+    parser.add_argument("--generate-key", metavar="KEY", help="write a new private key and print its public key")
+    args = parser.parse_args()
+
+    if args.generate_key:
+        generate_key(args.generate_key)
+        return
+    if not args.elf:
+        parser.error("the ELF file is required")
+
+    firmware = flat_firmware(load_segments(Path(args.elf).read_bytes()))
+    version = parse_version(args.version or package_version())
+    signature = sign(firmware, args.key) if args.key else bytes(64)
+    Path(args.output).write_bytes(image(firmware, version, signature))
+    signed = "signed" if args.key else "unsigned"
+    print(f"{args.output}: {signed} firmware {'.'.join(map(str, version))}, {len(firmware)} bytes")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,36 @@
+//! # Host Tests
+//! Runs the unit tests of the modules of the firmware that do not depend on the hardware on the host. The modules
+//! are compiled from `src/utility` as they are, their tests are next to the code in `#[cfg(test)]` blocks.
+//!
+//! Run them with the target of your machine, the parent folder builds for the Pico (`rustc -vV` shows the host):
+//! ```text
+//! cargo test --target x86_64-unknown-linux-gnu
+//! ```
+//! The modules derive `defmt::Format` only with the `defmt` feature, which the firmware enables and the tests
+//! leave off. Recorded responses, captured packets and other sample data of the tests are in `fixtures/`.
+
+/// The modules of `src/utility` that build on the host, at the same paths as in the firmware
+#[path = "../../src/utility"]
+#[allow(dead_code)]
+mod utility {
+    pub mod alarm_limits;
+    pub mod alarm_plan;
+    pub mod api_router;
+    pub mod backoff;
+    pub mod calendar;
+    pub mod console;
+    pub mod firmware_image;
+    pub mod home_assistant;
+    pub mod http_request;
+    pub mod http_response;
+    pub mod ics;
+    pub mod json_stream;
+    pub mod mdns;
+    pub mod mqtt;
+    pub mod peer_sync;
+    pub mod string_utils;
+    pub mod syslog;
+    pub mod tls_pin;
+    pub mod weather;
+    pub mod webhook;
+}
@@ -0,0 +1,247 @@
+#!/usr/bin/env python3
+"""Local stand-in for the time API, the weather API, a shared calendar and webhook receivers over plain HTTP, serving
+canned responses to test the time fetcher, the forecast, the calendar and the webhooks.
+
+Every path serves a different case, so one running server covers all of them. Point config/time_api.json
+at this machine and put the path of the case in "timezone", e.g.
+    "baseurl": "http://192.168.1.20:8080", "timezone": "/ok"
+
+Cases:
+    /ok              200 with the current time in the format of worldtimeapi.org
+    /redirect        302 to /ok
+    /redirect-loop   302 to itself, the fetcher gives up after a few redirects
+    /redirect-https  301 to an https URL, which is not followed without a pin
+    /busy            429 with "Retry-After: 120"
+    /unavailable     503 with an HTML page and "Retry-After" as a date five minutes ahead
+    /html            200 with an HTML page, the clock falls back to the Date header
+    /bad-datetime    200 with an invalid datetime, the clock falls back to the Date header
+
+Webhook cases, put the address of this machine and the path into "url" in config/webhooks.json, e.g.
+    "url": "http://192.168.1.20:8080/webhook"
+
+    /webhook         204, the request and its body are printed
+    /webhook-error   503, the clock retries the webhook a few times with backoff
+
+Weather case, put the address of this machine into "baseurl" in config/weather.json, e.g.
+    "latitude": 52.52, "longitude": 13.41, "baseurl": "http://192.168.1.20:8080"
+
+    /v1/forecast     200 with a forecast for today in the format of Open-Meteo, the query is ignored
+
+Calendar cases, put the address of this machine and the path into "url" in config/calendar.json, e.g.
+    "url": "http://192.168.1.20:8080/calendar.ics", "skip_keyword": "OFF", "alarm_before_first_event_minutes": 90
+
+    /calendar.ics    200 with shifts around today: an early shift on weekdays at 07:00 except tomorrow, the day
+                     after tomorrow tagged OFF, a late meeting in UTC and a cancelled event
+    /html            200 with an HTML page, the clock reports that it is not a calendar
+
+Firmware update case, start with --firmware and an image made by firmware_image.py, put the address of this machine
+and the path into "url" in config/ota.json, e.g.
+    "url": "http://192.168.1.20:8080/alarmclock.img"
+
+    /alarmclock.img            200 with the image
+    /alarmclock-truncated.img  200 with the first half of the image, the clock rejects it and keeps its firmware
+
+The Date header fallback needs the UTC offset of an earlier successful sync, so request /ok first.
+Run with --port to listen on another port than 8080.
+"""
+
+import argparse
+import json
+from datetime import datetime, timedelta, timezone
+from email.utils import format_datetime
+from http.server import BaseHTTPRequestHandler, ThreadingHTTPServer
+
+
+def time_body(datetime_value):
+    """Body in the format of worldtimeapi.org, with the fields the clock does not need"""
+    return json.dumps(
+        {
+            "abbreviation": "STANDIN",
+            "client_ip": "127.0.0.1",
+            "datetime": datetime_value,
+            "day_of_week": 0,
+            "timezone": "Local/Standin",
+            "utc_datetime": datetime.now(timezone.utc).isoformat(),
+        }
+    )
+
+
+def forecast_body(today):
+    """Body in the format of Open-Meteo for a single day, recorded with the fields the clock does not need"""
+    return json.dumps(
+        {
+            "latitude": 52.52,
+            "longitude": 13.419998,
+            "generationtime_ms": 0.041,
+            "utc_offset_seconds": 7200,
+            "timezone": "Europe/Berlin",
+            "timezone_abbreviation": "GMT+2",
+            "elevation": 38.0,
+            "daily_units": {
+                "time": "iso8601",
+                "weather_code": "wmo code",
+                "temperature_2m_max": "°C",
+                "temperature_2m_min": "°C",
+            },
+            "daily": {
+                "time": [today.isoformat()],
+                "weather_code": [95],
+                "temperature_2m_max": [21.4],
+                "temperature_2m_min": [-2.5],
+            },
+        },
+        ensure_ascii=False,
+    )
+
+
+def calendar_body(today):
+    """An iCalendar document with shifts around today, with folded lines, time zones and a nested alarm"""
+    tomorrow = today + timedelta(days=1)
+    day_after = today + timedelta(days=2)
+    lines = [
+        "BEGIN:VCALENDAR",
+        "VERSION:2.0",
+        "PRODID:-//Standin//Shifts//EN",
+        "BEGIN:VTIMEZONE",
+        "TZID:Europe/Berlin",
+        "BEGIN:DAYLIGHT",
+        "DTSTART:19700329T020000",
+        "TZOFFSETTO:+0200",
+        "END:DAYLIGHT",
+        "END:VTIMEZONE",
+        "BEGIN:VEVENT",
+        "UID:early@standin",
+        f"DTSTART;TZID=Europe/Berlin:{today - timedelta(days=14):%Y%m%d}T070000",
+        f"DTEND;TZID=Europe/Berlin:{today - timedelta(days=14):%Y%m%d}T150000",
+        "RRULE:FREQ=WEEKLY;BYDAY=MO,TU,WE,TH,FR",
+        f"EXDATE;TZID=Europe/Berlin:{tomorrow:%Y%m%d}T070000",
+        "SUMMARY:Early shift",
+        "DESCRIPTION:This description is folded onto the next line, the continuation",
+        " starts with a space.",
+        "BEGIN:VALARM",
+        "ACTION:DISPLAY",
+        "SUMMARY:OFF is not read inside the alarm",
+        "TRIGGER:-PT15M",
+        "END:VALARM",
+        "END:VEVENT",
+        "BEGIN:VEVENT",
+        "UID:off@standin",
+        f"DTSTART;VALUE=DATE:{day_after:%Y%m%d}",
+        f"DTEND;VALUE=DATE:{day_after + timedelta(days=1):%Y%m%d}",
+        "SUMMARY:Day off",
+        "CATEGORIES:OFF",
+        "END:VEVENT",
+        "BEGIN:VEVENT",
+        "UID:meeting@standin",
+        f"DTSTART:{tomorrow:%Y%m%d}T090000Z",
+        "SUMMARY:Team meeting",
+        "END:VEVENT",
+        "BEGIN:VEVENT",
+        "UID:cancelled@standin",
+        f"DTSTART:{tomorrow:%Y%m%d}T050000",
+        "STATUS:CANCELLED",
+        "SUMMARY:Cancelled inventory",
+        "END:VEVENT",
+        "END:VCALENDAR",
+    ]
+    return "\r\n".join(lines) + "\r\n"
+
+
+# the image served as /alarmclock.img, set with --firmware
+FIRMWARE_IMAGE = None
+
+
+def html_page(title):
+    """An error page like the ones proxies and load balancers send"""
+    return f"<html><head><title>{title}</title></head><body><h1>{title}</h1></body></html>"
+
+
+def canned_response(path):
+    """Status, extra headers and body for a path, None for unknown paths"""
+    now = datetime.now().astimezone()
+    responses = {
+        "/ok": (200, {"Content-Type": "application/json"}, time_body(now.isoformat())),
+        "/redirect": (302, {"Location": "/ok"}, ""),
+        "/redirect-loop": (302, {"Location": "/redirect-loop"}, ""),
+        "/redirect-https": (301, {"Location": "https://worldtimeapi.org/api/ip"}, ""),
+        "/busy": (429, {"Retry-After": "120", "Content-Type": "text/plain"}, "Too Many Requests"),
+        "/unavailable": (
+            503,
+            {
+                "Retry-After": format_datetime(datetime.now(timezone.utc) + timedelta(minutes=5), usegmt=True),
+                "Content-Type": "text/html",
+            },
+            html_page("503 Service Unavailable"),
+        ),
+        "/html": (200, {"Content-Type": "text/html"}, html_page("Welcome to the captive portal")),
+        "/bad-datetime": (200, {"Content-Type": "application/json"}, time_body("2024-13-45T25:61:00+02:00")),
+        "/v1/forecast": (200, {"Content-Type": "application/json"}, forecast_body(now.date())),
+        "/calendar.ics": (200, {"Content-Type": "text/calendar"}, calendar_body(now.date())),
+        "/webhook": (204, {}, ""),
+        "/webhook-error": (503, {"Content-Type": "text/html"}, html_page("503 Service Unavailable")),
+    }
+    if FIRMWARE_IMAGE:
+        responses["/alarmclock.img"] = (200, {"Content-Type": "application/octet-stream"}, FIRMWARE_IMAGE)
+        responses["/alarmclock-truncated.img"] = (
+            200,
+            {"Content-Type": "application/octet-stream"},
+            FIRMWARE_IMAGE[: len(FIRMWARE_IMAGE) // 2],
+        )
+    return responses.get(path)
+
+
+class Handler(BaseHTTPRequestHandler):
+    """Serves the canned response for the requested path"""
+
+    protocol_version = "HTTP/1.1"
+
+    def do_GET(self):
+        self.respond()
+
+    def do_POST(self):
+        self.respond()
+
+    def do_PUT(self):
+        self.respond()
+
+    def respond(self):
+        length = int(self.headers.get("Content-Length", 0))
+        if length:
+            body = self.rfile.read(length).decode(errors="replace")
+            print(f"{self.command} {self.path} ({self.headers.get('Content-Type')}): {body}")
+
+        response = canned_response(self.path.split("?")[0])
+        if response is None:
+            response = (404, {"Content-Type": "text/plain"}, "unknown case, see the docs of this script")
+        status, headers, body = response
+
+        if isinstance(body, str):
+            body = body.encode()
+        # send_response adds the Date header
+        self.send_response(status)
+        for name, value in headers.items():
+            self.send_header(name, value)
+        self.send_header("Content-Length", str(len(body)))
+        self.send_header("Connection", "close")
+        self.end_headers()
+        self.wfile.write(body)
+
+
+def main():
+    parser = argparse.ArgumentParser(description="Local stand-in for the time and weather APIs, a calendar and webhooks with canned responses")
+    parser.add_argument("--port", type=int, default=8080)
+    parser.add_argument("--firmware", help="firmware image to serve as /alarmclock.img")
+    args = parser.parse_args()
+
+    global FIRMWARE_IMAGE
+    if args.firmware:
+        with open(args.firmware, "rb") as image:
+            FIRMWARE_IMAGE = image.read()
+
+    server = ThreadingHTTPServer(("", args.port), Handler)
+    print(f"Serving canned time API, weather API, calendar and webhook responses on port {args.port}...")
+    server.serve_forever()
+
+
+if __name__ == "__main__":
+    main()
@@ -1,6 +1,17 @@
 MEMORY {
-    BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
-    RAM   : ORIGIN = 0x20000000, LENGTH = 256K
-    FLASH : ORIGIN = 0x10000100, LENGTH = 2020K 
-    /* original wozuld have been: 2048K - 0x100, but we reserve some space at the end to persist data for the alarm time */
+    /* the bootloader owns 0x10000000..0x10006000 with the boot stage 2, see bootloader/memory.x */
+    BOOTLOADER_STATE : ORIGIN = 0x10006000, LENGTH = 4K
+    /* the active slot, the bootloader starts the firmware from here */
+    FLASH            : ORIGIN = 0x10007000, LENGTH = 992K
+    /* the update slot, one page larger than the active slot as the swap needs a spare page */
+    DFU              : ORIGIN = 0x100FF000, LENGTH = 996K
+    /* 0x101F8000..0x101F9000 is unused, 0x101F9000..0x101FC000 persists the settings, see task::alarm_settings */
+    RAM              : ORIGIN = 0x20000000, LENGTH = 256K
 }
+
+/* offsets from the start of the flash, as the bootloader and the firmware updater expect them */
+__bootloader_state_start = ORIGIN(BOOTLOADER_STATE) - 0x10000000;
+__bootloader_state_end = ORIGIN(BOOTLOADER_STATE) + LENGTH(BOOTLOADER_STATE) - 0x10000000;
+
+__bootloader_dfu_start = ORIGIN(DFU) - 0x10000000;
+__bootloader_dfu_end = ORIGIN(DFU) + LENGTH(DFU) - 0x10000000;
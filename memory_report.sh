@@ -0,0 +1,65 @@
+#!/bin/sh
+
+# Report the memory footprint of the release firmware from the linker map.
+# Prints the size of the output sections in flash and RAM and the largest statics in RAM.
+# Set TOP to show more or less statics than 15. Pipe through `rustfilt` for readable symbol names.
+# Pass a git revision, e.g. `./memory_report.sh main`, to report the footprint of that revision first, built in a
+# worktree in `target/memory-base`, for a before and after of a change.
+
+cd "$(dirname "$0")" || exit 1
+TOP="${TOP:-15}"
+
+# Build the firmware in the folder with a linker map and print the report
+report() {
+    (cd "$1" && DEFMT_LOG=warn cargo rustc --release --quiet -- -C link-arg=-Map=target/memory.map) || exit 1
+    summarize "$1/target/memory.map"
+}
+
+# In the map of lld the output sections start in column 34, the input sections in column 42.
+summarize() {
+    awk -v top="$TOP" '
+    function hex(digits,    value, i) {
+        value = 0
+        for (i = 1; i <= length(digits); i++) {
+            value = value * 16 + index("0123456789abcdef", substr(digits, i, 1)) - 1
+        }
+        return value
+    }
+    NR == 1 { next }
+    substr($0, 34, 1) == "." {
+        section = $5
+        size = hex($3)
+        if (section ~ /^\.(vector_table|boot2|text|rodata|data)$/) { flash += size }
+        if (section ~ /^\.(data|bss|uninit)$/) { ram += size }
+        if (section ~ /^\.(vector_table|boot2|text|rodata|data|bss|uninit)$/) {
+            printf "%-14s %8d bytes\n", section, size
+        }
+        next
+    }
+    substr($0, 42, 1) != " " && $5 ~ /:\(/ && section ~ /^\.(data|bss|uninit)$/ {
+        name = $5
+        sub(/^.*:\(/, "", name)
+        sub(/\)$/, "", name)
+        statics[name] += hex($3)
+    }
+    END {
+        # .data is stored in flash and copied to RAM at startup, so it counts for both
+        printf "%-14s %8d bytes\n", "flash total", flash
+        printf "%-14s %8d bytes\n\n", "RAM total", ram
+        printf "largest statics in RAM:\n"
+        for (name in statics) { printf "%8d %s\n", statics[name], name | "sort -rn | head -n " top }
+    }
+' "$1"
+}
+
+if [ -n "$1" ]; then
+    BASE=target/memory-base
+    git worktree remove --force "$BASE" 2>/dev/null
+    git worktree add --quiet --detach "$BASE" "$1" || exit 1
+    echo "== $1"
+    report "$BASE"
+    git worktree remove --force "$BASE"
+    echo
+    echo "== working tree"
+fi
+report .
@@ -0,0 +1,562 @@
+//! # Display Simulator
+//! Draws the screens of the alarm clock on the host, with the drawing code of the firmware in
+//! `src/utility/screen.rs`, into a framebuffer of the size of the display.
+//!
+//! Every operation mode is drawn with sample content and compared with the golden images in `golden/`, so a change
+//! of the layout, the fonts or the images shows up before it is flashed. Lines longer than the display is wide are
+//! reported as well, like the text the display cuts off.
+//!
+//! Every screen is also drawn with the pixel shift of every time of the day, which must neither move a lit pixel off
+//! the display nor let regions cover each other, and the shift must move by at most a pixel at a time.
+//!
+//! Run it with the target of your machine, the parent folder builds for the Pico (`rustc -vV` shows the host):
+//! ```text
+//! cargo run --target x86_64-unknown-linux-gnu                 compare the screens with the golden images
+//! cargo run --target x86_64-unknown-linux-gnu -- update       write the golden images after a wanted change
+//! cargo run --target x86_64-unknown-linux-gnu -- dump out     write every screen as PNG and PBM into `out`
+//! ```
+//! The golden images are plain PBM files, so a difference is a readable diff.
+
+#[path = "../../src/utility/screen.rs"]
+#[allow(dead_code)]
+mod screen;
+
+use std::{
+    convert::Infallible,
+    fs,
+    io::BufWriter,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use embedded_graphics::{Pixel, pixelcolor::BinaryColor, prelude::*};
+use screen::{
+    Battery, Body, HEIGHT, Header, LINE_CHARS, Lines, MAX_SHIFT, Meridiem, SHIFT_MINUTES, Screen, Settings,
+    TITLE_CHARS, WIDTH,
+};
+
+/// Width of the display in pixels, as index
+const COLUMNS: usize = WIDTH as usize;
+
+/// Height of the display in pixels, as index
+const ROWS: usize = HEIGHT as usize;
+
+/// Scale of the PNG images, the display is too small to look at pixel for pixel
+const PNG_SCALE: usize = 4;
+
+/// Number of pixels per line of a PBM file, which should not have lines longer than 70 characters
+const PBM_LINE_PIXELS: usize = 64;
+
+/// A monochrome framebuffer of the size of the display
+struct Framebuffer {
+    /// The pixels, row by row, `true` is lit
+    pixels: [[bool; COLUMNS]; ROWS],
+    /// Number of lit pixels drawn off the display
+    outside: usize,
+}
+
+impl Framebuffer {
+    /// A dark framebuffer
+    const fn new() -> Self {
+        Self {
+            pixels: [[false; COLUMNS]; ROWS],
+            outside: 0,
+        }
+    }
+
+    /// Number of lit pixels
+    fn lit(&self) -> usize {
+        self.pixels.iter().flatten().filter(|&&lit| lit).count()
+    }
+
+    /// The framebuffer as plain PBM image
+    fn to_pbm(&self) -> String {
+        let mut pbm = format!("P1\n{WIDTH} {HEIGHT}\n");
+        for row in &self.pixels {
+            for chunk in row.chunks(PBM_LINE_PIXELS) {
+                pbm.extend(chunk.iter().map(|&lit| if lit { '1' } else { '0' }));
+                pbm.push('\n');
+            }
+        }
+        pbm
+    }
+
+    /// Write the framebuffer as PNG image, scaled up and lit pixels white like on the display
+    fn write_png(&self, path: &Path) -> Result<(), String> {
+        let file = fs::File::create(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        #[allow(clippy::cast_possible_truncation)]
+        let mut encoder = png::Encoder::new(
+            BufWriter::new(file),
+            (COLUMNS * PNG_SCALE) as u32,
+            (ROWS * PNG_SCALE) as u32,
+        );
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut data = Vec::with_capacity(COLUMNS * ROWS * PNG_SCALE * PNG_SCALE);
+        for row in &self.pixels {
+            for _ in 0..PNG_SCALE {
+                for &lit in row {
+                    data.extend([if lit { 0xFF } else { 0x00 }; PNG_SCALE]);
+                }
+            }
+        }
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&data))
+            .map_err(|e| format!("{}: {e}", path.display()))
+    }
+}
+
+impl OriginDimensions for Framebuffer {
+    fn size(&self) -> Size {
+        Size::new(WIDTH, HEIGHT)
+    }
+}
+
+impl DrawTarget for Framebuffer {
+    type Color = BinaryColor;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        // like the display, anything outside is dropped
+        for Pixel(point, color) in pixels {
+            if let (Ok(x), Ok(y)) = (usize::try_from(point.x), usize::try_from(point.y))
+                && x < COLUMNS
+                && y < ROWS
+            {
+                self.pixels[y][x] = color.is_on();
+            } else if color.is_on() {
+                self.outside += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Lines of content from text
+fn lines(texts: [&str; 3]) -> &'static Lines {
+    let mut lines = Lines::default();
+    for (line, text) in lines.iter_mut().zip(texts) {
+        // a text longer than the capacity would not make it to the display either
+        let _ = line.push_str(text);
+    }
+    Box::leak(Box::new(lines))
+}
+
+/// The date as the display task writes it, centered in 22 characters
+const DATE: &str = " Wednesday 01.05.2024 ";
+
+/// The screens of every operation mode with sample content. The system info lines are made by the firmware code,
+/// the other lines are written the way the display task writes them.
+#[allow(clippy::too_many_lines)]
+fn screens() -> Vec<(&'static str, Screen<'static>)> {
+    let time = Body::Time { hours: 7, minutes: 45 };
+    vec![
+        (
+            "normal",
+            Screen {
+                header: Header::None,
+                battery: Some(Battery::Level(3)),
+                body: time,
+                date: Some(DATE),
+                meridiem: None,
+                shift: Point::zero(),
+                inverted: false,
+            },
+        ),
+        (
+            "normal_alarm_enabled",
+            Screen {
+                header: Header::Saber,
+                battery: Some(Battery::Charging),
+                body: time,
+                date: Some(DATE),
+                meridiem: None,
+                shift: Point::zero(),
+                inverted: false,
+            },
+        ),
+        (
+            "normal_12h",
+            Screen {
+                header: Header::Saber,
+                battery: Some(Battery::Level(3)),
+                body: Body::Time { hours: 12, minutes: 5 },
+                date: Some(DATE),
+                meridiem: Some(Meridiem::Pm),
+                shift: Point::zero(),
+                inverted: false,
+            },
+        ),
+        (
+            "set_alarm_time",
+            Screen {
+                header: Header::Setup,
+                battery: Some(Battery::Charging),
+                body: Body::Time { hours: 6, minutes: 30 },
+                date: None,
+                meridiem: None,
+                shift: Point::zero(),
+                inverted: false,
+            },
+        ),
+        (
+            "set_alarm_time_12h",
+            Screen {
+                header: Header::Setup,
+                battery: Some(Battery::Charging),
+                body: Body::Time { hours: 6, minutes: 30 },
+                date: None,
+                meridiem: Some(Meridiem::Am),
+                shift: Point::zero(),
+                inverted: false,
+            },
+        ),
+        (
+            "alarm",
+            Screen {
+                header: Header::Title("Press Yellow!"),
+                battery: Some(Battery::Level(5)),
+                body: Body::Time { hours: 6, minutes: 30 },
+                date: Some(DATE),
+                meridiem: None,
+                shift: Point::zero(),
+                inverted: false,
+            },
+        ),
+        (
+            "menu",
+            Screen {
+                header: Header::Title("Menu"),
+                battery: Some(Battery::Level(0)),
+                body: Body::Menu,
+                date: None,
+                meridiem: None,
+                shift: Point::zero(),
+                inverted: false,
+            },
+        ),
+        (
+            "system_info",
+            Screen {
+                header: Header::Title("Sys.-Info"),
+                battery: Some(Battery::Level(4)),
+                body: Body::Lines {
+                    lines: Box::leak(Box::new(screen::system_info_lines(3.87, false, 4.07, 2.6))),
+                    icon: None,
+                },
+                date: None,
+                meridiem: None,
+                shift: Point::zero(),
+                inverted: false,
+            },
+        ),
+        (
+            "weather",
+            Screen {
+                header: Header::Title("Weather"),
+                battery: Some(Battery::Charging),
+                body: Body::Lines {
+                    lines: lines(["Partly cloudy", "10C to 21C", "For 01.05."]),
+                    icon: Some(1),
+                },
+                date: None,
+                meridiem: None,
+                shift: Point::zero(),
+                inverted: false,
+            },
+        ),
+        (
+            "weather_no_forecast",
+            Screen {
+                header: Header::Title("Weather"),
+                battery: Some(Battery::Charging),
+                body: Body::Lines {
+                    lines: lines(["No forecast yet", "Err HTTP 503", ""]),
+                    icon: None,
+                },
+                date: None,
+                meridiem: None,
+                shift: Point::zero(),
+                inverted: false,
+            },
+        ),
+        (
+            "network_wifi",
+            Screen {
+                header: Header::Title("Net-Info"),
+                battery: Some(Battery::Charging),
+                body: Body::Lines {
+                    lines: lines(["SSID HomeNetwork", "RSSI -67 dBm", "Connected"]),
+                    icon: None,
+                },
+                date: None,
+                meridiem: None,
+                shift: Point::zero(),
+                inverted: false,
+            },
+        ),
+        (
+            "network_addresses",
+            Screen {
+                header: Header::Title("Net-Info"),
+                battery: Some(Battery::Charging),
+                body: Body::Lines {
+                    lines: lines(["IP 192.168.178.42/24", "GW 192.168.178.1", "DNS 192.168.178.1"]),
+                    icon: None,
+                },
+                date: None,
+                meridiem: None,
+                shift: Point::zero(),
+                inverted: false,
+            },
+        ),
+        (
+            "network_config",
+            Screen {
+                header: Header::Title("Net-Info"),
+                battery: Some(Battery::Charging),
+                body: Body::Lines {
+                    lines: lines(["alarmclock", "IPv4 DHCP, SLAAC", ""]),
+                    icon: None,
+                },
+                date: None,
+                meridiem: None,
+                shift: Point::zero(),
+                inverted: false,
+            },
+        ),
+        (
+            "network_time_sync",
+            Screen {
+                header: Header::Title("Net-Info"),
+                battery: Some(Battery::Charging),
+                body: Body::Lines {
+                    lines: lines(["01.05. 06:00 via API", "OK 12  Failed 1", "Err -"]),
+                    icon: None,
+                },
+                date: None,
+                meridiem: None,
+                shift: Point::zero(),
+                inverted: false,
+            },
+        ),
+        (
+            "night_clock",
+            Screen {
+                header: Header::None,
+                battery: None,
+                body: Body::Time { hours: 2, minutes: 17 },
+                date: None,
+                meridiem: None,
+                shift: Point::zero(),
+                inverted: false,
+            },
+        ),
+        (
+            "normal_shifted",
+            Screen {
+                header: Header::Saber,
+                battery: Some(Battery::Level(3)),
+                body: time,
+                date: Some(DATE),
+                meridiem: None,
+                shift: Point::new(MAX_SHIFT, -MAX_SHIFT),
+                inverted: false,
+            },
+        ),
+        (
+            "normal_inverted",
+            Screen {
+                header: Header::Saber,
+                battery: Some(Battery::Level(3)),
+                body: time,
+                date: Some(DATE),
+                meridiem: None,
+                shift: Point::zero(),
+                inverted: true,
+            },
+        ),
+        (
+            "standby",
+            Screen {
+                header: Header::None,
+                battery: Some(Battery::Level(2)),
+                body: Body::Message("Going to sleep..."),
+                date: None,
+                meridiem: None,
+                shift: Point::zero(),
+                inverted: false,
+            },
+        ),
+    ]
+}
+
+/// The texts of a screen that are longer than the display is wide
+fn overflowing(screen: &Screen) -> Vec<String> {
+    let mut texts = Vec::new();
+    if let Header::Title(title) = screen.header
+        && title.chars().count() > TITLE_CHARS
+    {
+        texts.push(title.to_string());
+    }
+    match screen.body {
+        Body::Lines { lines, icon } => {
+            // the icon covers the end of the lines
+            let chars = if icon.is_some() { LINE_CHARS - 3 } else { LINE_CHARS };
+            texts.extend(
+                lines
+                    .iter()
+                    .filter(|line| line.chars().count() > chars)
+                    .map(ToString::to_string),
+            );
+        }
+        Body::Message(message) if message.chars().count() > LINE_CHARS => texts.push(message.to_string()),
+        _ => {}
+    }
+    texts
+}
+
+/// Check the pixel shift of every time of the day: it stays within `MAX_SHIFT` and moves by at most a pixel from one
+/// step to the next, also at midnight, and every screen keeps all of its lit pixels on the display with it.
+/// Returns whether all is well.
+fn check_shifts(settings: &Settings) -> bool {
+    let mut ok = true;
+    let shifts: Vec<(u8, u8, Point)> = (0..24)
+        .flat_map(|hour| {
+            (0..60)
+                .step_by(usize::from(SHIFT_MINUTES))
+                .map(move |minute| (hour, minute))
+        })
+        .map(|(hour, minute)| (hour, minute, screen::pixel_shift(hour, minute)))
+        .collect();
+    for (index, &(hour, minute, shift)) in shifts.iter().enumerate() {
+        let (_, _, next) = shifts[(index + 1) % shifts.len()];
+        if shift.x.abs() > MAX_SHIFT || shift.y.abs() > MAX_SHIFT {
+            println!("{hour:02}:{minute:02}: shift {shift:?} is beyond {MAX_SHIFT} pixels");
+            ok = false;
+        }
+        if (next.x - shift.x).abs() > 1 || (next.y - shift.y).abs() > 1 {
+            println!("{hour:02}:{minute:02}: shift jumps from {shift:?} to {next:?}");
+            ok = false;
+        }
+    }
+
+    let mut distinct: Vec<Point> = shifts.iter().map(|&(_, _, shift)| shift).collect();
+    distinct.sort_by_key(|shift| (shift.x, shift.y));
+    distinct.dedup();
+    for (name, screen) in screens() {
+        // regions covering each other would show in the number of lit pixels
+        let lit = render(&screen, settings).lit();
+        for &shift in &distinct {
+            let framebuffer = render(&Screen { shift, ..screen }, settings);
+            // inverted, the background of the images is lit and moves off the display with them, which is fine
+            let outside = if screen.inverted { 0 } else { framebuffer.outside };
+            if outside > 0 || framebuffer.lit() != lit {
+                println!(
+                    "{name}: shift {shift:?} moves {outside} pixels off the display, {} of {lit} are lit",
+                    framebuffer.lit()
+                );
+                ok = false;
+            }
+        }
+    }
+    ok
+}
+
+/// Draw a screen into a new framebuffer
+fn render(screen: &Screen, settings: &Settings) -> Framebuffer {
+    let mut framebuffer = Framebuffer::new();
+    screen::draw_screen(&mut framebuffer, screen, settings);
+    framebuffer
+}
+
+/// The folder of the golden images
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("golden")
+}
+
+/// Compare every screen with its golden image, returns whether all match and no text overflows
+fn check(settings: &Settings) -> bool {
+    let mut ok = true;
+    for (name, screen) in screens() {
+        for text in overflowing(&screen) {
+            println!("{name}: \"{text}\" is cut off by the display");
+            ok = false;
+        }
+        let path = golden_dir().join(format!("{name}.pbm"));
+        match fs::read_to_string(&path) {
+            Ok(golden) if golden == render(&screen, settings).to_pbm() => {}
+            Ok(_) => {
+                println!("{name}: differs from {}", path.display());
+                ok = false;
+            }
+            Err(e) => {
+                println!("{name}: {}: {e}", path.display());
+                ok = false;
+            }
+        }
+    }
+    ok
+}
+
+/// Write the golden image of every screen
+fn update(settings: &Settings) -> Result<(), String> {
+    let dir = golden_dir();
+    fs::create_dir_all(&dir).map_err(|e| format!("{}: {e}", dir.display()))?;
+    for (name, screen) in screens() {
+        let path = dir.join(format!("{name}.pbm"));
+        fs::write(&path, render(&screen, settings).to_pbm()).map_err(|e| format!("{}: {e}", path.display()))?;
+        println!("{}", path.display());
+    }
+    Ok(())
+}
+
+/// Write every screen as PNG and PBM into the folder
+fn dump(settings: &Settings, dir: &Path) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|e| format!("{}: {e}", dir.display()))?;
+    for (name, screen) in screens() {
+        let framebuffer = render(&screen, settings);
+        let pbm = dir.join(format!("{name}.pbm"));
+        fs::write(&pbm, framebuffer.to_pbm()).map_err(|e| format!("{}: {e}", pbm.display()))?;
+        let png = dir.join(format!("{name}.png"));
+        framebuffer.write_png(&png)?;
+        println!("{}", png.display());
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let settings = match Settings::new() {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("Failed to load {}", e.name);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        [] | ["check"] => {
+            if check(&settings) & check_shifts(&settings) {
+                println!("All screens match the golden images");
+                Ok(())
+            } else {
+                Err("Screens differ, run with `update` if the change is wanted".to_string())
+            }
+        }
+        ["update"] => update(&settings),
+        ["dump", dir] => dump(&settings, Path::new(dir)),
+        _ => Err("Usage: simulator [check | update | dump <folder>]".to_string()),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,445 @@
+//! # Errors
+//! Error types of the subsystems. Each error carries the underlying cause where the driver provides one,
+//! so that the reason for a failure can be shown beyond the defmt log.
+//!
+//! The last error of every subsystem is kept in the system state, see `LastErrors`.
+
+use defmt::Format;
+use embassy_boot_rp::FirmwareUpdaterError;
+use embassy_net::{dns, tcp};
+use embassy_rp::{flash, rtc::RtcError};
+use embedded_tls::TlsError;
+
+use crate::utility::{
+    backoff::FailureKind, firmware_image::ImageError, http_response::RedirectError, ics::IcsError,
+    json_stream::JsonStreamError, mqtt::PacketError, string_utils::DateTimeParseError, weather::ForecastError,
+};
+
+/// Errors when joining the `WiFi` network
+#[derive(PartialEq, Eq, Debug, Format, Clone)]
+pub enum WifiError {
+    /// The `CYW43` firmware refused to join, the data is the status it reported
+    Join {
+        /// Status code reported by the `CYW43` firmware
+        status: u32,
+    },
+    /// Joining did not finish within the timeout
+    JoinTimeout,
+}
+
+/// Errors when bringing up the network on top of the `WiFi` link
+#[derive(PartialEq, Eq, Debug, Format, Clone)]
+pub enum NetworkError {
+    /// No address was received from DHCP within the timeout
+    DhcpTimeout,
+    /// The link did not come up within the timeout
+    LinkTimeout,
+}
+
+/// Errors when connecting to the network
+#[derive(PartialEq, Eq, Debug, Format, Clone)]
+pub enum ConnectError {
+    /// Joining the `WiFi` network failed
+    Wifi(WifiError),
+    /// The network did not get ready after joining
+    Network(NetworkError),
+}
+
+impl ConnectError {
+    /// The failure class of this error, which decides how long to back off before reconnecting
+    pub const fn failure_kind(&self) -> FailureKind {
+        match self {
+            Self::Wifi(_) => FailureKind::WifiJoin,
+            Self::Network(_) => FailureKind::Network,
+        }
+    }
+}
+
+impl From<WifiError> for ConnectError {
+    fn from(error: WifiError) -> Self {
+        Self::Wifi(error)
+    }
+}
+
+impl From<NetworkError> for ConnectError {
+    fn from(error: NetworkError) -> Self {
+        Self::Network(error)
+    }
+}
+
+/// Errors when making an HTTP request
+#[derive(PartialEq, Debug, Format, Clone)]
+pub enum HttpError {
+    /// The static HTTP buffers are not available
+    BuffersUnavailable,
+    /// The URL of the server cannot be parsed
+    InvalidUrl,
+    /// The host name of the server could not be resolved
+    Dns(dns::Error),
+    /// The TCP connection to the server could not be established
+    Connect(tcp::Error),
+    /// The TLS handshake failed, which includes the server not matching the pin
+    Tls(TlsError),
+    /// The request could not be created: resolving the host or connecting to it failed
+    Request(reqwless::Error),
+    /// The request could not be sent or the response headers could not be read
+    Send(reqwless::Error),
+    /// The response body could not be read
+    Body(reqwless::Error),
+    /// The server responded with a status other than success or redirect
+    Status {
+        /// The HTTP status code
+        code: u16,
+        /// The delay the server asked for with `Retry-After`, in seconds
+        retry_after_secs: Option<u64>,
+    },
+    /// A redirect could not be followed
+    Redirect(RedirectError),
+    /// A redirect leads to a server we cannot verify
+    RedirectNotAllowed,
+    /// The server kept redirecting
+    TooManyRedirects,
+}
+
+/// Errors of a time synchronization cycle
+#[derive(PartialEq, Debug, Format, Clone)]
+pub enum TimeSyncError {
+    /// Joining the `WiFi` network failed
+    Wifi(WifiError),
+    /// The network did not get ready
+    Network(NetworkError),
+    /// The request to the time API failed
+    Http(HttpError),
+    /// The response of the time API does not hold the datetime as we expect it
+    Json(JsonStreamError),
+    /// The datetime in the response of the time API is invalid
+    DateTime(DateTimeParseError),
+    /// The RTC has not been handed over to its mutex yet
+    RtcNotInitialized,
+    /// The RTC refused the new datetime
+    Rtc(RtcError),
+}
+
+impl TimeSyncError {
+    /// The failure class of this error, which decides how long to back off before retrying
+    pub const fn failure_kind(&self) -> FailureKind {
+        match self {
+            Self::Wifi(_) => FailureKind::WifiJoin,
+            Self::Network(_) | Self::Http(HttpError::Request(reqwless::Error::Dns) | HttpError::Dns(_)) => {
+                FailureKind::Network
+            }
+            Self::Http(HttpError::BuffersUnavailable | HttpError::InvalidUrl)
+            | Self::RtcNotInitialized
+            | Self::Rtc(_) => FailureKind::Local,
+            Self::Http(_) | Self::Json(_) | Self::DateTime(_) => FailureKind::Http,
+        }
+    }
+
+    /// A short description of this error, that fits on a line of the display
+    pub const fn label(&self) -> &'static str {
+        match self {
+            Self::Wifi(WifiError::Join { .. }) => "WiFi join",
+            Self::Wifi(WifiError::JoinTimeout) => "WiFi timeout",
+            Self::Network(NetworkError::DhcpTimeout) => "DHCP timeout",
+            Self::Network(NetworkError::LinkTimeout) => "Link timeout",
+            Self::Http(HttpError::Dns(_) | HttpError::Request(reqwless::Error::Dns)) => "DNS",
+            Self::Http(HttpError::Connect(_)) => "Connect",
+            Self::Http(HttpError::Tls(_)) => "TLS",
+            Self::Http(HttpError::Status { .. }) => "HTTP status",
+            Self::Http(HttpError::Redirect(_) | HttpError::RedirectNotAllowed | HttpError::TooManyRedirects) => {
+                "Redirect"
+            }
+            Self::Http(_) => "HTTP",
+            Self::Json(_) => "Response",
+            Self::DateTime(_) => "Datetime",
+            Self::RtcNotInitialized | Self::Rtc(_) => "RTC",
+        }
+    }
+
+    /// The delay in seconds the server asked for before retrying, if it did
+    pub const fn retry_after_secs(&self) -> Option<u64> {
+        match self {
+            Self::Http(HttpError::Status { retry_after_secs, .. }) => *retry_after_secs,
+            _ => None,
+        }
+    }
+}
+
+impl From<WifiError> for TimeSyncError {
+    fn from(error: WifiError) -> Self {
+        Self::Wifi(error)
+    }
+}
+
+impl From<NetworkError> for TimeSyncError {
+    fn from(error: NetworkError) -> Self {
+        Self::Network(error)
+    }
+}
+
+impl From<ConnectError> for TimeSyncError {
+    fn from(error: ConnectError) -> Self {
+        match error {
+            ConnectError::Wifi(error) => Self::Wifi(error),
+            ConnectError::Network(error) => Self::Network(error),
+        }
+    }
+}
+
+impl From<HttpError> for TimeSyncError {
+    fn from(error: HttpError) -> Self {
+        Self::Http(error)
+    }
+}
+
+/// Errors when persisting settings in the flash memory
+#[derive(PartialEq, Eq, Debug, Format, Clone)]
+pub enum FlashError {
+    /// Reading the value of a key failed
+    Fetch {
+        /// The key that was read
+        key: u8,
+        /// The error reported by the storage
+        error: sequential_storage::Error<flash::Error>,
+    },
+    /// Writing the value of a key failed
+    Store {
+        /// The key that was written
+        key: u8,
+        /// The error reported by the storage
+        error: sequential_storage::Error<flash::Error>,
+    },
+    /// Erasing the settings failed
+    Erase(sequential_storage::Error<flash::Error>),
+}
+
+/// Errors of a connection to the MQTT broker
+#[derive(PartialEq, Eq, Debug, Format, Clone)]
+pub enum MqttError {
+    /// The host name of the broker could not be resolved
+    Dns(dns::Error),
+    /// The TCP connection to the broker could not be established
+    Connect(tcp::ConnectError),
+    /// Reading from or writing to the broker failed
+    Io(tcp::Error),
+    /// A packet could not be encoded, or the broker sent a packet we cannot decode
+    Packet(PacketError),
+    /// The broker refused the connection, e.g. because of wrong credentials
+    Refused {
+        /// The return code of the `CONNACK` packet
+        return_code: u8,
+    },
+    /// The broker refused a subscription
+    SubscriptionRefused,
+    /// The broker did not answer within the timeout
+    Timeout,
+    /// The broker closed the connection
+    Closed,
+}
+
+impl MqttError {
+    /// The failure class of this error, which decides how long to back off before reconnecting
+    pub const fn failure_kind(&self) -> FailureKind {
+        match self {
+            Self::Dns(_) | Self::Connect(_) => FailureKind::Network,
+            _ => FailureKind::Http,
+        }
+    }
+}
+
+impl From<tcp::Error> for MqttError {
+    fn from(error: tcp::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<PacketError> for MqttError {
+    fn from(error: PacketError) -> Self {
+        Self::Packet(error)
+    }
+}
+
+/// Errors when delivering a webhook
+#[derive(PartialEq, Debug, Format, Clone)]
+pub enum WebhookError {
+    /// The network could not be connected
+    Network(ConnectError),
+    /// The request to the webhook failed
+    Http(HttpError),
+}
+
+impl WebhookError {
+    /// The failure class of this error, which decides how long to back off before retrying
+    pub const fn failure_kind(&self) -> FailureKind {
+        match self {
+            Self::Network(error) => error.failure_kind(),
+            Self::Http(HttpError::Request(reqwless::Error::Dns) | HttpError::Dns(_)) => FailureKind::Network,
+            Self::Http(_) => FailureKind::Http,
+        }
+    }
+}
+
+impl From<ConnectError> for WebhookError {
+    fn from(error: ConnectError) -> Self {
+        Self::Network(error)
+    }
+}
+
+impl From<HttpError> for WebhookError {
+    fn from(error: HttpError) -> Self {
+        Self::Http(error)
+    }
+}
+
+/// Errors when fetching the weather forecast
+#[derive(PartialEq, Debug, Format, Clone)]
+pub enum WeatherError {
+    /// The request to the weather API failed
+    Http(HttpError),
+    /// The response is not a forecast we understand
+    Forecast(ForecastError),
+}
+
+impl From<HttpError> for WeatherError {
+    fn from(error: HttpError) -> Self {
+        Self::Http(error)
+    }
+}
+
+impl From<ForecastError> for WeatherError {
+    fn from(error: ForecastError) -> Self {
+        Self::Forecast(error)
+    }
+}
+
+/// Errors when fetching the calendar
+#[derive(PartialEq, Debug, Format, Clone)]
+pub enum CalendarError {
+    /// The request for the calendar failed
+    Http(HttpError),
+    /// The response is not an iCalendar document
+    Ics(IcsError),
+}
+
+impl From<HttpError> for CalendarError {
+    fn from(error: HttpError) -> Self {
+        Self::Http(error)
+    }
+}
+
+impl From<IcsError> for CalendarError {
+    fn from(error: IcsError) -> Self {
+        Self::Ics(error)
+    }
+}
+
+/// Errors when updating the firmware
+#[derive(PartialEq, Debug, Format, Clone)]
+pub enum FirmwareError {
+    /// The request for the image failed
+    Http(HttpError),
+    /// The image is damaged, too large or not signed
+    Image(ImageError),
+    /// Reading or writing the update slot or the state of the bootloader failed
+    Flash,
+    /// The signature of the image does not match the public key
+    Signature,
+    /// The bootloader is not in a state to take an update
+    BadState,
+    /// The firmware in the update slot does not match the image after writing it
+    Written,
+}
+
+impl From<HttpError> for FirmwareError {
+    fn from(error: HttpError) -> Self {
+        Self::Http(error)
+    }
+}
+
+impl From<ImageError> for FirmwareError {
+    fn from(error: ImageError) -> Self {
+        Self::Image(error)
+    }
+}
+
+impl From<FirmwareUpdaterError> for FirmwareError {
+    fn from(error: FirmwareUpdaterError) -> Self {
+        match error {
+            FirmwareUpdaterError::Flash(_) => Self::Flash,
+            FirmwareUpdaterError::Signature(_) => Self::Signature,
+            FirmwareUpdaterError::BadState => Self::BadState,
+        }
+    }
+}
+
+/// An error of one of the subsystems, as reported to the orchestrator
+#[derive(PartialEq, Debug, Format, Clone)]
+pub enum SystemError {
+    /// The time synchronization failed
+    TimeSync(TimeSyncError),
+    /// Persisting the settings failed
+    Flash(FlashError),
+    /// Keeping the network connected failed
+    Network(ConnectError),
+    /// The connection to the MQTT broker failed
+    Mqtt(MqttError),
+    /// Delivering a webhook failed
+    Webhook(WebhookError),
+    /// Fetching the weather forecast failed
+    Weather(WeatherError),
+    /// Fetching the calendar failed
+    Calendar(CalendarError),
+    /// Updating the firmware failed
+    Firmware(FirmwareError),
+}
+
+/// The last error of every subsystem, `None` if the subsystem did not fail since startup
+#[derive(PartialEq, Debug, Format, Clone)]
+pub struct LastErrors {
+    /// The last error of the time synchronization
+    pub time_sync: Option<TimeSyncError>,
+    /// The last error when persisting the settings
+    pub flash: Option<FlashError>,
+    /// The last error when keeping the network connected
+    pub network: Option<ConnectError>,
+    /// The last error of the connection to the MQTT broker
+    pub mqtt: Option<MqttError>,
+    /// The last error when delivering a webhook
+    pub webhook: Option<WebhookError>,
+    /// The last error when fetching the weather forecast
+    pub weather: Option<WeatherError>,
+    /// The last error when fetching the calendar
+    pub calendar: Option<CalendarError>,
+    /// The last error when updating the firmware
+    pub firmware: Option<FirmwareError>,
+}
+
+impl LastErrors {
+    /// Create a new `LastErrors` without any errors
+    pub const fn new() -> Self {
+        Self {
+            time_sync: None,
+            flash: None,
+            network: None,
+            mqtt: None,
+            webhook: None,
+            weather: None,
+            calendar: None,
+            firmware: None,
+        }
+    }
+
+    /// Keep the given error as the last error of its subsystem
+    pub fn record(&mut self, error: SystemError) {
+        match error {
+            SystemError::TimeSync(error) => self.time_sync = Some(error),
+            SystemError::Flash(error) => self.flash = Some(error),
+            SystemError::Network(error) => self.network = Some(error),
+            SystemError::Mqtt(error) => self.mqtt = Some(error),
+            SystemError::Webhook(error) => self.webhook = Some(error),
+            SystemError::Weather(error) => self.weather = Some(error),
+            SystemError::Calendar(error) => self.calendar = Some(error),
+            SystemError::Firmware(error) => self.firmware = Some(error),
+        }
+    }
+}
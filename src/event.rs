@@ -3,7 +3,18 @@
 use defmt::Format;
 use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
 
-use crate::state::AlarmSettings;
+use crate::{
+    error::SystemError,
+    state::{AlarmSettings, DisplaySettings, NetworkInfo, TimeSyncReport},
+    utility::{
+        alarm_plan::AlarmPlan,
+        api_router::{AlarmUpdate, DisplayUpdate},
+        calendar::DateTime,
+        console::WifiCredentials,
+        weather::Forecast,
+    },
+    warn_remote,
+};
 
 /// System event channel for sending and receiving events
 pub static EVENT_CHANNEL: Channel<CriticalSectionRawMutex, Event, EVENT_CHANNEL_CAPACITY> = Channel::new();
@@ -16,6 +27,14 @@ pub async fn send_event(event: Event) {
     EVENT_CHANNEL.sender().send(event).await;
 }
 
+/// Sends an event to the system channel without waiting, dropping it if the channel is full.
+/// Meant for reports that must not block the sender, e.g. errors from tasks the orchestrator might be waiting on.
+pub fn try_send_event(event: Event) {
+    if let Err(e) = EVENT_CHANNEL.sender().try_send(event) {
+        warn_remote!("Event channel full, dropping event {:?}", e);
+    }
+}
+
 /// Receives the next event from the system channel
 pub async fn receive_event() -> Event {
     EVENT_CHANNEL.receiver().receive().await
@@ -38,10 +57,26 @@ pub enum Event {
     AlarmSettingsReadFromFlash(AlarmSettings),
     /// The alarm settings need to be updated in the flash memory
     AlarmSettingsNeedUpdate,
+    /// The alarm settings were changed over the API, the data holds the fields to change
+    ChangeAlarmSettings(AlarmUpdate),
+    /// The display settings have been read from the flash memory, the data is the display settings
+    DisplaySettingsReadFromFlash(DisplaySettings),
+    /// The display settings were changed over the API or the console, the data holds the fields to change
+    ChangeDisplaySettings(DisplayUpdate),
+    /// The alarm was changed on a peer clock, the data holds the shared fields to change
+    PeerAlarmSettings(AlarmUpdate),
+    /// The time was set on the console, the data is the local time to set the RTC to
+    SetTime(DateTime),
+    /// `WiFi` credentials were set on the console, they are stored and the network is joined with them
+    ChangeWifiCredentials(WifiCredentials),
+    /// The system must restart
+    Reboot,
+    /// The settings and credentials in the flash memory must be erased, then the system must restart
+    FactoryReset,
     /// The scheduler has ticked, the data is the time in (hour, minute, second)
     Scheduler((u8, u8, u8)),
-    /// The rtc has been updated
-    RtcUpdated,
+    /// The rtc has been updated by a time sync, the data is the outcome of the sync
+    RtcUpdated(TimeSyncReport),
     /// The system must go to standby mode
     Standby,
     /// The system must wake up from standby mode
@@ -50,6 +85,22 @@ pub enum Event {
     Alarm,
     /// The alarm must be stopped
     AlarmStop,
+    /// The alarm was not stopped in time and must be stopped
+    AlarmExpired,
+    /// The alarm must be stopped and raised again after the snooze time
+    AlarmSnooze,
     /// The light effect `sunrise` has finished
     SunriseEffectFinished,
+    /// The network connection is up, the data is the active network configuration
+    NetworkConnected(NetworkInfo),
+    /// The network connection is down, it was left or it dropped
+    NetworkDisconnected,
+    /// The signal strength of the `WiFi` connection was measured, the data is the RSSI in dBm
+    WifiSignal(i32),
+    /// The weather forecast of the day was fetched
+    ForecastUpdated(Forecast),
+    /// The calendar was fetched, the data is what it holds for the alarm of the next days
+    CalendarUpdated(AlarmPlan),
+    /// A subsystem failed, the data is the error it reported
+    Error(SystemError),
 }
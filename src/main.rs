@@ -15,31 +15,43 @@ use embassy_rp::{
     flash::{Async, Flash},
     gpio::{Input, Level, Output, Pull},
     i2c::{Config as I2cConfig, I2c, InterruptHandler as I2cInterruptHandler},
-    peripherals::{I2C0, PIO0, UART1},
+    peripherals::{I2C0, PIO0, UART1, USB},
     pio::InterruptHandler as PioInterruptHandler,
     rtc::{InterruptHandler as RtcInterruptHandler, Rtc},
     spi::{Config as SpiConfig, Phase, Polarity, Spi},
     uart::{BufferedInterruptHandler, BufferedUart, Config as UartConfig},
+    usb::{Driver as UsbDriver, InterruptHandler as UsbInterruptHandler},
 };
+use embassy_sync::mutex::Mutex;
 use panic_probe as _;
 use static_cell::StaticCell;
 
 use crate::{
     event::Event,
     task::{
-        alarm_settings::alarm_settings_handler,
+        alarm_settings::{FLASH_SIZE, SharedFlash, alarm_settings_handler},
         alarm_trigger::alarm_trigger_task,
+        api_server::api_server_task,
         buttons::{Button, button_handler},
+        console::console_task,
         display::display_handler,
+        firmware_update::firmware_trial_task,
         light_effects::light_effects_handler,
-        orchestrate::{alarm_expirer, orchestrator, scheduler},
+        mdns::mdns_task,
+        mqtt::mqtt_task,
+        network::{WifiPeripherals, network_task},
+        orchestrate::{alarm_expirer, alarm_snoozer, orchestrator, scheduler},
+        peer_sync::peer_sync_task,
         power::{usb_power_detector, vsys_voltage_reader},
         sound::sound_handler,
+        syslog::syslog_task,
         time_updater::time_updater,
         watchdog::watchdog_task,
+        webhook::webhook_task,
     },
 };
 
+mod error;
 mod event;
 mod state;
 mod task;
@@ -52,6 +64,7 @@ bind_interrupts!(pub struct Irqs {
     UART1_IRQ => BufferedInterruptHandler<UART1>;
     ADC_IRQ_FIFO => AdcInterruptHandler;
     RTC_IRQ => RtcInterruptHandler;
+    USBCTRL_IRQ => UsbInterruptHandler<USB>;
 });
 
 /// Helper function to spawn tasks and unwrap, panicking if spawn fails.
@@ -80,6 +93,7 @@ async fn main(spawner: Spawner) {
     spawn_unwrap(spawner, orchestrator());
     spawn_unwrap(spawner, scheduler());
     spawn_unwrap(spawner, alarm_expirer());
+    spawn_unwrap(spawner, alarm_snoozer());
     spawn_unwrap(spawner, alarm_trigger_task());
 
     // Green button
@@ -120,14 +134,14 @@ async fn main(spawner: Spawner) {
     let dfplayer_pwr = Output::new(p.PIN_8, Level::Low);
     spawn_unwrap(spawner, sound_handler(uart, dfplayer_pwr));
 
-    // Alarm settings persistence
-    const FLASH_SIZE: usize = 2 * 1024 * 1024;
-    let flash = Flash::<_, Async, FLASH_SIZE>::new(p.FLASH, p.DMA_CH4);
+    // Alarm settings persistence and firmware updates, sharing the flash
+    static FLASH: StaticCell<SharedFlash> = StaticCell::new();
+    let flash = FLASH.init(Mutex::new(Flash::<_, Async, FLASH_SIZE>::new(p.FLASH, p.DMA_CH4)));
     spawn_unwrap(spawner, alarm_settings_handler(flash));
+    spawn_unwrap(spawner, firmware_trial_task(flash));
 
-    // Time updater with WiFi and RTC
-    let rtc = Rtc::new(p.RTC, Irqs);
-    let wifi_peripherals = crate::task::time_updater::WifiPeripherals {
+    // Network with WiFi
+    let wifi_peripherals = WifiPeripherals {
         pwr_pin: p.PIN_23,
         cs_pin: p.PIN_25,
         pio: p.PIO0,
@@ -135,7 +149,21 @@ async fn main(spawner: Spawner) {
         clk_pin: p.PIN_29,
         dma_ch: p.DMA_CH0,
     };
-    spawn_unwrap(spawner, time_updater(spawner, rtc, wifi_peripherals));
+    spawn_unwrap(spawner, network_task(spawner, wifi_peripherals));
+    spawn_unwrap(spawner, api_server_task());
+    spawn_unwrap(spawner, mqtt_task());
+    spawn_unwrap(spawner, mdns_task());
+    spawn_unwrap(spawner, webhook_task());
+    spawn_unwrap(spawner, syslog_task());
+    spawn_unwrap(spawner, peer_sync_task());
+
+    // Time updater with RTC
+    let rtc = Rtc::new(p.RTC, Irqs);
+    spawn_unwrap(spawner, time_updater(spawner, rtc));
+
+    // Serial console on USB
+    let usb_driver = UsbDriver::new(p.USB, Irqs);
+    spawn_unwrap(spawner, console_task(spawner, usb_driver));
 
     // Neopixel light effects
     let mut spi_config = SpiConfig::default();
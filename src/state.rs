@@ -3,11 +3,20 @@
 use defmt::Format;
 use embassy_rp::clocks::RoscRng;
 use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
+use heapless::String;
 use rand::Rng;
 
 use crate::{
+    error::LastErrors,
     event::{Event, send_event},
     task::buttons::Button,
+    utility::{
+        alarm_limits::ALARM_EVERY_DAY,
+        alarm_plan::{self, AlarmPlan},
+        calendar::DateTime,
+        console::SSID_CAPACITY,
+        weather::Forecast,
+    },
 };
 
 /// Type alias for the system state protected by a mutex.
@@ -34,10 +43,24 @@ pub struct SystemState {
     pub operation_mode: OperationMode,
     /// The settings for the alarm
     pub alarm_settings: AlarmSettings,
+    /// The settings for the display
+    pub display_settings: DisplaySettings,
     /// The state of the alarm
     pub alarm_state: AlarmState,
     /// The power state of the system
     pub power_state: PowerState,
+    /// The state of the network connection
+    pub network_state: NetworkState,
+    /// The status of the time synchronization
+    pub time_sync_status: TimeSyncStatus,
+    /// The last error of every subsystem
+    pub last_errors: LastErrors,
+    /// The last weather forecast, `None` until one was fetched
+    pub forecast: Option<Forecast>,
+    /// What the calendar holds for the alarm of the next days, `None` until it was fetched
+    pub alarm_plan: Option<AlarmPlan>,
+    /// Whether it is night by the night schedule, updated with every scheduler tick
+    pub night: bool,
 }
 
 /// State transitions and operations
@@ -48,6 +71,7 @@ impl SystemState {
         Self {
             operation_mode: OperationMode::Normal,
             alarm_settings: AlarmSettings::new_empty(),
+            display_settings: DisplaySettings::new_empty(),
             alarm_state: AlarmState::None,
             power_state: PowerState {
                 usb_power: false,
@@ -56,7 +80,35 @@ impl SystemState {
                 battery_voltage_empty: 2.6,
                 battery_level: BatteryLevel::Bat000,
             },
+            network_state: NetworkState {
+                connected: false,
+                info: None,
+            },
+            time_sync_status: TimeSyncStatus {
+                last_sync: None,
+                successes: 0,
+                failures: 0,
+            },
+            last_errors: LastErrors::new(),
+            forecast: None,
+            alarm_plan: None,
+            night: false,
+        }
+    }
+
+    /// The next time after `now` the alarm rings, following the calendar. `None` if the alarm is disabled or rings
+    /// on no day.
+    pub fn next_alarm(&self, now: &DateTime) -> Option<DateTime> {
+        if !self.alarm_settings.get_enabled() {
+            return None;
         }
+        alarm_plan::next_alarm(
+            now,
+            self.alarm_settings.get_hour(),
+            self.alarm_settings.get_minute(),
+            self.alarm_settings.get_days(),
+            self.alarm_plan.as_ref(),
+        )
     }
 
     /// Toggle the alarm enabled state
@@ -97,6 +149,16 @@ impl SystemState {
         self.operation_mode = OperationMode::SystemInfo;
     }
 
+    /// Set the system to weather mode
+    pub const fn set_weather_mode(&mut self) {
+        self.operation_mode = OperationMode::Weather;
+    }
+
+    /// Set the system to network info mode
+    pub const fn set_network_info_mode(&mut self, page: NetworkInfoPage) {
+        self.operation_mode = OperationMode::NetworkInfo(page);
+    }
+
     /// Increment the alarm hour
     pub const fn increment_alarm_hour(&mut self) {
         self.alarm_settings.increment_alarm_hour();
@@ -149,10 +211,61 @@ pub enum OperationMode {
     Menu,
     /// Displaying the system info
     SystemInfo,
+    /// Displaying the weather forecast of the day, reached from the system info
+    Weather,
+    /// Displaying a page of the network info, reached from the weather
+    NetworkInfo(NetworkInfoPage),
     /// The system is in standby mode, the display is off, the neopixel ring is off, the system is in a low power state.
     Standby,
 }
 
+impl OperationMode {
+    /// The name of the operation mode, as reported over the network
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Normal => "normal",
+            Self::SetAlarmTime => "set_alarm_time",
+            Self::Alarm => "alarm",
+            Self::Menu => "menu",
+            Self::SystemInfo => "system_info",
+            Self::Weather => "weather",
+            Self::NetworkInfo(_) => "network_info",
+            Self::Standby => "standby",
+        }
+    }
+}
+
+/// The pages of the network info, shown one after the other
+#[derive(Eq, PartialEq, Debug, Format, Clone, Copy)]
+pub enum NetworkInfoPage {
+    /// SSID, signal strength and whether the network is connected
+    Wifi,
+    /// IPv4 address, gateway and DNS server
+    Addresses,
+    /// Hostname and how the addresses are obtained
+    Config,
+    /// Last successful time sync, sync counts and the last error
+    TimeSync,
+}
+
+impl NetworkInfoPage {
+    /// The page after this one, `None` after the last page
+    pub const fn next(self) -> Option<Self> {
+        match self {
+            Self::Wifi => Some(Self::Addresses),
+            Self::Addresses => Some(Self::Config),
+            Self::Config => Some(Self::TimeSync),
+            Self::TimeSync => None,
+        }
+    }
+}
+
+/// The sunrise length in minutes when none was set
+pub const DEFAULT_SUNRISE_MINUTES: u8 = 1;
+
+/// The alarm volume when none was set
+pub const DEFAULT_ALARM_VOLUME: u8 = 13;
+
 /// The settings for the alarm
 #[derive(Eq, PartialEq, Debug, Format, Clone)]
 pub struct AlarmSettings {
@@ -160,6 +273,12 @@ pub struct AlarmSettings {
     time: (u8, u8),
     /// The alarm is enabled or disabled
     enabled: bool,
+    /// The days of the week the alarm rings on, bit 0 is Monday and bit 6 is Sunday
+    days: u8,
+    /// The length of the sunrise effect before the alarm sound in minutes
+    sunrise_minutes: u8,
+    /// The volume of the alarm sound, 0 to 30
+    volume: u8,
     /// The color sequence of buttons that need to be pressed to stop the alarm
     stop_alarm_button_sequence: [Button; 3],
 }
@@ -170,6 +289,9 @@ impl AlarmSettings {
         Self {
             time: (0, 0),
             enabled: false,
+            days: ALARM_EVERY_DAY,
+            sunrise_minutes: DEFAULT_SUNRISE_MINUTES,
+            volume: DEFAULT_ALARM_VOLUME,
             stop_alarm_button_sequence: [Button::Green, Button::Blue, Button::Yellow],
         }
     }
@@ -199,6 +321,36 @@ impl AlarmSettings {
         self.enabled
     }
 
+    /// Set the days of the week the alarm rings on, bit 0 is Monday and bit 6 is Sunday
+    pub const fn set_days(&mut self, days: u8) {
+        self.days = days;
+    }
+
+    /// Get the days of the week the alarm rings on, bit 0 is Monday and bit 6 is Sunday
+    pub const fn get_days(&self) -> u8 {
+        self.days
+    }
+
+    /// Set the length of the sunrise effect in minutes
+    pub const fn set_sunrise_minutes(&mut self, minutes: u8) {
+        self.sunrise_minutes = minutes;
+    }
+
+    /// Get the length of the sunrise effect in minutes
+    pub const fn get_sunrise_minutes(&self) -> u8 {
+        self.sunrise_minutes
+    }
+
+    /// Set the volume of the alarm sound
+    pub const fn set_volume(&mut self, volume: u8) {
+        self.volume = volume;
+    }
+
+    /// Get the volume of the alarm sound
+    pub const fn get_volume(&self) -> u8 {
+        self.volume
+    }
+
     /// Increment the alarm hour
     pub const fn increment_alarm_hour(&mut self) {
         let mut hour = self.get_hour();
@@ -269,6 +421,30 @@ impl AlarmSettings {
     }
 }
 
+/// The settings for the display, each clock keeps its own, they are not shared with peers
+#[derive(Eq, PartialEq, Debug, Format, Clone, Copy)]
+pub struct DisplaySettings {
+    /// The display shows the time in 12-hour format with AM and PM, the alarm time is kept in 24-hour format anyway
+    twelve_hour: bool,
+}
+
+impl DisplaySettings {
+    /// Create a new `DisplaySettings` with default values, the 24-hour format.
+    pub const fn new_empty() -> Self {
+        Self { twelve_hour: false }
+    }
+
+    /// Set whether the display shows the time in 12-hour format
+    pub const fn set_twelve_hour(&mut self, twelve_hour: bool) {
+        self.twelve_hour = twelve_hour;
+    }
+
+    /// Get whether the display shows the time in 12-hour format
+    pub const fn get_twelve_hour(&self) -> bool {
+        self.twelve_hour
+    }
+}
+
 /// The state of the alarm
 #[derive(Eq, PartialEq, Debug, Format, Clone)]
 pub enum AlarmState {
@@ -287,6 +463,15 @@ impl AlarmState {
     pub fn is_active(&self) -> bool {
         self != &Self::None
     }
+
+    /// The name of the alarm state, as reported over the network
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Sunrise => "sunrise",
+            Self::Noise => "noise",
+        }
+    }
 }
 
 /// The battery level of the system in steps of 20% from 0 to 100. One additional state is provided for charging.
@@ -308,6 +493,34 @@ pub enum BatteryLevel {
     Bat100,
 }
 
+impl BatteryLevel {
+    /// The battery level in percent, `None` while charging
+    pub const fn percent(&self) -> Option<u8> {
+        match self {
+            Self::Charging => None,
+            Self::Bat000 => Some(0),
+            Self::Bat020 => Some(20),
+            Self::Bat040 => Some(40),
+            Self::Bat060 => Some(60),
+            Self::Bat080 => Some(80),
+            Self::Bat100 => Some(100),
+        }
+    }
+
+    /// The name of the battery level, `charging` or the percentage, as reported over the network
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Charging => "charging",
+            Self::Bat000 => "0",
+            Self::Bat020 => "20",
+            Self::Bat040 => "40",
+            Self::Bat060 => "60",
+            Self::Bat080 => "80",
+            Self::Bat100 => "100",
+        }
+    }
+}
+
 /// The power state of the system
 #[derive(PartialEq, Debug, Format, Clone)]
 pub struct PowerState {
@@ -385,3 +598,126 @@ impl PowerState {
         self.set_battery_level();
     }
 }
+
+/// The active network configuration, as it was configured or received from DHCP
+#[derive(Eq, PartialEq, Debug, Format, Clone)]
+pub struct NetworkInfo {
+    /// The SSID of the `WiFi` network
+    pub ssid: String<SSID_CAPACITY>,
+    /// The signal strength in dBm
+    pub rssi_dbm: i32,
+    /// The hostname announced to DHCP
+    pub hostname: &'static str,
+    /// The IPv4 address is configured statically rather than taken from DHCP
+    pub static_ipv4: bool,
+    /// The IPv4 address and prefix length
+    pub address: Option<([u8; 4], u8)>,
+    /// The default gateway
+    pub gateway: Option<[u8; 4]>,
+    /// The first DNS server
+    pub dns_server: Option<[u8; 4]>,
+    /// IPv6 addresses are obtained by SLAAC
+    pub ipv6_slaac: bool,
+}
+
+/// The state of the network connection
+#[derive(Eq, PartialEq, Debug, Format, Clone)]
+pub struct NetworkState {
+    /// The network is joined and configured
+    connected: bool,
+    /// The network configuration of the last connection, `None` if there was none since startup
+    info: Option<NetworkInfo>,
+}
+
+impl NetworkState {
+    /// Check if the network is connected
+    pub const fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Get the network configuration of the last connection, `None` if there was none since startup
+    pub const fn get_info(&self) -> Option<&NetworkInfo> {
+        self.info.as_ref()
+    }
+
+    /// Set the network connected with the given configuration
+    pub const fn set_connected(&mut self, info: NetworkInfo) {
+        self.connected = true;
+        self.info = Some(info);
+    }
+
+    /// Set the network disconnected, keeping the configuration of the last connection
+    pub const fn set_disconnected(&mut self) {
+        self.connected = false;
+    }
+
+    /// Set the signal strength measured on the current connection
+    pub const fn set_rssi(&mut self, rssi_dbm: i32) {
+        if let Some(info) = self.info.as_mut() {
+            info.rssi_dbm = rssi_dbm;
+        }
+    }
+}
+
+/// Where the time of a sync came from
+#[derive(Eq, PartialEq, Debug, Format, Clone, Copy)]
+pub enum TimeSource {
+    /// The datetime in the body of the time API response
+    Api,
+    /// The `Date` header of the response, the coarse fallback
+    DateHeader,
+    /// Set by hand on the console
+    Console,
+}
+
+/// The outcome of a successful time sync, as reported by the time updater
+#[derive(Eq, PartialEq, Debug, Format, Clone)]
+pub struct TimeSyncReport {
+    /// The local time the RTC was set to, in seconds since the Unix epoch
+    pub synced_at: i64,
+    /// Where the time came from
+    pub source: TimeSource,
+    /// Drift of the RTC against the fetched time in seconds, if it could be measured
+    pub drift_secs: Option<i64>,
+    /// UTC offset of the local time in minutes, from this or an earlier sync. `None` if no sync told it yet.
+    pub utc_offset_minutes: Option<i16>,
+}
+
+/// The status of the time synchronization. The last error is kept in `LastErrors`.
+#[derive(Eq, PartialEq, Debug, Format, Clone)]
+pub struct TimeSyncStatus {
+    /// The last successful sync, `None` if there was none since startup
+    last_sync: Option<TimeSyncReport>,
+    /// Number of successful syncs since startup
+    successes: u32,
+    /// Number of failed syncs since startup
+    failures: u32,
+}
+
+impl TimeSyncStatus {
+    /// Get the last successful sync, `None` if there was none since startup
+    pub const fn get_last_sync(&self) -> Option<&TimeSyncReport> {
+        self.last_sync.as_ref()
+    }
+
+    /// Get the number of successful syncs since startup
+    pub const fn get_successes(&self) -> u32 {
+        self.successes
+    }
+
+    /// Get the number of failed syncs since startup
+    pub const fn get_failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// Record a successful sync
+    pub const fn record_success(&mut self, report: TimeSyncReport) {
+        self.last_sync = Some(report);
+        self.successes = self.successes.saturating_add(1);
+    }
+
+    /// Record a failed sync
+    pub const fn record_failure(&mut self) {
+        self.failures = self.failures.saturating_add(1);
+    }
+}
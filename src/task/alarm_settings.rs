@@ -1,15 +1,23 @@
 //! # Alarm Settings
 //! This module contains the functionality to persist the alarm settings in the flash memory.
 //!
-//! The alarm settings are stored in the flash memory as three separate key/value pairs.
+//! The alarm settings are stored in the flash memory as separate key/value pairs: hour, minute, enabled, days,
+//! sunrise minutes and volume. Settings that were stored before there were days, sunrise and volume are read with the
+//! defaults for those.
+//!
+//! The display settings, the 12-hour format for now, are kept in the same range under keys of their own, they are
+//! not part of the alarm settings.
+//!
+//! The `WiFi` credentials set on the console are kept in the same range under a key of their own, they are handed to
+//! the network task on startup. A factory reset erases the whole range and restarts the system.
 use core::ops::Range;
 
-use defmt::{Debug2Format, info, warn};
+use defmt::{info, warn};
 use embassy_rp::{
     flash::{Async, Flash},
     peripherals::FLASH,
 };
-use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel, mutex::Mutex};
 use sequential_storage::{
     self,
     cache::NoCache,
@@ -17,30 +25,82 @@ use sequential_storage::{
 };
 
 use crate::{
-    event::{Event, send_event},
-    state::AlarmSettings,
+    error::{FlashError, SystemError},
+    event::{Event, send_event, try_send_event},
+    state::{AlarmSettings, DEFAULT_ALARM_VOLUME, DEFAULT_SUNRISE_MINUTES, DisplaySettings},
+    task::{network::set_wifi_credentials, watchdog::request_reset},
+    utility::{alarm_limits::ALARM_EVERY_DAY, console::WifiCredentials},
+    warn_remote,
 };
 
-/// Channel for flash write commands
-static FLASH_CHANNEL: Channel<CriticalSectionRawMutex, AlarmSettings, 1> = Channel::new();
+/// The keys of the alarm settings in the flash memory
+const KEYS: [u8; 6] = [0, 1, 2, 3, 4, 5];
+
+/// The key of the `WiFi` credentials in the flash memory
+const WIFI_CREDENTIALS_KEY: u8 = 6;
+
+/// The key of the 12-hour format of the display in the flash memory
+const TWELVE_HOUR_KEY: u8 = 7;
+
+/// Commands for the flash task
+enum FlashCommand {
+    /// Write the alarm settings
+    WriteAlarmSettings(AlarmSettings),
+    /// Write the display settings
+    WriteDisplaySettings(DisplaySettings),
+    /// Write the `WiFi` credentials
+    WriteWifiCredentials(WifiCredentials),
+    /// Erase the settings and credentials, then restart
+    EraseAll,
+}
+
+/// Channel for flash commands
+static FLASH_CHANNEL: Channel<CriticalSectionRawMutex, FlashCommand, 1> = Channel::new();
 
 /// Sends alarm settings to be written to flash
 pub async fn send_flash_write_command(settings: AlarmSettings) {
-    FLASH_CHANNEL.sender().send(settings).await;
+    FLASH_CHANNEL
+        .sender()
+        .send(FlashCommand::WriteAlarmSettings(settings))
+        .await;
+}
+
+/// Sends display settings to be written to flash
+pub async fn send_display_settings_write_command(settings: DisplaySettings) {
+    FLASH_CHANNEL
+        .sender()
+        .send(FlashCommand::WriteDisplaySettings(settings))
+        .await;
+}
+
+/// Sends `WiFi` credentials to be written to flash
+pub async fn send_wifi_credentials_write_command(credentials: WifiCredentials) {
+    FLASH_CHANNEL
+        .sender()
+        .send(FlashCommand::WriteWifiCredentials(credentials))
+        .await;
+}
+
+/// Sends the command to erase the settings and credentials in flash and restart the system
+pub async fn send_flash_erase_command() {
+    FLASH_CHANNEL.sender().send(FlashCommand::EraseAll).await;
 }
 
-/// Waits for the next flash write command
-async fn wait_for_flash_write_command() -> AlarmSettings {
+/// Waits for the next flash command
+async fn wait_for_flash_command() -> FlashCommand {
     FLASH_CHANNEL.receiver().receive().await
 }
 
 /// The size of the flash memory in bytes.
-const FLASH_SIZE: usize = 2 * 1024 * 1024;
+pub const FLASH_SIZE: usize = 2 * 1024 * 1024;
+
+/// The flash memory, shared with the firmware updates, see `task::firmware_update`.
+pub type SharedFlash = Mutex<CriticalSectionRawMutex, Flash<'static, FLASH, Async, { FLASH_SIZE }>>;
 
 /// This struct is used to persist the alarm settings in the flash memory.
 pub struct PersistedAlarmSettings<'a> {
-    /// The flash peripheral used to read and write the alarm settings.
-    flash: Flash<'a, FLASH, Async, { FLASH_SIZE }>,
+    /// The flash memory used to read and write the alarm settings.
+    flash: &'a SharedFlash,
     /// The range of the flash memory used to store the alarm settings.
     flash_range: Range<u32>,
     /// A buffer used for reading and writing data to the flash memory.
@@ -49,8 +109,8 @@ pub struct PersistedAlarmSettings<'a> {
 
 impl<'a> PersistedAlarmSettings<'a> {
     /// This function creates a new instance of the `PersistedAlarmTime` struct.
-    /// It takes a Flash peripheral as an argument and returns a `PersistedAlarmTime` struct.
-    pub const fn new(flash: Flash<'a, FLASH, Async, { FLASH_SIZE }>) -> Self {
+    /// It takes the shared flash memory as an argument and returns a `PersistedAlarmTime` struct.
+    pub const fn new(flash: &'a SharedFlash) -> Self {
         Self {
             flash_range: 0x1F_9000..0x1FC_000,
             data_buffer: [0; 128],
@@ -61,13 +121,12 @@ impl<'a> PersistedAlarmSettings<'a> {
     /// this function reads the alarm time from the flash memory.
     /// Returns None if there's a critical error reading the settings.
     pub async fn read_alarm_settings_from_flash(&mut self) -> Option<AlarmSettings> {
-        let keys: [u8; 3] = [0, 1, 2];
-        let mut values = [None; 3];
+        let mut values = [None; KEYS.len()];
         let mut has_any_value = false;
 
-        for (i, key) in keys.iter().enumerate() {
+        for (i, key) in KEYS.iter().enumerate() {
             match fetch_item::<u8, u8, _>(
-                &mut self.flash,
+                &mut *self.flash.lock().await,
                 self.flash_range.clone(),
                 &mut NoCache::new(),
                 &mut self.data_buffer,
@@ -83,14 +142,18 @@ impl<'a> PersistedAlarmSettings<'a> {
                     info!("No value found for key {:?}", &key);
                 }
                 Err(e) => {
-                    warn!("Failed to fetch value for key {:?}: {:?}", &key, Debug2Format(&e));
+                    warn!("Failed to fetch value for key {:?}: {:?}", &key, &e);
+                    try_send_event(Event::Error(SystemError::Flash(FlashError::Fetch {
+                        key: *key,
+                        error: e,
+                    })));
                 }
             }
         }
 
         // If we didn't read any values successfully, return None
         if !has_any_value {
-            warn!("No alarm settings found in flash");
+            warn_remote!("No alarm settings found in flash");
             return None;
         }
 
@@ -98,22 +161,27 @@ impl<'a> PersistedAlarmSettings<'a> {
         let mut alarm_settings = AlarmSettings::new_empty();
         alarm_settings.set_time((values[0].unwrap_or(0), values[1].unwrap_or(0)));
         alarm_settings.set_enabled(values[2].unwrap_or(0) != 0);
+        alarm_settings.set_days(values[3].unwrap_or(ALARM_EVERY_DAY));
+        alarm_settings.set_sunrise_minutes(values[4].unwrap_or(DEFAULT_SUNRISE_MINUTES));
+        alarm_settings.set_volume(values[5].unwrap_or(DEFAULT_ALARM_VOLUME));
         Some(alarm_settings)
     }
 
     /// this function writes the alarm settings to the flash memory.
-    /// These values are written to the flash memory in three separate key/value pairs.
+    /// These values are written to the flash memory in separate key/value pairs.
     pub async fn write_alarm_settings_to_flash(&mut self, alarm_settings: AlarmSettings) {
-        let keys: [u8; 3] = [0, 1, 2];
         let values = [
             alarm_settings.get_hour(),
             alarm_settings.get_minute(),
             alarm_settings.get_enabled().into(),
+            alarm_settings.get_days(),
+            alarm_settings.get_sunrise_minutes(),
+            alarm_settings.get_volume(),
         ];
 
-        for (key, value) in keys.iter().zip(values.iter()) {
+        for (key, value) in KEYS.iter().zip(values.iter()) {
             match store_item::<u8, u8, _>(
-                &mut self.flash,
+                &mut *self.flash.lock().await,
                 self.flash_range.clone(),
                 &mut NoCache::new(),
                 &mut self.data_buffer,
@@ -128,21 +196,156 @@ impl<'a> PersistedAlarmSettings<'a> {
                 Err(e) => {
                     warn!(
                         "Failed to store alarm settings key {:?} value {:?}: {:?}",
-                        &key,
-                        &value,
-                        Debug2Format(&e)
+                        &key, &value, &e
                     );
+                    try_send_event(Event::Error(SystemError::Flash(FlashError::Store {
+                        key: *key,
+                        error: e,
+                    })));
                     // Continue trying to store other values even if one fails
                 }
             }
         }
     }
+
+    /// this function reads the display settings from the flash memory.
+    /// Returns None if none were stored or they could not be read.
+    pub async fn read_display_settings_from_flash(&mut self) -> Option<DisplaySettings> {
+        match fetch_item::<u8, u8, _>(
+            &mut *self.flash.lock().await,
+            self.flash_range.clone(),
+            &mut NoCache::new(),
+            &mut self.data_buffer,
+            &TWELVE_HOUR_KEY,
+        )
+        .await
+        {
+            Ok(Some(value)) => {
+                info!("Read display settings, twelve hour: {:?}", &value);
+                let mut display_settings = DisplaySettings::new_empty();
+                display_settings.set_twelve_hour(value != 0);
+                Some(display_settings)
+            }
+            Ok(None) => {
+                info!("No display settings in flash");
+                None
+            }
+            Err(e) => {
+                warn!("Failed to fetch display settings: {:?}", &e);
+                try_send_event(Event::Error(SystemError::Flash(FlashError::Fetch {
+                    key: TWELVE_HOUR_KEY,
+                    error: e,
+                })));
+                None
+            }
+        }
+    }
+
+    /// this function writes the display settings to the flash memory.
+    pub async fn write_display_settings_to_flash(&mut self, display_settings: DisplaySettings) {
+        let value: u8 = display_settings.get_twelve_hour().into();
+        match store_item::<u8, u8, _>(
+            &mut *self.flash.lock().await,
+            self.flash_range.clone(),
+            &mut NoCache::new(),
+            &mut self.data_buffer,
+            &TWELVE_HOUR_KEY,
+            &value,
+        )
+        .await
+        {
+            Ok(()) => info!("Display settings stored successfully"),
+            Err(e) => {
+                warn!("Failed to store display settings: {:?}", &e);
+                try_send_event(Event::Error(SystemError::Flash(FlashError::Store {
+                    key: TWELVE_HOUR_KEY,
+                    error: e,
+                })));
+            }
+        }
+    }
+
+    /// this function reads the `WiFi` credentials set on the console from the flash memory.
+    /// Returns None if none were set or they could not be read.
+    pub async fn read_wifi_credentials_from_flash(&mut self) -> Option<WifiCredentials> {
+        match fetch_item::<u8, &[u8], _>(
+            &mut *self.flash.lock().await,
+            self.flash_range.clone(),
+            &mut NoCache::new(),
+            &mut self.data_buffer,
+            &WIFI_CREDENTIALS_KEY,
+        )
+        .await
+        {
+            Ok(Some(bytes)) => {
+                let credentials = WifiCredentials::decode(bytes);
+                if credentials.is_none() {
+                    warn_remote!("Invalid WiFi credentials in flash, using wifi_config.json");
+                }
+                credentials
+            }
+            Ok(None) => {
+                info!("No WiFi credentials in flash, using wifi_config.json");
+                None
+            }
+            Err(e) => {
+                warn!("Failed to fetch WiFi credentials: {:?}", &e);
+                try_send_event(Event::Error(SystemError::Flash(FlashError::Fetch {
+                    key: WIFI_CREDENTIALS_KEY,
+                    error: e,
+                })));
+                None
+            }
+        }
+    }
+
+    /// this function writes the `WiFi` credentials to the flash memory, as a single value.
+    pub async fn write_wifi_credentials_to_flash(&mut self, credentials: &WifiCredentials) {
+        let mut encoded = [0; WifiCredentials::ENCODED_CAPACITY];
+        let value = credentials.encode(&mut encoded);
+        match store_item::<u8, &[u8], _>(
+            &mut *self.flash.lock().await,
+            self.flash_range.clone(),
+            &mut NoCache::new(),
+            &mut self.data_buffer,
+            &WIFI_CREDENTIALS_KEY,
+            &value,
+        )
+        .await
+        {
+            Ok(()) => info!("WiFi credentials for {} stored successfully", credentials.ssid.as_str()),
+            Err(e) => {
+                warn!("Failed to store WiFi credentials: {:?}", &e);
+                try_send_event(Event::Error(SystemError::Flash(FlashError::Store {
+                    key: WIFI_CREDENTIALS_KEY,
+                    error: e,
+                })));
+            }
+        }
+    }
+
+    /// this function erases the whole range of the settings in the flash memory.
+    /// Returns whether it succeeded.
+    pub async fn erase_all(&mut self) -> bool {
+        match sequential_storage::erase_all(&mut *self.flash.lock().await, self.flash_range.clone()).await {
+            Ok(()) => {
+                info!("Settings erased");
+                true
+            }
+            Err(e) => {
+                warn!("Failed to erase the settings: {:?}", &e);
+                try_send_event(Event::Error(SystemError::Flash(FlashError::Erase(e))));
+                false
+            }
+        }
+    }
 }
 
-/// This task reads the alarm settings from the flash memory on startup and sends it to the event channel.
-/// After that, it waits for commands to update the alarm settings.
+/// This task reads the alarm and display settings from the flash memory on startup and sends them to the event
+/// channel, and hands
+/// the `WiFi` credentials to the network task. After that, it waits for commands to update the flash memory.
 #[embassy_executor::task]
-pub async fn alarm_settings_handler(flash: Flash<'static, FLASH, Async, { FLASH_SIZE }>) {
+pub async fn alarm_settings_handler(flash: &'static SharedFlash) {
     let mut persisted_alarm_settings = PersistedAlarmSettings::new(flash);
 
     // Read the alarm settings from the flash memory only once at the start of the task
@@ -152,13 +355,39 @@ pub async fn alarm_settings_handler(flash: Flash<'static, FLASH, Async, { FLASH_
     } else {
         warn!("Failed to read alarm settings from flash on startup");
     }
+    if let Some(display_settings) = persisted_alarm_settings.read_display_settings_from_flash().await {
+        send_event(Event::DisplaySettingsReadFromFlash(display_settings)).await;
+    }
+    // the network task waits for this before joining, even if there are no credentials
+    set_wifi_credentials(persisted_alarm_settings.read_wifi_credentials_from_flash().await).await;
 
-    // and then we wait for commands to update the alarm settings
+    // and then we wait for commands to update the flash memory
     loop {
-        let alarm_settings = wait_for_flash_write_command().await;
-        info!("Received alarm settings write command: {:?}", &alarm_settings);
-        persisted_alarm_settings
-            .write_alarm_settings_to_flash(alarm_settings)
-            .await;
+        match wait_for_flash_command().await {
+            FlashCommand::WriteAlarmSettings(alarm_settings) => {
+                info!("Received alarm settings write command: {:?}", &alarm_settings);
+                persisted_alarm_settings
+                    .write_alarm_settings_to_flash(alarm_settings)
+                    .await;
+            }
+            FlashCommand::WriteDisplaySettings(display_settings) => {
+                info!("Received display settings write command: {:?}", &display_settings);
+                persisted_alarm_settings
+                    .write_display_settings_to_flash(display_settings)
+                    .await;
+            }
+            FlashCommand::WriteWifiCredentials(credentials) => {
+                info!("Received WiFi credentials write command: {:?}", &credentials);
+                persisted_alarm_settings
+                    .write_wifi_credentials_to_flash(&credentials)
+                    .await;
+            }
+            FlashCommand::EraseAll => {
+                info!("Received erase command");
+                if persisted_alarm_settings.erase_all().await {
+                    request_reset();
+                }
+            }
+        }
     }
 }
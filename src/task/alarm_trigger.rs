@@ -6,7 +6,7 @@
 use defmt::{Debug2Format, info, warn};
 use embassy_rp::{
     peripherals,
-    rtc::{DateTime, DateTimeFilter, DayOfWeek, Rtc},
+    rtc::{DateTimeFilter, Rtc},
 };
 use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
 use embassy_time::{Duration, Timer};
@@ -18,6 +18,10 @@ use crate::{
         time_updater::RTC_MUTEX,
         watchdog::{TaskId, report_task_success},
     },
+    utility::{
+        alarm_plan::{self, AlarmPlan},
+        calendar::DateTime,
+    },
 };
 
 /// Signal to update the alarm schedule when alarm settings change
@@ -53,6 +57,20 @@ struct AlarmConfig {
     hour: u8,
     /// Minute of the alarm (0-59)
     minute: u8,
+    /// Days of the week the alarm rings on, bit 0 is Monday and bit 6 is Sunday
+    days: u8,
+    /// What the calendar holds for the alarm of the next days
+    plan: Option<AlarmPlan>,
+}
+
+/// Result of scheduling the alarm
+enum ScheduleResult {
+    /// The alarm is scheduled in the RTC
+    Scheduled,
+    /// The alarm rings on no day, neither as set nor following the calendar
+    NoAlarm,
+    /// The RTC is not available
+    RtcUnavailable,
 }
 
 /// Result of waiting for alarm events
@@ -91,16 +109,22 @@ pub async fn alarm_trigger_task() {
         }
 
         // Step 3: Schedule the alarm in RTC
-        if !schedule_alarm(&config).await {
-            // Failed to schedule, retry
-            Timer::after(INIT_RETRY_DELAY).await;
-            continue;
+        match schedule_alarm(&config).await {
+            ScheduleResult::Scheduled => {}
+            ScheduleResult::NoAlarm => {
+                // Rings on no day, wait until the settings or the calendar change
+                info!("Alarm rings on no day, waiting for settings change");
+                wait_for_enable_signal().await;
+                continue;
+            }
+            ScheduleResult::RtcUnavailable => {
+                // Failed to schedule, retry
+                Timer::after(INIT_RETRY_DELAY).await;
+                continue;
+            }
         }
 
-        info!(
-            "Alarm scheduled for {:02}:{:02}, waiting for trigger or settings change",
-            config.hour, config.minute
-        );
+        info!("Alarm scheduled, waiting for trigger or settings change");
 
         // Report successful alarm scheduling to watchdog
         report_task_success(TaskId::AlarmTrigger).await;
@@ -139,6 +163,8 @@ async fn get_alarm_config() -> Option<AlarmConfig> {
         enabled: system_state.alarm_settings.get_enabled(),
         hour: system_state.alarm_settings.get_hour(),
         minute: system_state.alarm_settings.get_minute(),
+        days: system_state.alarm_settings.get_days(),
+        plan: system_state.alarm_plan.clone(),
     };
 
     // Explicitly drop the guard to release the lock early
@@ -153,68 +179,51 @@ async fn wait_for_enable_signal() {
     ALARM_SCHEDULE_UPDATE_SIGNAL.reset();
 }
 
-/// Schedules the alarm in the RTC based on the provided configuration
-/// Returns true if successful, false if RTC is not available
-async fn schedule_alarm(config: &AlarmConfig) -> bool {
+/// Schedules the alarm in the RTC based on the provided configuration, at the next time it rings following the
+/// calendar
+async fn schedule_alarm(config: &AlarmConfig) -> ScheduleResult {
     let mut rtc_guard = RTC_MUTEX.lock().await;
     let Some(rtc) = rtc_guard.as_mut() else {
         warn!("RTC not initialized");
-        return false;
+        return ScheduleResult::RtcUnavailable;
     };
 
     // Get current time
     let now = match rtc.now() {
-        Ok(dt) => dt,
+        Ok(dt) => DateTime::from(dt),
         Err(e) => {
             warn!("Failed to get current time from RTC: {:?}", Debug2Format(&e));
-            return false;
+            return ScheduleResult::RtcUnavailable;
         }
     };
 
-    // Determine if we need to schedule for today or tomorrow
-    let alarm_already_passed = is_alarm_time_in_past(&now, config.hour, config.minute);
+    // Determine the next time the alarm rings, skipping days off and moving it before the first event of a day
+    let Some(alarm) = alarm_plan::next_alarm(&now, config.hour, config.minute, config.days, config.plan.as_ref())
+    else {
+        return ScheduleResult::NoAlarm;
+    };
 
-    if alarm_already_passed {
-        schedule_alarm_for_tomorrow(rtc, &now, config.hour, config.minute);
-    } else {
-        schedule_alarm_for_today(rtc, config.hour, config.minute);
-    }
+    schedule_alarm_at(rtc, &alarm);
 
     // Explicitly drop the guard to release the lock early
     drop(rtc_guard);
 
-    true
+    ScheduleResult::Scheduled
 }
 
-/// Checks if the alarm time has already passed today
-const fn is_alarm_time_in_past(now: &DateTime, alarm_hour: u8, alarm_minute: u8) -> bool {
-    (alarm_hour < now.hour) || (alarm_hour == now.hour && alarm_minute <= now.minute)
-}
-
-/// Schedules the alarm for today at the specified time
-fn schedule_alarm_for_today(rtc: &mut Rtc<'static, peripherals::RTC>, hour: u8, minute: u8) {
-    info!("Scheduling alarm for today at {:02}:{:02}", hour, minute);
-
-    let filter = DateTimeFilter::default().hour(hour).minute(minute).second(0);
-
-    rtc.schedule_alarm(filter);
-}
-
-/// Schedules the alarm for tomorrow at the specified time
-fn schedule_alarm_for_tomorrow(rtc: &mut Rtc<'static, peripherals::RTC>, now: &DateTime, hour: u8, minute: u8) {
-    let tomorrow = calculate_tomorrow(now);
-
+/// Schedules the alarm at the specified date and time
+fn schedule_alarm_at(rtc: &mut Rtc<'static, peripherals::RTC>, alarm: &DateTime) {
     info!(
-        "Scheduling alarm for tomorrow: {:04}-{:02}-{:02} at {:02}:{:02}",
-        tomorrow.year, tomorrow.month, tomorrow.day, hour, minute
+        "Scheduling alarm for {:04}-{:02}-{:02} at {:02}:{:02}",
+        alarm.year, alarm.month, alarm.day, alarm.hour, alarm.minute
     );
 
     let filter = DateTimeFilter::default()
-        .year(tomorrow.year)
-        .month(tomorrow.month)
-        .day(tomorrow.day)
-        .hour(hour)
-        .minute(minute)
+        .year(alarm.year)
+        .month(alarm.month)
+        .day(alarm.day)
+        .hour(alarm.hour)
+        .minute(alarm.minute)
         .second(0);
 
     rtc.schedule_alarm(filter);
@@ -270,64 +279,3 @@ async fn handle_alarm_triggered() {
     // The alarm will be rescheduled in the next loop iteration if still enabled
     Timer::after(POST_ALARM_COOLDOWN).await;
 }
-
-/// Calculate tomorrow's date based on the current datetime
-fn calculate_tomorrow(now: &DateTime) -> DateTime {
-    let mut tomorrow = now.clone();
-    tomorrow.day += 1;
-
-    // Handle month rollover
-    if tomorrow.day > 28 {
-        let days_in_month = get_days_in_month(tomorrow.month, tomorrow.year);
-
-        if tomorrow.day > days_in_month {
-            tomorrow.day = 1;
-            tomorrow.month += 1;
-
-            // Handle year rollover
-            if tomorrow.month > 12 {
-                tomorrow.month = 1;
-                tomorrow.year += 1;
-            }
-        }
-    }
-
-    // Update day of week
-    tomorrow.day_of_week = next_day_of_week(tomorrow.day_of_week);
-
-    tomorrow
-}
-
-/// Get the number of days in a given month and year
-const fn get_days_in_month(month: u8, year: u16) -> u8 {
-    match month {
-        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
-        2 => {
-            if is_leap_year(year) {
-                29
-            } else {
-                28
-            }
-        }
-        _ => 30, // all other months
-    }
-}
-
-/// Get the next day of the week
-const fn next_day_of_week(day: DayOfWeek) -> DayOfWeek {
-    match day {
-        DayOfWeek::Monday => DayOfWeek::Tuesday,
-        DayOfWeek::Tuesday => DayOfWeek::Wednesday,
-        DayOfWeek::Wednesday => DayOfWeek::Thursday,
-        DayOfWeek::Thursday => DayOfWeek::Friday,
-        DayOfWeek::Friday => DayOfWeek::Saturday,
-        DayOfWeek::Saturday => DayOfWeek::Sunday,
-        DayOfWeek::Sunday => DayOfWeek::Monday,
-    }
-}
-
-/// Check if a year is a leap year
-/// A year is a leap year if it is divisible by 4, but not by 100, unless it is also divisible by 400.
-const fn is_leap_year(year: u16) -> bool {
-    (year.is_multiple_of(4) && !year.is_multiple_of(100)) || year.is_multiple_of(400)
-}
@@ -0,0 +1,366 @@
+//! # API Server Task
+//! This module contains the task that serves the local REST API and the web UI, the endpoints are listed in
+//! `utility::api_router`.
+//!
+//! The web UI is a single page in `web/index.html`, compressed by `build.rs` and served as is with
+//! `Content-Encoding: gzip`. There is no uncompressed copy in the flash, a client that does not accept gzip gets
+//! `406 Not Acceptable`; all browsers send `Accept-Encoding: gzip`. The page talks only to the JSON endpoints of
+//! this server.
+//! Queries are answered from the system state and the RTC. Commands are sent as events to the orchestrator, so that
+//! they take the same path as button presses, and are answered with `202 Accepted`.
+//! The server takes one connection at a time and closes it after the response. It does not hold the network, so it
+//! is reachable while the connectivity policy keeps the connection up.
+
+use core::fmt::Write as _;
+
+use defmt::{Debug2Format, info, warn};
+use embassy_net::tcp::{self, TcpSocket};
+use embassy_time::{Duration, Timer};
+use embedded_io_async::Write;
+use serde::Serialize;
+
+use crate::{
+    event::{Event, send_event},
+    state::SYSTEM_STATE,
+    task::{mdns::advertise_http_service, network::network_stack, time_updater::RTC_MUTEX},
+    utility::{
+        api_router::{ApiRequest, route},
+        http_request::{RequestError, parse_request},
+        http_response::reason_phrase,
+    },
+};
+
+/// The TCP port the API is served on
+const API_PORT: u16 = 80;
+
+/// Timeout for reading the request and writing the response
+const SOCKET_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Delay before listening again after accepting a connection failed
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Size of the socket buffers
+const SOCKET_BUFFER_SIZE: usize = 1024;
+
+/// Size of the buffer a request is collected in, requests must fit in it including the headers a browser sends
+const REQUEST_BUFFER_SIZE: usize = 2048;
+
+/// Size of the buffer for the JSON body of a response
+const RESPONSE_BODY_SIZE: usize = 128;
+
+/// The body sent when the JSON body of a response does not fit its buffer
+const OVERFLOW_BODY: &[u8] = br#"{"error":"response too large"}"#;
+
+/// The page of the web UI, compressed with gzip
+static WEB_UI_PAGE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/index.html.gz"));
+
+/// The body of the current time
+#[derive(Serialize)]
+struct TimeBody<'a> {
+    /// Date and time of the RTC, e.g. `2024-05-01T06:30:00`
+    datetime: &'a str,
+}
+
+/// The body of the alarm settings
+#[derive(Serialize)]
+struct AlarmBody {
+    /// The alarm hour
+    hour: u8,
+    /// The alarm minute
+    minute: u8,
+    /// Whether the alarm is enabled
+    enabled: bool,
+    /// The days of the week the alarm rings on, bit 0 is Monday and bit 6 is Sunday
+    days: u8,
+    /// The length of the sunrise effect in minutes
+    sunrise_minutes: u8,
+    /// The volume of the alarm sound
+    volume: u8,
+}
+
+/// The body of the display settings
+#[derive(Serialize)]
+struct DisplayBody {
+    /// Whether the display shows the time in 12-hour format
+    twelve_hour: bool,
+}
+
+/// The body of the power state
+#[derive(Serialize)]
+struct PowerBody {
+    /// Whether the clock runs on usb power
+    usb_power: bool,
+    /// The voltage of the system power supply
+    vsys: f32,
+    /// The battery level, `charging` or a percentage
+    battery_level: &'static str,
+}
+
+/// The body of the operation mode and the alarm state
+#[derive(Serialize)]
+struct ModeBody {
+    /// The operation mode
+    operation_mode: &'static str,
+    /// The state of the alarm
+    alarm_state: &'static str,
+}
+
+/// The body of an accepted command
+#[derive(Serialize)]
+struct AcceptedBody {
+    /// Always `accepted`, the orchestrator carries the command out later
+    status: &'static str,
+}
+
+/// The body of an error
+#[derive(Serialize)]
+struct ErrorBody {
+    /// What went wrong
+    error: &'static str,
+}
+
+/// The body of a response
+enum ResponseBody {
+    /// A JSON body, the buffer holding it and its length
+    Json([u8; RESPONSE_BODY_SIZE], usize),
+    /// A fixed JSON body
+    StaticJson(&'static [u8]),
+    /// The page of the web UI
+    Page,
+}
+
+/// A response of the API, the status code and the body
+struct ApiResponse {
+    /// The HTTP status code
+    status: u16,
+    /// The body
+    body: ResponseBody,
+}
+
+impl ApiResponse {
+    /// A response with `value` serialized as the JSON body
+    fn json<T: Serialize>(status: u16, value: &T) -> Self {
+        let mut buffer = [0; RESPONSE_BODY_SIZE];
+        match serde_json_core::to_slice(value, &mut buffer) {
+            Ok(len) => Self {
+                status,
+                body: ResponseBody::Json(buffer, len),
+            },
+            Err(e) => {
+                warn!("API response does not fit the buffer: {:?}", Debug2Format(&e));
+                Self {
+                    status: 500,
+                    body: ResponseBody::StaticJson(OVERFLOW_BODY),
+                }
+            }
+        }
+    }
+
+    /// The response with the page of the web UI
+    const fn page() -> Self {
+        Self {
+            status: 200,
+            body: ResponseBody::Page,
+        }
+    }
+
+    /// A response with the error message as the JSON body
+    fn error(status: u16, message: &'static str) -> Self {
+        Self::json(status, &ErrorBody { error: message })
+    }
+
+    /// The response to a command that was handed over to the orchestrator
+    fn accepted() -> Self {
+        Self::json(202, &AcceptedBody { status: "accepted" })
+    }
+
+    /// The content type and content encoding headers of the response
+    const fn content_headers(&self) -> &'static str {
+        match self.body {
+            ResponseBody::Json(..) | ResponseBody::StaticJson(_) => "Content-Type: application/json\r\n",
+            ResponseBody::Page => "Content-Type: text/html; charset=utf-8\r\nContent-Encoding: gzip\r\n",
+        }
+    }
+
+    /// The body of the response
+    fn body(&self) -> &[u8] {
+        match &self.body {
+            ResponseBody::Json(buffer, len) => &buffer[..*len],
+            ResponseBody::StaticJson(body) => body,
+            ResponseBody::Page => WEB_UI_PAGE,
+        }
+    }
+}
+
+/// This task serves the REST API on the network stack, answering one connection after the other.
+#[embassy_executor::task]
+pub async fn api_server_task() {
+    info!("API server task starting");
+    let stack = network_stack().await;
+    advertise_http_service(API_PORT);
+
+    let mut rx_buffer = [0; SOCKET_BUFFER_SIZE];
+    let mut tx_buffer = [0; SOCKET_BUFFER_SIZE];
+    let mut request_buffer = [0; REQUEST_BUFFER_SIZE];
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(SOCKET_TIMEOUT));
+
+        // waits for a client, also while the network is down
+        if let Err(e) = socket.accept(API_PORT).await {
+            warn!("API accept failed: {:?}", e);
+            Timer::after(ACCEPT_RETRY_DELAY).await;
+            continue;
+        }
+
+        if let Err(e) = serve_connection(&mut socket, &mut request_buffer).await {
+            warn!("API connection failed: {:?}", e);
+        }
+        socket.close();
+        let _ = socket.flush().await;
+    }
+}
+
+/// Read a request from the connection and answer it
+async fn serve_connection(socket: &mut TcpSocket<'_>, buffer: &mut [u8]) -> Result<(), tcp::Error> {
+    let mut len = 0;
+    let response = loop {
+        if len == buffer.len() {
+            break ApiResponse::error(413, "request too large");
+        }
+        let read = socket.read(&mut buffer[len..]).await?;
+        if read == 0 {
+            // the client closed the connection before sending the complete request
+            return Ok(());
+        }
+        len += read;
+
+        match parse_request(&buffer[..len]) {
+            Ok(request) => {
+                info!("API request: {} {}", request.method, request.path);
+                break match route(request.method, request.path, request.body) {
+                    Ok(ApiRequest::GetPage) if !request.accepts_gzip => {
+                        ApiResponse::error(406, "gzip encoding required")
+                    }
+                    Ok(api_request) => handle_request(api_request).await,
+                    Err(e) => ApiResponse::error(e.status(), e.message()),
+                };
+            }
+            Err(RequestError::Incomplete) => {}
+            Err(RequestError::Malformed) => break ApiResponse::error(400, "malformed request"),
+            Err(RequestError::ChunkedBody) => break ApiResponse::error(501, "chunked body not supported"),
+        }
+    };
+
+    write_response(socket, &response).await
+}
+
+/// Answer a query from the state, or hand a command over to the orchestrator
+async fn handle_request(request: ApiRequest) -> ApiResponse {
+    match request {
+        ApiRequest::GetPage => ApiResponse::page(),
+        ApiRequest::GetTime => get_time().await,
+        ApiRequest::GetAlarm | ApiRequest::GetPower | ApiRequest::GetMode | ApiRequest::GetDisplay => {
+            get_state(&request).await
+        }
+        ApiRequest::UpdateAlarm(update) => {
+            send_event(Event::ChangeAlarmSettings(update)).await;
+            ApiResponse::accepted()
+        }
+        ApiRequest::UpdateDisplay(update) => {
+            send_event(Event::ChangeDisplaySettings(update)).await;
+            ApiResponse::accepted()
+        }
+        ApiRequest::SnoozeAlarm => {
+            send_event(Event::AlarmSnooze).await;
+            ApiResponse::accepted()
+        }
+        ApiRequest::StopAlarm => {
+            send_event(Event::AlarmStop).await;
+            ApiResponse::accepted()
+        }
+    }
+}
+
+/// Answer the current time of the RTC
+async fn get_time() -> ApiResponse {
+    let dt = {
+        let rtc_guard = RTC_MUTEX.lock().await;
+        let Some(rtc) = rtc_guard.as_ref() else {
+            return ApiResponse::error(503, "rtc not initialized");
+        };
+        let Ok(dt) = rtc.now() else {
+            return ApiResponse::error(503, "rtc not running");
+        };
+        dt
+    };
+
+    let mut datetime: heapless::String<20> = heapless::String::new();
+    let _ = write!(
+        datetime,
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        dt.year, dt.month, dt.day, dt.hour, dt.minute, dt.second
+    );
+    ApiResponse::json(200, &TimeBody { datetime: &datetime })
+}
+
+/// Answer a query about the system state
+async fn get_state(request: &ApiRequest) -> ApiResponse {
+    let system_state_guard = SYSTEM_STATE.lock().await;
+    let Some(system_state) = system_state_guard.as_ref() else {
+        return ApiResponse::error(503, "system state not initialized");
+    };
+
+    match request {
+        ApiRequest::GetAlarm => ApiResponse::json(
+            200,
+            &AlarmBody {
+                hour: system_state.alarm_settings.get_hour(),
+                minute: system_state.alarm_settings.get_minute(),
+                enabled: system_state.alarm_settings.get_enabled(),
+                days: system_state.alarm_settings.get_days(),
+                sunrise_minutes: system_state.alarm_settings.get_sunrise_minutes(),
+                volume: system_state.alarm_settings.get_volume(),
+            },
+        ),
+        ApiRequest::GetDisplay => ApiResponse::json(
+            200,
+            &DisplayBody {
+                twelve_hour: system_state.display_settings.get_twelve_hour(),
+            },
+        ),
+        ApiRequest::GetPower => ApiResponse::json(
+            200,
+            &PowerBody {
+                usb_power: system_state.power_state.get_usb_power(),
+                vsys: system_state.power_state.get_vsys(),
+                battery_level: system_state.power_state.get_battery_level().name(),
+            },
+        ),
+        _ => ApiResponse::json(
+            200,
+            &ModeBody {
+                operation_mode: system_state.operation_mode.name(),
+                alarm_state: system_state.alarm_state.name(),
+            },
+        ),
+    }
+}
+
+/// Write the status line, the headers and the body of the response
+async fn write_response(socket: &mut TcpSocket<'_>, response: &ApiResponse) -> Result<(), tcp::Error> {
+    let body = response.body();
+    let mut head: heapless::String<160> = heapless::String::new();
+    let _ = write!(
+        head,
+        "HTTP/1.1 {} {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason_phrase(response.status),
+        response.content_headers(),
+        body.len()
+    );
+    socket.write_all(head.as_bytes()).await?;
+    socket.write_all(body).await?;
+    socket.flush().await
+}
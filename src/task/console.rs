@@ -0,0 +1,356 @@
+//! # Console Task
+//! This module contains the serial console on USB. While the clock is plugged into a computer it shows up as a
+//! CDC-ACM serial port, e.g. `/dev/ttyACM0` on Linux, which can be opened with any terminal at any baud rate.
+//! Enter shows the prompt, the commands are listed in `utility::console`.
+//!
+//! Queries are answered from the system state and the RTC. Commands are sent as events to the orchestrator, so that
+//! they take the same path as button presses. The answer is written before the event is sent, so that `reboot` and
+//! `factory-reset` are confirmed before the clock restarts.
+//!
+//! On battery nothing is plugged in and the console waits without using any power.
+
+use core::{
+    fmt::{Debug, Write as _},
+    net::Ipv4Addr,
+};
+
+use defmt::{info, unwrap};
+use embassy_executor::Spawner;
+use embassy_rp::{peripherals::USB, usb::Driver};
+use embassy_usb::{
+    Builder, Config, UsbDevice,
+    class::cdc_acm::{CdcAcmClass, State},
+    driver::EndpointError,
+};
+use heapless::String;
+use static_cell::StaticCell;
+
+use crate::{
+    event::{Event, send_event},
+    state::{SYSTEM_STATE, SystemState},
+    task::{
+        network::HOSTNAME,
+        time_updater::{self, RTC_MUTEX},
+    },
+    utility::{
+        api_router::{AlarmUpdate, DisplayUpdate},
+        calendar::{self, DateTime, DayOfWeek},
+        console::{Command, CommandError, HELP, LineEdit, LineEditor, parse_command},
+    },
+};
+
+/// The USB vendor id, the one of Raspberry Pi
+const USB_VID: u16 = 0x2E8A;
+
+/// The USB product id, the one the Pico SDK uses for its serial console
+const USB_PID: u16 = 0x000A;
+
+/// Size of the USB packets, the most a full speed bulk endpoint takes
+const MAX_PACKET_SIZE: u16 = 64;
+
+/// Capacity of an answer, the help is the longest
+const ANSWER_CAPACITY: usize = 768;
+
+/// The prompt, shown after every answer
+const PROMPT: &str = "> ";
+
+/// The days of the week in the order they are listed, starting with Monday like the bits of the alarm days
+const WEEKDAYS: [DayOfWeek; 7] = [
+    DayOfWeek::Monday,
+    DayOfWeek::Tuesday,
+    DayOfWeek::Wednesday,
+    DayOfWeek::Thursday,
+    DayOfWeek::Friday,
+    DayOfWeek::Saturday,
+    DayOfWeek::Sunday,
+];
+
+/// The USB driver of the RP2040
+type UsbDriver = Driver<'static, USB>;
+
+/// Buffer for the configuration descriptor
+static CONFIG_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
+
+/// Buffer for the BOS descriptor
+static BOS_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
+
+/// Buffer for control transfers
+static CONTROL_BUFFER: StaticCell<[u8; 64]> = StaticCell::new();
+
+/// State of the CDC-ACM class
+static CDC_ACM_STATE: StaticCell<State> = StaticCell::new();
+
+/// USB device task that answers the requests of the host.
+#[embassy_executor::task]
+async fn usb_task(mut usb: UsbDevice<'static, UsbDriver>) -> ! {
+    usb.run().await
+}
+
+/// Console task that sets up the USB device as serial port and runs the console whenever a host is connected.
+#[embassy_executor::task]
+pub async fn console_task(spawner: Spawner, driver: UsbDriver) {
+    let mut config = Config::new(USB_VID, USB_PID);
+    config.manufacturer = Some("pi-pico-alarmclock");
+    config.product = Some("Alarm clock console");
+    // several clocks on one computer are told apart by their hostname
+    config.serial_number = Some(HOSTNAME);
+    config.max_power = 100;
+    config.max_packet_size_0 = 64;
+
+    let mut builder = Builder::new(
+        driver,
+        config,
+        CONFIG_DESCRIPTOR.init([0; 256]),
+        BOS_DESCRIPTOR.init([0; 256]),
+        &mut [], // no Microsoft OS descriptors
+        CONTROL_BUFFER.init([0; 64]),
+    );
+    let mut class = CdcAcmClass::new(&mut builder, CDC_ACM_STATE.init(State::new()), MAX_PACKET_SIZE);
+    let usb = builder.build();
+    spawner.spawn(unwrap!(usb_task(usb)));
+
+    let mut editor = LineEditor::new();
+    loop {
+        class.wait_connection().await;
+        info!("Console connected");
+        editor.clear();
+        // the only error is the host going away
+        let _ = run_console(&mut class, &mut editor).await;
+        info!("Console disconnected");
+    }
+}
+
+/// Echo what is typed and answer every complete line, until the host goes away
+async fn run_console(
+    class: &mut CdcAcmClass<'static, UsbDriver>,
+    editor: &mut LineEditor,
+) -> Result<(), EndpointError> {
+    let mut packet = [0; MAX_PACKET_SIZE as usize];
+    loop {
+        let read = class.read_packet(&mut packet).await?;
+        for &byte in &packet[..read] {
+            match editor.feed(byte) {
+                LineEdit::Inserted(byte) => class.write_packet(&[byte]).await?,
+                LineEdit::Erased => class.write_packet(b"\x08 \x08").await?,
+                LineEdit::Completed => {
+                    class.write_packet(b"\r\n").await?;
+                    let (answer, event) = execute(editor.line()).await;
+                    editor.clear();
+                    write_text(class, &answer).await?;
+                    write_text(class, PROMPT).await?;
+                    if let Some(event) = event {
+                        send_event(event).await;
+                    }
+                }
+                LineEdit::Ignored => {}
+            }
+        }
+    }
+}
+
+/// Write a text in packets
+async fn write_text(class: &mut CdcAcmClass<'static, UsbDriver>, text: &str) -> Result<(), EndpointError> {
+    for chunk in text.as_bytes().chunks(usize::from(MAX_PACKET_SIZE)) {
+        class.write_packet(chunk).await?;
+    }
+    Ok(())
+}
+
+/// Parse a command line and answer it, together with the event to send once the answer is written
+async fn execute(line: &str) -> (String<ANSWER_CAPACITY>, Option<Event>) {
+    let mut answer = String::new();
+    let event = match parse_command(line) {
+        Ok(Command::Help) => {
+            let _ = answer.push_str(HELP);
+            None
+        }
+        Ok(Command::Status) => {
+            write_status(&mut answer).await;
+            None
+        }
+        Ok(Command::Log) => {
+            write_log(&mut answer).await;
+            None
+        }
+        Ok(Command::SetAlarmTime { hour, minute }) => {
+            let _ = write!(answer, "alarm set to {hour:02}:{minute:02}\r\n");
+            Some(Event::ChangeAlarmSettings(AlarmUpdate {
+                hour: Some(hour),
+                minute: Some(minute),
+                ..AlarmUpdate::default()
+            }))
+        }
+        Ok(Command::EnableAlarm(enabled)) => {
+            let _ = write!(answer, "alarm {}\r\n", if enabled { "on" } else { "off" });
+            Some(Event::ChangeAlarmSettings(AlarmUpdate {
+                enabled: Some(enabled),
+                ..AlarmUpdate::default()
+            }))
+        }
+        Ok(Command::SetTwelveHour(twelve_hour)) => {
+            let _ = write!(answer, "clock shows {}\r\n", if twelve_hour { "12h" } else { "24h" });
+            Some(Event::ChangeDisplaySettings(DisplayUpdate {
+                twelve_hour: Some(twelve_hour),
+            }))
+        }
+        Ok(Command::SetTime(datetime)) => {
+            let _ = answer.push_str("time set\r\n");
+            Some(Event::SetTime(datetime))
+        }
+        Ok(Command::SetWifi(credentials)) => {
+            let _ = write!(answer, "credentials for {} stored\r\n", credentials.ssid);
+            Some(Event::ChangeWifiCredentials(credentials))
+        }
+        Ok(Command::Reboot) => {
+            let _ = answer.push_str("restarting\r\n");
+            Some(Event::Reboot)
+        }
+        Ok(Command::FactoryReset) => {
+            let _ = answer.push_str("erasing settings and credentials, then restarting\r\n");
+            Some(Event::FactoryReset)
+        }
+        Err(CommandError::Empty) => None,
+        Err(error) => {
+            let _ = write!(answer, "{}\r\n", error.message());
+            None
+        }
+    };
+    (answer, event)
+}
+
+/// The current time of the RTC, `None` if it is not running yet
+async fn rtc_now() -> Option<DateTime> {
+    let rtc_guard = RTC_MUTEX.lock().await;
+    rtc_guard.as_ref().and_then(time_updater::rtc_now)
+}
+
+/// Write a date and time as `YYYY-MM-DD HH:MM:SS`
+fn write_datetime(answer: &mut String<ANSWER_CAPACITY>, dt: &DateTime) {
+    let _ = write!(
+        answer,
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        dt.year, dt.month, dt.day, dt.hour, dt.minute, dt.second
+    );
+}
+
+/// Write the state of the clock: mode, time, alarm, time sync, power and network
+async fn write_status(answer: &mut String<ANSWER_CAPACITY>) {
+    let now = rtc_now().await;
+    let system_state_guard = SYSTEM_STATE.lock().await;
+    let Some(system_state) = system_state_guard.as_ref() else {
+        let _ = answer.push_str("system state not initialized\r\n");
+        return;
+    };
+
+    let _ = write!(
+        answer,
+        "mode      {}, alarm state {}\r\ntime      ",
+        system_state.operation_mode.name(),
+        system_state.alarm_state.name()
+    );
+    match &now {
+        Some(now) => write_datetime(answer, now),
+        None => {
+            let _ = answer.push_str("not set");
+        }
+    }
+    let _ = answer.push_str("\r\n");
+    write_alarm(answer, system_state, now.as_ref());
+    write_time_sync(answer, system_state);
+
+    let power_state = &system_state.power_state;
+    let _ = write!(
+        answer,
+        "power     {}, vsys {:.2} V, battery {}\r\n",
+        if power_state.get_usb_power() { "usb" } else { "battery" },
+        power_state.get_vsys(),
+        power_state.get_battery_level().name()
+    );
+
+    let network_state = &system_state.network_state;
+    let connected = if network_state.is_connected() {
+        "connected"
+    } else {
+        "disconnected"
+    };
+    let _ = write!(answer, "wifi      {connected}");
+    if let Some(info) = network_state.get_info() {
+        let _ = write!(answer, ", ssid {}, rssi {} dBm", info.ssid, info.rssi_dbm);
+        if let Some((address, prefix_len)) = info.address {
+            let _ = write!(answer, ", address {}/{prefix_len}", Ipv4Addr::from(address));
+        }
+    }
+    let _ = answer.push_str("\r\n");
+}
+
+/// Write the alarm settings and when the alarm rings next
+fn write_alarm(answer: &mut String<ANSWER_CAPACITY>, system_state: &SystemState, now: Option<&DateTime>) {
+    let alarm_settings = &system_state.alarm_settings;
+    let _ = write!(
+        answer,
+        "alarm     {:02}:{:02} {}, days",
+        alarm_settings.get_hour(),
+        alarm_settings.get_minute(),
+        if alarm_settings.get_enabled() { "on" } else { "off" }
+    );
+    for day in WEEKDAYS {
+        if alarm_settings.get_days() & calendar::weekday_bit(day) != 0 {
+            let _ = write!(answer, " {}", calendar::weekday_abbreviation(day));
+        }
+    }
+    let _ = answer.push_str("\r\nnext      ");
+    match now.and_then(|now| system_state.next_alarm(now)) {
+        Some(next) => write_datetime(answer, &next),
+        None => {
+            let _ = answer.push('-');
+        }
+    }
+    let _ = answer.push_str("\r\n");
+}
+
+/// Write the last time sync and the number of syncs
+fn write_time_sync(answer: &mut String<ANSWER_CAPACITY>, system_state: &SystemState) {
+    let status = &system_state.time_sync_status;
+    let _ = answer.push_str("last sync ");
+    match status.get_last_sync() {
+        Some(report) => {
+            write_datetime(answer, &calendar::from_unix_time(report.synced_at));
+            let _ = write!(answer, " via {:?}", report.source);
+        }
+        None => {
+            let _ = answer.push('-');
+        }
+    }
+    let _ = write!(
+        answer,
+        ", {} ok, {} failed\r\n",
+        status.get_successes(),
+        status.get_failures()
+    );
+}
+
+/// Write the last error of every subsystem
+async fn write_log(answer: &mut String<ANSWER_CAPACITY>) {
+    let system_state_guard = SYSTEM_STATE.lock().await;
+    let Some(system_state) = system_state_guard.as_ref() else {
+        let _ = answer.push_str("system state not initialized\r\n");
+        return;
+    };
+    let errors = &system_state.last_errors;
+    write_error(answer, "time sync", errors.time_sync.as_ref());
+    write_error(answer, "flash", errors.flash.as_ref());
+    write_error(answer, "network", errors.network.as_ref());
+    write_error(answer, "mqtt", errors.mqtt.as_ref());
+    write_error(answer, "webhook", errors.webhook.as_ref());
+    write_error(answer, "weather", errors.weather.as_ref());
+    write_error(answer, "calendar", errors.calendar.as_ref());
+    write_error(answer, "firmware", errors.firmware.as_ref());
+}
+
+/// Write the last error of a subsystem, `-` if there was none since startup
+fn write_error(answer: &mut String<ANSWER_CAPACITY>, subsystem: &str, error: Option<&impl Debug>) {
+    let _ = match error {
+        Some(error) => write!(answer, "{subsystem:<10}{error:?}\r\n"),
+        None => write!(answer, "{subsystem:<10}-\r\n"),
+    };
+}
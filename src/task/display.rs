@@ -2,40 +2,72 @@
 //! This module contains the task that displays information on the OLED display.
 //!
 //! The task is responsible for initializing the display, displaying images and text, and updating the display.
-use core::fmt::Write;
+//! It composes the screen from the system state, the drawing is in `utility::screen`.
+//!
+//! At night the display dims, shows only the time or turns off, as configured in the optional `config/night.json`:
+//!```json
+//! {
+//!     "start": "22:30",
+//!     "end": "06:30",
+//!     "display": "clock",
+//!     "wake_seconds": 10,
+//!     "day_brightness": "normal"
+//! }
+//! ```
+//! - `start`, `end`: the night, it may span midnight
+//! - `display`: `dim` shows the screens with the lowest brightness, `clock` only the time and `off` nothing. Default
+//!   is `clock`.
+//! - `wake_seconds`: how long a button press shows the screens at night with `clock` and `off`, default 10. The press
+//!   that wakes the display does nothing else.
+//! - `day_brightness`: one of `dimmest`, `dim`, `normal`, `bright` and `brightest`, default `dimmest`
+//!
+//! The night only holds in the normal mode: the menus, the alarm and the sunrise show as during the day. The LEDs of
+//! the analog clock stay off at night, see `task::light_effects`. Without the file the display stays dimmest all day.
+//!
+//! Against burn-in the layout moves by a pixel every few minutes, see `utility::screen::pixel_shift`. With the
+//! `burn-in-inversion` feature the screen is also shown inverted for `INVERSION_DURATION` at the start of every hour
+//! during the day, in the normal mode.
+
+include!(concat!(env!("OUT_DIR"), "/night_config.rs"));
+
+use core::{cell::Cell, fmt::Write, net::Ipv4Addr};
 
 use defmt::{Debug2Format, info, warn};
+use embassy_futures::select::{Either3, select3};
 use embassy_rp::{
     i2c::{Async, I2c},
     peripherals::I2C0,
-    rtc::{DateTime, DayOfWeek},
 };
-use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
-use embassy_time::{Duration, Timer};
-use embedded_graphics::{
-    image::Image,
-    mono_font::{
-        MonoTextStyle, MonoTextStyleBuilder,
-        ascii::{FONT_6X13, FONT_8X13_BOLD},
-    },
-    pixelcolor::{BinaryColor, Gray8},
-    prelude::*,
-    text::{Baseline, Text},
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+    signal::Signal,
 };
+use embassy_time::{Duration, Instant, Timer};
 use heapless::String;
 use ssd1306_async::{I2CDisplayInterface, Ssd1306, prelude::*};
-use tinybmp::Bmp;
 
 use crate::{
-    state::{BatteryLevel, OperationMode, SYSTEM_STATE},
+    error::{HttpError, TimeSyncError, WeatherError},
+    state::{BatteryLevel, NetworkInfoPage, OperationMode, SYSTEM_STATE, SystemState, TimeSource},
     task::{
-        buttons::Button,
         time_updater::RTC_MUTEX,
         watchdog::{TaskId, report_task_success},
+        weather::FORECAST_URL,
+    },
+    utility::{
+        calendar::{self, DateTime, DayOfWeek},
+        night::{DayBrightness, NightDisplay},
+        screen::{
+            Battery, Body, Header, Lines, Screen, Settings, TITLE_CHARS, draw_screen, pixel_shift, system_info_lines,
+            twelve_hour,
+        },
+        string_utils::StringUtils,
     },
-    utility::string_utils::StringUtils,
 };
 
+/// How long the screen is shown inverted every hour with the `burn-in-inversion` feature
+const INVERSION_DURATION: Duration = Duration::from_secs(10);
+
 /// Signal for triggering display updates
 static DISPLAY_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
 
@@ -44,334 +76,154 @@ pub fn signal_display_update() {
     DISPLAY_SIGNAL.signal(());
 }
 
+/// Signal for waking the display at night
+static DISPLAY_WAKE_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Whether the display sleeps at night, showing only the time or nothing
+static DISPLAY_ASLEEP: Mutex<CriticalSectionRawMutex, Cell<bool>> = Mutex::new(Cell::new(false));
+
 /// Waits for the next display update signal
 async fn wait_for_display_update() {
     DISPLAY_SIGNAL.wait().await;
 }
 
-/// Loads and holds BMP images and Points for the display
-/// Holds some settings for composing the display
-struct Settings<'a> {
-    /// BMP image of the saber icon
-    saber: Bmp<'static, Gray8>,
-    /// BMP image of the colon icon
-    colon: Bmp<'static, Gray8>,
-    /// BMP images of the digits 0-9
-    digits: [Bmp<'static, Gray8>; 10],
-    /// BMP images of the battery status icons
-    bat: [Bmp<'static, Gray8>; 6],
-    /// BMP image of the battery mains icon
-    bat_mains: Bmp<'static, Gray8>,
-    /// BMP image of the settings icon
-    setup: Bmp<'static, Gray8>,
-    /// Position of the state indicator images, hight is 16
-    state_indicator_position: Point,
-    /// Position of the battery status images, hight is 11
-    bat_position: Point,
-    /// (Starting) Position of the time digits, hight is 24
-    time_digit_start_position: Point,
-    /// Position of the date text
-    date_position: Point,
-    /// (Starting) Position of content
-    content_start_position: Point,
-    /// Style of the state area text
-    state_indicator_text_style: MonoTextStyle<'a, BinaryColor>,
-    /// Style of the date text
-    date_text_style: MonoTextStyle<'a, BinaryColor>,
-    /// Style of the menu and system info content text
-    content_text_style: MonoTextStyle<'a, BinaryColor>,
+/// Wakes the display at night for the configured time, or keeps it awake if it is. Updates the display as well.
+pub fn signal_display_wake() {
+    DISPLAY_WAKE_SIGNAL.signal(());
 }
 
-impl Settings<'_> {
-    /// Creates a new Settings struct, loading all BMP images from the media folder
-    #[allow(clippy::expect_used, clippy::too_many_lines)]
-    fn new() -> Self {
-        Self {
-            saber: Bmp::from_slice(include_bytes!("../media/saber.bmp")).unwrap_or_else(|_| {
-                warn!("Failed to load saber.bmp, using fallback");
-                Bmp::from_slice(include_bytes!("../media/0.bmp")).expect("Fallback 0.bmp image must be available")
-            }),
-            colon: Bmp::from_slice(include_bytes!("../media/colon.bmp")).unwrap_or_else(|_| {
-                warn!("Failed to load colon.bmp, using fallback");
-                Bmp::from_slice(include_bytes!("../media/0.bmp")).expect("Fallback 0.bmp image must be available")
-            }),
-            digits: [
-                Bmp::from_slice(include_bytes!("../media/0.bmp")).unwrap_or_else(|_| {
-                    warn!("Failed to load 0.bmp, using fallback");
-                    Bmp::from_slice(include_bytes!("../media/0.bmp")).expect("Fallback 0.bmp image must be available")
-                }),
-                Bmp::from_slice(include_bytes!("../media/1.bmp")).unwrap_or_else(|_| {
-                    warn!("Failed to load 1.bmp, using fallback");
-                    Bmp::from_slice(include_bytes!("../media/0.bmp")).expect("Fallback 0.bmp image must be available")
-                }),
-                Bmp::from_slice(include_bytes!("../media/2.bmp")).unwrap_or_else(|_| {
-                    warn!("Failed to load 2.bmp, using fallback");
-                    Bmp::from_slice(include_bytes!("../media/0.bmp")).expect("Fallback 0.bmp image must be available")
-                }),
-                Bmp::from_slice(include_bytes!("../media/3.bmp")).unwrap_or_else(|_| {
-                    warn!("Failed to load 3.bmp, using fallback");
-                    Bmp::from_slice(include_bytes!("../media/0.bmp")).expect("Fallback 0.bmp image must be available")
-                }),
-                Bmp::from_slice(include_bytes!("../media/4.bmp")).unwrap_or_else(|_| {
-                    warn!("Failed to load 4.bmp, using fallback");
-                    Bmp::from_slice(include_bytes!("../media/0.bmp")).expect("Fallback 0.bmp image must be available")
-                }),
-                Bmp::from_slice(include_bytes!("../media/5.bmp")).unwrap_or_else(|_| {
-                    warn!("Failed to load 5.bmp, using fallback");
-                    Bmp::from_slice(include_bytes!("../media/0.bmp")).expect("Fallback 0.bmp image must be available")
-                }),
-                Bmp::from_slice(include_bytes!("../media/6.bmp")).unwrap_or_else(|_| {
-                    warn!("Failed to load 6.bmp, using fallback");
-                    Bmp::from_slice(include_bytes!("../media/0.bmp")).expect("Fallback 0.bmp image must be available")
-                }),
-                Bmp::from_slice(include_bytes!("../media/7.bmp")).unwrap_or_else(|_| {
-                    warn!("Failed to load 7.bmp, using fallback");
-                    Bmp::from_slice(include_bytes!("../media/0.bmp")).expect("Fallback 0.bmp image must be available")
-                }),
-                Bmp::from_slice(include_bytes!("../media/8.bmp")).unwrap_or_else(|_| {
-                    warn!("Failed to load 8.bmp, using fallback");
-                    Bmp::from_slice(include_bytes!("../media/0.bmp")).expect("Fallback 0.bmp image must be available")
-                }),
-                Bmp::from_slice(include_bytes!("../media/9.bmp")).unwrap_or_else(|_| {
-                    warn!("Failed to load 9.bmp, using fallback");
-                    Bmp::from_slice(include_bytes!("../media/0.bmp")).expect("Fallback 0.bmp image must be available")
-                }),
-            ],
-            bat: [
-                Bmp::from_slice(include_bytes!("../media/bat_000.bmp")).unwrap_or_else(|_| {
-                    warn!("Failed to load bat_000.bmp, using fallback");
-                    Bmp::from_slice(include_bytes!("../media/0.bmp")).expect("Fallback 0.bmp image must be available")
-                }),
-                Bmp::from_slice(include_bytes!("../media/bat_020.bmp")).unwrap_or_else(|_| {
-                    warn!("Failed to load bat_020.bmp, using fallback");
-                    Bmp::from_slice(include_bytes!("../media/0.bmp")).expect("Fallback 0.bmp image must be available")
-                }),
-                Bmp::from_slice(include_bytes!("../media/bat_040.bmp")).unwrap_or_else(|_| {
-                    warn!("Failed to load bat_040.bmp, using fallback");
-                    Bmp::from_slice(include_bytes!("../media/0.bmp")).expect("Fallback 0.bmp image must be available")
-                }),
-                Bmp::from_slice(include_bytes!("../media/bat_060.bmp")).unwrap_or_else(|_| {
-                    warn!("Failed to load bat_060.bmp, using fallback");
-                    Bmp::from_slice(include_bytes!("../media/0.bmp")).expect("Fallback 0.bmp image must be available")
-                }),
-                Bmp::from_slice(include_bytes!("../media/bat_080.bmp")).unwrap_or_else(|_| {
-                    warn!("Failed to load bat_080.bmp, using fallback");
-                    Bmp::from_slice(include_bytes!("../media/0.bmp")).expect("Fallback 0.bmp image must be available")
-                }),
-                Bmp::from_slice(include_bytes!("../media/bat_100.bmp")).unwrap_or_else(|_| {
-                    warn!("Failed to load bat_100.bmp, using fallback");
-                    Bmp::from_slice(include_bytes!("../media/0.bmp")).expect("Fallback 0.bmp image must be available")
-                }),
-            ],
-            bat_mains: Bmp::from_slice(include_bytes!("../media/bat_mains.bmp")).unwrap_or_else(|_| {
-                warn!("Failed to load bat_mains.bmp, using fallback");
-                Bmp::from_slice(include_bytes!("../media/0.bmp")).expect("Fallback 0.bmp image must be available")
-            }),
-            setup: Bmp::from_slice(include_bytes!("../media/settings.bmp")).unwrap_or_else(|_| {
-                warn!("Failed to load settings.bmp, using fallback");
-                Bmp::from_slice(include_bytes!("../media/0.bmp")).expect("Fallback 0.bmp image must be available")
-            }),
-            state_indicator_position: Point::new(0, 0),
-            bat_position: Point::new(108, 0),
-            time_digit_start_position: Point::new(13, 21),
-            content_start_position: Point::new(0, 19),
-            date_position: Point::new(0, 51),
-            state_indicator_text_style: MonoTextStyleBuilder::new()
-                .font(&FONT_8X13_BOLD)
-                .text_color(BinaryColor::On)
-                .build(),
-            date_text_style: MonoTextStyleBuilder::new()
-                .font(&FONT_6X13)
-                .text_color(BinaryColor::On)
-                .build(),
-            content_text_style: MonoTextStyleBuilder::new()
-                .font(&FONT_6X13)
-                .text_color(BinaryColor::On)
-                .build(),
-        }
-    }
+/// Whether the display sleeps at night, a button press should only wake it then
+pub fn is_display_asleep() -> bool {
+    DISPLAY_ASLEEP.lock(Cell::get)
 }
 
-/// Draws the state indicator in the top-left area of the display
-fn draw_state_indicator<D>(display: &mut D, operation_mode: &OperationMode, alarm_enabled: bool, settings: &Settings)
-where
-    D: embedded_graphics::draw_target::DrawTarget<Color = BinaryColor>,
-{
-    match operation_mode {
-        OperationMode::Normal => {
-            if alarm_enabled {
-                let saber = Image::new(&settings.saber, settings.state_indicator_position);
-                let _ = saber.draw(&mut display.color_converted());
-            }
-        }
-        OperationMode::SetAlarmTime => {
-            let setup_img = Image::new(&settings.setup, settings.state_indicator_position);
-            let _ = setup_img.draw(&mut display.color_converted());
+/// The lines of a page of the network info
+fn network_info_lines(page: NetworkInfoPage, system_state: &SystemState) -> Lines {
+    let network_state = &system_state.network_state;
+    let info = network_state.get_info();
+    let mut lines: Lines = [String::new(), String::new(), String::new()];
+    match page {
+        NetworkInfoPage::Wifi => {
+            let _ = write!(lines[0], "SSID {}", info.map_or("-", |info| info.ssid.as_str()));
+            let _ = match info {
+                Some(info) => write!(lines[1], "RSSI {} dBm", info.rssi_dbm),
+                None => write!(lines[1], "RSSI -"),
+            };
+            let connected = if network_state.is_connected() {
+                "Connected"
+            } else {
+                "Not connected"
+            };
+            let _ = write!(lines[2], "{connected}");
         }
-        OperationMode::Menu => {
-            let _ = Text::with_baseline(
-                "Menu",
-                settings.state_indicator_position,
-                settings.state_indicator_text_style,
-                Baseline::Top,
-            )
-            .draw(display);
+        NetworkInfoPage::Addresses => {
+            let _ = match info.and_then(|info| info.address) {
+                Some((address, prefix_len)) => write!(lines[0], "IP {}/{prefix_len}", Ipv4Addr::from(address)),
+                None => write!(lines[0], "IP -"),
+            };
+            let _ = match info.and_then(|info| info.gateway) {
+                Some(gateway) => write!(lines[1], "GW {}", Ipv4Addr::from(gateway)),
+                None => write!(lines[1], "GW -"),
+            };
+            let _ = match info.and_then(|info| info.dns_server) {
+                Some(dns_server) => write!(lines[2], "DNS {}", Ipv4Addr::from(dns_server)),
+                None => write!(lines[2], "DNS -"),
+            };
         }
-        OperationMode::SystemInfo => {
-            let _ = Text::with_baseline(
-                "Sys.-Info",
-                settings.state_indicator_position,
-                settings.state_indicator_text_style,
-                Baseline::Top,
-            )
-            .draw(display);
+        NetworkInfoPage::Config => {
+            let _ = write!(lines[0], "{}", info.map_or("-", |info| info.hostname));
+            if let Some(info) = info {
+                let ipv4 = if info.static_ipv4 { "static" } else { "DHCP" };
+                let ipv6 = if info.ipv6_slaac { ", SLAAC" } else { "" };
+                let _ = write!(lines[1], "IPv4 {ipv4}{ipv6}");
+            }
         }
-        OperationMode::Alarm | OperationMode::Standby => {
-            // Button info is drawn separately in alarm mode - this is handled in main content
-            // Nothing shown for standby mode
+        NetworkInfoPage::TimeSync => {
+            let status = &system_state.time_sync_status;
+            let _ = match status.get_last_sync() {
+                Some(report) => {
+                    let dt = calendar::from_unix_time(report.synced_at);
+                    let source = match report.source {
+                        TimeSource::Api => "API",
+                        TimeSource::DateHeader => "Date",
+                        TimeSource::Console => "Console",
+                    };
+                    write!(
+                        lines[0],
+                        "{:02}.{:02}. {:02}:{:02} via {source}",
+                        dt.day, dt.month, dt.hour, dt.minute
+                    )
+                }
+                None => write!(lines[0], "No sync yet"),
+            };
+            let _ = write!(
+                lines[1],
+                "OK {}  Failed {}",
+                status.get_successes(),
+                status.get_failures()
+            );
+            let _ = match &system_state.last_errors.time_sync {
+                Some(TimeSyncError::Http(HttpError::Status { code, .. })) => write!(lines[2], "Err HTTP {code}"),
+                Some(error) => write!(lines[2], "Err {}", error.label()),
+                None => write!(lines[2], "Err -"),
+            };
         }
     }
+    lines
 }
 
-/// Draws the battery status indicator in the top-right area of the display
-fn draw_battery_status<D>(display: &mut D, battery_level: &BatteryLevel, settings: &Settings)
-where
-    D: embedded_graphics::draw_target::DrawTarget<Color = BinaryColor>,
-{
-    let bat_image: Image<Bmp<'static, Gray8>> = match battery_level {
-        BatteryLevel::Bat000 => Image::new(&settings.bat[0], settings.bat_position),
-        BatteryLevel::Bat020 => Image::new(&settings.bat[1], settings.bat_position),
-        BatteryLevel::Bat040 => Image::new(&settings.bat[2], settings.bat_position),
-        BatteryLevel::Bat060 => Image::new(&settings.bat[3], settings.bat_position),
-        BatteryLevel::Bat080 => Image::new(&settings.bat[4], settings.bat_position),
-        BatteryLevel::Bat100 => Image::new(&settings.bat[5], settings.bat_position),
-        BatteryLevel::Charging => Image::new(&settings.bat_mains, settings.bat_position),
-    };
-    let _ = bat_image.draw(&mut display.color_converted());
-}
-
-/// Draws the time display in the center area of the display
-fn draw_time_display<D>(display: &mut D, hours: u8, minutes: u8, settings: &Settings)
-where
-    D: embedded_graphics::draw_target::DrawTarget<Color = BinaryColor>,
-{
-    let mut digit_next_position = settings.time_digit_start_position;
-
-    let first_hour_digit = Image::new(&settings.digits[(hours / 10) as usize], digit_next_position);
-    digit_next_position.x += 24;
-
-    let second_hour_digit = Image::new(&settings.digits[(hours % 10) as usize], digit_next_position);
-    digit_next_position.x += 24;
-
-    let colon = Image::new(&settings.colon, digit_next_position);
-    digit_next_position.x += 11;
-
-    let first_minute_digit = Image::new(&settings.digits[(minutes / 10) as usize], digit_next_position);
-    digit_next_position.x += 24;
-
-    let second_minute_digit = Image::new(&settings.digits[(minutes % 10) as usize], digit_next_position);
-
-    let _ = first_hour_digit.draw(&mut display.color_converted());
-    let _ = second_hour_digit.draw(&mut display.color_converted());
-    let _ = colon.draw(&mut display.color_converted());
-    let _ = first_minute_digit.draw(&mut display.color_converted());
-    let _ = second_minute_digit.draw(&mut display.color_converted());
-}
-
-/// Draws the menu content in the center area of the display
-fn draw_menu_content<D>(display: &mut D, settings: &Settings)
-where
-    D: embedded_graphics::draw_target::DrawTarget<Color = BinaryColor>,
-{
-    let mut content_next_position = settings.content_start_position;
-    let _ = Text::with_baseline(
-        "Green: Sys. Info",
-        content_next_position,
-        settings.content_text_style,
-        Baseline::Top,
-    )
-    .draw(display);
-    content_next_position.y += 15;
-    let _ = Text::with_baseline(
-        "Blue: Standby",
-        content_next_position,
-        settings.content_text_style,
-        Baseline::Top,
-    )
-    .draw(display);
-    content_next_position.y += 15;
-    let _ = Text::with_baseline(
-        "Yellow: Back",
-        content_next_position,
-        settings.content_text_style,
-        Baseline::Top,
-    )
-    .draw(display);
+/// The lines of the weather forecast and the index of the icon of the condition
+fn weather_lines(system_state: &SystemState) -> (Lines, Option<usize>) {
+    let mut lines: Lines = [String::new(), String::new(), String::new()];
+    let mut icon = None;
+    if let Some(forecast) = system_state.forecast {
+        icon = Some(forecast.condition as usize);
+        let _ = write!(lines[0], "{}", forecast.condition.name());
+        let _ = write!(lines[1], "{}C to {}C", forecast.min_celsius, forecast.max_celsius);
+        let (_, month, day) = forecast.date;
+        let _ = write!(lines[2], "For {day:02}.{month:02}.");
+    } else if FORECAST_URL.is_none() {
+        let _ = write!(lines[0], "No location set");
+    } else {
+        let _ = write!(lines[0], "No forecast yet");
+        let _ = match &system_state.last_errors.weather {
+            Some(WeatherError::Http(HttpError::Status { code, .. })) => write!(lines[1], "Err HTTP {code}"),
+            Some(WeatherError::Http(_)) => write!(lines[1], "Err HTTP"),
+            Some(WeatherError::Forecast(_)) => write!(lines[1], "Err Response"),
+            None => Ok(()),
+        };
+    }
+    (lines, icon)
 }
 
-/// Draws the system info content in the center area of the display
-fn draw_system_info_content<D>(display: &mut D, vsys: f32, usb_power: bool, upper: f32, lower: f32, settings: &Settings)
-where
-    D: embedded_graphics::draw_target::DrawTarget<Color = BinaryColor>,
-{
-    let mut content_next_position = settings.content_start_position;
-
-    let mut vsys_txt: String<20> = String::new();
-    let _ = write!(vsys_txt, "Vsys:  {vsys}V");
-    let _ = Text::with_baseline(
-        &vsys_txt,
-        content_next_position,
-        settings.content_text_style,
-        Baseline::Top,
-    )
-    .draw(display);
-    content_next_position.y += 15;
-
-    let mut usb_txt: String<20> = String::new();
-    let _ = write!(usb_txt, "USB:   {usb_power}");
-    let _ = Text::with_baseline(
-        &usb_txt,
-        content_next_position,
-        settings.content_text_style,
-        Baseline::Top,
-    )
-    .draw(display);
-    content_next_position.y += 15;
-
-    let mut bounds_txt: String<20> = String::new();
-    let _ = write!(bounds_txt, "Upper/Lower {upper}/{lower}V");
-    let _ = Text::with_baseline(
-        &bounds_txt,
-        content_next_position,
-        settings.content_text_style,
-        Baseline::Top,
-    )
-    .draw(display);
+/// The title of the state indicator in the modes that show one
+const fn title(operation_mode: &OperationMode) -> Option<&'static str> {
+    match operation_mode {
+        OperationMode::Menu => Some("Menu"),
+        OperationMode::SystemInfo => Some("Sys.-Info"),
+        OperationMode::Weather => Some("Weather"),
+        OperationMode::NetworkInfo(_) => Some("Net-Info"),
+        _ => None,
+    }
 }
 
-/// Draws the alarm button prompt in the state indicator area
-fn draw_alarm_button_prompt<D>(display: &mut D, button: &Button, settings: &Settings)
-where
-    D: embedded_graphics::draw_target::DrawTarget<Color = BinaryColor>,
-{
-    let mut btn_txt: String<13> = String::new();
-    let _ = write!(btn_txt, "Press {button:?}!");
-    let _ = Text::with_baseline(
-        &btn_txt,
-        settings.state_indicator_position,
-        settings.state_indicator_text_style,
-        Baseline::Top,
-    )
-    .draw(display);
+/// The brightness of the display during the day
+const fn day_brightness(brightness: DayBrightness) -> Brightness {
+    match brightness {
+        DayBrightness::Dimmest => Brightness::DIMMEST,
+        DayBrightness::Dim => Brightness::DIM,
+        DayBrightness::Normal => Brightness::NORMAL,
+        DayBrightness::Bright => Brightness::BRIGHT,
+        DayBrightness::Brightest => Brightness::BRIGHTEST,
+    }
 }
 
-/// Draws the date text at the bottom of the display
-fn draw_date<D>(display: &mut D, dt: &DateTime, settings: &Settings)
-where
-    D: embedded_graphics::draw_target::DrawTarget<Color = BinaryColor>,
-{
-    let date = StringUtils::convert_datetime_to_str(dt);
-    let _ = Text::with_baseline(&date, settings.date_position, settings.date_text_style, Baseline::Top).draw(display);
+/// The battery status indicator for the battery level
+const fn battery(battery_level: &BatteryLevel) -> Battery {
+    match battery_level.percent() {
+        Some(percent) => Battery::Level(percent / 20),
+        None => Battery::Charging,
+    }
 }
 
 #[embassy_executor::task]
@@ -387,13 +239,47 @@ pub async fn display_handler(i2c: I2c<'static, I2C0, Async>) {
         return;
     }
 
-    let _ = display.set_brightness(Brightness::DIMMEST).await;
-
-    let settings = Settings::new();
+    let _ = display
+        .set_brightness(NIGHT_SCHEDULE.map_or(Brightness::DIMMEST, |schedule| day_brightness(schedule.day_brightness)))
+        .await;
+    // whether the display is dimmed for the night and whether it is switched on
+    let mut dimmed = false;
+    let mut display_on = true;
+    // at night a button press shows the screens until then
+    let mut awake_until: Option<Instant> = None;
+    // the screen is shown inverted until then, once in the hour it was last
+    let mut inverted_until: Option<Instant> = None;
+    let mut inverted_hour: Option<u8> = None;
+
+    let settings = match Settings::new() {
+        Ok(settings) => settings,
+        Err(e) => {
+            warn!("Failed to load {}, display task ending", e.name);
+            return;
+        }
+    };
 
     'mainloop: loop {
-        // Wait for a signal to update the display
-        wait_for_display_update().await;
+        // Wait for a signal to update the display, a button press waking it or the end of the wake or inversion time
+        let until = [awake_until, inverted_until].into_iter().flatten().min();
+        match select3(
+            wait_for_display_update(),
+            DISPLAY_WAKE_SIGNAL.wait(),
+            Timer::at(until.unwrap_or(Instant::MAX)),
+        )
+        .await
+        {
+            Either3::First(()) => {}
+            Either3::Second(()) => {
+                awake_until =
+                    NIGHT_SCHEDULE.map(|schedule| Instant::now() + Duration::from_secs(u64::from(schedule.wake_secs)));
+            }
+            Either3::Third(()) => {
+                let now = Instant::now();
+                awake_until = awake_until.filter(|until| *until > now);
+                inverted_until = inverted_until.filter(|until| *until > now);
+            }
+        }
 
         // get the current time out of the mutex and quickly drop the mutex
         let dt: DateTime = {
@@ -405,7 +291,7 @@ pub async fn display_handler(i2c: I2c<'static, I2C0, Async>) {
                 continue 'mainloop;
             };
             match rtc.now() {
-                Ok(dt) => dt,
+                Ok(dt) => DateTime::from(dt),
                 Err(e) => {
                     info!("RTC not running: {:?}", Debug2Format(&e));
                     // Return an empty DateTime
@@ -430,78 +316,144 @@ pub async fn display_handler(i2c: I2c<'static, I2C0, Async>) {
             Timer::after(Duration::from_secs(1)).await;
             continue 'mainloop;
         };
-
-        // Store operation mode locally to avoid move issues
-        let operation_mode = system_state.operation_mode.clone();
-
-        // prepare the display, note that nothing is sent to the display before flush()
-        display.clear();
-
-        // Draw state indicator (or alarm button prompt)
-        if operation_mode == OperationMode::Alarm {
-            let btn = system_state.alarm_settings.get_first_valid_stop_alarm_button();
-            draw_alarm_button_prompt(&mut display, &btn, &settings);
-        } else {
-            draw_state_indicator(
-                &mut display,
-                &operation_mode,
-                system_state.alarm_settings.get_enabled(),
-                &settings,
-            );
+        drop(system_state_guard);
+
+        let operation_mode = &system_state.operation_mode;
+        let alarm_settings = &system_state.alarm_settings;
+
+        // at night the display is dimmed, and sleeps in the normal mode unless a button press woke it
+        let night_display = NIGHT_SCHEDULE
+            .filter(|_| system_state.night)
+            .map(|schedule| schedule.display);
+        if let Some(schedule) = NIGHT_SCHEDULE
+            && dimmed != system_state.night
+        {
+            dimmed = system_state.night;
+            let brightness = if dimmed {
+                Brightness::DIMMEST
+            } else {
+                day_brightness(schedule.day_brightness)
+            };
+            let _ = display.set_brightness(brightness).await;
         }
-
-        // Draw battery status
-        draw_battery_status(&mut display, &system_state.power_state.get_battery_level(), &settings);
-
-        // Draw main content (time or menu)
-        let (hours, minutes) = match operation_mode {
-            OperationMode::Normal | OperationMode::Alarm => (dt.hour, dt.minute),
-            OperationMode::SetAlarmTime => (
-                system_state.alarm_settings.get_hour(),
-                system_state.alarm_settings.get_minute(),
-            ),
-            _ => (0, 0),
-        };
-
-        match operation_mode {
-            OperationMode::Normal | OperationMode::Alarm | OperationMode::SetAlarmTime => {
-                // Display the time
-                draw_time_display(&mut display, hours, minutes, &settings);
+        let awake = awake_until.is_some_and(|until| Instant::now() < until);
+        let asleep = matches!(night_display, Some(NightDisplay::Clock | NightDisplay::Off))
+            && *operation_mode == OperationMode::Normal
+            && !awake;
+        DISPLAY_ASLEEP.lock(|display_asleep| display_asleep.set(asleep));
+
+        if asleep && night_display == Some(NightDisplay::Off) {
+            if display_on {
+                info!("Night, switching the display off");
+                display_on = false;
+                let _ = display.set_display_on(false).await;
             }
-            OperationMode::Menu => {
-                draw_menu_content(&mut display, &settings);
+            report_task_success(TaskId::Display).await;
+            continue 'mainloop;
+        }
+        if !display_on {
+            display_on = true;
+            let _ = display.set_display_on(true).await;
+        }
+
+        // the texts the screen shows, composed before it is drawn as it only borrows them
+        let mut prompt: String<TITLE_CHARS> = String::new();
+        let header = match operation_mode {
+            OperationMode::Normal if alarm_settings.get_enabled() => Header::Saber,
+            OperationMode::SetAlarmTime => Header::Setup,
+            OperationMode::Alarm => {
+                let button = alarm_settings.get_first_valid_stop_alarm_button();
+                let _ = write!(prompt, "Press {button:?}!");
+                Header::Title(&prompt)
             }
+            _ => title(operation_mode).map_or(Header::None, Header::Title),
+        };
+        let (lines, icon) = match operation_mode {
             OperationMode::SystemInfo => {
-                let vsys = system_state.power_state.get_vsys();
-                let usb_power = system_state.power_state.get_usb_power();
-                let upper = system_state.power_state.get_battery_voltage_fully_charged();
-                let lower = system_state.power_state.get_battery_voltage_empty();
-
-                draw_system_info_content(&mut display, vsys, usb_power, upper, lower, &settings);
+                let power_state = &system_state.power_state;
+                let lines = system_info_lines(
+                    power_state.get_vsys(),
+                    power_state.get_usb_power(),
+                    power_state.get_battery_voltage_fully_charged(),
+                    power_state.get_battery_voltage_empty(),
+                );
+                (lines, None)
             }
-            OperationMode::Standby => {
-                let _ = Text::with_baseline(
-                    "Going to sleep...",
-                    settings.content_start_position,
-                    settings.content_text_style,
-                    Baseline::Top,
-                )
-                .draw(&mut display);
-                let _ = display.flush().await;
-                Timer::after(Duration::from_secs(5)).await;
-                display.clear();
-                let _ = display.flush().await;
+            OperationMode::Weather => weather_lines(&system_state),
+            OperationMode::NetworkInfo(page) => (network_info_lines(*page, &system_state), None),
+            _ => Default::default(),
+        };
+        // the alarm time is set in the format the time is shown in, the alarm settings keep it in 24-hour format
+        let (hours, minutes) = match operation_mode {
+            OperationMode::SetAlarmTime => (alarm_settings.get_hour(), alarm_settings.get_minute()),
+            _ => (dt.hour, dt.minute),
+        };
+        let (hours, meridiem) = if system_state.display_settings.get_twelve_hour() {
+            let (hours, meridiem) = twelve_hour(hours);
+            (hours, Some(meridiem))
+        } else {
+            (hours, None)
+        };
+        let body = match operation_mode {
+            OperationMode::Normal | OperationMode::Alarm | OperationMode::SetAlarmTime => Body::Time { hours, minutes },
+            OperationMode::Menu => Body::Menu,
+            OperationMode::SystemInfo | OperationMode::Weather | OperationMode::NetworkInfo(_) => {
+                Body::Lines { lines: &lines, icon }
             }
+            OperationMode::Standby => Body::Message("Going to sleep..."),
+        };
+        // once an hour the lit and the dark pixels swap for a while, never at night as it would light up the room
+        if cfg!(feature = "burn-in-inversion")
+            && dt.minute == 0
+            && inverted_hour != Some(dt.hour)
+            && *operation_mode == OperationMode::Normal
+            && !system_state.night
+        {
+            inverted_hour = Some(dt.hour);
+            inverted_until = Some(Instant::now() + INVERSION_DURATION);
         }
+        let inverted = inverted_until.is_some_and(|until| Instant::now() < until);
+
+        let date = StringUtils::convert_datetime_to_str(&dt);
+        let shift = pixel_shift(dt.hour, dt.minute);
+        let screen = if asleep {
+            // the night clock shows nothing but the time
+            Screen {
+                header: Header::None,
+                battery: None,
+                body,
+                date: None,
+                meridiem: None,
+                shift,
+                inverted: false,
+            }
+        } else {
+            Screen {
+                header,
+                battery: Some(battery(&system_state.power_state.get_battery_level())),
+                body,
+                date: matches!(operation_mode, OperationMode::Normal | OperationMode::Alarm).then_some(date.as_str()),
+                // the prompt of the alarm fills the state indicator
+                meridiem: meridiem.filter(|_| *operation_mode != OperationMode::Alarm),
+                shift,
+                inverted,
+            }
+        };
 
-        // Draw date (if in normal/alarm mode)
-        if matches!(operation_mode, OperationMode::Normal | OperationMode::Alarm) {
-            draw_date(&mut display, &dt, &settings);
-        }
+        // prepare the display, note that nothing is sent to the display before flush()
+        display.clear();
+        draw_screen(&mut display, &screen, &settings);
 
         // finally: send the display buffer to the display and we are done for this cycle
         let _ = display.flush().await;
 
+        // in standby the message is shown for a while, then the display goes dark
+        if *operation_mode == OperationMode::Standby {
+            Timer::after(Duration::from_secs(5)).await;
+            display.clear();
+            let _ = display.flush().await;
+        }
+
         // Report successful display update to watchdog
         report_task_success(TaskId::Display).await;
     }
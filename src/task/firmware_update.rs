@@ -0,0 +1,279 @@
+//! # Firmware Update
+//! This module updates the firmware over `WiFi` and confirms or rolls back an update after the restart.
+//!
+//! The flash is split by the bootloader in `bootloader/` into an active slot the firmware runs from and an update
+//! slot, see `memory.x`. An update is written into the update slot and marked, then the system restarts and the
+//! bootloader swaps the slots. The new firmware runs on trial: it is only marked as booted once the tasks monitored
+//! by `task::watchdog` that report periodically, the orchestrator, the display and the time updater after a time sync,
+//! reported healthy within `TRIAL_WINDOW`. The alarm trigger does not count, it does not report while the alarm is
+//! disabled. If they do not, or the firmware restarts before, the bootloader swaps the previous firmware back in.
+//!
+//! There is no task looking for updates: the image is fetched in the cycle of the time updater, after a successful
+//! time sync, the forecast and the calendar. Only the header is read unless the image holds a newer version than the
+//! running one, so bump the version in `Cargo.toml` for every update. Images are made with `firmware_image.py`, the
+//! checks are in `utility::firmware_image`. No update is installed while the alarm rings or while an update is on
+//! trial.
+//!
+//! The image is configured in the optional `config/ota.json`:
+//!```json
+//! {
+//!     "url": "http://192.168.1.20/alarmclock.img",
+//!     "public_key": "<64 hex digits>"
+//! }
+//! ```
+//! - `url`: a plain `http` URL of the image
+//! - `public_key`: the Ed25519 public key printed by `firmware_image.py --generate-key`, only with the `ota-signature`
+//!   feature, which makes the firmware refuse images without a valid signature
+//!
+//! Without the file no updates are looked for, an update that is on trial is still confirmed or rolled back.
+
+include!(concat!(env!("OUT_DIR"), "/ota_config.rs"));
+
+use defmt::{info, warn};
+use embassy_boot_rp::{AlignedBuffer, FirmwareUpdater, FirmwareUpdaterConfig, State};
+use embassy_net::{
+    Stack, dns,
+    tcp::client::{TcpClient, TcpClientState},
+};
+use embassy_sync::once_lock::OnceLock;
+use embassy_time::{Duration, with_timeout};
+use embedded_io_async::Read;
+use reqwless::{
+    client::HttpClient,
+    request::{Method, RequestBuilder},
+};
+use sha2::Sha256;
+
+use crate::{
+    error::{FirmwareError, HttpError, SystemError},
+    event::{Event, try_send_event},
+    state::{OperationMode, SYSTEM_STATE},
+    syslog,
+    task::{
+        alarm_settings::SharedFlash,
+        watchdog::{release_hardware_watchdog, request_reset, wait_until_periodic_tasks_healthy},
+    },
+    utility::{
+        firmware_image::{HEADER_SIZE, ImageError, ImageHeader, ImageVerifier, Version},
+        http_response::is_success,
+        syslog::Severity,
+    },
+    warn_remote,
+};
+
+/// How long an updated firmware has to get the periodic tasks healthy, the first time sync must be within
+const TRIAL_WINDOW: Duration = Duration::from_secs(600);
+
+/// Size of the active slot, see `memory.x`, the firmware of an image must fit into it
+const ACTIVE_SLOT_SIZE: u32 = 992 * 1024;
+
+/// Size of a flash page, the update slot is erased and written a page at a time
+const PAGE_SIZE: usize = 4096;
+
+/// Size of the receive buffer, which must hold the response headers
+const RX_BUFFER_SIZE: usize = 1024;
+
+/// Size of the `TCP` socket receive buffer
+const TCP_RX_BUFFER_SIZE: usize = 1024;
+
+/// Size of the `TCP` socket transmit buffer, the request is well below that
+const TCP_TX_BUFFER_SIZE: usize = 256;
+
+/// Size of the chunks the update slot is read in when checking what was written
+const HASH_CHUNK_SIZE: usize = 256;
+
+/// The flash memory, set once the trial task runs
+static FLASH: OnceLock<&'static SharedFlash> = OnceLock::new();
+
+/// This task confirms an updated firmware once the monitored tasks that report periodically are healthy, whatever the
+/// alarm settings, or rolls it back by restarting if they do not get healthy within the trial window. The hardware
+/// watchdog is released once the firmware is confirmed or if it is not on trial. It also hands the flash to the
+/// updates.
+#[embassy_executor::task]
+pub async fn firmware_trial_task(flash: &'static SharedFlash) {
+    let _ = FLASH.init(flash);
+    let running = Version::running();
+
+    let mut aligned = AlignedBuffer([0; 1]);
+    let mut updater = FirmwareUpdater::new(FirmwareUpdaterConfig::from_linkerfile(flash, flash), &mut aligned.0);
+    match updater.get_state().await {
+        Ok(State::Swap) => {
+            info!("Firmware {} runs on trial", running);
+            if with_timeout(TRIAL_WINDOW, wait_until_periodic_tasks_healthy())
+                .await
+                .is_err()
+            {
+                warn_remote!(
+                    "Firmware {}.{}.{} did not get healthy within {} seconds, rolling back",
+                    running.major,
+                    running.minor,
+                    running.patch,
+                    TRIAL_WINDOW.as_secs()
+                );
+                request_reset();
+                return;
+            }
+            match updater.mark_booted().await {
+                Ok(()) => {
+                    info!("Firmware {} confirmed", running);
+                    release_hardware_watchdog();
+                    syslog!(
+                        Severity::Notice,
+                        "firmware",
+                        "Firmware {}.{}.{} confirmed",
+                        running.major,
+                        running.minor,
+                        running.patch
+                    );
+                }
+                // the bootloader rolls back on the next restart, which is better than running unconfirmed, and the
+                // hardware watchdog stays fed
+                Err(error) => warn!("Confirming the firmware failed: {:?}", error),
+            }
+        }
+        Ok(_) => {
+            info!("Running firmware {}", running);
+            release_hardware_watchdog();
+        }
+        Err(error) => warn!("Reading the state of the bootloader failed: {:?}", error),
+    }
+}
+
+/// Look for a newer firmware and install it, then restart into it. A failure is only reported as error.
+/// Does nothing if no image is configured.
+pub async fn update_firmware(stack: &Stack<'static>) {
+    let Some(url) = FIRMWARE_URL else {
+        return;
+    };
+    let Some(flash) = FLASH.try_get().copied() else {
+        return;
+    };
+    let alarm_ringing = SYSTEM_STATE
+        .lock()
+        .await
+        .as_ref()
+        .is_some_and(|state| state.operation_mode == OperationMode::Alarm);
+    if alarm_ringing {
+        info!("Alarm ringing, not looking for a firmware update");
+        return;
+    }
+
+    match install_firmware(stack, url, flash).await {
+        Ok(Some(version)) => {
+            warn_remote!(
+                "Firmware {}.{}.{} installed, restarting",
+                version.major,
+                version.minor,
+                version.patch
+            );
+            request_reset();
+        }
+        Ok(None) => {}
+        Err(error) => {
+            warn!("Updating the firmware failed: {:?}", error);
+            try_send_event(Event::Error(SystemError::Firmware(error)));
+        }
+    }
+}
+
+/// Fetch the image and write its firmware into the update slot if it is newer than the running one.
+/// Returns the version of the installed firmware, `None` if there is nothing to install.
+async fn install_firmware(
+    stack: &Stack<'static>,
+    url: &str,
+    flash: &'static SharedFlash,
+) -> Result<Option<Version>, FirmwareError> {
+    let mut aligned = AlignedBuffer([0; 1]);
+    let mut updater = FirmwareUpdater::new(FirmwareUpdaterConfig::from_linkerfile(flash, flash), &mut aligned.0);
+    if updater.get_state().await? != State::Boot {
+        info!("Firmware on trial, not looking for an update");
+        return Ok(None);
+    }
+
+    let mut rx_buffer = [0; RX_BUFFER_SIZE];
+    let client_state = TcpClientState::<1, TCP_RX_BUFFER_SIZE, TCP_TX_BUFFER_SIZE>::new();
+    let tcp_client = TcpClient::new(*stack, &client_state);
+    let dns_client = dns::DnsSocket::new(*stack);
+    let mut http_client = HttpClient::new(&tcp_client, &dns_client);
+
+    let mut request = http_client
+        .request(Method::GET, url)
+        .await
+        .map_err(HttpError::Request)?;
+    let response = request.send(&mut rx_buffer).await.map_err(HttpError::Send)?;
+
+    let status = response.status.0;
+    if !is_success(status) {
+        return Err(HttpError::Status {
+            code: status,
+            retry_after_secs: None,
+        }
+        .into());
+    }
+
+    let mut body = response.body().reader();
+    let mut header = [0; HEADER_SIZE];
+    let read = read_full(&mut body, &mut header).await?;
+    if read < HEADER_SIZE {
+        // a response shorter than the header cannot be an image
+        return Err(ImageError::Magic.into());
+    }
+    let header = ImageHeader::parse(&header, ACTIVE_SLOT_SIZE)?;
+    let running = Version::running();
+    if header.version <= running {
+        info!("Firmware {} is up to date, the image holds {}", running, header.version);
+        return Ok(None);
+    }
+    if cfg!(feature = "ota-signature") && !header.is_signed() {
+        return Err(ImageError::Unsigned.into());
+    }
+
+    info!("Installing firmware {}, {} bytes", header.version, header.length);
+    let mut verifier = ImageVerifier::new(&header);
+    let mut page = [0; PAGE_SIZE];
+    let mut offset = 0;
+    loop {
+        let read = read_full(&mut body, &mut page).await?;
+        if read == 0 {
+            break;
+        }
+        verifier.update(&page[..read])?;
+        // the last page is filled up like erased flash, pages are written whole
+        page[read..].fill(0xFF);
+        updater.write_firmware(offset, &page).await?;
+        offset += PAGE_SIZE;
+        if read < PAGE_SIZE {
+            break;
+        }
+    }
+    verifier.finish()?;
+
+    // check what ended up in the flash, not just what was received
+    let mut chunk = [0; HASH_CHUNK_SIZE];
+    let mut written = [0; 32];
+    updater.hash::<Sha256>(header.length, &mut chunk, &mut written).await?;
+    if written != header.sha256 {
+        return Err(FirmwareError::Written);
+    }
+
+    #[cfg(feature = "ota-signature")]
+    updater
+        .verify_and_mark_updated(&FIRMWARE_PUBLIC_KEY, &header.signature, header.length)
+        .await?;
+    #[cfg(not(feature = "ota-signature"))]
+    updater.mark_updated().await?;
+    Ok(Some(header.version))
+}
+
+/// Read until the buffer is full or the body ends, returns the number of bytes read
+async fn read_full<R: Read<Error = reqwless::Error>>(body: &mut R, buffer: &mut [u8]) -> Result<usize, HttpError> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        let read = body.read(&mut buffer[filled..]).await.map_err(HttpError::Body)?;
+        if read == 0 {
+            break;
+        }
+        filled += read;
+    }
+    Ok(filled)
+}
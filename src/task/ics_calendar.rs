@@ -0,0 +1,134 @@
+//! # iCalendar
+//! This module fetches an iCalendar (`.ics`) document, e.g. the shared calendar of the shifts, and makes the plan of
+//! the alarm for the next days from its events, see `utility::alarm_plan`. The document is read by `utility::ics`
+//! while it streams in, so it may be of any size.
+//!
+//! There is no task of its own: the calendar is fetched in the cycle of the time updater, right after a successful
+//! time sync and the forecast, while the network connection is borrowed anyway. A failed fetch is reported as error, the alarm
+//! keeps following the last plan until the next cycle.
+//!
+//! The calendar and the rules are configured in the optional `config/calendar.json`:
+//!```json
+//! {
+//!     "url": "http://192.168.1.20/shifts.ics",
+//!     "skip_keyword": "OFF",
+//!     "alarm_before_first_event_minutes": 90
+//! }
+//! ```
+//! - `url`: a plain `http` URL of the calendar
+//! - `skip_keyword`: optional, the alarm does not ring on days with an event holding this word in its summary or
+//!   categories
+//! - `alarm_before_first_event_minutes`: optional, the alarm rings this many minutes before the first timed event of
+//!   a day instead of at the alarm time, at most 720
+//!
+//! Without the file no calendar is fetched and the alarm rings as set.
+
+include!(concat!(env!("OUT_DIR"), "/calendar_config.rs"));
+
+use defmt::{info, warn};
+use embassy_net::{
+    Stack, dns,
+    tcp::client::{TcpClient, TcpClientState},
+};
+use embedded_io_async::Read;
+use reqwless::{
+    client::HttpClient,
+    request::{Method, RequestBuilder},
+};
+
+use crate::{
+    error::{CalendarError, HttpError, SystemError},
+    event::{Event, send_event, try_send_event},
+    task::time_updater::{RTC_MUTEX, rtc_now},
+    utility::{
+        alarm_plan::AlarmPlan,
+        calendar::DateTime,
+        http_response::is_success,
+        ics::{CalendarEvent, IcsReader},
+    },
+};
+
+/// Size of the receive buffer, which must hold the response headers
+const RX_BUFFER_SIZE: usize = 1024;
+
+/// Size of the chunks the body is read in
+const BODY_CHUNK_SIZE: usize = 256;
+
+/// Size of the `TCP` socket receive buffer
+const TCP_RX_BUFFER_SIZE: usize = 1024;
+
+/// Size of the `TCP` socket transmit buffer, the request is well below that
+const TCP_TX_BUFFER_SIZE: usize = 256;
+
+/// Fetch the calendar and report the plan of the alarm for the next days to the orchestrator, a failure is only
+/// reported as error. Times in UTC are converted with the UTC offset of the last time sync, or taken as local time
+/// if there was none. Does nothing if no calendar is configured.
+pub async fn update_calendar(stack: &Stack<'static>, utc_offset_minutes: Option<i16>) {
+    let Some(url) = CALENDAR_URL else {
+        return;
+    };
+    let today = {
+        let rtc_guard = RTC_MUTEX.lock().await;
+        rtc_guard.as_ref().and_then(rtc_now)
+    };
+    let Some(today) = today else {
+        warn!("RTC not available, not fetching the calendar");
+        return;
+    };
+
+    match fetch_calendar(stack, url, &today, utc_offset_minutes.unwrap_or(0)).await {
+        Ok(alarm_plan) => {
+            info!("Calendar: {:?}", alarm_plan);
+            send_event(Event::CalendarUpdated(alarm_plan)).await;
+        }
+        Err(error) => {
+            warn!("Fetching the calendar failed: {:?}", error);
+            try_send_event(Event::Error(SystemError::Calendar(error)));
+        }
+    }
+}
+
+/// Make the `GET` request for the calendar and read the plan starting on the day of `today` from it
+async fn fetch_calendar(
+    stack: &Stack<'static>,
+    url: &str,
+    today: &DateTime,
+    utc_offset_minutes: i16,
+) -> Result<AlarmPlan, CalendarError> {
+    let mut rx_buffer = [0; RX_BUFFER_SIZE];
+
+    let client_state = TcpClientState::<1, TCP_RX_BUFFER_SIZE, TCP_TX_BUFFER_SIZE>::new();
+    let tcp_client = TcpClient::new(*stack, &client_state);
+    let dns_client = dns::DnsSocket::new(*stack);
+    let mut http_client = HttpClient::new(&tcp_client, &dns_client);
+
+    let mut request = http_client
+        .request(Method::GET, url)
+        .await
+        .map_err(HttpError::Request)?;
+    let response = request.send(&mut rx_buffer).await.map_err(HttpError::Send)?;
+
+    let status = response.status.0;
+    if !is_success(status) {
+        return Err(HttpError::Status {
+            code: status,
+            retry_after_secs: None,
+        }
+        .into());
+    }
+
+    let mut alarm_plan = AlarmPlan::new(today, CALENDAR_RULES.lead_minutes);
+    let mut add_event = |event: &CalendarEvent| alarm_plan.add_event(event);
+    let mut reader = IcsReader::new(CALENDAR_RULES.skip_keyword, utc_offset_minutes);
+    let mut body = response.body().reader();
+    let mut chunk = [0; BODY_CHUNK_SIZE];
+    loop {
+        let read = body.read(&mut chunk).await.map_err(HttpError::Body)?;
+        if read == 0 {
+            break;
+        }
+        reader.feed(&chunk[..read], &mut add_event);
+    }
+    reader.finish(&mut add_event)?;
+    Ok(alarm_plan)
+}
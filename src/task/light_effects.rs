@@ -231,25 +231,25 @@ struct SunriseParams {
 }
 
 impl SunriseParams {
-    /// Creates standard sunrise effect parameters (60 second sunrise)
-    const fn new() -> Self {
+    /// Creates standard sunrise effect parameters for a sunrise of the given length
+    const fn new(minutes: u8) -> Self {
         Self {
             start_color: RGB8::new(139, 0, 0),
             end_color: RGB8::new(255, 250, 244),
             end_brightness: 100.0,
-            duration_ms: 60_000,
+            duration_ms: minutes as u32 * 60_000,
         }
     }
 }
 
-/// Displays the sunrise effect
-async fn sunrise_effect(np: &mut NeopixelType) {
+/// Displays the sunrise effect, taking the given number of minutes
+async fn sunrise_effect(np: &mut NeopixelType, minutes: u8) {
     info!("Sunrise effect");
 
     let mut data = [RGB8::default(); NUM_LEDS_USIZE];
     let _ = np.write(brightness(data.iter().copied(), 0)).await;
 
-    let params = SunriseParams::new();
+    let params = SunriseParams::new(minutes);
     let start_time = Instant::now();
 
     // Loop for duration milliseconds
@@ -363,7 +363,8 @@ async fn handle_normal_mode(
     second: u8,
     colors: &ClockColors,
 ) {
-    if system_state.alarm_settings.get_enabled() {
+    // the analog clock keeps the room from being dark at night
+    if system_state.alarm_settings.get_enabled() || system_state.night {
         turn_off_all_leds(np).await;
     } else {
         display_analog_clock(np, neopixel_mgr, hour, minute, second, colors).await;
@@ -374,7 +375,7 @@ async fn handle_normal_mode(
 async fn handle_alarm_mode(np: &mut NeopixelType, neopixel_mgr: &NeopixelManager, system_state: &SystemState) {
     match system_state.alarm_state {
         AlarmState::Sunrise => {
-            sunrise_effect(np).await;
+            sunrise_effect(np, system_state.alarm_settings.get_sunrise_minutes()).await;
         }
         AlarmState::Noise => {
             noise_effect(np, neopixel_mgr).await;
@@ -418,7 +419,12 @@ pub async fn light_effects_handler(spi: Spi<'static, SPI0, embassy_rp::spi::Asyn
         info!("{}", system_state);
 
         match system_state.operation_mode {
-            OperationMode::Normal | OperationMode::Menu | OperationMode::SetAlarmTime | OperationMode::SystemInfo => {
+            OperationMode::Normal
+            | OperationMode::Menu
+            | OperationMode::SetAlarmTime
+            | OperationMode::SystemInfo
+            | OperationMode::Weather
+            | OperationMode::NetworkInfo(_) => {
                 handle_normal_mode(&mut np, &neopixel_mgr, &system_state, hour, minute, second, &colors).await;
             }
             OperationMode::Alarm => {
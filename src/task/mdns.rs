@@ -0,0 +1,164 @@
+//! # mDNS Task
+//! This module contains the task that makes the clock reachable as `<hostname>.local`, so that its address need not
+//! be looked up in the router. The answers are built by `utility::mdns`.
+//!
+//! While the network is up the task listens on the mDNS multicast group and answers queries for the hostname. Once
+//! the API server serves the web interface, it also advertises the `_http._tcp` service, so that the clock shows up
+//! in service browsers. All records are announced twice when the network comes up and again when the service starts.
+//! The task does not hold the network, like the API server it is reachable while the connectivity policy keeps the
+//! connection up.
+
+use defmt::{info, warn};
+use embassy_futures::select::{Either3, select3};
+use embassy_net::{
+    IpAddress, IpEndpoint, Ipv4Address, Stack,
+    udp::{PacketMetadata, UdpSocket},
+};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, once_lock::OnceLock, signal::Signal};
+use embassy_time::{Duration, Timer};
+
+use crate::{
+    task::network::{HOSTNAME, network_stack},
+    utility::mdns::{MDNS_GROUP, MDNS_PORT, Responder},
+};
+
+/// Size of the socket buffers
+const SOCKET_BUFFER_SIZE: usize = 1024;
+
+/// Number of packets the socket buffers hold
+const SOCKET_PACKETS: usize = 4;
+
+/// Size of the buffers for a query and a response, queries with many known answers are cut off
+const PACKET_SIZE: usize = 512;
+
+/// Time between the two announcements
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Delay before trying again after the socket could not be bound
+const BIND_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// The port of the web interface, set by the API server once it serves
+static HTTP_SERVICE_PORT: OnceLock<u16> = OnceLock::new();
+
+/// Signal for announcing the records again, the web interface has started
+static MDNS_ANNOUNCE_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Advertise the web interface on the given port as `_http._tcp` service
+pub fn advertise_http_service(port: u16) {
+    if HTTP_SERVICE_PORT.init(port).is_ok() {
+        MDNS_ANNOUNCE_SIGNAL.signal(());
+    }
+}
+
+/// Waits until the records must be announced again
+async fn wait_for_mdns_announce() {
+    MDNS_ANNOUNCE_SIGNAL.wait().await;
+}
+
+/// The endpoint of the mDNS multicast group
+fn group_endpoint() -> IpEndpoint {
+    IpEndpoint::new(IpAddress::Ipv4(Ipv4Address::from(MDNS_GROUP)), MDNS_PORT)
+}
+
+/// This task answers mDNS queries for the clock while the network is up.
+#[embassy_executor::task]
+pub async fn mdns_task() {
+    info!("mDNS task starting");
+    let stack = network_stack().await;
+
+    let mut rx_meta = [PacketMetadata::EMPTY; SOCKET_PACKETS];
+    let mut rx_buffer = [0; SOCKET_BUFFER_SIZE];
+    let mut tx_meta = [PacketMetadata::EMPTY; SOCKET_PACKETS];
+    let mut tx_buffer = [0; SOCKET_BUFFER_SIZE];
+
+    loop {
+        stack.wait_config_up().await;
+        let Some(address) = stack.config_v4().map(|config| config.address.address().octets()) else {
+            // IPv6 only, there is no address to answer with
+            stack.wait_config_down().await;
+            continue;
+        };
+
+        if let Err(e) = stack.join_multicast_group(Ipv4Address::from(MDNS_GROUP)) {
+            warn!("mDNS: joining the multicast group failed: {:?}", e);
+        }
+        let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+        if let Err(e) = socket.bind(MDNS_PORT) {
+            warn!("mDNS: binding the socket failed: {:?}", e);
+            Timer::after(BIND_RETRY_DELAY).await;
+            continue;
+        }
+
+        info!("mDNS responding for {}.local", HOSTNAME);
+        serve(stack, &mut socket, address).await;
+        socket.close();
+        let _ = stack.leave_multicast_group(Ipv4Address::from(MDNS_GROUP));
+    }
+}
+
+/// Announce the records and answer the queries until the network goes down
+async fn serve(stack: Stack<'static>, socket: &mut UdpSocket<'_>, address: [u8; 4]) {
+    let mut query = [0; PACKET_SIZE];
+    let mut response = [0; PACKET_SIZE];
+
+    announce(socket, address, &mut response).await;
+    loop {
+        let event = select3(
+            socket.recv_from(&mut query),
+            stack.wait_config_down(),
+            wait_for_mdns_announce(),
+        )
+        .await;
+        match event {
+            Either3::First(Ok((len, metadata))) => {
+                // simple resolvers send from another port and expect a direct answer
+                let legacy = metadata.endpoint.port != MDNS_PORT;
+                match responder(address).respond(&query[..len], legacy, &mut response) {
+                    Ok(Some(answer)) => {
+                        let destination = if answer.unicast {
+                            metadata.endpoint
+                        } else {
+                            group_endpoint()
+                        };
+                        if let Err(e) = socket.send_to(&response[..answer.len], destination).await {
+                            warn!("mDNS: sending the answer failed: {:?}", e);
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => warn!("mDNS: query from {} not answered: {:?}", metadata.endpoint, e),
+                }
+            }
+            Either3::First(Err(e)) => warn!("mDNS: receiving a query failed: {:?}", e),
+            Either3::Second(()) => return,
+            Either3::Third(()) => announce(socket, address, &mut response).await,
+        }
+    }
+}
+
+/// Announce all records twice, one second apart
+async fn announce(socket: &mut UdpSocket<'_>, address: [u8; 4], buffer: &mut [u8]) {
+    let len = match responder(address).announce(buffer) {
+        Ok(len) => len,
+        Err(e) => {
+            warn!("mDNS: announcement does not fit the buffer: {:?}", e);
+            return;
+        }
+    };
+    for round in 0..2 {
+        if round > 0 {
+            Timer::after(ANNOUNCE_INTERVAL).await;
+        }
+        if let Err(e) = socket.send_to(&buffer[..len], group_endpoint()).await {
+            warn!("mDNS: sending the announcement failed: {:?}", e);
+        }
+    }
+}
+
+/// The responder for the current address, with the web interface if it is served
+fn responder(address: [u8; 4]) -> Responder<'static> {
+    Responder {
+        hostname: HOSTNAME,
+        address,
+        http_port: HTTP_SERVICE_PORT.try_get().copied(),
+    }
+}
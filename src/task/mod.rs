@@ -1,11 +1,22 @@
 //! Tasks that make up the application.
 pub mod alarm_settings;
 pub mod alarm_trigger;
+pub mod api_server;
 pub mod buttons;
+pub mod console;
 pub mod display;
+pub mod firmware_update;
+pub mod ics_calendar;
 pub mod light_effects;
+pub mod mdns;
+pub mod mqtt;
+pub mod network;
 pub mod orchestrate;
+pub mod peer_sync;
 pub mod power;
 pub mod sound;
+pub mod syslog;
 pub mod time_updater;
 pub mod watchdog;
+pub mod weather;
+pub mod webhook;
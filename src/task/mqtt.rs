@@ -0,0 +1,538 @@
+//! # MQTT Task
+//! This module contains the task that connects the clock to an MQTT broker. It publishes the state of the clock and
+//! takes commands, and announces itself to Home Assistant by MQTT discovery, see `utility::home_assistant`.
+//!
+//! The broker is configured in the optional `mqtt` section of `wifi_config.json`, without it the task ends right away:
+//!```json
+//!     "mqtt": {
+//!         "host": "192.168.1.10",
+//!         "port": 1883,
+//!         "username": "clock",
+//!         "password": "secret",
+//!         "topic_prefix": "alarmclock/bedroom"
+//!     }
+//! ```
+//! Only the host is required. The port defaults to 1883 and the topic prefix to `alarmclock/<hostname>`.
+//!
+//! # Topics
+//! The state is published retained below the topic prefix: `alarm` (`ON`/`OFF`), `alarm_state`, `next_alarm`
+//! (e.g. `Mon 06:30` or `off`), `battery_voltage`, `battery_level` (not while charging), `usb_power` (`ON`/`OFF`) and
+//! `operation_mode`. A value is only published when it changed. Commands are taken on `alarm/set` (`ON`/`OFF`),
+//! `alarm/snooze` and `alarm/stop` and sent as events to the orchestrator, like the commands of the REST API.
+//!
+//! The client speaks MQTT 3.1.1 at `QoS` 0 over plain TCP. The connection only lives while the network is up, so the
+//! connectivity policy decides when the clock is reachable. A lost connection is retried with exponential backoff.
+
+use core::{fmt::Write as _, net::SocketAddr};
+
+use defmt::{info, warn};
+use embassy_futures::select::{Either3, select3};
+use embassy_net::{Stack, dns, tcp::TcpSocket};
+use embassy_rp::clocks::RoscRng;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::{Duration, Instant, Timer, with_timeout};
+use embedded_io_async::Write;
+use embedded_nal_async::{AddrType, Dns};
+use heapless::String;
+
+use crate::{
+    error::{MqttError, SystemError},
+    event::{Event, send_event, try_send_event},
+    state::SYSTEM_STATE,
+    task::{
+        network::{HOSTNAME, MQTT_BROKER, network_stack},
+        time_updater::{RTC_MUTEX, rtc_now},
+    },
+    utility::{
+        api_router::AlarmUpdate,
+        backoff::Backoff,
+        calendar,
+        home_assistant::{AVAILABILITY, Entity, OFFLINE, ONLINE, write_discovery_payload, write_discovery_topic},
+        mqtt::{self, Connect, Packet, PacketError, Will},
+    },
+};
+
+/// The MQTT broker the clock connects to, generated from `wifi_config.json`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct MqttBroker {
+    /// The host name or address of the broker
+    pub host: &'static str,
+    /// The TCP port of the broker
+    pub port: u16,
+    /// The user name, if the broker wants one
+    pub username: Option<&'static str>,
+    /// The password
+    pub password: Option<&'static str>,
+    /// The start of all topics of the clock, without a trailing slash
+    pub topic_prefix: &'static str,
+}
+
+/// The keep alive interval announced to the broker in seconds
+const KEEP_ALIVE_SECS: u16 = 60;
+
+/// The interval of the pings, well within the keep alive interval
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Timeout for the answer to `CONNECT` and for reading and writing the socket
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Size of the socket buffers
+const SOCKET_BUFFER_SIZE: usize = 1024;
+
+/// Size of the buffers a packet is encoded in and received packets are collected in, the largest packet we send is a
+/// discovery payload
+const PACKET_BUFFER_SIZE: usize = 512;
+
+/// The packet identifier of the only subscription
+const SUBSCRIBE_PACKET_ID: u16 = 1;
+
+/// The MQTT topics are all short, the prefix is at most the hostname and a few letters
+type Topic = String<96>;
+
+/// The payload of a state, e.g. `Mon 06:30`
+type StatePayload = String<16>;
+
+/// Signal for publishing the state, the state might have changed
+static MQTT_STATE_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Signals the MQTT task that the state might have changed
+pub fn signal_mqtt_state_update() {
+    MQTT_STATE_SIGNAL.signal(());
+}
+
+/// Waits for the state to change
+async fn wait_for_mqtt_state_update() {
+    MQTT_STATE_SIGNAL.wait().await;
+}
+
+/// The state of the clock as published, one payload per entity with a state
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct StateSnapshot {
+    /// Whether the alarm is enabled
+    alarm_enabled: bool,
+    /// The state of the alarm
+    alarm_state: &'static str,
+    /// The next time the alarm rings, or `off`
+    next_alarm: StatePayload,
+    /// The voltage of the system power supply in volts
+    battery_voltage: StatePayload,
+    /// The battery level in percent, `None` while charging
+    battery_level: Option<u8>,
+    /// Whether the clock runs on usb power
+    usb_power: bool,
+    /// The operation mode
+    operation_mode: &'static str,
+}
+
+impl StateSnapshot {
+    /// Take the snapshot from the system state and the RTC, `None` if the system state is not initialized yet
+    async fn take() -> Option<Self> {
+        let now = {
+            let rtc_guard = RTC_MUTEX.lock().await;
+            rtc_guard.as_ref().and_then(rtc_now)
+        };
+
+        let system_state_guard = SYSTEM_STATE.lock().await;
+        let system_state = system_state_guard.as_ref()?;
+        let alarm_settings = &system_state.alarm_settings;
+
+        let mut next_alarm = StatePayload::new();
+        match now.and_then(|now| system_state.next_alarm(&now)) {
+            Some(alarm) => {
+                let _ = write!(
+                    next_alarm,
+                    "{} {:02}:{:02}",
+                    calendar::weekday_abbreviation(alarm.day_of_week),
+                    alarm.hour,
+                    alarm.minute
+                );
+            }
+            None => {
+                let _ = write!(next_alarm, "off");
+            }
+        }
+
+        let mut battery_voltage = StatePayload::new();
+        let _ = write!(battery_voltage, "{:.2}", system_state.power_state.get_vsys());
+
+        Some(Self {
+            alarm_enabled: alarm_settings.get_enabled(),
+            alarm_state: system_state.alarm_state.name(),
+            next_alarm,
+            battery_voltage,
+            battery_level: system_state.power_state.get_battery_level().percent(),
+            usb_power: system_state.power_state.get_usb_power(),
+            operation_mode: system_state.operation_mode.name(),
+        })
+    }
+
+    /// The payload of the state of an entity, `None` if there is nothing to publish
+    fn payload(&self, entity: Entity) -> Option<StatePayload> {
+        let mut payload = StatePayload::new();
+        let _ = match entity {
+            Entity::Alarm => write!(payload, "{}", on_off(self.alarm_enabled)),
+            Entity::AlarmState => write!(payload, "{}", self.alarm_state),
+            Entity::NextAlarm => write!(payload, "{}", self.next_alarm),
+            Entity::BatteryVoltage => write!(payload, "{}", self.battery_voltage),
+            Entity::BatteryLevel => write!(payload, "{}", self.battery_level?),
+            Entity::UsbPower => write!(payload, "{}", on_off(self.usb_power)),
+            Entity::OperationMode => write!(payload, "{}", self.operation_mode),
+            Entity::Snooze | Entity::Stop => return None,
+        };
+        Some(payload)
+    }
+}
+
+/// The payload of a switch or binary sensor
+const fn on_off(value: bool) -> &'static str {
+    if value { "ON" } else { "OFF" }
+}
+
+/// Collects the packets received from the broker
+struct Receiver {
+    /// The bytes received and not handled yet
+    buffer: [u8; PACKET_BUFFER_SIZE],
+    /// The number of bytes in the buffer
+    len: usize,
+}
+
+impl Receiver {
+    /// Create an empty receiver
+    const fn new() -> Self {
+        Self {
+            buffer: [0; PACKET_BUFFER_SIZE],
+            len: 0,
+        }
+    }
+
+    /// Read more bytes from the socket
+    async fn fill(&mut self, socket: &mut TcpSocket<'_>) -> Result<(), MqttError> {
+        if self.len == self.buffer.len() {
+            // the packet is larger than anything we subscribed to should be
+            return Err(MqttError::Packet(PacketError::BufferTooSmall));
+        }
+        match socket.read(&mut self.buffer[self.len..]).await? {
+            0 => Err(MqttError::Closed),
+            read => {
+                self.len += read;
+                Ok(())
+            }
+        }
+    }
+
+    /// The first complete packet and its length, `None` if more bytes are needed
+    fn packet(&self) -> Result<Option<(Packet<'_>, usize)>, MqttError> {
+        match mqtt::decode_packet(&self.buffer[..self.len]) {
+            Ok(decoded) => Ok(Some(decoded)),
+            Err(PacketError::Incomplete) => Ok(None),
+            Err(error) => Err(MqttError::Packet(error)),
+        }
+    }
+
+    /// Drop the first `len` bytes, after the packet they hold was handled
+    fn consume(&mut self, len: usize) {
+        self.buffer.copy_within(len..self.len, 0);
+        self.len -= len;
+    }
+}
+
+/// A connection to the broker
+struct Session<'s> {
+    /// The socket connected to the broker
+    socket: TcpSocket<'s>,
+    /// The broker
+    broker: MqttBroker,
+    /// The buffer packets are encoded in
+    packet: [u8; PACKET_BUFFER_SIZE],
+    /// The packets received from the broker
+    receiver: Receiver,
+    /// The state published last, `None` right after connecting
+    published: Option<StateSnapshot>,
+    /// Whether a ping was sent that was not answered yet
+    ping_pending: bool,
+}
+
+impl Session<'_> {
+    /// The full topic of a suffix, e.g. `alarmclock/bedroom/alarm/set`
+    fn topic(&self, suffix: &str) -> Topic {
+        let mut topic = Topic::new();
+        let _ = write!(topic, "{}/{suffix}", self.broker.topic_prefix);
+        topic
+    }
+
+    /// Send the first `len` bytes of the packet buffer
+    async fn send(&mut self, len: usize) -> Result<(), MqttError> {
+        self.socket.write_all(&self.packet[..len]).await?;
+        Ok(())
+    }
+
+    /// Publish a payload on a topic
+    async fn publish(&mut self, topic: &str, payload: &[u8], retain: bool) -> Result<(), MqttError> {
+        let len = mqtt::encode_publish(&mut self.packet, topic, payload, retain)?;
+        self.send(len).await
+    }
+
+    /// Connect to the broker with the last will `offline`, and wait until it accepted the connection
+    async fn connect(&mut self) -> Result<(), MqttError> {
+        let will_topic = self.topic(AVAILABILITY);
+        let connect = Connect {
+            client_id: HOSTNAME,
+            username: self.broker.username,
+            password: self.broker.password,
+            keep_alive_secs: KEEP_ALIVE_SECS,
+            will: Some(Will {
+                topic: &will_topic,
+                payload: OFFLINE.as_bytes(),
+                retain: true,
+            }),
+        };
+        let len = mqtt::encode_connect(&mut self.packet, &connect)?;
+        self.send(len).await?;
+
+        // the broker answers with CONNACK before anything else
+        let return_code = with_timeout(RESPONSE_TIMEOUT, async {
+            loop {
+                self.receiver.fill(&mut self.socket).await?;
+                if let Some((packet, len)) = self.receiver.packet()? {
+                    let Packet::ConnAck { return_code, .. } = packet else {
+                        return Err(MqttError::Packet(PacketError::Malformed));
+                    };
+                    self.receiver.consume(len);
+                    return Ok(return_code);
+                }
+            }
+        })
+        .await
+        .map_err(|_| MqttError::Timeout)??;
+
+        if return_code == 0 {
+            Ok(())
+        } else {
+            Err(MqttError::Refused { return_code })
+        }
+    }
+
+    /// Announce the entities to Home Assistant, go online and subscribe to the commands
+    async fn announce(&mut self) -> Result<(), MqttError> {
+        for entity in Entity::ALL {
+            let mut topic = Topic::new();
+            let _ = write_discovery_topic(&mut topic, HOSTNAME, entity);
+            let mut payload: String<PACKET_BUFFER_SIZE> = String::new();
+            write_discovery_payload(
+                &mut payload,
+                HOSTNAME,
+                self.broker.topic_prefix,
+                env!("CARGO_PKG_VERSION"),
+                entity,
+            )
+            .map_err(|_| MqttError::Packet(PacketError::BufferTooSmall))?;
+            self.publish(&topic, payload.as_bytes(), true).await?;
+        }
+
+        let availability = self.topic(AVAILABILITY);
+        self.publish(&availability, ONLINE.as_bytes(), true).await?;
+
+        let [set, snooze, stop] = [Entity::Alarm, Entity::Snooze, Entity::Stop]
+            .map(|entity| self.topic(entity.command().unwrap_or_default()));
+        let len = mqtt::encode_subscribe(
+            &mut self.packet,
+            SUBSCRIBE_PACKET_ID,
+            &[set.as_str(), snooze.as_str(), stop.as_str()],
+        )?;
+        self.send(len).await
+    }
+
+    /// Publish the states that changed since they were published last
+    async fn publish_state(&mut self) -> Result<(), MqttError> {
+        let Some(snapshot) = StateSnapshot::take().await else {
+            return Ok(());
+        };
+        if self.published.as_ref() == Some(&snapshot) {
+            return Ok(());
+        }
+
+        for entity in Entity::ALL {
+            let Some(payload) = snapshot.payload(entity) else {
+                continue;
+            };
+            if self
+                .published
+                .as_ref()
+                .and_then(|published| published.payload(entity))
+                .as_ref()
+                == Some(&payload)
+            {
+                continue;
+            }
+            let topic = self.topic(entity.object_id());
+            self.publish(&topic, payload.as_bytes(), true).await?;
+        }
+        self.published = Some(snapshot);
+        Ok(())
+    }
+
+    /// Handle the packets received from the broker
+    async fn handle_packets(&mut self) -> Result<(), MqttError> {
+        while let Some((packet, len)) = self.receiver.packet()? {
+            let event = match packet {
+                Packet::Publish { topic, payload } => self.command_event(topic, payload),
+                Packet::SubAck { refused: true, .. } => return Err(MqttError::SubscriptionRefused),
+                Packet::PingResp => {
+                    self.ping_pending = false;
+                    None
+                }
+                Packet::ConnAck { .. } | Packet::SubAck { .. } | Packet::Other { .. } => None,
+            };
+            // the packet borrows the receiver, it is dropped before the event is sent
+            self.receiver.consume(len);
+            if let Some(event) = event {
+                send_event(event).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// The event for a command received on a topic, `None` if the topic or the payload is not a command
+    fn command_event(&self, topic: &str, payload: &[u8]) -> Option<Event> {
+        let suffix = topic
+            .strip_prefix(self.broker.topic_prefix)
+            .and_then(|topic| topic.strip_prefix('/'))?;
+        let event = match Entity::from_command(suffix)? {
+            Entity::Alarm => {
+                let enabled = match payload {
+                    b"ON" => true,
+                    b"OFF" => false,
+                    _ => {
+                        warn!("MQTT: unknown payload for {}", topic);
+                        return None;
+                    }
+                };
+                Event::ChangeAlarmSettings(AlarmUpdate {
+                    enabled: Some(enabled),
+                    ..AlarmUpdate::default()
+                })
+            }
+            Entity::Snooze => Event::AlarmSnooze,
+            Entity::Stop => Event::AlarmStop,
+            _ => return None,
+        };
+        info!("MQTT command: {}", topic);
+        Some(event)
+    }
+
+    /// Publish the state when it changes, take commands and keep the connection alive, until it fails
+    async fn run(&mut self) -> Result<(), MqttError> {
+        self.publish_state().await?;
+        let mut next_ping = Instant::now() + PING_INTERVAL;
+        loop {
+            let event = select3(
+                self.receiver.fill(&mut self.socket),
+                wait_for_mqtt_state_update(),
+                Timer::at(next_ping),
+            )
+            .await;
+            match event {
+                Either3::First(filled) => {
+                    filled?;
+                    self.handle_packets().await?;
+                }
+                Either3::Second(()) => self.publish_state().await?,
+                Either3::Third(()) => {
+                    if self.ping_pending {
+                        return Err(MqttError::Timeout);
+                    }
+                    let len = mqtt::encode_pingreq(&mut self.packet)?;
+                    self.send(len).await?;
+                    self.ping_pending = true;
+                    next_ping = Instant::now() + PING_INTERVAL;
+                }
+            }
+        }
+    }
+}
+
+/// Resolve the address of the broker, the host is either an IP address or a host name
+async fn resolve(stack: Stack<'static>, broker: &MqttBroker) -> Result<SocketAddr, MqttError> {
+    let address = match broker.host.parse() {
+        Ok(address) => address,
+        Err(_) => dns::DnsSocket::new(stack)
+            .get_host_by_name(broker.host, AddrType::IPv4)
+            .await
+            .map_err(MqttError::Dns)?,
+    };
+    Ok(SocketAddr::new(address, broker.port))
+}
+
+/// Connect to the broker and run the session until the connection fails
+async fn run_session(
+    stack: Stack<'static>,
+    broker: MqttBroker,
+    rx_buffer: &mut [u8],
+    tx_buffer: &mut [u8],
+    backoff: &mut Backoff,
+) -> Result<(), MqttError> {
+    let address = resolve(stack, &broker).await?;
+
+    // the timeout only applies to data in flight, an idle connection is kept up by the pings
+    let mut socket = TcpSocket::new(stack, rx_buffer, tx_buffer);
+    socket.set_timeout(Some(RESPONSE_TIMEOUT));
+    socket.connect(address).await.map_err(MqttError::Connect)?;
+
+    let mut session = Session {
+        socket,
+        broker,
+        packet: [0; PACKET_BUFFER_SIZE],
+        receiver: Receiver::new(),
+        published: None,
+        ping_pending: false,
+    };
+    let result = async {
+        session.connect().await?;
+        info!("MQTT connected to {}", broker.host);
+        backoff.reset();
+        session.announce().await?;
+        session.run().await
+    }
+    .await;
+
+    // say goodbye, the broker then drops the last will
+    if let Ok(len) = mqtt::encode_disconnect(&mut session.packet) {
+        let _ = with_timeout(RESPONSE_TIMEOUT, session.send(len)).await;
+    }
+    session.socket.close();
+    let _ = with_timeout(RESPONSE_TIMEOUT, session.socket.flush()).await;
+    result
+}
+
+/// This task keeps the connection to the MQTT broker while the network is up, if a broker is configured.
+#[embassy_executor::task]
+pub async fn mqtt_task() {
+    let Some(broker) = MQTT_BROKER else {
+        info!("MQTT not configured, MQTT task ending");
+        return;
+    };
+    info!("MQTT task starting");
+    let stack = network_stack().await;
+
+    let mut rx_buffer = [0; SOCKET_BUFFER_SIZE];
+    let mut tx_buffer = [0; SOCKET_BUFFER_SIZE];
+    let mut backoff = Backoff::new();
+
+    loop {
+        stack.wait_config_up().await;
+
+        let Err(error) = run_session(stack, broker, &mut rx_buffer, &mut tx_buffer, &mut backoff).await else {
+            continue;
+        };
+        if !stack.is_config_up() {
+            // the network went down, e.g. released by the connectivity policy, this is not a failure of MQTT
+            info!("MQTT disconnected, network down");
+            continue;
+        }
+
+        warn!("MQTT connection failed: {:?}", error);
+        let delay_secs = backoff.record_failure(error.failure_kind(), RoscRng.next_u32());
+        try_send_event(Event::Error(SystemError::Mqtt(error)));
+        Timer::after(Duration::from_secs(delay_secs)).await;
+    }
+}
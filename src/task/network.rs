@@ -0,0 +1,591 @@
+//! # Network Task
+//! This module owns the `WiFi` chip and the network stack. Other tasks do not join the network themselves,
+//! they borrow the connection from the network task with `acquire_network` and hand it back with `release_network`.
+//!
+//! # Connectivity policy
+//! When the network is up is decided by the connectivity policy, set in `wifi_config.json` next to the credentials:
+//!```json
+//!  {
+//!     "ssid": "some_ssid_here",
+//!     "password": "some_password_here",
+//!     "connectivity": "usb_power"
+//! }
+//! ```
+//! - `on_demand` (default): `WiFi` is only up while a task holds the connection, e.g. while the time is fetched.
+//!   This is the right choice on battery.
+//! - `usb_power`: the connection is kept up while the clock runs on USB power, and on demand on battery.
+//! - `always`: the connection is kept up all the time.
+//!
+//! # Addresses and hostname
+//! By default the address is taken from DHCP, announcing the hostname `alarmclck`. Several clocks on one network
+//! are told apart by their hostname, and a static address, gateway and up to three DNS servers replace DHCP.
+//! IPv6 addresses can be obtained by SLAAC in addition to IPv4. All of it is optional, in the `network` section:
+//!```json
+//!     "network": {
+//!         "hostname": "alarmclock-bedroom",
+//!         "ipv4": {
+//!             "address": "192.168.1.50/24",
+//!             "gateway": "192.168.1.1",
+//!             "dns": ["192.168.1.1"]
+//!         },
+//!         "ipv6": "slaac"
+//!     }
+//! ```
+//! The active configuration is shown on the network info page. While connected, the clock is also reachable as
+//! `<hostname>.local`, see the mDNS task.
+//!
+//! # Supervision
+//! While the connection is kept up, the network task supervises it: it checks link and DHCP state periodically
+//! and reconnects with exponential backoff when it dropped. Every change of the connectivity is reported as
+//! `Event::NetworkConnected` or `Event::NetworkDisconnected`.
+//!
+//! # Credentials
+//! The credentials can be replaced without flashing, with `wifi set` on the console. They are stored in flash and
+//! take precedence over those of `wifi_config.json` until a factory reset. The connection is joined again with the
+//! new credentials right away if it is up.
+
+include!(concat!(env!("OUT_DIR"), "/wifi_secrets.rs"));
+
+use cyw43::{JoinOptions, PowerManagementMode};
+use cyw43_pio::{DEFAULT_CLOCK_DIVIDER, PioSpi};
+use defmt::{Format, info, unwrap, warn};
+use embassy_executor::Spawner;
+use embassy_futures::select::{Either3, select3};
+use embassy_net::{
+    Config, ConfigV4, ConfigV6, DhcpConfig, Ipv4Address, Ipv4Cidr, Stack, StackResources, StaticConfigV4,
+};
+use embassy_rp::{
+    Peri,
+    clocks::RoscRng,
+    gpio::{Level, Output},
+    peripherals::{self, DMA_CH0, PIO0},
+    pio::Pio,
+};
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel, mutex::Mutex, once_lock::OnceLock, signal::Signal,
+};
+use embassy_time::{Duration, Instant, Timer, with_timeout};
+use static_cell::StaticCell;
+
+use crate::{
+    Irqs,
+    error::{ConnectError, NetworkError, SystemError, WifiError},
+    event::{Event, send_event, try_send_event},
+    state::NetworkInfo,
+    utility::{backoff::Backoff, console::WifiCredentials},
+    warn_remote,
+};
+
+/// When the network connection is kept up
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Format)]
+pub enum ConnectivityPolicy {
+    /// Only while a task holds the connection
+    OnDemand,
+    /// While the system runs on USB power, on demand otherwise
+    WhileOnUsbPower,
+    /// All the time
+    Always,
+}
+
+impl ConnectivityPolicy {
+    /// Check if the connection is kept up, even if no task holds it
+    const fn keep_connected(self, usb_power: bool) -> bool {
+        match self {
+            Self::OnDemand => false,
+            Self::WhileOnUsbPower => usb_power,
+            Self::Always => true,
+        }
+    }
+}
+
+/// Static IPv4 configuration, replacing DHCP
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct StaticIpv4 {
+    /// The address of the clock
+    pub address: [u8; 4],
+    /// The prefix length of the network, e.g. 24 for a netmask of 255.255.255.0
+    pub prefix_len: u8,
+    /// The default gateway
+    pub gateway: Option<[u8; 4]>,
+    /// Up to three DNS servers
+    pub dns_servers: &'static [[u8; 4]],
+}
+
+/// Requests to the network task
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Format)]
+enum NetworkRequest {
+    /// A task needs the connection
+    Acquire,
+    /// A task no longer needs the connection
+    Release,
+    /// The credentials changed, the connection must be joined again if it is up
+    Rejoin,
+}
+
+/// Channel for requests to the network task
+static NETWORK_REQUEST_CHANNEL: Channel<CriticalSectionRawMutex, NetworkRequest, 4> = Channel::new();
+
+/// Signal for the answer to an acquire request: the stack, or why the connection could not be established
+static NETWORK_ACQUIRED_SIGNAL: Signal<CriticalSectionRawMutex, Result<Stack<'static>, ConnectError>> = Signal::new();
+
+/// Mutex serializing acquire requests, so that every task gets the answer to its own request
+static NETWORK_ACQUIRE_MUTEX: Mutex<CriticalSectionRawMutex, ()> = Mutex::new(());
+
+/// Signal for the usb power state, the data is whether the system runs on usb power
+static NETWORK_USB_POWER_SIGNAL: Signal<CriticalSectionRawMutex, bool> = Signal::new();
+
+/// Signals the network task that the usb power state has changed
+pub fn signal_network_usb_power(usb_power: bool) {
+    NETWORK_USB_POWER_SIGNAL.signal(usb_power);
+}
+
+/// Waits for the usb power signal
+async fn wait_for_network_usb_power() -> bool {
+    NETWORK_USB_POWER_SIGNAL.wait().await
+}
+
+/// The credentials set on the console, `None` to use those of `wifi_config.json`
+static WIFI_CREDENTIALS: Mutex<CriticalSectionRawMutex, Option<WifiCredentials>> = Mutex::new(None);
+
+/// Set once the credentials were read from flash, joining waits for it so that it does not use outdated ones
+static WIFI_CREDENTIALS_READ: OnceLock<()> = OnceLock::new();
+
+/// Set the credentials to join with, `None` to use those of `wifi_config.json`.
+/// Takes effect on the next join, see `request_rejoin`.
+pub async fn set_wifi_credentials(credentials: Option<WifiCredentials>) {
+    *WIFI_CREDENTIALS.lock().await = credentials;
+    // only the first call matters, the credentials are read from flash then
+    let _ = WIFI_CREDENTIALS_READ.init(());
+}
+
+/// Ask the network task to join the network again with the current credentials, if the connection is up.
+/// Does not wait, the request is dropped if the network task is busy, the credentials are used on the next join then.
+pub fn request_rejoin() {
+    if NETWORK_REQUEST_CHANNEL.try_send(NetworkRequest::Rejoin).is_err() {
+        warn!("Network task busy, not joining again");
+    }
+}
+
+/// The credentials set on the console, `None` to use those of `wifi_config.json`. Waits until they were read from
+/// flash.
+async fn stored_wifi_credentials() -> Option<WifiCredentials> {
+    WIFI_CREDENTIALS_READ.get().await;
+    WIFI_CREDENTIALS.lock().await.clone()
+}
+
+/// The network stack, set once the `WiFi` chip is initialized
+static NETWORK_STACK: OnceLock<Stack<'static>> = OnceLock::new();
+
+/// Get the network stack, waiting until it is initialized.
+/// This is for servers that accept connections whenever the network happens to be up, without holding it.
+pub async fn network_stack() -> Stack<'static> {
+    *NETWORK_STACK.get().await
+}
+
+/// Get the network connection, joining the `WiFi` network if it is not up yet.
+/// Every successful call must be followed by `release_network` once the connection is no longer needed.
+#[allow(clippy::significant_drop_tightening)]
+pub async fn acquire_network() -> Result<Stack<'static>, ConnectError> {
+    let _guard = NETWORK_ACQUIRE_MUTEX.lock().await;
+    NETWORK_REQUEST_CHANNEL.send(NetworkRequest::Acquire).await;
+    NETWORK_ACQUIRED_SIGNAL.wait().await
+}
+
+/// Hand back the network connection. Depending on the connectivity policy the network task leaves the network.
+pub async fn release_network() {
+    NETWORK_REQUEST_CHANNEL.send(NetworkRequest::Release).await;
+}
+
+/// `WiFi` peripheral resources needed for the network task
+pub struct WifiPeripherals {
+    /// Power pin for `WiFi` module
+    pub pwr_pin: Peri<'static, peripherals::PIN_23>,
+    /// Chip select pin for `WiFi` module
+    pub cs_pin: Peri<'static, peripherals::PIN_25>,
+    /// `PIO` peripheral for `WiFi` communication
+    pub pio: Peri<'static, peripherals::PIO0>,
+    /// Data I/O pin for `WiFi` module
+    pub dio_pin: Peri<'static, peripherals::PIN_24>,
+    /// Clock pin for `WiFi` module
+    pub clk_pin: Peri<'static, peripherals::PIN_29>,
+    /// `DMA` channel for `WiFi` communication
+    pub dma_ch: Peri<'static, peripherals::DMA_CH0>,
+}
+
+/// Static cell for `CYW43` `WiFi` state.
+static WIFI_STATE: StaticCell<cyw43::State> = StaticCell::new();
+
+/// Number of sockets of the network stack: DHCP, DNS, the time updater, the API server, MQTT, mDNS, syslog, webhooks
+/// and peer sync, with room to spare
+const NETWORK_SOCKETS: usize = 10;
+
+/// Static cell for network stack resources.
+static NETWORK_RESOURCES: StaticCell<StackResources<NETWORK_SOCKETS>> = StaticCell::new();
+
+/// Timeout for joining the `WiFi` network
+const JOIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often the link and DHCP state are checked while the connection is kept up
+const SUPERVISION_INTERVAL: Duration = Duration::from_secs(30);
+
+/// `WiFi` driver task that runs the `CYW43` firmware.
+#[embassy_executor::task]
+async fn wifi_task(runner: cyw43::Runner<'static, Output<'static>, PioSpi<'static, PIO0, 0, DMA_CH0>>) -> ! {
+    runner.run().await
+}
+
+/// Network stack task that handles TCP/IP networking.
+#[embassy_executor::task]
+async fn net_task(mut runner: embassy_net::Runner<'static, cyw43::NetDriver<'static>>) -> ! {
+    runner.run().await
+}
+
+/// Initialize `WiFi` hardware and return the control handle and network device.
+async fn setup_wifi(
+    spawner: &Spawner,
+    wifi_peripherals: WifiPeripherals,
+) -> (cyw43::Control<'static>, cyw43::NetDriver<'static>) {
+    info!("init wifi");
+    let pwr = Output::new(wifi_peripherals.pwr_pin, Level::Low);
+    let cs = Output::new(wifi_peripherals.cs_pin, Level::High);
+    let mut pio = Pio::new(wifi_peripherals.pio, Irqs);
+    let spi = PioSpi::new(
+        &mut pio.common,
+        pio.sm0,
+        DEFAULT_CLOCK_DIVIDER,
+        pio.irq0,
+        cs,
+        wifi_peripherals.dio_pin,
+        wifi_peripherals.clk_pin,
+        wifi_peripherals.dma_ch,
+    );
+
+    let fw = include_bytes!("../wifi-firmware/cyw43-firmware/43439A0.bin");
+    let clm = include_bytes!("../wifi-firmware/cyw43-firmware/43439A0_clm.bin");
+
+    let state = WIFI_STATE.init(cyw43::State::new());
+
+    let (net_device, mut control, runner) = cyw43::new(state, pwr, spi, fw).await;
+    spawner.spawn(unwrap!(wifi_task(runner)));
+
+    info!("init control");
+    control.init(clm).await;
+    control.set_power_management(PowerManagementMode::Aggressive).await;
+
+    (control, net_device)
+}
+
+/// The configuration of the network stack: DHCP or a static address, and IPv6 by SLAAC if enabled.
+fn stack_config() -> Config {
+    let ipv4 = if let Some(static_ipv4) = STATIC_IPV4 {
+        let mut config = StaticConfigV4 {
+            address: Ipv4Cidr::new(Ipv4Address::from(static_ipv4.address), static_ipv4.prefix_len),
+            gateway: static_ipv4.gateway.map(Ipv4Address::from),
+            dns_servers: Default::default(),
+        };
+        for server in static_ipv4.dns_servers {
+            // build.rs allows no more servers than there is room for
+            let _ = config.dns_servers.push(Ipv4Address::from(*server));
+        }
+        ConfigV4::Static(config)
+    } else {
+        let mut dhcp_config = DhcpConfig::default();
+        // build.rs checked the length of the hostname, so this won't fail
+        dhcp_config.hostname = HOSTNAME.try_into().ok();
+        ConfigV4::Dhcp(dhcp_config)
+    };
+    let ipv6 = if IPV6_SLAAC { ConfigV6::Slaac } else { ConfigV6::None };
+    Config { ipv4, ipv6 }
+}
+
+/// Setup network stack with the configuration from `wifi_config.json`.
+fn setup_network_stack(spawner: &Spawner, net_device: cyw43::NetDriver<'static>, seed: u64) -> Stack<'static> {
+    let (stack, runner) = embassy_net::new(
+        net_device,
+        stack_config(),
+        NETWORK_RESOURCES.init(StackResources::<NETWORK_SOCKETS>::new()),
+        seed,
+    );
+    spawner.spawn(unwrap!(net_task(runner)));
+    stack
+}
+
+/// Connect to `WiFi` network with timeout handling.
+async fn connect_to_wifi(control: &mut cyw43::Control<'static>, ssid: &str, password: &str) -> Result<(), WifiError> {
+    let join_result = with_timeout(JOIN_TIMEOUT, control.join(ssid, JoinOptions::new(password.as_bytes()))).await;
+
+    match join_result {
+        Ok(Ok(())) => {
+            control.gpio_set(0, true).await;
+            info!("Connected to wifi");
+            Ok(())
+        }
+        Ok(Err(e)) => {
+            warn!("Error connecting to wifi, status {:?}", e.status);
+            Err(WifiError::Join { status: e.status })
+        }
+        Err(_) => {
+            warn!("Timeout while trying to connect to wifi");
+            Err(WifiError::JoinTimeout)
+        }
+    }
+}
+
+/// Wait for network to be ready (DHCP and link up).
+async fn wait_for_network_ready(stack: Stack<'static>) -> Result<(), NetworkError> {
+    // Wait for DHCP
+    let mut timeout_counter = 0;
+    while !stack.is_config_up() {
+        Timer::after_millis(100).await;
+        timeout_counter += 1;
+        if timeout_counter > 100 {
+            warn!("DHCP timeout");
+            return Err(NetworkError::DhcpTimeout);
+        }
+    }
+
+    // Wait for link
+    timeout_counter = 0;
+    while !stack.is_link_up() {
+        Timer::after_millis(500).await;
+        timeout_counter += 1;
+        if timeout_counter > 100 {
+            warn!("Link timeout");
+            return Err(NetworkError::LinkTimeout);
+        }
+    }
+
+    stack.wait_config_up().await;
+    Ok(())
+}
+
+/// Disconnect from `WiFi` and turn off `LED`.
+async fn disconnect_wifi(control: &mut cyw43::Control<'static>) {
+    control.leave().await;
+    control.gpio_set(0, false).await;
+    info!("Disconnected from wifi");
+}
+
+/// State of the network task
+struct NetworkSupervisor {
+    /// Control handle of the `WiFi` chip
+    control: cyw43::Control<'static>,
+    /// The network stack
+    stack: Stack<'static>,
+    /// When the connection is kept up
+    policy: ConnectivityPolicy,
+    /// The system runs on usb power
+    usb_power: bool,
+    /// Number of tasks holding the connection
+    holders: u8,
+    /// The network is joined and configured
+    connected: bool,
+    /// Exponential backoff for reconnecting while the connection is kept up
+    backoff: Backoff,
+    /// When to try to reconnect next, `None` if no reconnect is due
+    reconnect_at: Option<Instant>,
+}
+
+impl NetworkSupervisor {
+    /// Create a new `NetworkSupervisor`, not connected yet
+    const fn new(control: cyw43::Control<'static>, stack: Stack<'static>, policy: ConnectivityPolicy) -> Self {
+        Self {
+            control,
+            stack,
+            policy,
+            usb_power: false,
+            holders: 0,
+            connected: false,
+            backoff: Backoff::new(),
+            reconnect_at: None,
+        }
+    }
+
+    /// Check if the connection is kept up, even if no task holds it
+    const fn keep_connected(&self) -> bool {
+        self.policy.keep_connected(self.usb_power)
+    }
+
+    /// Join the network and wait for it to get ready, if not connected yet
+    async fn connect(&mut self) -> Result<(), ConnectError> {
+        if self.connected {
+            return Ok(());
+        }
+
+        // Set performance mode for connection
+        self.control
+            .set_power_management(PowerManagementMode::Performance)
+            .await;
+        let stored_credentials = stored_wifi_credentials().await;
+        let (ssid, password) = stored_credentials.as_ref().map_or((SSID, PASSWORD), |credentials| {
+            (credentials.ssid.as_str(), credentials.password.as_str())
+        });
+        let result = match connect_to_wifi(&mut self.control, ssid, password).await {
+            Ok(()) => wait_for_network_ready(self.stack).await.map_err(ConnectError::from),
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = result {
+            disconnect_wifi(&mut self.control).await;
+            self.control.set_power_management(PowerManagementMode::Aggressive).await;
+            return Err(e);
+        }
+
+        // a connection that is kept up must not drain the battery, but stay reachable
+        if self.keep_connected() {
+            self.control.set_power_management(PowerManagementMode::PowerSave).await;
+        }
+        self.connected = true;
+        self.backoff.reset();
+        self.reconnect_at = None;
+        let rssi_dbm = self.control.get_rssi().await;
+        send_event(Event::NetworkConnected(self.network_info(ssid, rssi_dbm))).await;
+        Ok(())
+    }
+
+    /// The active network configuration, as it was configured or received from DHCP
+    fn network_info(&self, ssid: &str, rssi_dbm: i32) -> NetworkInfo {
+        let ipv4 = self.stack.config_v4();
+        NetworkInfo {
+            // the network was joined, so the SSID is no longer than 32 bytes
+            ssid: ssid.try_into().unwrap_or_default(),
+            rssi_dbm,
+            hostname: HOSTNAME,
+            static_ipv4: STATIC_IPV4.is_some(),
+            address: ipv4
+                .as_ref()
+                .map(|config| (config.address.address().octets(), config.address.prefix_len())),
+            gateway: ipv4
+                .as_ref()
+                .and_then(|config| config.gateway)
+                .map(|gateway| gateway.octets()),
+            dns_server: ipv4
+                .as_ref()
+                .and_then(|config| config.dns_servers.first())
+                .map(Ipv4Address::octets),
+            ipv6_slaac: IPV6_SLAAC,
+        }
+    }
+
+    /// Leave the network, if connected
+    async fn disconnect(&mut self) {
+        if !self.connected {
+            return;
+        }
+        disconnect_wifi(&mut self.control).await;
+        self.control.set_power_management(PowerManagementMode::Aggressive).await;
+        self.connected = false;
+        send_event(Event::NetworkDisconnected).await;
+    }
+
+    /// Leave the network if neither the policy nor a task needs it anymore
+    async fn disconnect_if_unneeded(&mut self) {
+        if self.holders == 0 && !self.keep_connected() {
+            self.reconnect_at = None;
+            self.disconnect().await;
+        }
+    }
+
+    /// Connect because the policy keeps the connection up, scheduling a reconnect with backoff if it fails
+    async fn reconnect(&mut self) {
+        if let Err(error) = self.connect().await {
+            let delay_secs = self.backoff.record_failure(error.failure_kind(), RoscRng.next_u32());
+            warn!("Reconnecting failed: {:?}, retrying in {:?} seconds", error, delay_secs);
+            try_send_event(Event::Error(SystemError::Network(error)));
+            self.reconnect_at = Some(Instant::now() + Duration::from_secs(delay_secs));
+        }
+    }
+
+    /// Check that the link and DHCP are still up, and reconnect if the connection dropped.
+    /// While connected the signal strength is measured again, it changes while the connection is kept up.
+    async fn supervise(&mut self) {
+        if self.connected && (!self.stack.is_link_up() || !self.stack.is_config_up()) {
+            warn_remote!("Network connection lost");
+            self.disconnect().await;
+        }
+        if self.connected {
+            let rssi_dbm = self.control.get_rssi().await;
+            send_event(Event::WifiSignal(rssi_dbm)).await;
+        }
+        if !self.connected && self.keep_connected() {
+            self.reconnect().await;
+        }
+    }
+
+    /// Handle a request of another task
+    async fn handle_request(&mut self, request: NetworkRequest) {
+        match request {
+            NetworkRequest::Acquire => {
+                let result = self.connect().await.map(|()| self.stack);
+                if result.is_ok() {
+                    self.holders = self.holders.saturating_add(1);
+                }
+                NETWORK_ACQUIRED_SIGNAL.signal(result);
+            }
+            NetworkRequest::Release => {
+                self.holders = self.holders.saturating_sub(1);
+                self.disconnect_if_unneeded().await;
+            }
+            NetworkRequest::Rejoin => {
+                if self.connected {
+                    self.disconnect().await;
+                    self.reconnect().await;
+                }
+            }
+        }
+    }
+
+    /// Handle a change of the usb power state
+    async fn handle_usb_power(&mut self, usb_power: bool) {
+        self.usb_power = usb_power;
+        if self.keep_connected() {
+            if self.connected {
+                self.control.set_power_management(PowerManagementMode::PowerSave).await;
+            } else {
+                self.reconnect().await;
+            }
+        } else {
+            self.disconnect_if_unneeded().await;
+        }
+    }
+
+    /// When the supervisor has to act next without being asked
+    fn next_check(&self) -> Option<Instant> {
+        if self.connected {
+            self.keep_connected().then(|| Instant::now() + SUPERVISION_INTERVAL)
+        } else {
+            self.reconnect_at
+        }
+    }
+}
+
+/// Network task that owns the `WiFi` chip, connects on request of other tasks, and keeps the connection up
+/// as the connectivity policy demands.
+#[embassy_executor::task]
+pub async fn network_task(spawner: Spawner, wifi_peripherals: WifiPeripherals) {
+    info!("network task started, connectivity policy {:?}", CONNECTIVITY_POLICY);
+
+    // Initialize WiFi and network stack
+    let (control, net_device) = setup_wifi(&spawner, wifi_peripherals).await;
+    let stack = setup_network_stack(&spawner, net_device, RoscRng.next_u64());
+    // the stack is only set here, so this cannot fail
+    let _ = NETWORK_STACK.init(stack);
+
+    let mut supervisor = NetworkSupervisor::new(control, stack, CONNECTIVITY_POLICY);
+    // the policy may want the connection up right away, e.g. `always`
+    supervisor.supervise().await;
+
+    loop {
+        let next_check = Timer::at(supervisor.next_check().unwrap_or(Instant::MAX));
+        match select3(
+            NETWORK_REQUEST_CHANNEL.receive(),
+            wait_for_network_usb_power(),
+            next_check,
+        )
+        .await
+        {
+            Either3::First(request) => supervisor.handle_request(request).await,
+            Either3::Second(usb_power) => supervisor.handle_usb_power(usb_power).await,
+            Either3::Third(()) => supervisor.supervise().await,
+        }
+    }
+}
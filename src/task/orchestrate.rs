@@ -1,24 +1,38 @@
 //! # Orchestrate Tasks
 //! Task to orchestrate the state transitions of the system.
 use defmt::{Debug2Format, info, warn};
-use embassy_futures::select::select;
-use embassy_rp::rtc::{DateTime, DayOfWeek};
+use embassy_futures::select::{Either, select};
 use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
 use embassy_time::{Duration, Ticker, Timer};
 
 use crate::{
+    error::SystemError,
     event::{Event, receive_event, send_event},
-    state::{AlarmState, OperationMode, SYSTEM_STATE, SystemState},
+    state::{AlarmState, NetworkInfoPage, OperationMode, SYSTEM_STATE, SystemState, TimeSource, TimeSyncReport},
+    syslog,
     task::{
-        alarm_settings::send_flash_write_command,
+        alarm_settings::{
+            send_display_settings_write_command, send_flash_erase_command, send_flash_write_command,
+            send_wifi_credentials_write_command,
+        },
         alarm_trigger::{signal_alarm_schedule_disable, signal_alarm_schedule_update},
         buttons::Button,
-        display::signal_display_update,
+        display::{NIGHT_SCHEDULE, is_display_asleep, signal_display_update, signal_display_wake},
         light_effects::{signal_lightfx_start, signal_lightfx_stop},
+        mqtt::signal_mqtt_state_update,
+        network::{request_rejoin, set_wifi_credentials, signal_network_usb_power},
+        peer_sync::publish_alarm_settings,
         power::signal_vsys_wake,
         sound::{signal_sound_start, signal_sound_stop},
-        time_updater::{RTC_MUTEX, signal_time_updater_resume, signal_time_updater_suspend},
-        watchdog::{TaskId, report_task_success},
+        time_updater::{RTC_MUTEX, signal_time_updater_resume, signal_time_updater_suspend, update_rtc_with_time},
+        watchdog::{TaskId, report_task_success, request_reset},
+        webhook::queue_webhooks,
+    },
+    utility::{
+        api_router::{AlarmUpdate, DisplayUpdate},
+        calendar::{self, DateTime, DayOfWeek},
+        syslog::Severity,
+        webhook::WebhookEvent,
     },
 };
 
@@ -31,8 +45,17 @@ static SCHEDULER_START_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new
 /// Signal for waking the scheduler early
 static SCHEDULER_WAKE_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
 
-/// Signal for the alarm expiry command
-static ALARM_EXPIRER_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+/// Signal for the alarm expiry command, `true` starts the expiry time and `false` cancels it
+static ALARM_EXPIRER_SIGNAL: Signal<CriticalSectionRawMutex, bool> = Signal::new();
+
+/// Signal for the alarm snooze command, `true` starts the snooze time and `false` cancels it
+static ALARM_SNOOZE_SIGNAL: Signal<CriticalSectionRawMutex, bool> = Signal::new();
+
+/// The time after which a snoozed alarm is raised again
+const SNOOZE_DURATION: Duration = Duration::from_secs(9 * 60);
+
+/// The time the alarm sound rings before the alarm expires, counted from the end of the sunrise
+const ALARM_EXPIRY_DURATION: Duration = Duration::from_secs(5 * 60);
 
 /// Signals the scheduler to stop
 pub fn signal_scheduler_stop() {
@@ -51,7 +74,22 @@ pub fn signal_scheduler_wake() {
 
 /// Signals the alarm expirer to start
 fn signal_alarm_expirer() {
-    ALARM_EXPIRER_SIGNAL.signal(());
+    ALARM_EXPIRER_SIGNAL.signal(true);
+}
+
+/// Signals the alarm expirer to cancel, a snoozed alarm must not be stopped for good
+fn signal_alarm_expirer_cancel() {
+    ALARM_EXPIRER_SIGNAL.signal(false);
+}
+
+/// Signals the alarm snoozer to start the snooze time
+fn signal_alarm_snooze_start() {
+    ALARM_SNOOZE_SIGNAL.signal(true);
+}
+
+/// Signals the alarm snoozer to cancel a running snooze time
+fn signal_alarm_snooze_cancel() {
+    ALARM_SNOOZE_SIGNAL.signal(false);
 }
 
 /// This task is responsible for the state transitions of the system. It acts as the main task of the system.
@@ -68,6 +106,7 @@ pub async fn orchestrator() {
     loop {
         // receive the events, halting the task until an event is received
         let event = receive_event().await;
+        syslog_event(&event);
 
         // Lock the mutex to get a mutable reference to the system state
         let mut system_state_guard = SYSTEM_STATE.lock().await;
@@ -79,6 +118,9 @@ pub async fn orchestrator() {
         // react to the events
         handle_event(event, system_state).await;
 
+        // the state might have changed, MQTT publishes what did
+        signal_mqtt_state_update();
+
         // Report successful event handling to watchdog
         report_task_success(TaskId::Orchestrator).await;
 
@@ -86,8 +128,27 @@ pub async fn orchestrator() {
     }
 }
 
+/// Forward an event to the syslog collector, subsystem errors with severity error.
+/// The periodic ones, the scheduler ticks and the measurements, are left out.
+fn syslog_event(event: &Event) {
+    match event {
+        Event::Scheduler(_) | Event::Vsys(_) | Event::WifiSignal(_) => {}
+        Event::Error(error) => syslog!(Severity::Error, "error", "{:?}", error),
+        event => syslog!(Severity::Informational, "event", "{:?}", event),
+    }
+}
+
 /// Handles a single event by updating the system state and signaling appropriate tasks.
 async fn handle_event(event: Event, system_state: &mut SystemState) {
+    if matches!(event, Event::BlueBtn | Event::GreenBtn | Event::YellowBtn) {
+        // at night the first press only wakes the display, the following ones keep it awake
+        let asleep = is_display_asleep();
+        signal_display_wake();
+        if asleep {
+            info!("Button pressed while the display sleeps, waking it");
+            return;
+        }
+    }
     match event {
         Event::BlueBtn => {
             handle_blue_button_press(system_state).await;
@@ -104,6 +165,7 @@ async fn handle_event(event: Event, system_state: &mut SystemState) {
         Event::Vbus(usb) => {
             info!("Vbus event, usb: {}", usb);
             system_state.power_state.set_usb_power(usb);
+            signal_network_usb_power(usb);
             if !system_state.power_state.get_usb_power() {
                 signal_vsys_wake();
             }
@@ -123,13 +185,51 @@ async fn handle_event(event: Event, system_state: &mut SystemState) {
             info!("Scheduler event");
             handle_scheduler_event(system_state, hour, minute, second);
         }
-        Event::RtcUpdated => {
-            info!("RTC updated event");
+        Event::RtcUpdated(report) => {
+            info!("RTC updated event: {:?}", report);
+            system_state.time_sync_status.record_success(report);
             signal_display_update();
         }
         Event::AlarmSettingsNeedUpdate => {
             info!("Alarm settings must be updated event");
             handle_alarm_settings_update(system_state).await;
+            publish_alarm_settings(&system_state.alarm_settings);
+        }
+        Event::ChangeAlarmSettings(update) => {
+            info!("Change alarm settings event: {:?}", update);
+            handle_change_alarm_settings_event(system_state, &update).await;
+        }
+        Event::DisplaySettingsReadFromFlash(display_settings) => {
+            info!("Display settings read from flash: {:?}", display_settings);
+            system_state.display_settings = display_settings;
+            signal_display_update();
+        }
+        Event::ChangeDisplaySettings(update) => {
+            info!("Change display settings event: {:?}", update);
+            handle_change_display_settings_event(system_state, &update).await;
+        }
+        Event::PeerAlarmSettings(update) => {
+            info!("Peer alarm settings event: {:?}", update);
+            handle_peer_alarm_settings_event(system_state, &update).await;
+        }
+        Event::SetTime(datetime) => {
+            info!("Set time event: {:?}", datetime);
+            handle_set_time_event(system_state, datetime).await;
+        }
+        Event::ChangeWifiCredentials(credentials) => {
+            info!("Change WiFi credentials event: {:?}", credentials);
+            send_wifi_credentials_write_command(credentials.clone()).await;
+            set_wifi_credentials(Some(credentials)).await;
+            request_rejoin();
+        }
+        Event::Reboot => {
+            info!("Reboot event");
+            request_reset();
+        }
+        Event::FactoryReset => {
+            info!("Factory reset event");
+            // the flash task restarts the system once the flash is erased
+            send_flash_erase_command().await;
         }
         Event::Standby => {
             handle_standby_event();
@@ -143,14 +243,56 @@ async fn handle_event(event: Event, system_state: &mut SystemState) {
         Event::AlarmStop => {
             handle_alarm_stop_event(system_state);
         }
+        Event::AlarmExpired => {
+            handle_alarm_expired_event(system_state);
+        }
+        Event::AlarmSnooze => {
+            handle_alarm_snooze_event(system_state);
+        }
         Event::SunriseEffectFinished => {
             handle_sunrise_effect_finished_event(system_state);
         }
+        Event::NetworkConnected(network_info) => {
+            info!("Network connected event: {:?}", network_info);
+            system_state.network_state.set_connected(network_info);
+            signal_display_update();
+        }
+        Event::NetworkDisconnected => {
+            info!("Network disconnected event");
+            system_state.network_state.set_disconnected();
+            signal_display_update();
+        }
+        Event::WifiSignal(rssi_dbm) => {
+            info!("WiFi signal event, rssi: {} dBm", rssi_dbm);
+            system_state.network_state.set_rssi(rssi_dbm);
+        }
+        Event::ForecastUpdated(forecast) => {
+            info!("Forecast updated event: {:?}", forecast);
+            system_state.forecast = Some(forecast);
+            signal_display_update();
+        }
+        Event::CalendarUpdated(alarm_plan) => {
+            info!("Calendar updated event: {:?}", alarm_plan);
+            system_state.alarm_plan = Some(alarm_plan);
+            signal_alarm_schedule_update();
+        }
+        Event::Error(error) => {
+            warn!("Subsystem error: {:?}", error);
+            if matches!(error, SystemError::TimeSync(_)) {
+                system_state.time_sync_status.record_failure();
+            }
+            system_state.last_errors.record(error);
+        }
     }
 }
 
-/// Handles the scheduler event which updates display and light effects.
-fn handle_scheduler_event(system_state: &SystemState, hour: u8, minute: u8, second: u8) {
+/// Handles the scheduler event which updates the night, the display and the light effects.
+fn handle_scheduler_event(system_state: &mut SystemState, hour: u8, minute: u8, second: u8) {
+    let night = NIGHT_SCHEDULE.is_some_and(|schedule| schedule.contains(hour, minute));
+    if night != system_state.night {
+        info!("Night {}", if night { "started" } else { "ended" });
+        system_state.night = night;
+    }
     // update the light effects if the alarm is not enabled and the alarm state is None
     if system_state.alarm_state == AlarmState::None && !system_state.alarm_settings.get_enabled() {
         signal_lightfx_start(hour, minute, second);
@@ -159,6 +301,33 @@ fn handle_scheduler_event(system_state: &SystemState, hour: u8, minute: u8, seco
     signal_display_update();
 }
 
+/// Handles setting the time on the console: the RTC is set, which is recorded like a time sync, and the alarm is
+/// scheduled again for the new time.
+async fn handle_set_time_event(system_state: &mut SystemState, datetime: DateTime) {
+    let synced_at = calendar::to_unix_time(&datetime);
+    match update_rtc_with_time(datetime).await {
+        Ok(_) => {
+            // the console does not tell the UTC offset, the one of the last sync still holds
+            let utc_offset_minutes = system_state
+                .time_sync_status
+                .get_last_sync()
+                .and_then(|report| report.utc_offset_minutes);
+            system_state.time_sync_status.record_success(TimeSyncReport {
+                synced_at,
+                source: TimeSource::Console,
+                drift_secs: None,
+                utc_offset_minutes,
+            });
+            signal_alarm_schedule_update();
+            signal_display_update();
+        }
+        Err(error) => {
+            warn!("Setting the time failed: {:?}", error);
+            system_state.last_errors.record(SystemError::TimeSync(error));
+        }
+    }
+}
+
 /// Handles alarm settings update by writing to flash and coordinating with alarm task.
 async fn handle_alarm_settings_update(system_state: &SystemState) {
     send_flash_write_command(system_state.alarm_settings.clone()).await;
@@ -169,11 +338,58 @@ async fn handle_alarm_settings_update(system_state: &SystemState) {
         signal_alarm_schedule_update();
     } else {
         // if the alarm is disabled, we must signal the alarm task to disable and wake up the scheduler early
+        // a snoozed alarm must not come back either
         signal_alarm_schedule_disable();
+        signal_alarm_snooze_cancel();
         signal_scheduler_wake();
     }
 }
 
+/// Handles a change of the alarm settings over the API like setting the alarm time with the buttons.
+async fn handle_change_alarm_settings_event(system_state: &mut SystemState, update: &AlarmUpdate) {
+    apply_alarm_update(system_state, update);
+    system_state.save_alarm_settings().await;
+    signal_display_update();
+}
+
+/// Handles a change of the alarm settings on a peer clock. The settings are saved and the alarm rescheduled like for
+/// a local change, but not shared again, which would echo the change back to the group.
+async fn handle_peer_alarm_settings_event(system_state: &mut SystemState, update: &AlarmUpdate) {
+    apply_alarm_update(system_state, update);
+    handle_alarm_settings_update(system_state).await;
+    signal_display_update();
+}
+
+/// Set the fields of the alarm settings that are in the update.
+fn apply_alarm_update(system_state: &mut SystemState, update: &AlarmUpdate) {
+    let settings = &mut system_state.alarm_settings;
+    let hour = update.hour.unwrap_or(settings.get_hour());
+    let minute = update.minute.unwrap_or(settings.get_minute());
+    settings.set_time((hour, minute));
+    if let Some(enabled) = update.enabled {
+        settings.set_enabled(enabled);
+    }
+    if let Some(days) = update.days {
+        settings.set_days(days);
+    }
+    if let Some(minutes) = update.sunrise_minutes {
+        settings.set_sunrise_minutes(minutes);
+    }
+    if let Some(volume) = update.volume {
+        settings.set_volume(volume);
+    }
+}
+
+/// Handles a change of the display settings over the API or the console. The settings are saved and the display is
+/// redrawn, they are not shared with peers.
+async fn handle_change_display_settings_event(system_state: &mut SystemState, update: &DisplayUpdate) {
+    if let Some(twelve_hour) = update.twelve_hour {
+        system_state.display_settings.set_twelve_hour(twelve_hour);
+    }
+    send_display_settings_write_command(system_state.display_settings).await;
+    signal_display_update();
+}
+
 /// Handles the standby event by stopping scheduler and suspending time updater.
 fn handle_standby_event() {
     info!("Standby event");
@@ -199,27 +415,67 @@ fn handle_alarm_event(system_state: &mut SystemState) {
     system_state.set_alarm_mode();
     signal_display_update();
     signal_lightfx_start(0, 0, 0);
-    signal_alarm_expirer();
+    queue_webhooks(WebhookEvent::Alarm, alarm_time(system_state));
 }
 
 /// Handles the alarm stop event by transitioning back to normal mode.
+/// Stopping also ends a snooze, the alarm is not raised again.
 fn handle_alarm_stop_event(system_state: &mut SystemState) {
     info!("Alarm stop event");
-    if system_state.alarm_state.is_active() {
+    signal_alarm_snooze_cancel();
+    if stop_active_alarm(system_state) {
+        queue_webhooks(WebhookEvent::AlarmStop, alarm_time(system_state));
+    }
+}
+
+/// Handles the alarm expired event, the alarm was not stopped in time and is stopped like by the buttons.
+fn handle_alarm_expired_event(system_state: &mut SystemState) {
+    info!("Alarm expired event");
+    signal_alarm_snooze_cancel();
+    if stop_active_alarm(system_state) {
+        queue_webhooks(WebhookEvent::AlarmExpired, alarm_time(system_state));
+    }
+}
+
+/// Handles the alarm snooze event by stopping the active alarm and starting the snooze time.
+fn handle_alarm_snooze_event(system_state: &mut SystemState) {
+    info!("Alarm snooze event");
+    if stop_active_alarm(system_state) {
+        signal_alarm_expirer_cancel();
+        signal_alarm_snooze_start();
+    }
+}
+
+/// Stops the alarm effects and sound and returns to normal mode, returns whether an alarm was active.
+fn stop_active_alarm(system_state: &mut SystemState) -> bool {
+    let active = system_state.alarm_state.is_active();
+    if active {
         system_state.set_normal_mode();
         signal_display_update();
         signal_lightfx_stop();
         signal_lightfx_start(0, 0, 0);
         signal_sound_stop();
     }
+    active
 }
 
-/// Handles the sunrise effect finished event by transitioning to noise phase.
+/// Handles the sunrise effect finished event by transitioning to noise phase. The alarm expires only once the sound
+/// has rung for a while, however long the sunrise is.
 fn handle_sunrise_effect_finished_event(system_state: &mut SystemState) {
     info!("Sunrise effect finished event");
     system_state.set_alarm_state(AlarmState::Noise);
-    signal_sound_start();
+    signal_sound_start(system_state.alarm_settings.get_volume());
     signal_lightfx_start(0, 0, 0);
+    signal_alarm_expirer();
+    queue_webhooks(WebhookEvent::SunriseFinished, alarm_time(system_state));
+}
+
+/// The alarm time in (hour, minute), for the webhooks
+const fn alarm_time(system_state: &SystemState) -> (u8, u8) {
+    (
+        system_state.alarm_settings.get_hour(),
+        system_state.alarm_settings.get_minute(),
+    )
 }
 
 /// Handle state changes when the green button is pressed
@@ -232,7 +488,12 @@ async fn handle_green_button_press(system_state: &mut SystemState) {
             system_state.increment_alarm_hour();
         }
         OperationMode::Menu => system_state.set_system_info_mode(),
-        OperationMode::SystemInfo => system_state.set_normal_mode(),
+        OperationMode::SystemInfo => system_state.set_weather_mode(),
+        OperationMode::Weather => system_state.set_network_info_mode(NetworkInfoPage::Wifi),
+        OperationMode::NetworkInfo(page) => match page.next() {
+            Some(next) => system_state.set_network_info_mode(next),
+            None => system_state.set_normal_mode(),
+        },
         OperationMode::Alarm => {
             if system_state.alarm_settings.get_first_valid_stop_alarm_button() == Button::Green {
                 system_state.alarm_settings.erase_first_valid_stop_alarm_button();
@@ -260,7 +521,9 @@ async fn handle_blue_button_press(system_state: &mut SystemState) {
         OperationMode::Menu => {
             system_state.set_standby_mode().await;
         }
-        OperationMode::SystemInfo => system_state.set_normal_mode(),
+        OperationMode::SystemInfo | OperationMode::Weather | OperationMode::NetworkInfo(_) => {
+            system_state.set_normal_mode();
+        }
         OperationMode::Alarm => {
             if system_state.alarm_settings.get_first_valid_stop_alarm_button() == Button::Blue {
                 system_state.alarm_settings.erase_first_valid_stop_alarm_button();
@@ -281,7 +544,7 @@ async fn handle_yellow_button_press(system_state: &mut SystemState) {
         OperationMode::Normal => {
             system_state.set_menu_mode();
         }
-        OperationMode::Menu | OperationMode::SystemInfo => {
+        OperationMode::Menu | OperationMode::SystemInfo | OperationMode::Weather | OperationMode::NetworkInfo(_) => {
             system_state.set_normal_mode();
         }
         OperationMode::SetAlarmTime => system_state.increment_alarm_minute(),
@@ -326,7 +589,7 @@ pub async fn scheduler() {
                 continue 'mainloop;
             };
             dt = match rtc.now() {
-                Ok(dt) => dt,
+                Ok(dt) => DateTime::from(dt),
                 Err(e) => {
                     info!("RTC not running: {:?}", Debug2Format(&e));
                     // Return an empty DateTime
@@ -380,18 +643,45 @@ pub async fn scheduler() {
     }
 }
 
-/// This task handles the expiration of the alarm after 5 minutes.
+/// This task handles the expiration of the alarm 5 minutes after the sound started.
 #[embassy_executor::task]
 pub async fn alarm_expirer() {
     info!("Alarm expirer task started");
     '_mainloop: loop {
-        // wait for the alarm expiry watcher signal
-        ALARM_EXPIRER_SIGNAL.wait().await;
-        // wait for 5 minutes
-        Timer::after(Duration::from_secs(300)).await;
-        // send the alarm stop event
-        send_event(Event::AlarmStop).await;
+        // wait for the alarm expiry watcher signal, cancelling when no alarm is ringing has nothing to do
+        if !ALARM_EXPIRER_SIGNAL.wait().await {
+            continue;
+        }
+        // wait for 5 minutes, unless the alarm is snoozed before
+        if let Either::First(()) = select(Timer::after(ALARM_EXPIRY_DURATION), ALARM_EXPIRER_SIGNAL.wait()).await {
+            // send the alarm expired event
+            send_event(Event::AlarmExpired).await;
+        }
         // Report successful alarm expiry to watchdog
         report_task_success(TaskId::Orchestrator).await;
     }
 }
+
+/// This task raises a snoozed alarm again after the snooze time, unless the snooze is cancelled.
+#[embassy_executor::task]
+pub async fn alarm_snoozer() {
+    info!("Alarm snoozer task started");
+    '_mainloop: loop {
+        // wait for a snooze to start, cancelling when none is running has nothing to do
+        if !ALARM_SNOOZE_SIGNAL.wait().await {
+            continue;
+        }
+        // wait for the snooze time, a new snooze restarts it and a cancel ends it
+        loop {
+            match select(Timer::after(SNOOZE_DURATION), ALARM_SNOOZE_SIGNAL.wait()).await {
+                Either::First(()) => {
+                    send_event(Event::Alarm).await;
+                    break;
+                }
+                Either::Second(true) => {}
+                Either::Second(false) => break,
+            }
+        }
+        report_task_success(TaskId::Orchestrator).await;
+    }
+}
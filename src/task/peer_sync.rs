@@ -0,0 +1,322 @@
+//! # Peer Sync Task
+//! This module contains the task that shares the alarm with the other clocks on the LAN, so that setting the alarm
+//! on one clock sets it on all of them. The protocol and the conflict resolution are in `utility::peer_sync`.
+//!
+//! The clocks of a group are configured in the optional `peer_sync` section of `wifi_config.json`, without it the
+//! alarm is not shared and the task ends right away:
+//!```json
+//!     "peer_sync": {
+//!         "group": "bedrooms",
+//!         "share": ["time", "enabled", "days"]
+//!     }
+//! ```
+//! - `group`: the name of the group, only clocks with the same name share their alarm
+//! - `share`: the fields of the alarm this clock takes from and gives to its peers, any of `time`, `enabled`,
+//!   `days`, `sunrise` and `volume`, defaults to time, enabled and days. A field is only taken over if both clocks
+//!   share it.
+//!
+//! While the network is up the task listens on the multicast group of the protocol and greets the group, the peers
+//! answer with their alarm. A change made on this clock, with the buttons, the API, MQTT or the console, is sent to
+//! the group; the newest alarm wins. After a restart the alarm of the peers wins over the one read from the flash.
+//! Like mDNS the task does not hold the network, changes made while it is down are sent once it is back.
+
+use defmt::{info, warn};
+use embassy_futures::select::{Either, Either4, select, select4};
+use embassy_net::{
+    IpAddress, IpEndpoint, Ipv4Address, Stack,
+    udp::{PacketMetadata, UdpSocket},
+};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::{Duration, Instant, Ticker, Timer};
+
+use crate::{
+    event::{Event, send_event},
+    state::{AlarmSettings, SYSTEM_STATE},
+    syslog,
+    task::{
+        network::{HOSTNAME, PEER_SYNC, network_stack},
+        time_updater::{RTC_MUTEX, rtc_now},
+    },
+    utility::{
+        api_router::AlarmUpdate,
+        calendar,
+        peer_sync::{
+            AlarmValues, MAX_PACKET_SIZE, Message, PEER_GROUP, PEER_PORT, PeerSyncError, PeerTable, Received,
+            SharedFields, SyncState, fnv1a, origin,
+        },
+        syslog::Severity,
+    },
+};
+
+/// The alarm sharing, generated from `wifi_config.json`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct PeerSyncConfig {
+    /// The name of the group of clocks that share their alarm
+    pub group: &'static str,
+    /// The fields of the alarm this clock shares
+    pub shared: SharedFields,
+}
+
+/// Size of the socket buffers
+const SOCKET_BUFFER_SIZE: usize = 512;
+
+/// Number of packets the socket buffers hold
+const SOCKET_PACKETS: usize = 4;
+
+/// Time between the greetings, which bring peers that missed a change up to date
+const HELLO_INTERVAL: Duration = Duration::from_secs(30 * 60);
+
+/// Peers not heard from for this many greetings are forgotten
+const PEER_EXPIRY_HELLOS: u64 = 3;
+
+/// Delay before trying again after the socket could not be bound
+const BIND_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Signal for the task that the alarm was changed on this clock, the data is the changed alarm
+static PEER_SYNC_CHANGE_SIGNAL: Signal<CriticalSectionRawMutex, AlarmValues> = Signal::new();
+
+/// Share a change of the alarm made on this clock with the peers. Does nothing if the alarm is not shared.
+pub fn publish_alarm_settings(alarm_settings: &AlarmSettings) {
+    if PEER_SYNC.is_some() {
+        PEER_SYNC_CHANGE_SIGNAL.signal(alarm_values(alarm_settings));
+    }
+}
+
+/// The values of the alarm settings that can be shared
+const fn alarm_values(alarm_settings: &AlarmSettings) -> AlarmValues {
+    AlarmValues {
+        hour: alarm_settings.get_hour(),
+        minute: alarm_settings.get_minute(),
+        enabled: alarm_settings.get_enabled(),
+        days: alarm_settings.get_days(),
+        sunrise_minutes: alarm_settings.get_sunrise_minutes(),
+        volume: alarm_settings.get_volume(),
+    }
+}
+
+/// The endpoint of the peer multicast group
+fn group_endpoint() -> IpEndpoint {
+    IpEndpoint::new(IpAddress::Ipv4(Ipv4Address::from(PEER_GROUP)), PEER_PORT)
+}
+
+/// The local time in seconds since 1970, `None` if the RTC is not running
+async fn local_time() -> Option<i64> {
+    let rtc_guard = RTC_MUTEX.lock().await;
+    rtc_guard
+        .as_ref()
+        .and_then(rtc_now)
+        .map(|now| calendar::to_unix_time(&now))
+}
+
+/// The task's view of the group: the shared alarm, the peers and whether a change still has to be sent
+struct Peers {
+    /// The hash of the group name, sent with every packet
+    group: u32,
+    /// The fields this clock shares
+    shared: SharedFields,
+    /// The shared alarm and its version
+    sync: SyncState,
+    /// The peers heard from
+    table: PeerTable,
+    /// A change made while the network was down
+    pending: bool,
+}
+
+/// This task shares the alarm with the peers of the group while the network is up, if sharing is configured.
+#[embassy_executor::task]
+pub async fn peer_sync_task() {
+    let Some(config) = PEER_SYNC else {
+        info!("Peer sync not configured, peer sync task ending");
+        return;
+    };
+    info!("Peer sync task starting");
+    let stack = network_stack().await;
+
+    // the alarm is read from the flash before the network comes up
+    stack.wait_config_up().await;
+    let values = {
+        let system_state_guard = SYSTEM_STATE.lock().await;
+        system_state_guard
+            .as_ref()
+            .map(|system_state| alarm_values(&system_state.alarm_settings))
+    };
+    let Some(values) = values else {
+        warn!("Peer sync: system state not initialized, peer sync task ending");
+        return;
+    };
+    // changes made before are in the values already
+    PEER_SYNC_CHANGE_SIGNAL.reset();
+    let mut peers = Peers {
+        group: fnv1a(config.group),
+        shared: config.shared,
+        sync: SyncState::new(config.shared, origin(HOSTNAME), values),
+        table: PeerTable::new(),
+        pending: false,
+    };
+
+    let mut rx_meta = [PacketMetadata::EMPTY; SOCKET_PACKETS];
+    let mut rx_buffer = [0; SOCKET_BUFFER_SIZE];
+    let mut tx_meta = [PacketMetadata::EMPTY; SOCKET_PACKETS];
+    let mut tx_buffer = [0; SOCKET_BUFFER_SIZE];
+
+    loop {
+        // record the changes made while the network is down
+        while !stack.is_config_up() {
+            if let Either::Second(values) = select(stack.wait_config_up(), PEER_SYNC_CHANGE_SIGNAL.wait()).await {
+                peers.pending |= peers.local_change(values).await;
+            }
+        }
+
+        if let Err(e) = stack.join_multicast_group(Ipv4Address::from(PEER_GROUP)) {
+            warn!("Peer sync: joining the multicast group failed: {:?}", e);
+        }
+        let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+        if let Err(e) = socket.bind(PEER_PORT) {
+            warn!("Peer sync: binding the socket failed: {:?}", e);
+            Timer::after(BIND_RETRY_DELAY).await;
+            continue;
+        }
+
+        info!("Peer sync in group {}", config.group);
+        peers.serve(stack, &socket).await;
+        socket.close();
+        let _ = stack.leave_multicast_group(Ipv4Address::from(PEER_GROUP));
+    }
+}
+
+impl Peers {
+    /// Greet the group and send pending changes, then answer the peers and send changes until the network goes down
+    async fn serve(&mut self, stack: Stack<'static>, socket: &UdpSocket<'_>) {
+        let mut packet = [0; MAX_PACKET_SIZE];
+
+        self.send(socket, &Message::Hello { hostname: HOSTNAME }, group_endpoint())
+            .await;
+        if core::mem::take(&mut self.pending) {
+            self.send_snapshot(socket, group_endpoint()).await;
+        }
+
+        let mut ticker = Ticker::every(HELLO_INTERVAL);
+        loop {
+            let event = select4(
+                socket.recv_from(&mut packet),
+                stack.wait_config_down(),
+                PEER_SYNC_CHANGE_SIGNAL.wait(),
+                ticker.next(),
+            )
+            .await;
+            match event {
+                Either4::First(Ok((len, metadata))) => self.receive(socket, &packet[..len], metadata.endpoint).await,
+                Either4::First(Err(e)) => warn!("Peer sync: receiving failed: {:?}", e),
+                Either4::Second(()) => return,
+                Either4::Third(values) => {
+                    if self.local_change(values).await {
+                        self.send_snapshot(socket, group_endpoint()).await;
+                    }
+                }
+                Either4::Fourth(()) => {
+                    let expiry_secs = HELLO_INTERVAL.as_secs() * PEER_EXPIRY_HELLOS;
+                    self.table.expire(Instant::now().as_secs().saturating_sub(expiry_secs));
+                    self.send(socket, &Message::Hello { hostname: HOSTNAME }, group_endpoint())
+                        .await;
+                }
+            }
+        }
+    }
+
+    /// The alarm was changed on this clock, returns whether a shared field changed and must be sent
+    async fn local_change(&mut self, values: AlarmValues) -> bool {
+        // without the time the change still wins over the version it replaces
+        let timestamp = local_time().await.unwrap_or(i64::MIN);
+        self.sync.local_change(values, timestamp).is_some()
+    }
+
+    /// Handle a packet of a peer
+    async fn receive(&mut self, socket: &UdpSocket<'_>, packet: &[u8], sender: IpEndpoint) {
+        let message = match Message::decode(packet, self.group) {
+            Ok(message) => message,
+            // other groups share the port and the multicast group
+            Err(PeerSyncError::OtherGroup) => return,
+            Err(e) => {
+                warn!("Peer sync: packet from {} ignored: {:?}", sender, e);
+                return;
+            }
+        };
+        // multicast packets come back to the sender
+        if message.hostname() == HOSTNAME {
+            return;
+        }
+
+        if let IpAddress::Ipv4(address) = sender.addr
+            && self
+                .table
+                .seen(message.hostname(), address.octets(), Instant::now().as_secs())
+        {
+            info!("Peer {} at {}", message.hostname(), sender);
+            syslog!(
+                Severity::Notice,
+                "peer_sync",
+                "Peer {} at {}, {} peers",
+                message.hostname(),
+                sender,
+                self.table.len()
+            );
+        }
+
+        match message {
+            Message::Hello { .. } => self.send_snapshot(socket, sender).await,
+            Message::Snapshot { hostname, snapshot } => match self.sync.receive(&snapshot) {
+                Received::Apply(values) => {
+                    info!("Alarm of peer {} applied: {:?}", hostname, values);
+                    let fields = self.shared.intersection(snapshot.fields);
+                    send_event(Event::PeerAlarmSettings(alarm_update(values, fields))).await;
+                }
+                Received::UpToDate => {}
+                // the whole group missed our change, not just the sender
+                Received::Stale => self.send_snapshot(socket, group_endpoint()).await,
+            },
+        }
+    }
+
+    /// Send our shared alarm
+    async fn send_snapshot(&self, socket: &UdpSocket<'_>, destination: IpEndpoint) {
+        let snapshot = self.sync.snapshot();
+        self.send(
+            socket,
+            &Message::Snapshot {
+                hostname: HOSTNAME,
+                snapshot,
+            },
+            destination,
+        )
+        .await;
+    }
+
+    /// Encode and send a packet, a packet that cannot be sent is dropped
+    async fn send(&self, socket: &UdpSocket<'_>, message: &Message<'_>, destination: IpEndpoint) {
+        let mut packet = [0; MAX_PACKET_SIZE];
+        match message.encode(self.group, &mut packet) {
+            Ok(len) => {
+                if let Err(e) = socket.send_to(&packet[..len], destination).await {
+                    warn!("Peer sync: sending to {} failed: {:?}", destination, e);
+                }
+            }
+            Err(e) => warn!("Peer sync: packet does not fit the buffer: {:?}", e),
+        }
+    }
+}
+
+/// The change of the alarm settings that sets the given fields to the values
+const fn alarm_update(values: AlarmValues, fields: SharedFields) -> AlarmUpdate {
+    /// The value if the field is shared
+    const fn shared<T: Copy>(fields: SharedFields, field: SharedFields, value: T) -> Option<T> {
+        if fields.contains(field) { Some(value) } else { None }
+    }
+    AlarmUpdate {
+        hour: shared(fields, SharedFields::TIME, values.hour),
+        minute: shared(fields, SharedFields::TIME, values.minute),
+        enabled: shared(fields, SharedFields::ENABLED, values.enabled),
+        days: shared(fields, SharedFields::DAYS, values.days),
+        sunrise_minutes: shared(fields, SharedFields::SUNRISE, values.sunrise_minutes),
+        volume: shared(fields, SharedFields::VOLUME, values.volume),
+    }
+}
@@ -8,15 +8,15 @@ use embassy_rp::{gpio::Output, uart::BufferedUart};
 use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
 use embassy_time::{Delay, Duration, Instant, Timer};
 
-/// Signal for starting the sound
-static SOUND_START_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+/// Signal for starting the sound, the data is the volume
+static SOUND_START_SIGNAL: Signal<CriticalSectionRawMutex, u8> = Signal::new();
 
 /// Signal for stopping the sound
 static SOUND_STOP_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
 
-/// Signals the sound task to start playing
-pub fn signal_sound_start() {
-    SOUND_START_SIGNAL.signal(());
+/// Signals the sound task to start playing at the given volume, 0 to 30
+pub fn signal_sound_start(volume: u8) {
+    SOUND_START_SIGNAL.signal(volume);
 }
 
 /// Signals the sound task to stop playing
@@ -24,9 +24,9 @@ pub fn signal_sound_stop() {
     SOUND_STOP_SIGNAL.signal(());
 }
 
-/// Waits for the next sound start signal
-async fn wait_for_sound_start() {
-    SOUND_START_SIGNAL.wait().await;
+/// Waits for the next sound start signal and returns the volume
+async fn wait_for_sound_start() -> u8 {
+    SOUND_START_SIGNAL.wait().await
 }
 
 /// Waits for the next sound stop signal
@@ -60,7 +60,7 @@ pub async fn sound_handler(mut uart: BufferedUart, mut pwr: Output<'static>) {
 
     loop {
         // wait for the signal to start playing sound
-        wait_for_sound_start().await;
+        let volume = wait_for_sound_start().await;
 
         // power on the dfplayer
         info!("Powering on the dfplayer");
@@ -87,7 +87,7 @@ pub async fn sound_handler(mut uart: BufferedUart, mut pwr: Output<'static>) {
 
         info!("Playing sound");
         if let Ok(ref mut dfp) = dfp_result {
-            let _ = dfp.set_volume(13).await;
+            let _ = dfp.set_volume(volume).await;
             Timer::after(Duration::from_millis(100)).await;
             let _ = dfp.set_equalizer(Equalizer::Classic).await;
             Timer::after(Duration::from_millis(100)).await;
@@ -0,0 +1,295 @@
+//! # Syslog Task
+//! This module contains the task that forwards log messages to a syslog collector over UDP, so that the clock can be
+//! watched in the field without a debug probe. The messages are formatted by `utility::syslog`.
+//!
+//! The collector is configured in the optional `syslog` section of `wifi_config.json`, without it nothing is kept
+//! and the task ends right away:
+//!```json
+//!     "syslog": {
+//!         "host": "192.168.1.10",
+//!         "port": 514
+//!     }
+//! ```
+//! Only the host is required, the port defaults to 514.
+//!
+//! The orchestrator forwards every event it handles, subsystem errors with severity error. Warnings that matter in
+//! the field are logged with `warn_remote!`, which logs to `defmt` as well; anything else can be logged with
+//! `syslog!`. Both only format the message into a ring of the last messages, so they can be used anywhere. The task
+//! sends the ring while the network is up; while it is down the oldest messages are dropped when the ring is full,
+//! which is reported once the network is back. Like MQTT the task does not hold the network, with the default
+//! connectivity policy the messages go out with the time sync.
+
+use core::{cell::RefCell, fmt::Write as _, net::SocketAddr};
+
+use defmt::{info, warn};
+use embassy_futures::select::{Either, select};
+use embassy_net::{
+    Stack, dns,
+    udp::{PacketMetadata, UdpSocket},
+};
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+    signal::Signal,
+};
+use embassy_time::{Duration, Instant, Timer};
+use embedded_nal_async::{AddrType, Dns};
+use heapless::{Deque, String};
+
+use crate::{
+    state::SYSTEM_STATE,
+    task::{
+        network::{HOSTNAME, SYSLOG_SERVER, network_stack},
+        time_updater::{RTC_MUTEX, rtc_now},
+    },
+    utility::{
+        calendar,
+        syslog::{Severity, Timestamp, Truncating, format_message},
+    },
+};
+
+/// Log a message to the syslog collector, formatted like `format!`, e.g.
+/// `syslog!(Severity::Notice, "alarm", "Alarm moved to {:02}:{:02}", hour, minute)`. The arguments must implement
+/// `core::fmt` traits. Does nothing if no collector is configured.
+#[macro_export]
+macro_rules! syslog {
+    ($severity:expr, $message_id:expr, $($arg:tt)*) => {
+        $crate::task::syslog::log($severity, $message_id, format_args!($($arg)*))
+    };
+}
+
+/// Log a warning to `defmt` and to the syslog collector, e.g. `warn_remote!("Task {:?} is unhealthy", task_id)`.
+/// The format string must suit both, so the arguments must implement `Format` and `core::fmt` traits.
+#[macro_export]
+macro_rules! warn_remote {
+    ($($arg:tt)*) => {{
+        defmt::warn!($($arg)*);
+        $crate::syslog!($crate::utility::syslog::Severity::Warning, "warning", $($arg)*);
+    }};
+}
+
+/// The syslog collector, generated from `wifi_config.json`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct SyslogServer {
+    /// The host name or address of the collector
+    pub host: &'static str,
+    /// The UDP port of the collector
+    pub port: u16,
+}
+
+/// Capacity of the text of a message, longer ones are cut off
+const MESSAGE_CAPACITY: usize = 120;
+
+/// Number of messages kept while they cannot be sent
+const RING_CAPACITY: usize = 16;
+
+/// Capacity of a formatted syslog message, the header and the text
+const PACKET_CAPACITY: usize = 224;
+
+/// Size of the socket transmit buffer
+const SOCKET_BUFFER_SIZE: usize = 512;
+
+/// Size of the socket receive buffer, the collector does not answer
+const SOCKET_RX_BUFFER_SIZE: usize = 64;
+
+/// Number of packets the socket transmit buffer holds
+const SOCKET_PACKETS: usize = 2;
+
+/// Delay before trying again after the collector could not be resolved or the socket could not be bound
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// A message waiting to be sent
+struct Record {
+    /// The severity of the message
+    severity: Severity,
+    /// The message id, what the message is about
+    message_id: &'static str,
+    /// When the message was logged, to stamp it with the time it happened rather than when it is sent
+    logged_at: Instant,
+    /// The text of the message
+    message: String<MESSAGE_CAPACITY>,
+}
+
+/// The messages waiting to be sent and the number of messages dropped since the last were sent
+struct Ring {
+    /// The messages, oldest first
+    records: Deque<Record, RING_CAPACITY>,
+    /// Number of messages dropped because the ring was full
+    dropped: u32,
+}
+
+/// The ring of messages waiting to be sent, filled from anywhere without waiting
+static SYSLOG_RING: Mutex<CriticalSectionRawMutex, RefCell<Ring>> = Mutex::new(RefCell::new(Ring {
+    records: Deque::new(),
+    dropped: 0,
+}));
+
+/// Signal for the task that there are messages to send
+static SYSLOG_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Keep a message for the syslog collector, dropping the oldest if the ring is full. Use `syslog!` or
+/// `warn_remote!` rather than calling this directly.
+pub fn log(severity: Severity, message_id: &'static str, args: core::fmt::Arguments) {
+    if SYSLOG_SERVER.is_none() {
+        return;
+    }
+    let mut message = String::new();
+    let _ = Truncating(&mut message).write_fmt(args);
+    let record = Record {
+        severity,
+        message_id,
+        logged_at: Instant::now(),
+        message,
+    };
+    SYSLOG_RING.lock(|ring| {
+        let mut ring = ring.borrow_mut();
+        if ring.records.is_full() {
+            ring.records.pop_front();
+            ring.dropped = ring.dropped.saturating_add(1);
+        }
+        let _ = ring.records.push_back(record);
+    });
+    SYSLOG_SIGNAL.signal(());
+}
+
+/// Take the oldest message waiting to be sent
+fn take_record() -> Option<Record> {
+    SYSLOG_RING.lock(|ring| ring.borrow_mut().records.pop_front())
+}
+
+/// Take the number of messages dropped since the last time
+fn take_dropped() -> u32 {
+    SYSLOG_RING.lock(|ring| core::mem::take(&mut ring.borrow_mut().dropped))
+}
+
+/// This task sends the logged messages to the syslog collector while the network is up, if one is configured.
+#[embassy_executor::task]
+pub async fn syslog_task() {
+    let Some(server) = SYSLOG_SERVER else {
+        info!("Syslog not configured, syslog task ending");
+        return;
+    };
+    info!("Syslog task starting");
+    let stack = network_stack().await;
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0; SOCKET_RX_BUFFER_SIZE];
+    let mut tx_meta = [PacketMetadata::EMPTY; SOCKET_PACKETS];
+    let mut tx_buffer = [0; SOCKET_BUFFER_SIZE];
+
+    loop {
+        stack.wait_config_up().await;
+
+        let address = match resolve(stack, server).await {
+            Ok(address) => address,
+            Err(e) => {
+                warn!("Syslog: resolving {} failed: {:?}", server.host, e);
+                Timer::after(RETRY_DELAY).await;
+                continue;
+            }
+        };
+        let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+        // port 0 binds to an ephemeral port, the collector does not answer
+        if let Err(e) = socket.bind(0) {
+            warn!("Syslog: binding the socket failed: {:?}", e);
+            Timer::after(RETRY_DELAY).await;
+            continue;
+        }
+
+        info!("Syslog sending to {}", server.host);
+        loop {
+            send_records(stack, &socket, address).await;
+            if let Either::Second(()) = select(SYSLOG_SIGNAL.wait(), stack.wait_config_down()).await {
+                break;
+            }
+        }
+        socket.close();
+    }
+}
+
+/// Resolve the address of the collector, the host is either an IP address or a host name
+async fn resolve(stack: Stack<'static>, server: SyslogServer) -> Result<SocketAddr, dns::Error> {
+    let address = match server.host.parse() {
+        Ok(address) => address,
+        Err(_) => {
+            dns::DnsSocket::new(stack)
+                .get_host_by_name(server.host, AddrType::IPv4)
+                .await?
+        }
+    };
+    Ok(SocketAddr::new(address, server.port))
+}
+
+/// Send the messages waiting in the ring while the network is up, preceded by a notice if messages were dropped
+async fn send_records(stack: Stack<'static>, socket: &UdpSocket<'_>, address: SocketAddr) {
+    let mut packet: String<PACKET_CAPACITY> = String::new();
+
+    let dropped = take_dropped();
+    if dropped > 0 {
+        let mut message: String<MESSAGE_CAPACITY> = String::new();
+        let _ = write!(message, "{dropped} messages dropped while they could not be sent");
+        let record = Record {
+            severity: Severity::Notice,
+            message_id: "syslog",
+            logged_at: Instant::now(),
+            message,
+        };
+        send_record(socket, address, &record, &mut packet).await;
+    }
+
+    // messages logged while the network goes down stay in the ring
+    while stack.is_config_up()
+        && let Some(record) = take_record()
+    {
+        send_record(socket, address, &record, &mut packet).await;
+    }
+}
+
+/// Format a message and send it, a message that cannot be sent is dropped
+async fn send_record(
+    socket: &UdpSocket<'_>,
+    address: SocketAddr,
+    record: &Record,
+    packet: &mut String<PACKET_CAPACITY>,
+) {
+    // the time the message was logged, if the RTC holds a synced time and the UTC offset is known
+    let utc_offset_minutes = {
+        let system_state_guard = SYSTEM_STATE.lock().await;
+        system_state_guard
+            .as_ref()
+            .and_then(|system_state| system_state.time_sync_status.get_last_sync())
+            .and_then(|report| report.utc_offset_minutes)
+    };
+    let now = {
+        let rtc_guard = RTC_MUTEX.lock().await;
+        rtc_guard.as_ref().and_then(rtc_now)
+    };
+    #[allow(clippy::cast_possible_wrap)]
+    let logged_at = now.map(|now| {
+        let age_secs = (Instant::now() - record.logged_at).as_secs() as i64;
+        calendar::from_unix_time(calendar::to_unix_time(&now) - age_secs)
+    });
+    let timestamp = logged_at
+        .as_ref()
+        .zip(utc_offset_minutes)
+        .map(|(datetime, utc_offset_minutes)| Timestamp {
+            datetime,
+            utc_offset_minutes,
+        });
+
+    if format_message(
+        packet,
+        record.severity,
+        timestamp,
+        HOSTNAME,
+        record.message_id,
+        &record.message,
+    )
+    .is_err()
+    {
+        warn!("Syslog: message does not fit the buffer");
+        return;
+    }
+    if let Err(e) = socket.send_to(packet.as_bytes(), address).await {
+        warn!("Syslog: sending failed: {:?}", e);
+    }
+}
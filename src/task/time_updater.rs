@@ -1,16 +1,9 @@
 //! # Time Updater Task
 //! This module contains the task that updates the RTC using a time API.
-//! The task is responsible for connecting to a wifi network, making a request to a time API, parsing the response, and updating the RTC.
-//!
-//! # populate constants SSID and PASSWORD
-//! make sure to have a `wifi_config.json` file in the config folder formatted as follows:
-//!```json
-//!  {
-//!     "ssid": "some_ssid_here",
-//!     "password": "some_password_here"
-//! }
-//! ```
-//! also make sure that `build.rs` loads the `wifi_config.json` file and writes it to `wifi_secrets.rs`
+//! The task is responsible for borrowing the network connection, making a request to a time API, parsing the response,
+//! and updating the RTC. Joining the `WiFi` network is left to the network task, see `task::network`.
+//! While the connection is borrowed, the weather forecast, the calendar and firmware updates are fetched as well, see
+//! `task::weather`, `task::ics_calendar` and `task::firmware_update`.
 //!
 //! # populate constant `TIME_SERVER_URL`
 //! make sure to have a `time_api_config.json` file in the config folder formatted as follows:
@@ -22,47 +15,75 @@
 //!     }
 //! }
 //! ```
+//!
+//! # HTTPS with a pinned server
+//! For an `https` baseurl add a `tls` section next to `baseurl` and `timezone`, pinning either the SHA-256 hash
+//! of the server's public key (`"pin": "public_key"`) or of its complete certificate (`"pin": "certificate"`):
+//! ```json
+//! "tls": {
+//!     "pin": "public_key",
+//!     "sha256": "dece9062788292551162a7c7b73c0ede067dc789b53b56ac6b30a10950ffc90d"
+//! }
+//! ```
+//! The hash of the public key of a server can be obtained with
+//! `openssl s_client -connect host:443 </dev/null | openssl x509 -pubkey -noout | openssl pkey -pubin -outform der | sha256sum`.
+//! The build fails for an `https` baseurl without a pin. The server must support TLS 1.3 with a P-256 key,
+//! see `utility::pinned_tls`. For testing, `tls_standin_server.sh` runs such a server locally.
 
-include!(concat!(env!("OUT_DIR"), "/wifi_secrets.rs"));
 include!(concat!(env!("OUT_DIR"), "/time_api_config.rs"));
 
-use core::str::from_utf8;
+use core::{net::SocketAddr, str::from_utf8};
 
-use cyw43::JoinOptions;
-use cyw43_pio::{DEFAULT_CLOCK_DIVIDER, PioSpi};
 use defmt::{info, unwrap, warn};
 use defmt_rtt as _;
 use embassy_executor::Spawner;
 use embassy_futures::select::select;
 use embassy_net::{
-    Config, DhcpConfig, StackResources, dns,
+    Stack, dns,
     tcp::client::{TcpClient, TcpClientState},
 };
 use embassy_rp::{
-    Peri,
     clocks::RoscRng,
-    gpio::{Level, Output},
-    peripherals::{self, DMA_CH0, PIO0},
-    pio::Pio,
-    rtc::Rtc,
+    peripherals,
+    rtc::{self, Rtc},
 };
 use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex, signal::Signal};
-use embassy_time::{Duration, Timer, with_timeout};
+use embassy_time::{Duration, Instant, Timer};
+use embedded_io_async::Read;
+use embedded_nal_async::{AddrType, Dns, TcpConnect};
+use embedded_tls::{TlsConfig, TlsConnection, TlsContext};
 use heapless;
+use nourl::Url;
 use panic_probe as _;
 use reqwless::{
-    client::{HttpClient, TlsConfig, TlsVerify},
-    request::Method,
+    client::{HttpClient, HttpConnection},
+    request::{Method, Request, RequestBuilder},
+    response::Response,
 };
-use serde::Deserialize;
-use serde_json_core;
-use static_cell::StaticCell;
 
 use crate::{
-    Irqs,
-    event::{Event, send_event},
-    task::watchdog::{TaskId, report_task_failure, report_task_success},
-    utility::string_utils::StringUtils,
+    error::{HttpError, SystemError, TimeSyncError},
+    event::{Event, send_event, try_send_event},
+    state::{TimeSource, TimeSyncReport},
+    task::{
+        firmware_update::update_firmware,
+        ics_calendar::update_calendar,
+        network::{acquire_network, release_network},
+        watchdog::{TaskId, report_task_failure, report_task_success},
+        weather::update_forecast,
+    },
+    utility::{
+        backoff::{AdaptiveRefresh, Backoff, FailureKind},
+        calendar::{self, DateTime, DayOfWeek},
+        http_response::{
+            MAX_RETRY_AFTER_SECS, RedirectError, is_redirect, is_success, parse_retry_after, redirect_allowed,
+            resolve_redirect,
+        },
+        json_stream::JsonFieldExtractor,
+        pinned_tls::PinnedProvider,
+        string_utils::{ParsedDateTime, StringUtils},
+        tls_pin::Pin,
+    },
 };
 
 /// Signal for suspending the time updater task
@@ -96,35 +117,99 @@ async fn wait_for_time_updater_resume() {
     TIME_UPDATER_RESUME_SIGNAL.wait().await;
 }
 
-/// `WiFi` peripheral resources needed for the time updater task
-pub struct WifiPeripherals {
-    /// Power pin for `WiFi` module
-    pub pwr_pin: Peri<'static, peripherals::PIN_23>,
-    /// Chip select pin for `WiFi` module
-    pub cs_pin: Peri<'static, peripherals::PIN_25>,
-    /// `PIO` peripheral for `WiFi` communication
-    pub pio: Peri<'static, peripherals::PIO0>,
-    /// Data I/O pin for `WiFi` module
-    pub dio_pin: Peri<'static, peripherals::PIN_24>,
-    /// Clock pin for `WiFi` module
-    pub clk_pin: Peri<'static, peripherals::PIN_29>,
-    /// `DMA` channel for `WiFi` communication
-    pub dma_ch: Peri<'static, peripherals::DMA_CH0>,
-}
-
 /// Type alias for the RTC mutex.
 type RtcType = Mutex<CriticalSectionRawMutex, Option<Rtc<'static, peripherals::RTC>>>;
 /// The RTC mutex, which is used to access the RTC from multiple tasks. There was no apparent place to put this anywhere else, so it is here.
 pub static RTC_MUTEX: RtcType = Mutex::new(None);
 
-/// Static cell for `CYW43` `WiFi` state.
-static WIFI_STATE: StaticCell<cyw43::State> = StaticCell::new();
+impl From<rtc::DayOfWeek> for DayOfWeek {
+    fn from(day_of_week: rtc::DayOfWeek) -> Self {
+        match day_of_week {
+            rtc::DayOfWeek::Sunday => Self::Sunday,
+            rtc::DayOfWeek::Monday => Self::Monday,
+            rtc::DayOfWeek::Tuesday => Self::Tuesday,
+            rtc::DayOfWeek::Wednesday => Self::Wednesday,
+            rtc::DayOfWeek::Thursday => Self::Thursday,
+            rtc::DayOfWeek::Friday => Self::Friday,
+            rtc::DayOfWeek::Saturday => Self::Saturday,
+        }
+    }
+}
+
+impl From<DayOfWeek> for rtc::DayOfWeek {
+    fn from(day_of_week: DayOfWeek) -> Self {
+        match day_of_week {
+            DayOfWeek::Sunday => Self::Sunday,
+            DayOfWeek::Monday => Self::Monday,
+            DayOfWeek::Tuesday => Self::Tuesday,
+            DayOfWeek::Wednesday => Self::Wednesday,
+            DayOfWeek::Thursday => Self::Thursday,
+            DayOfWeek::Friday => Self::Friday,
+            DayOfWeek::Saturday => Self::Saturday,
+        }
+    }
+}
+
+impl From<rtc::DateTime> for DateTime {
+    fn from(dt: rtc::DateTime) -> Self {
+        Self {
+            year: dt.year,
+            month: dt.month,
+            day: dt.day,
+            day_of_week: dt.day_of_week.into(),
+            hour: dt.hour,
+            minute: dt.minute,
+            second: dt.second,
+        }
+    }
+}
+
+impl From<DateTime> for rtc::DateTime {
+    fn from(dt: DateTime) -> Self {
+        Self {
+            year: dt.year,
+            month: dt.month,
+            day: dt.day,
+            day_of_week: dt.day_of_week.into(),
+            hour: dt.hour,
+            minute: dt.minute,
+            second: dt.second,
+        }
+    }
+}
+
+/// Read the RTC, `None` while it is not running
+pub fn rtc_now(rtc: &Rtc<'static, peripherals::RTC>) -> Option<DateTime> {
+    rtc.now().ok().map(DateTime::from)
+}
+
+/// Size of the receive buffer, which must hold the response headers
+const RX_BUFFER_SIZE: usize = 1024;
+
+/// Size of each `TLS` buffer: a full TLS record when talking to a pinned server, nothing for plain HTTP
+const TLS_BUFFER_SIZE: usize = if TIME_SERVER_PIN.is_some() { 16_640 } else { 0 };
+
+/// Size of the `TCP` socket receive buffer
+const TCP_RX_BUFFER_SIZE: usize = 512;
+
+/// Size of the `TCP` socket transmit buffer, the request is well below that
+const TCP_TX_BUFFER_SIZE: usize = 256;
+
+/// Size of the chunks the response body is read in
+const BODY_CHUNK_SIZE: usize = 128;
+
+/// Capacity for the datetime of the response, e.g. `2024-06-26T22:01:27.106426+02:00` has 32 bytes
+const DATETIME_CAPACITY: usize = 40;
+
+/// Capacity for the URL of the time API, including the URLs we are redirected to
+const URL_CAPACITY: usize = 256;
 
-/// Static cell for network stack.
-static NETWORK_STACK: StaticCell<embassy_net::Stack<'_>> = StaticCell::new();
+/// Number of redirects we follow before giving up
+const MAX_REDIRECTS: u8 = 3;
 
-/// Static cell for network stack resources.
-static NETWORK_RESOURCES: StaticCell<StackResources<5>> = StaticCell::new();
+/// The longest refresh interval, once a day if the RTC keeps time well. The watchdog gives the time updater this
+/// long between successful syncs, see `task::watchdog`.
+pub const MAX_REFRESH_SECS: u64 = 86_400;
 
 /// Static buffers for HTTP communication (protected by mutex to allow reuse).
 static HTTP_BUFFERS: embassy_sync::mutex::Mutex<
@@ -135,12 +220,12 @@ static HTTP_BUFFERS: embassy_sync::mutex::Mutex<
 /// HTTP communication buffers.
 #[allow(clippy::struct_field_names)]
 struct HttpBuffers {
-    /// Receive buffer for `HTTP` responses
-    rx_buffer: [u8; 8192],
+    /// Receive buffer for `HTTP` response headers, the body is streamed in chunks
+    rx_buffer: [u8; RX_BUFFER_SIZE],
     /// `TLS` read buffer
-    tls_read_buffer: [u8; 16640],
+    tls_read_buffer: [u8; TLS_BUFFER_SIZE],
     /// `TLS` write buffer
-    tls_write_buffer: [u8; 16640],
+    tls_write_buffer: [u8; TLS_BUFFER_SIZE],
 }
 
 impl HttpBuffers {
@@ -148,63 +233,55 @@ impl HttpBuffers {
     #[allow(clippy::large_stack_arrays)]
     const fn new() -> Self {
         Self {
-            rx_buffer: [0; 8192],
-            tls_read_buffer: [0; 16640],
-            tls_write_buffer: [0; 16640],
+            rx_buffer: [0; RX_BUFFER_SIZE],
+            tls_read_buffer: [0; TLS_BUFFER_SIZE],
+            tls_write_buffer: [0; TLS_BUFFER_SIZE],
         }
     }
 }
 
-/// Configuration for the time updater task.
+/// Configuration and retry/refresh state of the time updater task.
 pub struct TimeUpdater {
-    /// `WiFi` SSID
-    ssid: &'static str,
-    /// `WiFi` password
-    password: &'static str,
     /// Time API URL
     time_api_url: &'static str,
-    /// Seconds to wait before refreshing time
-    refresh_after_secs: u64,
-    /// Seconds to wait before retrying on error
-    retry_after_secs: u64,
-    /// Timeout duration for network operations
-    timeout_duration: Duration,
+    /// Pin of the time server, `None` for plain HTTP
+    time_api_pin: Option<Pin>,
+    /// UTC offset of the last successful sync, to convert the `Date` header fallback to local time
+    utc_offset_minutes: Option<i16>,
+    /// Refresh interval, adapting to the measured drift of the RTC
+    refresh: AdaptiveRefresh,
+    /// When the last successful sync was, the drift of the RTC is measured over the time since
+    last_sync: Option<Instant>,
+    /// Exponential backoff for retries after errors
+    backoff: Backoff,
 }
 
 impl TimeUpdater {
     /// Creates a new `TimeUpdater` instance with default configuration.
     pub const fn new() -> Self {
         Self {
-            ssid: SSID,
-            password: PASSWORD,
             time_api_url: TIME_SERVER_URL,
-            refresh_after_secs: 21_600, // 6 hours
-            retry_after_secs: 30,
-            timeout_duration: Duration::from_secs(10),
+            time_api_pin: TIME_SERVER_PIN,
+            utc_offset_minutes: None,
+            refresh: AdaptiveRefresh::new(
+                21_600, // start with 6 hours
+                3_600,  // at least every hour if the RTC drifts a lot
+                MAX_REFRESH_SECS,
+            ),
+            last_sync: None,
+            backoff: Backoff::new(),
         }
     }
 
-    /// Returns the `WiFi` credentials as a tuple of (ssid, password).
-    const fn credentials(&self) -> (&str, &str) {
-        (self.ssid, self.password)
-    }
-
     /// Returns the time API URL.
     const fn time_api_url(&self) -> &str {
         self.time_api_url
     }
-}
 
-/// `WiFi` driver task that runs the `CYW43` firmware.
-#[embassy_executor::task]
-async fn wifi_task(runner: cyw43::Runner<'static, Output<'static>, PioSpi<'static, PIO0, 0, DMA_CH0>>) -> ! {
-    runner.run().await
-}
-
-/// Network stack task that handles TCP/IP networking.
-#[embassy_executor::task]
-async fn net_task(mut runner: embassy_net::Runner<'static, cyw43::NetDriver<'static>>) -> ! {
-    runner.run().await
+    /// Returns the pin of the time server, `None` for plain HTTP.
+    const fn time_api_pin(&self) -> Option<Pin> {
+        self.time_api_pin
+    }
 }
 
 /// RTC management task that stores the RTC in a static mutex for access by other tasks.
@@ -221,241 +298,293 @@ async fn rtc_task(rtc: embassy_rp::rtc::Rtc<'static, embassy_rp::peripherals::RT
     }
 }
 
-/// Initialize `WiFi` hardware and return the control handle and network device.
-async fn setup_wifi(
-    spawner: &Spawner,
-    wifi_peripherals: WifiPeripherals,
-) -> (cyw43::Control<'static>, cyw43::NetDriver<'static>) {
-    info!("init wifi");
-    let pwr = Output::new(wifi_peripherals.pwr_pin, Level::Low);
-    let cs = Output::new(wifi_peripherals.cs_pin, Level::High);
-    let mut pio = Pio::new(wifi_peripherals.pio, Irqs);
-    let spi = PioSpi::new(
-        &mut pio.common,
-        pio.sm0,
-        DEFAULT_CLOCK_DIVIDER,
-        pio.irq0,
-        cs,
-        wifi_peripherals.dio_pin,
-        wifi_peripherals.clk_pin,
-        wifi_peripherals.dma_ch,
-    );
-
-    let fw = include_bytes!("../wifi-firmware/cyw43-firmware/43439A0.bin");
-    let clm = include_bytes!("../wifi-firmware/cyw43-firmware/43439A0_clm.bin");
-
-    let state = WIFI_STATE.init(cyw43::State::new());
+/// A successful response of the time `API`
+struct TimeResponse {
+    /// The `datetime` field of the body, or why it could not be read
+    datetime: Result<heapless::String<DATETIME_CAPACITY>, TimeSyncError>,
+    /// The `Date` header of the response in UTC, if it had a valid one
+    date: Option<DateTime>,
+}
 
-    let (net_device, mut control, runner) = cyw43::new(state, pwr, spi, fw).await;
-    spawner.spawn(unwrap!(wifi_task(runner)));
+/// What a single request to the time `API` brought back
+enum Fetched {
+    /// A successful response
+    Time(TimeResponse),
+    /// A redirect to the given location
+    Redirect(heapless::String<URL_CAPACITY>),
+}
 
-    info!("init control");
-    control.init(clm).await;
-    control
-        .set_power_management(cyw43::PowerManagementMode::Aggressive)
-        .await;
+/// Fetch the datetime from the `API` using static buffers, following redirects.
+/// With a pin configured the request goes over TLS to the pinned server, otherwise it is plain HTTP.
+#[allow(clippy::significant_drop_tightening)]
+async fn fetch_time_from_api(
+    stack: &Stack<'static>,
+    url: &str,
+    pin: Option<Pin>,
+) -> Result<TimeResponse, TimeSyncError> {
+    let mut buffers_guard = HTTP_BUFFERS.lock().await;
+    let buffers = buffers_guard.as_mut().ok_or(HttpError::BuffersUnavailable)?;
+
+    let mut url: heapless::String<URL_CAPACITY> = heapless::String::try_from(url).map_err(|_| HttpError::InvalidUrl)?;
+    for _ in 0..=MAX_REDIRECTS {
+        let fetched = match pin {
+            Some(pin) => fetch_over_pinned_tls(stack, &url, pin, buffers).await?,
+            None => fetch_over_http(stack, &url, &mut buffers.rx_buffer).await?,
+        };
+
+        match fetched {
+            Fetched::Time(response) => return Ok(response),
+            Fetched::Redirect(location) => {
+                let target = resolve_redirect(&url, &location).map_err(HttpError::Redirect)?;
+                check_redirect_allowed(&url, &target, pin.is_some())?;
+                info!("Redirected to {:?}", target.as_str());
+                url = target;
+            }
+        }
+    }
 
-    (control, net_device)
+    warn!("Too many redirects");
+    Err(HttpError::TooManyRedirects.into())
 }
 
-/// Setup network stack with DHCP configuration.
-fn setup_network_stack(
-    spawner: &Spawner,
-    net_device: cyw43::NetDriver<'static>,
-    seed: u64,
-) -> &'static embassy_net::Stack<'static> {
-    let mut default_config = DhcpConfig::default();
-    // Hostname is a valid const string, so this won't fail
-    default_config.hostname = "alarmclck".try_into().ok();
-    let config = Config::dhcpv4(default_config);
-
-    let (stack, runner) = embassy_net::new(
-        net_device,
-        config,
-        NETWORK_RESOURCES.init(StackResources::<5>::new()),
-        seed,
-    );
-    let stack = NETWORK_STACK.init(stack);
-    spawner.spawn(unwrap!(net_task(runner)));
-    stack
+/// Check that we may follow a redirect, see `redirect_allowed`
+fn check_redirect_allowed(url: &str, target: &str, pinned: bool) -> Result<(), HttpError> {
+    if redirect_allowed(url, target, pinned) {
+        Ok(())
+    } else {
+        warn!("Not following the redirect to {:?}", target);
+        Err(HttpError::RedirectNotAllowed)
+    }
 }
 
-/// Connect to `WiFi` network with timeout handling.
-async fn connect_to_wifi(
-    control: &mut cyw43::Control<'static>,
-    ssid: &str,
-    password: &str,
-    timeout: Duration,
-) -> Result<(), &'static str> {
-    let join_result = with_timeout(timeout, control.join(ssid, JoinOptions::new(password.as_bytes()))).await;
-
-    match join_result {
-        Ok(Ok(())) => {
-            control.gpio_set(0, true).await;
-            info!("Connected to wifi");
-            Ok(())
-        }
-        Ok(Err(_)) => {
-            warn!("Error connecting to wifi");
-            Err("Failed to join network")
-        }
-        Err(_) => {
-            warn!("Timeout while trying to connect to wifi");
-            Err("Connection timeout")
+/// Act on the status and headers of a response: return the redirect location, fail with the status,
+/// or read the `datetime` field from the body of a successful response.
+async fn read_response<C>(response: Response<'_, '_, C>) -> Result<Fetched, TimeSyncError>
+where
+    C: Read,
+{
+    let status = response.status.0;
+    let mut date = None;
+    let mut retry_after = None;
+    let mut location = None;
+    for (name, value) in response.headers() {
+        let Ok(value) = from_utf8(value) else {
+            continue;
+        };
+        if name.eq_ignore_ascii_case("date") {
+            date = StringUtils::parse_http_date(value).ok();
+        } else if name.eq_ignore_ascii_case("retry-after") {
+            retry_after = Some(value);
+        } else if name.eq_ignore_ascii_case("location") {
+            location = Some(value);
         }
     }
-}
 
-/// Wait for network to be ready (DHCP and link up).
-async fn wait_for_network_ready(stack: &embassy_net::Stack<'static>) -> Result<(), &'static str> {
-    // Wait for DHCP
-    let mut timeout_counter = 0;
-    while !stack.is_config_up() {
-        Timer::after_millis(100).await;
-        timeout_counter += 1;
-        if timeout_counter > 100 {
-            warn!("DHCP timeout");
-            return Err("DHCP timeout");
-        }
+    if is_redirect(status) {
+        let location = location.ok_or(HttpError::Redirect(RedirectError::InvalidLocation))?;
+        let location = heapless::String::try_from(location).map_err(|_| HttpError::Redirect(RedirectError::TooLong))?;
+        return Ok(Fetched::Redirect(location));
     }
 
-    // Wait for link
-    timeout_counter = 0;
-    while !stack.is_link_up() {
-        Timer::after_millis(500).await;
-        timeout_counter += 1;
-        if timeout_counter > 100 {
-            warn!("Link timeout");
-            return Err("Link timeout");
+    if !is_success(status) {
+        let retry_after_secs = retry_after.and_then(|value| parse_retry_after(value, date.as_ref()));
+        warn!(
+            "Time API responded with status {:?}, retry after {:?} seconds",
+            status, retry_after_secs
+        );
+        return Err(HttpError::Status {
+            code: status,
+            retry_after_secs,
         }
+        .into());
     }
 
-    stack.wait_config_up().await;
-    Ok(())
+    let datetime = read_datetime_field(response.body().reader()).await;
+    Ok(Fetched::Time(TimeResponse { datetime, date }))
 }
 
-/// API response structure for time data.
-#[derive(Deserialize)]
-struct ApiResponse<'a> {
-    /// ISO 8601 datetime string
-    datetime: &'a str,
-    /// Day of week (0-6, where 0 is Sunday)
-    day_of_week: u8,
+/// Read the response body in chunks and extract the `datetime` field while it streams in.
+/// The response holds more fields (like `day_of_week`), we only need the datetime and compute the rest from it.
+async fn read_datetime_field<R>(mut body: R) -> Result<heapless::String<DATETIME_CAPACITY>, TimeSyncError>
+where
+    R: Read<Error = reqwless::Error>,
+{
+    let mut extractor = JsonFieldExtractor::<DATETIME_CAPACITY>::new("datetime");
+    let mut chunk = [0; BODY_CHUNK_SIZE];
+    loop {
+        let read = body.read(&mut chunk).await.map_err(HttpError::Body)?;
+        // stop reading as soon as we have the field, the rest of the body is of no interest
+        if read == 0 || extractor.feed(&chunk[..read]).map_err(TimeSyncError::Json)? {
+            break;
+        }
+    }
+    extractor.finish().map_err(TimeSyncError::Json)
 }
 
-/// Fetch time data from the `API` using static buffers.
-#[allow(clippy::significant_drop_tightening)]
-async fn fetch_time_from_api(
-    stack: &embassy_net::Stack<'static>,
-    url: &str,
-    seed: u64,
-) -> Result<heapless::String<8192>, &'static str> {
-    let mut buffers_guard = HTTP_BUFFERS.lock().await;
-    let buffers = buffers_guard.as_mut().ok_or("HTTP buffers not available")?;
-
-    let client_state = TcpClientState::<1, 1024, 1024>::new();
+/// Make a plain HTTP `GET` request.
+async fn fetch_over_http(stack: &Stack<'static>, url: &str, rx_buffer: &mut [u8]) -> Result<Fetched, TimeSyncError> {
+    let client_state = TcpClientState::<1, TCP_RX_BUFFER_SIZE, TCP_TX_BUFFER_SIZE>::new();
     let tcp_client = TcpClient::new(*stack, &client_state);
     let dns_client = dns::DnsSocket::new(*stack);
-    let _tls_config = TlsConfig::new(
-        seed,
-        &mut buffers.tls_read_buffer,
-        &mut buffers.tls_write_buffer,
-        TlsVerify::None,
-    );
 
     let mut http_client = HttpClient::new(&tcp_client, &dns_client);
 
+    // the request is created by resolving the host and connecting to it
     let mut request = http_client
         .request(Method::GET, url)
         .await
-        .map_err(|_| "Failed to create HTTP request")?;
+        .map_err(HttpError::Request)?;
 
-    let response = request
-        .send(&mut buffers.rx_buffer)
-        .await
-        .map_err(|_| "Failed to send HTTP request")?;
+    let response = request.send(rx_buffer).await.map_err(HttpError::Send)?;
+
+    read_response(response).await
+}
+
+/// Make an HTTPS `GET` request, accepting only the pinned server.
+///
+/// `reqwless` can only do TLS without verifying the server, so we resolve the host, connect and open the
+/// TLS connection with our own verifier ourselves, and hand the open connection to `reqwless` for the request.
+async fn fetch_over_pinned_tls(
+    stack: &Stack<'static>,
+    url: &str,
+    pin: Pin,
+    buffers: &mut HttpBuffers,
+) -> Result<Fetched, TimeSyncError> {
+    let url = Url::parse(url).map_err(|_| HttpError::InvalidUrl)?;
 
-    let response_bytes = response
-        .body()
-        .read_to_end()
+    let dns_client = dns::DnsSocket::new(*stack);
+    let address = dns_client
+        .get_host_by_name(url.host(), AddrType::IPv4)
         .await
-        .map_err(|_| "Failed to read response body")?;
+        .map_err(HttpError::Dns)?;
 
-    let body_str = from_utf8(response_bytes).map_err(|_| "Failed to parse response as UTF-8")?;
+    let client_state = TcpClientState::<1, TCP_RX_BUFFER_SIZE, TCP_TX_BUFFER_SIZE>::new();
+    let tcp_client = TcpClient::new(*stack, &client_state);
+    let socket = tcp_client
+        .connect(SocketAddr::new(address, url.port_or_default()))
+        .await
+        .map_err(HttpError::Connect)?;
 
-    info!("Response body: {:?}", &body_str);
+    let tls_config = TlsConfig::new().with_server_name(url.host());
+    let mut tls = TlsConnection::new(socket, &mut buffers.tls_read_buffer, &mut buffers.tls_write_buffer);
+    tls.open(TlsContext::new(&tls_config, PinnedProvider::new(pin)))
+        .await
+        .map_err(|e| {
+            warn!("TLS handshake with the time server failed: {:?}", e);
+            HttpError::Tls(e)
+        })?;
+    info!("TLS connection to the pinned time server established");
+
+    let mut connection = HttpConnection::Tls(tls);
+    let request = Request::get(url.path()).host(url.host()).build();
+    let response = connection
+        .send(request, &mut buffers.rx_buffer)
+        .await
+        .map_err(HttpError::Send)?;
 
-    // Copy to a heapless string to avoid lifetime issues
-    heapless::String::try_from(body_str).map_err(|_| "Response too large for buffer")
+    read_response(response).await
 }
 
-/// Parse the time `API` response and return datetime and day of week.
-fn parse_time_response(body: &str) -> Result<(&str, u8), &'static str> {
-    let bytes = body.as_bytes();
-    let response: ApiResponse = serde_json_core::de::from_slice::<ApiResponse>(bytes)
-        .map_err(|_| "Failed to parse JSON response")?
-        .0;
+/// The time taken from a response of the time `API`
+struct SyncedTime {
+    /// The local time
+    datetime: DateTime,
+    /// The UTC offset the time was given in, `None` if the response did not tell
+    utc_offset_minutes: Option<i16>,
+    /// Where the time came from, the body or the coarse fallback of the `Date` header
+    source: TimeSource,
+}
 
-    info!("Datetime: {:?}", response.datetime);
-    info!("Day of week: {:?}", response.day_of_week);
+/// Take the time from the response: the datetime of the body, or the `Date` header as a coarse fallback.
+/// The `Date` header is in UTC, it is converted to local time with the UTC offset of the last successful sync.
+/// Without a known offset there is no fallback, and across a daylight saving change the fallback is an hour off
+/// until the next sync with a usable body.
+fn parse_time_response(
+    response: TimeResponse,
+    last_utc_offset_minutes: Option<i16>,
+) -> Result<SyncedTime, TimeSyncError> {
+    let body_error = match response
+        .datetime
+        .and_then(|datetime| StringUtils::parse_datetime(&datetime).map_err(TimeSyncError::DateTime))
+    {
+        Ok(ParsedDateTime {
+            datetime,
+            utc_offset_minutes,
+        }) => {
+            info!("UTC offset: {:?} minutes", utc_offset_minutes);
+            return Ok(SyncedTime {
+                datetime,
+                utc_offset_minutes,
+                source: TimeSource::Api,
+            });
+        }
+        Err(error) => error,
+    };
 
-    Ok((response.datetime, response.day_of_week))
+    let (Some(date), Some(utc_offset_minutes)) = (response.date, last_utc_offset_minutes) else {
+        return Err(body_error);
+    };
+    warn!(
+        "Unusable response body: {:?}, falling back to the Date header",
+        body_error
+    );
+    Ok(SyncedTime {
+        datetime: calendar::add_minutes(&date, i32::from(utc_offset_minutes)),
+        utc_offset_minutes: None,
+        source: TimeSource::DateHeader,
+    })
 }
 
 /// Update the RTC with the fetched time data.
+/// Returns the drift of the RTC against the fetched time in seconds, if it could be measured.
 #[allow(clippy::significant_drop_tightening)]
-async fn update_rtc_with_time(datetime_str: &str, day_of_week: u8) -> Result<(), &'static str> {
-    let dt = StringUtils::convert_str_to_datetime(datetime_str, day_of_week);
-
-    {
+pub async fn update_rtc_with_time(dt: DateTime) -> Result<Option<i64>, TimeSyncError> {
+    let drift_secs = {
         let mut rtc_guard = RTC_MUTEX.lock().await;
-        let rtc = rtc_guard.as_mut().ok_or("RTC not initialized")?;
-        rtc.set_datetime(dt).map_err(|_| "Failed to set datetime")?;
-    }
+        let rtc = rtc_guard.as_mut().ok_or(TimeSyncError::RtcNotInitialized)?;
+        // the RTC is not running before the first sync after boot, then there is no drift to measure
+        let drift_secs = rtc_now(rtc).map(|previous| calendar::to_unix_time(&previous) - calendar::to_unix_time(&dt));
+        rtc.set_datetime(dt.into()).map_err(TimeSyncError::Rtc)?;
+        drift_secs
+    };
 
-    // Send event to state manager
-    send_event(Event::RtcUpdated).await;
-    Ok(())
+    Ok(drift_secs)
 }
 
-/// Disconnect from `WiFi` and turn off `LED`.
-async fn disconnect_wifi(control: &mut cyw43::Control<'static>) {
-    control.leave().await;
-    control.gpio_set(0, false).await;
-    info!("Disconnected from wifi");
+/// Outcome of a successful time update cycle
+struct TimeSync {
+    /// Drift of the RTC against the fetched time in seconds, if it could be measured
+    drift_secs: Option<i64>,
+    /// UTC offset of the fetched time in minutes, if the response told
+    utc_offset_minutes: Option<i16>,
 }
 
 /// Handle the retry delay after an error.
-async fn handle_retry_delay(retry_secs: u64, error_msg: &str) {
-    warn!("{} Retrying in {:?} seconds", error_msg, retry_secs);
-    Timer::after(Duration::from_secs(retry_secs)).await;
+/// A resume signal (waking up from standby) cuts the delay short, so that the time is fetched right away.
+async fn handle_retry_delay(retry_secs: u64, error: &TimeSyncError) {
+    warn!("{:?} Retrying in {:?} seconds", error, retry_secs);
+    let retry_timer = Timer::after(Duration::from_secs(retry_secs));
+    select(retry_timer, wait_for_time_updater_resume()).await;
 }
 
-/// Main time updater task that periodically connects to `WiFi`, fetches time from an API,
+/// Main time updater task that periodically borrows the network connection, fetches time from an API,
 /// and updates the `RTC`.
 ///
-/// This task manages the entire lifecycle of `WiFi` connectivity, `HTTP` requests,
-/// and `RTC` synchronization.
+/// This task manages the `HTTP` requests and `RTC` synchronization, the `WiFi` connection is managed
+/// by the network task.
 #[allow(clippy::large_futures)]
 #[embassy_executor::task]
-pub async fn time_updater(spawner: Spawner, rtc: Rtc<'static, peripherals::RTC>, wifi_peripherals: WifiPeripherals) {
+pub async fn time_updater(spawner: Spawner, rtc: Rtc<'static, peripherals::RTC>) {
     info!("time updater task started");
 
     // Initialize RTC task
     info!("init rtc");
     spawner.spawn(unwrap!(rtc_task(rtc)));
 
-    // Initialize WiFi and network stack
-    let (mut control, net_device) = setup_wifi(&spawner, wifi_peripherals).await;
-
     let mut rng = RoscRng;
-    let seed = rng.next_u64();
-
-    let stack = setup_network_stack(&spawner, net_device, seed);
 
     // Get configuration
-    let time_updater = TimeUpdater::new();
-    let (ssid, password) = time_updater.credentials();
+    let mut time_updater = TimeUpdater::new();
 
     info!("starting loop");
     loop {
@@ -466,81 +595,96 @@ pub async fn time_updater(spawner: Spawner, rtc: Rtc<'static, peripherals::RTC>,
         }
 
         // Attempt to update time
-        if let Err(error_msg) = update_time_once(&mut control, stack, ssid, password, &time_updater, seed).await {
-            // Report failure to watchdog on error path
-            report_task_failure(TaskId::TimeUpdater).await;
-            handle_retry_delay(time_updater.retry_after_secs, error_msg).await;
-            continue;
+        let sync = match update_time_once(&time_updater).await {
+            Ok(sync) => sync,
+            Err(error) => {
+                // An unreachable router or server is expected every now and then and is handled by backing off.
+                // Only local failures indicate that something is wrong with the system itself.
+                let kind = error.failure_kind();
+                if kind == FailureKind::Local {
+                    report_task_failure(TaskId::TimeUpdater).await;
+                }
+                try_send_event(Event::Error(SystemError::TimeSync(error.clone())));
+                // a server asking us to come back later gets at least the delay it asked for
+                let retry_after_secs = error.retry_after_secs().unwrap_or(0).min(MAX_RETRY_AFTER_SECS);
+                let retry_secs = time_updater
+                    .backoff
+                    .record_failure(kind, rng.next_u32())
+                    .max(retry_after_secs);
+                handle_retry_delay(retry_secs, &error).await;
+                continue;
+            }
+        };
+
+        // the watchdog was told about the success before the extras were fetched
+        time_updater.backoff.reset();
+
+        if sync.utc_offset_minutes.is_some() {
+            time_updater.utc_offset_minutes = sync.utc_offset_minutes;
         }
 
-        // Successfully updated - report to watchdog before sleeping
-        report_task_success(TaskId::TimeUpdater).await;
+        // Adapt the refresh interval to how well the RTC kept time since the last sync
+        let now = Instant::now();
+        let elapsed_secs = time_updater
+            .last_sync
+            .map_or(0, |last_sync| now.duration_since(last_sync).as_secs());
+        time_updater.last_sync = Some(now);
+        time_updater.refresh.update(sync.drift_secs, elapsed_secs);
+        if let Some(drift_secs) = sync.drift_secs {
+            info!("RTC drift since last sync: {:?} seconds", drift_secs);
+        }
 
         // Wait for next refresh
         info!(
             "Waiting for {:?} seconds before reconnecting",
-            time_updater.refresh_after_secs
+            time_updater.refresh.interval_secs()
         );
-        let downtime_timer = Timer::after(Duration::from_secs(time_updater.refresh_after_secs));
+        let downtime_timer = Timer::after(Duration::from_secs(time_updater.refresh.interval_secs()));
         select(downtime_timer, wait_for_time_updater_resume()).await;
     }
 }
 
-/// Perform a single time update cycle.
-async fn update_time_once(
-    control: &mut cyw43::Control<'static>,
-    stack: &embassy_net::Stack<'static>,
-    ssid: &str,
-    password: &str,
-    config: &TimeUpdater,
-    seed: u64,
-) -> Result<(), &'static str> {
-    // Set performance mode for connection
-    control
-        .set_power_management(cyw43::PowerManagementMode::Performance)
-        .await;
-
-    // Connect to WiFi
-    if let Err(e) = connect_to_wifi(control, ssid, password, config.timeout_duration).await {
-        disconnect_wifi(control).await;
-        return Err(e);
-    }
-
-    // Wait for network to be ready
-    if let Err(e) = wait_for_network_ready(stack).await {
-        disconnect_wifi(control).await;
-        return Err(e);
-    }
-
-    // Fetch time from API
-    let body = match fetch_time_from_api(stack, config.time_api_url(), seed).await {
-        Ok(b) => b,
-        Err(e) => {
-            disconnect_wifi(control).await;
-            return Err(e);
-        }
-    };
-
-    // Parse the response
-    let (datetime_str, day_of_week) = match parse_time_response(&body) {
-        Ok(data) => data,
-        Err(e) => {
-            disconnect_wifi(control).await;
-            return Err(e);
-        }
-    };
-
-    // Update RTC
-    if let Err(e) = update_rtc_with_time(datetime_str, day_of_week).await {
-        disconnect_wifi(control).await;
-        return Err(e);
+/// Perform a single time update cycle, holding the network connection while fetching the time, the forecast, the
+/// calendar and a firmware update. Returns the measured drift of the RTC and the UTC offset of the fetched time.
+///
+/// The forecast, the calendar and the firmware update ride along on the connection only after a successful sync.
+/// Whether they fail does not matter for the time sync, and while the time server is unreachable they are not
+/// tried at every retry of the backoff. The watchdog is told about the sync before them, so that their downloads do
+/// not count against the time updater.
+async fn update_time_once(config: &TimeUpdater) -> Result<TimeSync, TimeSyncError> {
+    let stack = acquire_network().await?;
+    let result = sync_time(&stack, config).await;
+    if let Ok(sync) = &result {
+        report_task_success(TaskId::TimeUpdater).await;
+        update_forecast(&stack).await;
+        update_calendar(&stack, sync.utc_offset_minutes.or(config.utc_offset_minutes)).await;
+        // last, as installing an update restarts the system
+        update_firmware(&stack).await;
     }
+    release_network().await;
+    result
+}
 
-    // Cleanup
-    disconnect_wifi(control).await;
-    control
-        .set_power_management(cyw43::PowerManagementMode::Aggressive)
-        .await;
-
-    Ok(())
+/// Fetch the time from the API and update the RTC with it.
+async fn sync_time(stack: &Stack<'static>, config: &TimeUpdater) -> Result<TimeSync, TimeSyncError> {
+    let response = fetch_time_from_api(stack, config.time_api_url(), config.time_api_pin()).await?;
+    let synced = parse_time_response(response, config.utc_offset_minutes)?;
+    let synced_at = calendar::to_unix_time(&synced.datetime);
+    let drift_secs = update_rtc_with_time(synced.datetime).await?;
+
+    // the fallback time is only accurate to the second at best, that does not tell anything about the drift
+    let drift_secs = drift_secs.filter(|_| synced.source == TimeSource::Api);
+
+    // Report the sync to the state manager
+    send_event(Event::RtcUpdated(TimeSyncReport {
+        synced_at,
+        source: synced.source,
+        drift_secs,
+        utc_offset_minutes: synced.utc_offset_minutes.or(config.utc_offset_minutes),
+    }))
+    .await;
+    Ok(TimeSync {
+        drift_secs,
+        utc_offset_minutes: synced.utc_offset_minutes,
+    })
 }
@@ -7,18 +7,44 @@
 //! The watchdog will trigger a system reset if:
 //! - Critical tasks don't report success within the countdown period
 //! - The countdown timer expires without all tasks being healthy
+//!
+//! It also owns the hardware watchdog for restarts that are asked for, e.g. on the console. The bootloader leaves the
+//! hardware watchdog running when it starts the firmware. It is fed until the firmware is confirmed, so that an
+//! updated firmware that hangs before is restarted and rolled back by the bootloader, then it is stopped.
+//!
+//! An updated firmware waits for the monitored tasks that report periodically to be healthy before it confirms
+//! itself, see `task::firmware_update`. The alarm trigger is not waited for, it does not report while the alarm is
+//! disabled or rings on no day. A firmware that is not on trial releases the hardware watchdog right away.
 
 use defmt::{Format, info, warn};
+use embassy_futures::select::{Either3, select3};
 use embassy_rp::{Peri, peripherals::WATCHDOG, watchdog::Watchdog};
-use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex, signal::Signal};
 use embassy_time::{Duration, Instant, Timer};
 
+use crate::{task::time_updater::MAX_REFRESH_SECS, warn_remote};
+
 /// How long our custom countdown timer runs before triggering a reset (15 minutes)
 const COUNTDOWN_TIMEOUT: Duration = Duration::from_secs(900);
 /// How often we check task health and update our countdown
 const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(60);
-/// Hardware watchdog timeout (short, used only for actual reset)
+/// Hardware watchdog timeout (short, used for actual resets and while the firmware is not confirmed)
 const HARDWARE_WATCHDOG_TIMEOUT: Duration = Duration::from_millis(8000);
+/// How often the hardware watchdog is fed until the firmware is confirmed, well within its timeout
+const HARDWARE_WATCHDOG_FEED_INTERVAL: Duration = Duration::from_secs(2);
+/// How often the health is checked while waiting for all tasks to be healthy
+const ALL_HEALTHY_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// All monitored tasks, in the order of `SystemHealth::tasks`
+const MONITORED_TASKS: [TaskId; 4] = [
+    TaskId::Orchestrator,
+    TaskId::Display,
+    TaskId::AlarmTrigger,
+    TaskId::TimeUpdater,
+];
+
+// an updated firmware installed while the alarm is disabled must get confirmed, the alarm trigger does not report then
+const _: () = assert!(!TaskId::AlarmTrigger.reports_periodically());
 
 /// Task identifiers for health tracking
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Format)]
@@ -34,12 +60,22 @@ pub enum TaskId {
 }
 
 impl TaskId {
+    /// Whether this task reports periodically whatever the settings, only those decide whether an updated firmware is
+    /// healthy. The alarm trigger waits without reporting while the alarm is disabled or rings on no day.
+    const fn reports_periodically(self) -> bool {
+        match self {
+            Self::Orchestrator | Self::Display | Self::TimeUpdater => true,
+            Self::AlarmTrigger => false,
+        }
+    }
+
     /// Returns the maximum time allowed between health reports for this task
     const fn max_report_interval(self) -> Duration {
         match self {
             Self::Orchestrator | Self::Display => Duration::from_secs(120), // 2 minutes
             Self::AlarmTrigger => Duration::from_secs(300),                 // 5 minutes
-            Self::TimeUpdater => Duration::from_secs(25200),                // 7 hours (refreshes every 6h)
+            // reports after every successful sync: the longest refresh interval, and an hour for the sync and retries
+            Self::TimeUpdater => Duration::from_secs(MAX_REFRESH_SECS + 3_600),
         }
     }
 }
@@ -120,15 +156,9 @@ impl SystemHealth {
         }
 
         // Check which tasks are unhealthy
-        let task_ids = [
-            TaskId::Orchestrator,
-            TaskId::Display,
-            TaskId::AlarmTrigger,
-            TaskId::TimeUpdater,
-        ];
         let mut unhealthy_count = 0;
 
-        for (index, task_id) in task_ids.iter().enumerate() {
+        for (index, task_id) in MONITORED_TASKS.iter().enumerate() {
             let task = &self.tasks[index];
 
             // Skip tasks that haven't reported yet (still initializing)
@@ -138,7 +168,7 @@ impl SystemHealth {
 
             if !task.is_healthy(task_id.max_report_interval()) {
                 unhealthy_count += 1;
-                warn!("Task {:?} is unhealthy", task_id);
+                warn_remote!("Task {:?} is unhealthy", task_id);
             }
         }
 
@@ -153,12 +183,12 @@ impl SystemHealth {
             self.countdown_deadline = Some(Instant::now() + COUNTDOWN_TIMEOUT);
         } else if self.countdown_deadline.is_none() {
             // First detection of unhealthy tasks - start countdown
-            warn!("{} task(s) unhealthy, starting countdown", unhealthy_count);
+            warn_remote!("{} task(s) unhealthy, starting countdown", unhealthy_count);
             self.countdown_deadline = Some(Instant::now() + COUNTDOWN_TIMEOUT);
         } else {
             // Countdown already running - just log status
             if let Some(remaining) = self.time_until_reset() {
-                warn!(
+                warn_remote!(
                     "{} task(s) still unhealthy, {} seconds until reset",
                     unhealthy_count,
                     remaining.as_secs()
@@ -167,6 +197,16 @@ impl SystemHealth {
         }
     }
 
+    /// Check if every monitored task that reports periodically has reported and is healthy, unlike the countdown this
+    /// does not skip tasks that have not reported yet
+    fn periodic_tasks_healthy(&self) -> bool {
+        MONITORED_TASKS
+            .iter()
+            .zip(self.tasks.iter())
+            .filter(|(task_id, _)| task_id.reports_periodically())
+            .all(|(task_id, task)| task.has_reported && task.is_healthy(task_id.max_report_interval()))
+    }
+
     /// Check if countdown has expired and we should trigger hardware watchdog
     fn should_trigger_reset(&self) -> bool {
         if self.in_startup_grace_period() {
@@ -193,6 +233,22 @@ impl SystemHealth {
 /// Global system health tracker
 static SYSTEM_HEALTH: Mutex<CriticalSectionRawMutex, SystemHealth> = Mutex::new(SystemHealth::new());
 
+/// Signal for a restart that was asked for
+static RESET_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Signal that the firmware is confirmed and the hardware watchdog no longer needs to be fed
+static FIRMWARE_CONFIRMED_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Restart the system right away, e.g. on the console or after a factory reset
+pub fn request_reset() {
+    RESET_SIGNAL.signal(());
+}
+
+/// Stop feeding the hardware watchdog and stop it, once the firmware is confirmed or not on trial at all
+pub fn release_hardware_watchdog() {
+    FIRMWARE_CONFIRMED_SIGNAL.signal(());
+}
+
 /// Report a successful task iteration
 ///
 /// Only critical tasks should call this periodically to indicate they are functioning correctly.
@@ -207,13 +263,24 @@ pub async fn report_task_success(task_id: TaskId) {
     health.set_task_succeeded(task_id);
 }
 
+/// Wait until every monitored task that reports periodically has reported and is healthy, e.g. to confirm an updated
+/// firmware, which must not depend on the alarm settings
+pub async fn wait_until_periodic_tasks_healthy() {
+    loop {
+        if SYSTEM_HEALTH.lock().await.periodic_tasks_healthy() {
+            return;
+        }
+        Timer::after(ALL_HEALTHY_CHECK_INTERVAL).await;
+    }
+}
+
 /// Report a failed task iteration
 ///
 /// Tasks should call this when they encounter critical errors that might indicate
 /// system instability. This immediately marks the task as unhealthy and will prevent
 /// the countdown timer from resetting until the task reports success again.
 pub async fn report_task_failure(task_id: TaskId) {
-    warn!("Task {:?} reported failure", task_id);
+    warn_remote!("Task {:?} reported failure", task_id);
     let mut health = SYSTEM_HEALTH.lock().await;
     let index = task_id as usize;
     // Clear the last report time to mark as unhealthy
@@ -225,12 +292,19 @@ pub async fn report_task_failure(task_id: TaskId) {
 ///
 /// This task periodically checks the health of all monitored tasks. If all tasks
 /// are healthy, it resets the countdown timer. If the countdown expires while
-/// tasks are unhealthy, it triggers a hardware watchdog reset.
+/// tasks are unhealthy, it triggers a hardware watchdog reset. Until the firmware
+/// is confirmed it also feeds the hardware watchdog the bootloader left running.
 ///
 /// # Arguments
 /// * `watchdog` - The watchdog peripheral from the RP2040
 #[embassy_executor::task]
 pub async fn watchdog_task(watchdog: Peri<'static, WATCHDOG>) {
+    // the bootloader leaves the hardware watchdog running, it is fed until the firmware is confirmed. Starting it
+    // again sets the timeout that feeding reloads.
+    let mut wd = Watchdog::new(watchdog);
+    wd.start(HARDWARE_WATCHDOG_TIMEOUT);
+    let mut feeding = true;
+
     info!("Watchdog started - monitoring Orchestrator, Display, AlarmTrigger, TimeUpdater");
     info!(
         "Countdown: {}s, health checks every {}s, startup grace: 120s",
@@ -249,8 +323,7 @@ pub async fn watchdog_task(watchdog: Peri<'static, WATCHDOG>) {
         if should_reset {
             warn!("Countdown expired - system will reset due to unhealthy tasks");
 
-            // Initialize hardware watchdog and don't feed it - this will cause reset
-            let mut wd = Watchdog::new(watchdog);
+            // Start the hardware watchdog and don't feed it - this will cause reset
             wd.pause_on_debug(false); // Don't pause during debug - we want the reset
             wd.start(HARDWARE_WATCHDOG_TIMEOUT);
 
@@ -265,7 +338,42 @@ pub async fn watchdog_task(watchdog: Peri<'static, WATCHDOG>) {
             }
         }
 
-        // Wait before next health check
-        Timer::after(HEALTH_CHECK_INTERVAL).await;
+        // Wait before next health check, feeding the hardware watchdog until the firmware is confirmed, unless a
+        // restart is asked for
+        let next_check = Instant::now() + HEALTH_CHECK_INTERVAL;
+        while Instant::now() < next_check {
+            let wake_up = if feeding {
+                next_check.min(Instant::now() + HARDWARE_WATCHDOG_FEED_INTERVAL)
+            } else {
+                next_check
+            };
+            match select3(
+                Timer::at(wake_up),
+                RESET_SIGNAL.wait(),
+                FIRMWARE_CONFIRMED_SIGNAL.wait(),
+            )
+            .await
+            {
+                Either3::First(()) => {
+                    if feeding {
+                        wd.feed();
+                    }
+                }
+                Either3::Second(()) => {
+                    info!("Restart requested - resetting the system");
+                    wd.trigger_reset();
+
+                    // the reset takes effect right away, this is never reached
+                    loop {
+                        Timer::after_secs(1).await;
+                    }
+                }
+                Either3::Third(()) => {
+                    info!("Firmware confirmed - stopping the hardware watchdog");
+                    wd.stop();
+                    feeding = false;
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,99 @@
+//! # Weather
+//! This module fetches the weather forecast of the day from Open-Meteo, the response is parsed by `utility::weather`.
+//!
+//! There is no task of its own: the forecast is fetched in the cycle of the time updater, right after a successful
+//! time sync, while the network connection is borrowed anyway. A failed fetch is reported as error and tried again in the next cycle,
+//! it never counts against the time sync.
+//!
+//! The location is configured in the optional `config/weather.json`:
+//!```json
+//! {
+//!     "latitude": 52.52,
+//!     "longitude": 13.41
+//! }
+//! ```
+//! An optional `baseurl`, e.g. `"http://192.168.1.20:8080"`, fetches from another server with the same API, like
+//! `http_standin_server.py`. Without the file no forecast is fetched.
+
+include!(concat!(env!("OUT_DIR"), "/weather_config.rs"));
+
+use defmt::{info, warn};
+use embassy_net::{
+    Stack, dns,
+    tcp::client::{TcpClient, TcpClientState},
+};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
+use reqwless::{
+    client::HttpClient,
+    request::{Method, RequestBuilder},
+};
+
+use crate::{
+    error::{HttpError, SystemError, WeatherError},
+    event::{Event, send_event, try_send_event},
+    utility::{
+        http_response::is_success,
+        weather::{Forecast, parse_forecast},
+    },
+};
+
+/// Size of the buffer for the response, the headers and the whole body, which is about 500 bytes
+const FORECAST_BUFFER_SIZE: usize = 2048;
+
+/// Size of the `TCP` socket receive buffer
+const TCP_RX_BUFFER_SIZE: usize = 512;
+
+/// Size of the `TCP` socket transmit buffer, the request is well below that
+const TCP_TX_BUFFER_SIZE: usize = 256;
+
+/// Static buffer for the response, so that it does not live in the future of the time updater
+static FORECAST_BUFFER: Mutex<CriticalSectionRawMutex, [u8; FORECAST_BUFFER_SIZE]> =
+    Mutex::new([0; FORECAST_BUFFER_SIZE]);
+
+/// Fetch the forecast of the day and report it to the orchestrator, a failure is only reported as error.
+/// Does nothing if no location is configured.
+pub async fn update_forecast(stack: &Stack<'static>) {
+    let Some(url) = FORECAST_URL else {
+        return;
+    };
+    match fetch_forecast(stack, url).await {
+        Ok(forecast) => {
+            info!("Forecast: {:?}", forecast);
+            send_event(Event::ForecastUpdated(forecast)).await;
+        }
+        Err(error) => {
+            warn!("Fetching the forecast failed: {:?}", error);
+            try_send_event(Event::Error(SystemError::Weather(error)));
+        }
+    }
+}
+
+/// Make the `GET` request for the forecast and parse the response
+#[allow(clippy::significant_drop_tightening)]
+async fn fetch_forecast(stack: &Stack<'static>, url: &str) -> Result<Forecast, WeatherError> {
+    let mut buffer = FORECAST_BUFFER.lock().await;
+
+    let client_state = TcpClientState::<1, TCP_RX_BUFFER_SIZE, TCP_TX_BUFFER_SIZE>::new();
+    let tcp_client = TcpClient::new(*stack, &client_state);
+    let dns_client = dns::DnsSocket::new(*stack);
+    let mut http_client = HttpClient::new(&tcp_client, &dns_client);
+
+    let mut request = http_client
+        .request(Method::GET, url)
+        .await
+        .map_err(HttpError::Request)?;
+    let response = request.send(buffer.as_mut_slice()).await.map_err(HttpError::Send)?;
+
+    let status = response.status.0;
+    if !is_success(status) {
+        return Err(HttpError::Status {
+            code: status,
+            retry_after_secs: None,
+        }
+        .into());
+    }
+
+    // the body is small, it is read as a whole into what is left of the buffer
+    let body = response.body().read_to_end().await.map_err(HttpError::Body)?;
+    Ok(parse_forecast(body)?)
+}
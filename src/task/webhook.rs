@@ -0,0 +1,231 @@
+//! # Webhook Task
+//! This module contains the task that fires HTTP webhooks on the alarm lifecycle, e.g. to start a coffee machine when
+//! the alarm is raised, turn on the room lights when the sunrise has finished or notify a phone when the alarm expired
+//! unattended.
+//!
+//! The webhooks are configured in the optional `config/webhooks.json`:
+//!```json
+//! {
+//!     "webhooks": [
+//!         { "event": "alarm", "url": "http://192.168.1.20/coffee", "method": "POST", "body": { "on": true } },
+//!         { "event": "alarm_expired", "url": "http://192.168.1.21/notify", "body": "{hostname}: alarm at {alarm} expired" }
+//!     ]
+//! }
+//! ```
+//! - `event`: `alarm`, `sunrise_finished`, `alarm_stop` or `alarm_expired`
+//! - `url`: a plain `http` URL
+//! - `method`: `GET`, `POST` (default) or `PUT`
+//! - `body`: optional, a JSON value or a string with placeholders, see `utility::webhook`. A JSON value is sent as
+//!   `application/json`, a string as `text/plain`.
+//!
+//! The orchestrator only queues the event, the webhooks are delivered here. Each delivery borrows the network
+//! connection and is retried with exponential backoff, a few times at most. Events that come in while a delivery is
+//! retried wait in the queue, when the queue is full they are dropped.
+
+include!(concat!(env!("OUT_DIR"), "/webhook_config.rs"));
+
+use core::fmt::Write as _;
+
+use defmt::{info, warn};
+use embassy_net::{
+    Stack, dns,
+    tcp::client::{TcpClient, TcpClientState},
+};
+use embassy_rp::clocks::RoscRng;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_time::{Duration, Timer};
+use heapless::String;
+use reqwless::{
+    client::HttpClient,
+    headers::ContentType,
+    request::{Method, RequestBuilder},
+};
+
+use crate::{
+    error::{HttpError, SystemError, WebhookError},
+    event::{Event, try_send_event},
+    task::{
+        network::{HOSTNAME, acquire_network, release_network},
+        time_updater::{RTC_MUTEX, rtc_now},
+    },
+    utility::{
+        backoff::Backoff,
+        http_response::is_success,
+        webhook::{BodyFormat, TemplateValues, WebhookEvent, render_template},
+    },
+};
+
+/// A webhook, generated from `webhooks.json`
+#[derive(Debug, Clone, Copy)]
+pub struct Webhook {
+    /// The event the webhook is fired on
+    pub event: WebhookEvent,
+    /// The HTTP method of the request
+    pub method: Method,
+    /// The URL of the request, plain `http`
+    pub url: &'static str,
+    /// The template of the body, `None` for a request without body
+    pub body: Option<&'static str>,
+    /// Whether the body is JSON or plain text
+    pub body_format: BodyFormat,
+}
+
+/// An event webhooks are fired on, with the values for the placeholders that only the orchestrator knows
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct WebhookTrigger {
+    /// The event
+    event: WebhookEvent,
+    /// The alarm time in (hour, minute)
+    alarm_time: (u8, u8),
+}
+
+/// The number of events waiting for their webhooks to be delivered
+const QUEUE_CAPACITY: usize = 8;
+
+/// The queue of events waiting for their webhooks to be delivered
+static WEBHOOK_QUEUE: Channel<CriticalSectionRawMutex, WebhookTrigger, QUEUE_CAPACITY> = Channel::new();
+
+/// Number of attempts to deliver a webhook
+const MAX_ATTEMPTS: u32 = 4;
+
+/// Capacity for the rendered body of a webhook
+const BODY_CAPACITY: usize = 512;
+
+/// Size of the receive buffer, which must hold the response headers
+const RX_BUFFER_SIZE: usize = 1024;
+
+/// Size of the `TCP` socket receive buffer
+const TCP_RX_BUFFER_SIZE: usize = 512;
+
+/// Size of the `TCP` socket transmit buffer, the request and the body
+const TCP_TX_BUFFER_SIZE: usize = 1024;
+
+/// Queue the webhooks of an event without waiting, the event is dropped if the queue is full.
+/// Nothing is queued if no webhook is configured for the event.
+pub fn queue_webhooks(event: WebhookEvent, alarm_time: (u8, u8)) {
+    if !WEBHOOKS.iter().any(|webhook| webhook.event == event) {
+        return;
+    }
+    if WEBHOOK_QUEUE.try_send(WebhookTrigger { event, alarm_time }).is_err() {
+        warn!("Webhook queue full, dropping {:?}", event);
+    }
+}
+
+/// This task delivers the webhooks of the queued events, one after the other.
+#[embassy_executor::task]
+pub async fn webhook_task() {
+    info!("Webhook task starting, {} webhooks configured", WEBHOOKS.len());
+    let mut rx_buffer = [0; RX_BUFFER_SIZE];
+
+    loop {
+        let trigger = WEBHOOK_QUEUE.receive().await;
+
+        // the time is taken once, retries report when the event happened
+        let mut time: String<20> = String::new();
+        {
+            let rtc_guard = RTC_MUTEX.lock().await;
+            if let Some(dt) = rtc_guard.as_ref().and_then(rtc_now) {
+                let _ = write!(
+                    time,
+                    "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+                    dt.year, dt.month, dt.day, dt.hour, dt.minute, dt.second
+                );
+            }
+        }
+        let mut alarm: String<5> = String::new();
+        let _ = write!(alarm, "{:02}:{:02}", trigger.alarm_time.0, trigger.alarm_time.1);
+        let values = TemplateValues {
+            event: trigger.event,
+            hostname: HOSTNAME,
+            time: &time,
+            alarm: &alarm,
+        };
+
+        for webhook in WEBHOOKS.iter().filter(|webhook| webhook.event == trigger.event) {
+            deliver(webhook, &values, &mut rx_buffer).await;
+        }
+    }
+}
+
+/// Deliver a webhook, retrying with backoff until it succeeded or the attempts are used up
+async fn deliver(webhook: &Webhook, values: &TemplateValues<'_>, rx_buffer: &mut [u8]) {
+    let mut body: String<BODY_CAPACITY> = String::new();
+    if let Some(template) = webhook.body
+        && render_template(&mut body, template, webhook.body_format, values).is_err()
+    {
+        warn!("Webhook {}: body does not fit the buffer", webhook.url);
+        return;
+    }
+
+    let mut backoff = Backoff::new();
+    for attempt in 1..=MAX_ATTEMPTS {
+        match send_webhook(webhook, &body, rx_buffer).await {
+            Ok(()) => {
+                info!("Webhook {} delivered for {:?}", webhook.url, values.event);
+                return;
+            }
+            Err(error) => {
+                warn!("Webhook {} failed, attempt {}: {:?}", webhook.url, attempt, error);
+                let delay_secs = backoff.record_failure(error.failure_kind(), RoscRng.next_u32());
+                try_send_event(Event::Error(SystemError::Webhook(error)));
+                if attempt < MAX_ATTEMPTS {
+                    Timer::after(Duration::from_secs(delay_secs)).await;
+                }
+            }
+        }
+    }
+    warn!("Webhook {} given up for {:?}", webhook.url, values.event);
+}
+
+/// Send the request of a webhook once, borrowing the network connection for it
+async fn send_webhook(webhook: &Webhook, body: &str, rx_buffer: &mut [u8]) -> Result<(), WebhookError> {
+    let stack = acquire_network().await?;
+    let result = request(&stack, webhook, body, rx_buffer).await;
+    release_network().await;
+    result
+}
+
+/// Make the request of a webhook, it succeeded if the server answered with a success status
+async fn request(
+    stack: &Stack<'static>,
+    webhook: &Webhook,
+    body: &str,
+    rx_buffer: &mut [u8],
+) -> Result<(), WebhookError> {
+    let client_state = TcpClientState::<1, TCP_RX_BUFFER_SIZE, TCP_TX_BUFFER_SIZE>::new();
+    let tcp_client = TcpClient::new(*stack, &client_state);
+    let dns_client = dns::DnsSocket::new(*stack);
+    let mut http_client = HttpClient::new(&tcp_client, &dns_client);
+
+    let request = http_client
+        .request(webhook.method, webhook.url)
+        .await
+        .map_err(HttpError::Request)?;
+    let status = if webhook.body.is_some() {
+        let content_type = match webhook.body_format {
+            BodyFormat::Json => ContentType::ApplicationJson,
+            BodyFormat::Text => ContentType::TextPlain,
+        };
+        request
+            .body(body.as_bytes())
+            .content_type(content_type)
+            .send(rx_buffer)
+            .await
+            .map_err(HttpError::Send)?
+            .status
+            .0
+    } else {
+        let mut request = request;
+        request.send(rx_buffer).await.map_err(HttpError::Send)?.status.0
+    };
+
+    if is_success(status) {
+        Ok(())
+    } else {
+        Err(HttpError::Status {
+            code: status,
+            retry_after_secs: None,
+        }
+        .into())
+    }
+}
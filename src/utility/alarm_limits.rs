@@ -0,0 +1,63 @@
+//! # Alarm Limits
+//! This module holds the ranges of the alarm settings and the one check against them. Everything that takes alarm
+//! settings from outside of the clock uses it, the REST API as well as the snapshots of peers, so they accept exactly
+//! the same values.
+
+/// The days the alarm rings on when no days were set, every day
+pub const ALARM_EVERY_DAY: u8 = 0x7F;
+
+/// The longest sunrise in minutes
+pub const MAX_SUNRISE_MINUTES: u8 = 30;
+
+/// The highest alarm volume the `DFPlayer` supports
+pub const MAX_ALARM_VOLUME: u8 = 30;
+
+/// Whether the given alarm values are in range, values that are `None` are not checked.
+/// The alarm must ring on at least one day and the sunrise must last at least a minute.
+pub const fn alarm_values_in_range(
+    hour: Option<u8>,
+    minute: Option<u8>,
+    days: Option<u8>,
+    sunrise_minutes: Option<u8>,
+    volume: Option<u8>,
+) -> bool {
+    !matches!(hour, Some(24..))
+        && !matches!(minute, Some(60..))
+        && !matches!(days, Some(days) if days == 0 || days & !ALARM_EVERY_DAY != 0)
+        && !matches!(sunrise_minutes, Some(minutes) if minutes == 0 || minutes > MAX_SUNRISE_MINUTES)
+        && !matches!(volume, Some(volume) if volume > MAX_ALARM_VOLUME)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The bounds of each value, one at a time
+    #[test]
+    fn checks_each_value() {
+        let hour = |hour| alarm_values_in_range(Some(hour), None, None, None, None);
+        let minute = |minute| alarm_values_in_range(None, Some(minute), None, None, None);
+        let days = |days| alarm_values_in_range(None, None, Some(days), None, None);
+        let sunrise = |minutes| alarm_values_in_range(None, None, None, Some(minutes), None);
+        let volume = |volume| alarm_values_in_range(None, None, None, None, Some(volume));
+        assert!(hour(0) && hour(23) && !hour(24));
+        assert!(minute(0) && minute(59) && !minute(60));
+        assert!(days(1) && days(ALARM_EVERY_DAY) && !days(0) && !days(0x80));
+        assert!(sunrise(1) && sunrise(MAX_SUNRISE_MINUTES) && !sunrise(0) && !sunrise(MAX_SUNRISE_MINUTES + 1));
+        assert!(volume(0) && volume(MAX_ALARM_VOLUME) && !volume(MAX_ALARM_VOLUME + 1));
+    }
+
+    /// Missing values are not checked, one bad value fails all
+    #[test]
+    fn skips_missing_values() {
+        assert!(alarm_values_in_range(None, None, None, None, None));
+        assert!(alarm_values_in_range(Some(6), Some(30), Some(0x1F), Some(10), Some(20)));
+        assert!(!alarm_values_in_range(
+            Some(6),
+            Some(30),
+            Some(0x1F),
+            Some(10),
+            Some(31)
+        ));
+    }
+}
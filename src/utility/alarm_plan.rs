@@ -0,0 +1,240 @@
+//! # Alarm Plan
+//! This module applies the calendar rules to the events of the next days and decides when the alarm rings next.
+//!
+//! Two rules can be configured, see `CalendarRules`:
+//! - skip: the alarm does not ring on days with an event tagged with the keyword, e.g. `OFF`
+//! - lead: on days with a timed event, the alarm rings the given minutes before the first of them instead of at the
+//!   alarm time. This also holds on days the alarm does not ring on otherwise, and may be the evening before for an
+//!   event shortly after midnight.
+//!
+//! The plan covers `PLAN_DAYS` days from the day it is made, beyond those the alarm rings as set.
+
+use core::cmp::Ordering;
+
+use crate::utility::{
+    calendar::{self, DateTime},
+    ics::CalendarEvent,
+};
+
+/// Number of days the plan covers, starting with the day it is made
+pub const PLAN_DAYS: usize = 7;
+
+/// The rules applied to the calendar, generated from `calendar.json`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct CalendarRules {
+    /// Days with an event tagged with this keyword are skipped, `None` to skip no day
+    pub skip_keyword: Option<&'static str>,
+    /// The alarm rings these minutes before the first timed event of a day, `None` to ring at the alarm time.
+    /// At most 720, so that the alarm moves at most to the evening before.
+    pub lead_minutes: Option<u16>,
+}
+
+/// What the calendar holds for a day
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DayPlan {
+    /// An event tagged with the skip keyword takes place on the day
+    pub off: bool,
+    /// The start of the first timed event of the day in (hour, minute), tagged events are not counted
+    pub first_event: Option<(u8, u8)>,
+}
+
+/// What the calendar holds for the next days
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AlarmPlan {
+    /// The first day of the plan, at the start of the day
+    first_day: DateTime,
+    /// The days of the plan, starting with the first
+    days: [DayPlan; PLAN_DAYS],
+    /// The lead of the alarm before the first event of a day, `None` if events do not move the alarm
+    lead_minutes: Option<u16>,
+}
+
+impl AlarmPlan {
+    /// Create an empty plan starting on the day of `first_day`
+    pub fn new(first_day: &DateTime, lead_minutes: Option<u16>) -> Self {
+        Self {
+            first_day: calendar::start_of_day(first_day),
+            days: [DayPlan::default(); PLAN_DAYS],
+            lead_minutes,
+        }
+    }
+
+    /// Add an event of the calendar to the days of the plan it takes place on
+    pub fn add_event(&mut self, event: &CalendarEvent) {
+        for (offset, plan) in (0..).zip(self.days.iter_mut()) {
+            let day = calendar::add_days(&self.first_day, offset);
+            if event.tagged {
+                plan.off |= event.covers(&day);
+            } else if let Some(time) = event.time
+                && event.starts_on(&day)
+            {
+                plan.first_event = Some(plan.first_event.map_or(time, |first| first.min(time)));
+            }
+        }
+    }
+
+    /// The plan of the day of `day`, `None` if the plan does not cover it
+    pub fn day(&self, day: &DateTime) -> Option<&DayPlan> {
+        usize::try_from(calendar::days_between(&self.first_day, day))
+            .ok()
+            .and_then(|index| self.days.get(index))
+    }
+
+    /// The time the alarm rings for a day, given at its start. `None` if it does not ring for the day. The time may
+    /// be on the day before if the alarm rings before an early event.
+    fn alarm_time(&self, day: &DateTime, hour: u8, minute: u8, weekdays: u8) -> Option<DateTime> {
+        let plan = self.day(day).copied().unwrap_or_default();
+        if plan.off {
+            return None;
+        }
+        if let (Some(lead), Some((event_hour, event_minute))) = (self.lead_minutes, plan.first_event) {
+            let event = DateTime {
+                hour: event_hour,
+                minute: event_minute,
+                ..day.clone()
+            };
+            return Some(calendar::add_minutes(&event, -i32::from(lead)));
+        }
+        (weekdays & calendar::weekday_bit(day.day_of_week) != 0).then(|| DateTime {
+            hour,
+            minute,
+            ..day.clone()
+        })
+    }
+}
+
+/// The next time after `now` the alarm rings, set to `hour:minute` on the `weekdays` as given by
+/// `calendar::weekday_bit` and following the plan for the days it covers. `None` if it rings on no day.
+pub fn next_alarm(now: &DateTime, hour: u8, minute: u8, weekdays: u8, plan: Option<&AlarmPlan>) -> Option<DateTime> {
+    let Some(plan) = plan else {
+        return calendar::days_until_next(now, hour, minute, weekdays).map(|days| DateTime {
+            hour,
+            minute,
+            second: 0,
+            ..calendar::add_days(now, days)
+        });
+    };
+    let today = calendar::start_of_day(now);
+    // all days the plan covers and a week beyond it, the alarm of tomorrow may ring today. A plan starts on the day it
+    // is made, the clamp only bounds the search.
+    let plan_start = i32::try_from(calendar::days_between(&today, &plan.first_day).clamp(0, 7)).unwrap_or_default();
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    let last_day = plan_start + PLAN_DAYS as i32 + 7;
+    (0..=last_day)
+        .filter_map(|offset| plan.alarm_time(&calendar::add_days(&today, offset), hour, minute, weekdays))
+        .filter(|alarm| calendar::compare(alarm, now) == Ordering::Greater)
+        .min_by(calendar::compare)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utility::ics::IcsReader;
+
+    /// A time on a day
+    fn date_time(year: u16, month: u8, day: u8, hour: u8, minute: u8) -> DateTime {
+        DateTime {
+            year,
+            month,
+            day,
+            day_of_week: calendar::day_of_week(year, month, day),
+            hour,
+            minute,
+            second: 0,
+        }
+    }
+
+    /// The plan of a calendar in a fixture, starting on the day of `today`, with the keyword `OFF`
+    fn plan(document: &[u8], today: &DateTime, lead_minutes: Option<u16>) -> AlarmPlan {
+        let mut plan = AlarmPlan::new(today, lead_minutes);
+        let mut reader = IcsReader::new(Some("OFF"), 120);
+        for chunk in document.chunks(100) {
+            reader.feed(chunk, &mut |event| plan.add_event(event));
+        }
+        reader.finish(&mut |event| plan.add_event(event)).unwrap();
+        plan
+    }
+
+    /// Date and time of the next alarm
+    fn next(now: &DateTime, weekdays: u8, plan: Option<&AlarmPlan>) -> Option<(u16, u8, u8, u8, u8)> {
+        next_alarm(now, 6, 45, weekdays, plan)
+            .map(|alarm| (alarm.year, alarm.month, alarm.day, alarm.hour, alarm.minute))
+    }
+
+    /// Shifts move the alarm before them, days off are skipped, beyond the plan the alarm rings as set
+    #[test]
+    fn follows_the_shifts() {
+        let shifts = include_bytes!("../../host-tests/fixtures/ics/shifts.ics");
+        let plan = plan(shifts, &date_time(2024, 5, 6, 0, 0), Some(90));
+        let weekdays = 0x1F;
+        assert!(plan.day(&date_time(2024, 5, 9, 0, 0)).unwrap().off);
+        assert_eq!(plan.day(&date_time(2024, 5, 13, 0, 0)), None);
+        // 90 minutes before the shift at 07:00 on Monday
+        assert_eq!(
+            next(&date_time(2024, 5, 6, 5, 0), weekdays, Some(&plan)),
+            Some((2024, 5, 6, 5, 30))
+        );
+        // no shift on Tuesday, it is excluded
+        assert_eq!(
+            next(&date_time(2024, 5, 6, 5, 30), weekdays, Some(&plan)),
+            Some((2024, 5, 7, 6, 45))
+        );
+        assert_eq!(
+            next(&date_time(2024, 5, 7, 7, 0), weekdays, Some(&plan)),
+            Some((2024, 5, 8, 5, 30))
+        );
+        // Thursday and Friday off, the night shift at 00:30 on Sunday moves the alarm to Saturday
+        assert_eq!(
+            next(&date_time(2024, 5, 8, 6, 0), weekdays, Some(&plan)),
+            Some((2024, 5, 11, 23, 0))
+        );
+        assert_eq!(
+            next(&date_time(2024, 5, 11, 23, 0), weekdays, Some(&plan)),
+            Some((2024, 5, 13, 6, 45))
+        );
+
+        let skip_only = self::plan(shifts, &date_time(2024, 5, 6, 0, 0), None);
+        assert_eq!(
+            next(&date_time(2024, 5, 8, 7, 0), weekdays, Some(&skip_only)),
+            Some((2024, 5, 13, 6, 45))
+        );
+    }
+
+    /// Without a plan the alarm rings as set, and never without days
+    #[test]
+    fn rings_as_set_without_a_plan() {
+        assert_eq!(
+            next(&date_time(2024, 5, 10, 7, 0), 0x1F, None),
+            Some((2024, 5, 13, 6, 45))
+        );
+        assert_eq!(
+            next(&date_time(2024, 5, 10, 6, 0), 0x1F, None),
+            Some((2024, 5, 10, 6, 45))
+        );
+        assert_eq!(next(&date_time(2024, 5, 10, 6, 0), 0, None), None);
+    }
+
+    /// A week of a stand-in, a meeting in UTC on the excluded shift day
+    #[test]
+    fn follows_the_standin_week() {
+        let today = date_time(2026, 10, 18, 12, 0);
+        let plan = plan(
+            include_bytes!("../../host-tests/fixtures/ics/standin.ics"),
+            &today,
+            Some(90),
+        );
+        let mut now = today;
+        let mut alarms = std::vec::Vec::new();
+        for _ in 0..5 {
+            let alarm = next_alarm(&now, 6, 45, 0x7F, Some(&plan)).unwrap();
+            alarms.push((alarm.day, alarm.hour, alarm.minute));
+            now = alarm;
+        }
+        assert_eq!(
+            alarms,
+            [(19, 9, 30), (21, 5, 30), (22, 5, 30), (23, 5, 30), (24, 6, 45)]
+        );
+    }
+}
@@ -0,0 +1,278 @@
+//! # API Router
+//! This module maps the requests of the local REST API to what the clock should do, without doing any of it.
+//! The server task answers the queries from the system state and turns the commands into events, so that they
+//! go through the orchestrator like button presses.
+//!
+//! | Method       | Path                | Request                               |
+//! |--------------|---------------------|---------------------------------------|
+//! | `GET`        | `/`                 | the web UI                            |
+//! | `GET`        | `/api/time`         | current time of the RTC               |
+//! | `GET`        | `/api/alarm`        | alarm settings                        |
+//! | `PUT`/`POST` | `/api/alarm`        | change alarm settings                 |
+//! | `POST`       | `/api/alarm/snooze` | snooze the ringing alarm              |
+//! | `POST`       | `/api/alarm/stop`   | stop the ringing alarm                |
+//! | `GET`        | `/api/power`        | usb power, vsys and battery level     |
+//! | `GET`        | `/api/mode`         | operation mode and alarm state        |
+//! | `GET`        | `/api/display`      | display settings                      |
+//! | `PUT`/`POST` | `/api/display`      | change display settings               |
+//!
+//! The body to change the alarm is a JSON object with any of the fields `hour`, `minute`, `enabled`, `days`,
+//! `sunrise_minutes` and `volume`, e.g. `{"hour": 6, "minute": 30, "enabled": true}`. The `days` are the days of the
+//! week the alarm rings on as bits, bit 0 is Monday and bit 6 is Sunday, so `31` rings on workdays only.
+//!
+//! The body to change the display is a JSON object with the field `twelve_hour`, e.g. `{"twelve_hour": true}`, which
+//! switches the display to the 12-hour format. The alarm `hour` is always from 0 to 23.
+
+use serde::Deserialize;
+
+use crate::utility::alarm_limits::alarm_values_in_range;
+
+/// What a request to the API asks for
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ApiRequest {
+    /// The page of the web UI
+    GetPage,
+    /// The current time
+    GetTime,
+    /// The alarm settings
+    GetAlarm,
+    /// The power state
+    GetPower,
+    /// The operation mode and the alarm state
+    GetMode,
+    /// The display settings
+    GetDisplay,
+    /// Change the alarm settings
+    UpdateAlarm(AlarmUpdate),
+    /// Change the display settings
+    UpdateDisplay(DisplayUpdate),
+    /// Snooze the ringing alarm
+    SnoozeAlarm,
+    /// Stop the ringing alarm
+    StopAlarm,
+}
+
+/// A change of the alarm settings, fields that are `None` stay as they are
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AlarmUpdate {
+    /// The new alarm hour
+    pub hour: Option<u8>,
+    /// The new alarm minute
+    pub minute: Option<u8>,
+    /// Enable or disable the alarm
+    pub enabled: Option<bool>,
+    /// The new days of the week the alarm rings on, bit 0 is Monday and bit 6 is Sunday
+    pub days: Option<u8>,
+    /// The new length of the sunrise effect in minutes
+    pub sunrise_minutes: Option<u8>,
+    /// The new volume of the alarm sound
+    pub volume: Option<u8>,
+}
+
+/// A change of the display settings, fields that are `None` stay as they are
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DisplayUpdate {
+    /// Show the time in 12-hour format or in 24-hour format
+    pub twelve_hour: Option<bool>,
+}
+
+/// Errors when routing a request to the API
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ApiError {
+    /// No such path
+    NotFound,
+    /// The path does not support the method
+    MethodNotAllowed,
+    /// The body is not the JSON object the path expects
+    InvalidBody,
+    /// A value in the body is out of range, e.g. an hour of 24
+    OutOfRange,
+}
+
+impl ApiError {
+    /// The HTTP status code to respond with
+    pub const fn status(self) -> u16 {
+        match self {
+            Self::NotFound => 404,
+            Self::MethodNotAllowed => 405,
+            Self::InvalidBody => 400,
+            Self::OutOfRange => 422,
+        }
+    }
+
+    /// A short message for the body of the response
+    pub const fn message(self) -> &'static str {
+        match self {
+            Self::NotFound => "not found",
+            Self::MethodNotAllowed => "method not allowed",
+            Self::InvalidBody => "invalid body",
+            Self::OutOfRange => "value out of range",
+        }
+    }
+}
+
+/// Route a request by its method and path, parsing the body of commands
+pub fn route(method: &str, path: &str, body: &[u8]) -> Result<ApiRequest, ApiError> {
+    // a trailing slash makes no difference
+    let path = path.strip_suffix('/').filter(|path| !path.is_empty()).unwrap_or(path);
+    let read = method == "GET";
+    let write = method == "PUT" || method == "POST";
+
+    let request = match path {
+        "/" | "/index.html" if read => ApiRequest::GetPage,
+        "/api/time" if read => ApiRequest::GetTime,
+        "/api/alarm" if read => ApiRequest::GetAlarm,
+        "/api/alarm" if write => ApiRequest::UpdateAlarm(parse_alarm_update(body)?),
+        "/api/alarm/snooze" if method == "POST" => ApiRequest::SnoozeAlarm,
+        "/api/alarm/stop" if method == "POST" => ApiRequest::StopAlarm,
+        "/api/power" if read => ApiRequest::GetPower,
+        "/api/mode" if read => ApiRequest::GetMode,
+        "/api/display" if read => ApiRequest::GetDisplay,
+        "/api/display" if write => ApiRequest::UpdateDisplay(parse_display_update(body)?),
+        "/" | "/index.html" | "/api/time" | "/api/alarm" | "/api/alarm/snooze" | "/api/alarm/stop" | "/api/power"
+        | "/api/mode" | "/api/display" => {
+            return Err(ApiError::MethodNotAllowed);
+        }
+        _ => return Err(ApiError::NotFound),
+    };
+    Ok(request)
+}
+
+/// Parse and check the body of a request to change the alarm settings
+fn parse_alarm_update(body: &[u8]) -> Result<AlarmUpdate, ApiError> {
+    let (update, _) = serde_json_core::from_slice::<AlarmUpdate>(body).map_err(|_| ApiError::InvalidBody)?;
+    let AlarmUpdate {
+        hour,
+        minute,
+        enabled,
+        days,
+        sunrise_minutes,
+        volume,
+    } = update;
+    if hour.is_none()
+        && minute.is_none()
+        && enabled.is_none()
+        && days.is_none()
+        && sunrise_minutes.is_none()
+        && volume.is_none()
+    {
+        return Err(ApiError::InvalidBody);
+    }
+    if !alarm_values_in_range(hour, minute, days, sunrise_minutes, volume) {
+        return Err(ApiError::OutOfRange);
+    }
+    Ok(update)
+}
+
+/// Parse the body of a request to change the display settings
+fn parse_display_update(body: &[u8]) -> Result<DisplayUpdate, ApiError> {
+    let (update, _) = serde_json_core::from_slice::<DisplayUpdate>(body).map_err(|_| ApiError::InvalidBody)?;
+    if update.twelve_hour.is_none() {
+        return Err(ApiError::InvalidBody);
+    }
+    Ok(update)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A change of the alarm settings as the router reads it
+    fn update(body: &[u8]) -> Result<ApiRequest, ApiError> {
+        route("PUT", "/api/alarm", body)
+    }
+
+    /// Every endpoint with its method, trailing slashes make no difference
+    #[test]
+    fn routes_the_endpoints() {
+        assert_eq!(route("GET", "/", b""), Ok(ApiRequest::GetPage));
+        assert_eq!(route("GET", "/index.html", b""), Ok(ApiRequest::GetPage));
+        assert_eq!(route("GET", "/api/time/", b""), Ok(ApiRequest::GetTime));
+        assert_eq!(route("GET", "/api/alarm", b""), Ok(ApiRequest::GetAlarm));
+        assert_eq!(route("GET", "/api/power", b""), Ok(ApiRequest::GetPower));
+        assert_eq!(route("GET", "/api/mode", b""), Ok(ApiRequest::GetMode));
+        assert_eq!(route("GET", "/api/display", b""), Ok(ApiRequest::GetDisplay));
+        assert_eq!(route("POST", "/api/alarm/snooze", b""), Ok(ApiRequest::SnoozeAlarm));
+        assert_eq!(route("POST", "/api/alarm/stop/", b""), Ok(ApiRequest::StopAlarm));
+    }
+
+    /// Known paths with the wrong method and unknown paths
+    #[test]
+    fn rejects_unknown_requests() {
+        assert_eq!(route("POST", "/", b""), Err(ApiError::MethodNotAllowed));
+        assert_eq!(route("DELETE", "/api/alarm", b""), Err(ApiError::MethodNotAllowed));
+        assert_eq!(route("GET", "/api/alarm/stop", b""), Err(ApiError::MethodNotAllowed));
+        assert_eq!(route("PUT", "/api/alarm/snooze", b""), Err(ApiError::MethodNotAllowed));
+        assert_eq!(route("GET", "/nope", b""), Err(ApiError::NotFound));
+        assert_eq!(route("GET", "/api", b""), Err(ApiError::NotFound));
+        assert_eq!(ApiError::NotFound.status(), 404);
+        assert_eq!(ApiError::OutOfRange.status(), 422);
+    }
+
+    /// The fields that are given are changed, unknown fields are ignored
+    #[test]
+    fn parses_alarm_changes() {
+        assert_eq!(
+            update(br#"{"hour": 6, "minute": 30, "enabled": true, "extra": [1, {"a": 2}]}"#),
+            Ok(ApiRequest::UpdateAlarm(AlarmUpdate {
+                hour: Some(6),
+                minute: Some(30),
+                enabled: Some(true),
+                ..AlarmUpdate::default()
+            }))
+        );
+        assert_eq!(
+            route("POST", "/api/alarm", br#"{"days":31,"sunrise_minutes":10,"volume":30}"#),
+            Ok(ApiRequest::UpdateAlarm(AlarmUpdate {
+                days: Some(31),
+                sunrise_minutes: Some(10),
+                volume: Some(30),
+                ..AlarmUpdate::default()
+            }))
+        );
+    }
+
+    /// Values out of range are refused as such, bodies that are not a change at all as invalid
+    #[test]
+    fn rejects_invalid_alarm_changes() {
+        assert_eq!(update(br#"{"hour":24}"#), Err(ApiError::OutOfRange));
+        assert_eq!(update(br#"{"minute":60}"#), Err(ApiError::OutOfRange));
+        assert_eq!(update(br#"{"days":0}"#), Err(ApiError::OutOfRange));
+        assert_eq!(update(br#"{"days":128}"#), Err(ApiError::OutOfRange));
+        assert_eq!(update(br#"{"sunrise_minutes":0}"#), Err(ApiError::OutOfRange));
+        assert_eq!(update(br#"{"volume":31}"#), Err(ApiError::OutOfRange));
+        assert_eq!(update(br#"{"hour":-1}"#), Err(ApiError::InvalidBody));
+        assert_eq!(update(br#"{"enabled":"yes"}"#), Err(ApiError::InvalidBody));
+        assert_eq!(update(br"{}"), Err(ApiError::InvalidBody));
+        assert_eq!(update(b""), Err(ApiError::InvalidBody));
+        assert_eq!(update(b"nope"), Err(ApiError::InvalidBody));
+        assert_eq!(update(br#"{"twelve_hour":true}"#), Err(ApiError::InvalidBody));
+    }
+
+    /// The 12-hour format is a display setting of its own, bodies without it are invalid
+    #[test]
+    fn parses_display_changes() {
+        assert_eq!(
+            route("PUT", "/api/display", br#"{"twelve_hour": true}"#),
+            Ok(ApiRequest::UpdateDisplay(DisplayUpdate {
+                twelve_hour: Some(true)
+            }))
+        );
+        assert_eq!(
+            route("POST", "/api/display/", br#"{"twelve_hour":false,"hour":6}"#),
+            Ok(ApiRequest::UpdateDisplay(DisplayUpdate {
+                twelve_hour: Some(false)
+            }))
+        );
+        assert_eq!(route("PUT", "/api/display", br"{}"), Err(ApiError::InvalidBody));
+        assert_eq!(
+            route("PUT", "/api/display", br#"{"twelve_hour":1}"#),
+            Err(ApiError::InvalidBody)
+        );
+        assert_eq!(route("DELETE", "/api/display", b""), Err(ApiError::MethodNotAllowed));
+    }
+}
@@ -0,0 +1,248 @@
+//! # Backoff
+//! This module contains the timing logic for the time updater: how long to wait before retrying after an error,
+//! and how long to wait before the next regular refresh.
+//!
+//! Everything in here is plain arithmetic on seconds, no timers and no hardware, so it can be exercised on the host.
+//! The random input for the jitter is passed in by the caller.
+
+/// The class of failure that made a time update fail. Each class has its own backoff policy.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FailureKind {
+    /// Joining the `WiFi` network failed or timed out. Typically the router is off, so we back off the longest.
+    WifiJoin,
+    /// The network came up but DHCP, the link or DNS did not get ready in time.
+    Network,
+    /// The server could be reached but the request or its response failed.
+    Http,
+    /// A local problem, e.g. the RTC not being available yet. Retried quickly.
+    Local,
+}
+
+/// Base delay and upper bound of the exponential backoff for one failure class.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct BackoffPolicy {
+    /// Delay after the first failure in seconds
+    pub base: u64,
+    /// Upper bound for the delay in seconds
+    pub max: u64,
+}
+
+impl FailureKind {
+    /// Number of failure classes, used for sizing the per-class counters
+    const COUNT: usize = 4;
+
+    /// Returns the backoff policy for this failure class
+    pub const fn policy(self) -> BackoffPolicy {
+        match self {
+            Self::WifiJoin => BackoffPolicy {
+                base: 60,
+                max: 3_600, // 1 hour
+            },
+            Self::Network => BackoffPolicy {
+                base: 30,
+                max: 1_800, // 30 minutes
+            },
+            Self::Http => BackoffPolicy {
+                base: 30,
+                max: 900, // 15 minutes
+            },
+            Self::Local => BackoffPolicy { base: 5, max: 60 },
+        }
+    }
+
+    /// Index of this failure class in the per-class counters
+    const fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Exponential backoff with jitter, tracking consecutive failures per failure class.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Backoff {
+    /// Consecutive failures per failure class
+    failures: [u8; FailureKind::COUNT],
+}
+
+impl Backoff {
+    /// Create a new `Backoff` without any recorded failures
+    pub const fn new() -> Self {
+        Self {
+            failures: [0; FailureKind::COUNT],
+        }
+    }
+
+    /// Forget all recorded failures, called after a successful update
+    pub const fn reset(&mut self) {
+        self.failures = [0; FailureKind::COUNT];
+    }
+
+    /// Record a failure of the given class and return the delay in seconds before the next attempt.
+    ///
+    /// The delay doubles with every consecutive failure of the same class, starting at the base delay
+    /// and capped at the maximum of the class. To avoid several clocks hammering a recovering router in lockstep
+    /// the delay is jittered into the upper half of its range: `delay / 2 + random % (delay / 2 + 1)`.
+    pub fn record_failure(&mut self, kind: FailureKind, random: u32) -> u64 {
+        let index = kind.index();
+        self.failures[index] = self.failures[index].saturating_add(1);
+
+        let policy = kind.policy();
+        let exponent = u32::from(self.failures[index] - 1).min(16);
+        let delay = policy.base.saturating_mul(1 << exponent).min(policy.max);
+
+        let half = delay / 2;
+        half + u64::from(random) % (half + 1)
+    }
+}
+
+/// Drift below this many milliseconds per hour counts as low, the refresh interval is lengthened
+const LOW_DRIFT_MS_PER_HOUR: u64 = 300;
+
+/// Drift above this many milliseconds per hour counts as high, the refresh interval is shortened
+const HIGH_DRIFT_MS_PER_HOUR: u64 = 1_500;
+
+/// Refresh interval that adapts to the measured drift of the RTC.
+///
+/// When the RTC was close to the fetched time on the last sync, the interval doubles up to the maximum.
+/// When it was off by a lot, the interval halves down to the minimum. In between it stays as it is.
+///
+/// The drift grows with the time since the last sync, so it is compared as rate per hour. Compared as plain seconds,
+/// a longer interval would measure a larger drift and shorten the interval again, which would never settle.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AdaptiveRefresh {
+    /// Current refresh interval in seconds
+    interval: u64,
+    /// Lower bound for the refresh interval in seconds
+    min: u64,
+    /// Upper bound for the refresh interval in seconds
+    max: u64,
+}
+
+impl AdaptiveRefresh {
+    /// Create a new `AdaptiveRefresh` starting at `initial_secs`, kept within `min_secs..=max_secs`
+    pub const fn new(initial_secs: u64, min_secs: u64, max_secs: u64) -> Self {
+        Self {
+            interval: initial_secs,
+            min: min_secs,
+            max: max_secs,
+        }
+    }
+
+    /// The current refresh interval in seconds
+    pub const fn interval_secs(&self) -> u64 {
+        self.interval
+    }
+
+    /// Adapt the interval to the drift measured on the last sync, `elapsed_secs` after the sync before.
+    /// `None` means the drift could not be measured (e.g. first sync after boot), the interval is left alone, as it is
+    /// if no time elapsed.
+    pub fn update(&mut self, drift_secs: Option<i64>, elapsed_secs: u64) {
+        let Some(drift_secs) = drift_secs else {
+            return;
+        };
+        if elapsed_secs == 0 {
+            return;
+        }
+
+        let drift_ms_per_hour = drift_secs.unsigned_abs().saturating_mul(3_600_000) / elapsed_secs;
+        if drift_ms_per_hour <= LOW_DRIFT_MS_PER_HOUR {
+            self.interval = self.interval.saturating_mul(2).min(self.max);
+        } else if drift_ms_per_hour >= HIGH_DRIFT_MS_PER_HOUR {
+            self.interval = (self.interval / 2).max(self.min);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The delays of consecutive failures of a class, with the jitter at its bottom: half the delay
+    fn delays(kind: FailureKind, failures: usize) -> Vec<u64> {
+        let mut backoff = Backoff::new();
+        (0..failures).map(|_| backoff.record_failure(kind, 0)).collect()
+    }
+
+    /// The intervals of consecutive syncs of an RTC drifting at a constant rate
+    fn intervals(refresh: &mut AdaptiveRefresh, drift_ms_per_hour: i64, syncs: usize) -> Vec<u64> {
+        (0..syncs)
+            .map(|_| {
+                let elapsed_secs = refresh.interval_secs();
+                #[allow(clippy::cast_possible_wrap)]
+                let drift_secs = drift_ms_per_hour * elapsed_secs as i64 / 3_600_000;
+                refresh.update(Some(drift_secs), elapsed_secs);
+                refresh.interval_secs()
+            })
+            .collect()
+    }
+
+    /// The delay doubles up to the maximum of the class
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        assert_eq!(delays(FailureKind::Http, 7), [15, 30, 60, 120, 240, 450, 450]);
+        assert_eq!(delays(FailureKind::Local, 5), [2, 5, 10, 20, 30]);
+        assert_eq!(delays(FailureKind::WifiJoin, 300).last(), Some(&1_800));
+    }
+
+    /// The jitter keeps the delay in the upper half of its range
+    #[test]
+    fn backoff_jitter_stays_in_the_upper_half() {
+        for random in [0, 1, 7, 15, 16, u32::MAX / 3, u32::MAX] {
+            let mut backoff = Backoff::new();
+            let delay = backoff.record_failure(FailureKind::Network, random);
+            assert!((15..=30).contains(&delay), "{random}: {delay}");
+        }
+        assert_eq!(Backoff::new().record_failure(FailureKind::Local, 0), 2);
+    }
+
+    /// Each class counts its own failures, a reset forgets all of them
+    #[test]
+    fn backoff_classes_are_independent() {
+        let mut backoff = Backoff::new();
+        backoff.record_failure(FailureKind::WifiJoin, 0);
+        backoff.record_failure(FailureKind::WifiJoin, 0);
+        assert_eq!(backoff.record_failure(FailureKind::Http, 0), 15);
+        assert_eq!(backoff.record_failure(FailureKind::WifiJoin, 0), 120);
+        backoff.reset();
+        assert_eq!(backoff, Backoff::new());
+        assert_eq!(backoff.record_failure(FailureKind::WifiJoin, 0), 30);
+    }
+
+    /// A well-keeping RTC is synced ever less often, a drifting one ever more often
+    #[test]
+    fn refresh_follows_the_drift_rate() {
+        let mut refresh = AdaptiveRefresh::new(21_600, 3_600, 86_400);
+        assert_eq!(intervals(&mut refresh, 100, 4), [43_200, 86_400, 86_400, 86_400]);
+        assert_eq!(
+            intervals(&mut refresh, -5_000, 6),
+            [43_200, 21_600, 10_800, 5_400, 3_600, 3_600]
+        );
+        // the drift is measured in whole seconds, less than one in an hour reads as none until the interval grows
+        assert_eq!(intervals(&mut refresh, 800, 3), [7_200, 7_200, 7_200]);
+    }
+
+    /// An RTC drifting at a constant rate settles on one interval instead of going back and forth
+    #[test]
+    fn refresh_settles() {
+        for drift_ms_per_hour in (0..3_000).step_by(50) {
+            for initial in [3_600, 21_600, 86_400] {
+                let mut refresh = AdaptiveRefresh::new(initial, 3_600, 86_400);
+                let settled = intervals(&mut refresh, drift_ms_per_hour, 6)[5];
+                assert_eq!(
+                    intervals(&mut refresh, drift_ms_per_hour, 6),
+                    [settled; 6],
+                    "{drift_ms_per_hour}"
+                );
+            }
+        }
+    }
+
+    /// A drift that could not be measured leaves the interval alone
+    #[test]
+    fn refresh_ignores_unmeasured_drift() {
+        let mut refresh = AdaptiveRefresh::new(21_600, 3_600, 86_400);
+        refresh.update(None, 21_600);
+        refresh.update(Some(60), 0);
+        assert_eq!(refresh.interval_secs(), 21_600);
+    }
+}
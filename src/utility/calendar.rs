@@ -0,0 +1,446 @@
+//! # Calendar
+//! This module contains the `DateTime` the firmware works with and the calendar arithmetic on it that is used
+//! throughout the project: leap years, month lengths, the day of the week, adding days and minutes, comparing,
+//! conversion to and from Unix time, finding the next day of a set of weekdays, and naming the days of the week.
+//! A date without time is a `DateTime` at the start of its day, see `start_of_day`.
+//!
+//! `DateTime` has the fields of the `DateTime` of the RTC driver, it is converted where the RTC is read and set, see
+//! `task::time_updater`. So everything that works with dates and times builds and is tested on the host.
+//!
+//! All calculations are done in the proleptic gregorian calendar without time zones, a `DateTime` is treated as if it
+//! was UTC. This is exactly what we need for the RTC, which holds local time and knows nothing about offsets.
+//! The day-count conversions follow Howard Hinnant's `days_from_civil` and `civil_from_days` algorithms.
+
+use core::cmp::Ordering;
+
+/// A date and a time of the day in local time, as the RTC holds it
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DateTime {
+    /// The year, 0 to 4095
+    pub year: u16,
+    /// The month, 1 to 12
+    pub month: u8,
+    /// The day of the month, 1 to 31
+    pub day: u8,
+    /// The day of the week, which must match the date
+    pub day_of_week: DayOfWeek,
+    /// The hour, 0 to 23
+    pub hour: u8,
+    /// The minute, 0 to 59
+    pub minute: u8,
+    /// The second, 0 to 59
+    pub second: u8,
+}
+
+/// A day of the week
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DayOfWeek {
+    /// Sunday
+    Sunday,
+    /// Monday
+    Monday,
+    /// Tuesday
+    Tuesday,
+    /// Wednesday
+    Wednesday,
+    /// Thursday
+    Thursday,
+    /// Friday
+    Friday,
+    /// Saturday
+    Saturday,
+}
+
+/// Seconds per day
+const SECONDS_PER_DAY: i64 = 86_400;
+
+/// Days per 400 year era of the gregorian calendar
+const DAYS_PER_ERA: i64 = 146_097;
+
+/// Days from 0000-03-01 to 1970-01-01, the epoch of Unix time
+const UNIX_EPOCH_DAYS: i64 = 719_468;
+
+/// Check if a year is a leap year
+/// A year is a leap year if it is divisible by 4, but not by 100, unless it is also divisible by 400.
+pub const fn is_leap_year(year: u16) -> bool {
+    (year.is_multiple_of(4) && !year.is_multiple_of(100)) || year.is_multiple_of(400)
+}
+
+/// Get the number of days in a given month and year
+pub const fn days_in_month(month: u8, year: u16) -> u8 {
+    match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        2 => {
+            if is_leap_year(year) {
+                29
+            } else {
+                28
+            }
+        }
+        _ => 30, // all other months
+    }
+}
+
+/// Compute the day of the week of a date
+pub fn day_of_week(year: u16, month: u8, day: u8) -> DayOfWeek {
+    day_of_week_from_days(days_from_civil(year, month, day))
+}
+
+/// Convert a `DateTime` to seconds since 1970-01-01T00:00:00, ignoring its `day_of_week`
+pub fn to_unix_time(dt: &DateTime) -> i64 {
+    days_from_civil(dt.year, dt.month, dt.day) * SECONDS_PER_DAY
+        + i64::from(dt.hour) * 3_600
+        + i64::from(dt.minute) * 60
+        + i64::from(dt.second)
+}
+
+/// Convert seconds since 1970-01-01T00:00:00 to a `DateTime`, including its `day_of_week`.
+/// The RTC cannot hold years outside of 0-4095, such times are clamped to that range.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub fn from_unix_time(seconds: i64) -> DateTime {
+    let min = to_unix_time(&DateTime {
+        year: 0,
+        month: 1,
+        day: 1,
+        day_of_week: DayOfWeek::Saturday,
+        hour: 0,
+        minute: 0,
+        second: 0,
+    });
+    let max = to_unix_time(&DateTime {
+        year: 4095,
+        month: 12,
+        day: 31,
+        day_of_week: DayOfWeek::Saturday,
+        hour: 23,
+        minute: 59,
+        second: 59,
+    });
+    let seconds = seconds.clamp(min, max);
+
+    let days = seconds.div_euclid(SECONDS_PER_DAY);
+    let seconds_of_day = seconds.rem_euclid(SECONDS_PER_DAY);
+    let (year, month, day) = civil_from_days(days);
+
+    // all casts below are within range: the year is clamped above, the rest are bounded by the day length
+    DateTime {
+        year,
+        month,
+        day,
+        day_of_week: day_of_week_from_days(days),
+        hour: (seconds_of_day / 3_600) as u8,
+        minute: (seconds_of_day % 3_600 / 60) as u8,
+        second: (seconds_of_day % 60) as u8,
+    }
+}
+
+/// Add a number of days (which may be negative) to a `DateTime`
+pub fn add_days(dt: &DateTime, days: i32) -> DateTime {
+    from_unix_time(to_unix_time(dt) + i64::from(days) * SECONDS_PER_DAY)
+}
+
+/// Add a number of minutes (which may be negative) to a `DateTime`
+pub fn add_minutes(dt: &DateTime, minutes: i32) -> DateTime {
+    from_unix_time(to_unix_time(dt) + i64::from(minutes) * 60)
+}
+
+/// Compare two `DateTime` values by their date and time, ignoring their `day_of_week`
+pub fn compare(a: &DateTime, b: &DateTime) -> Ordering {
+    (a.year, a.month, a.day, a.hour, a.minute, a.second).cmp(&(b.year, b.month, b.day, b.hour, b.minute, b.second))
+}
+
+/// The start of the day of a `DateTime`, which is how a date without time is held
+pub fn start_of_day(dt: &DateTime) -> DateTime {
+    DateTime {
+        hour: 0,
+        minute: 0,
+        second: 0,
+        ..dt.clone()
+    }
+}
+
+/// Number of days from the day of `from` to the day of `to`, negative if `to` is on an earlier day. The times of the
+/// day do not matter.
+pub fn days_between(from: &DateTime, to: &DateTime) -> i64 {
+    days_from_civil(to.year, to.month, to.day) - days_from_civil(from.year, from.month, from.day)
+}
+
+/// The bit of a day of the week in a set of weekdays, bit 0 is Monday and bit 6 is Sunday
+pub const fn weekday_bit(day: DayOfWeek) -> u8 {
+    match day {
+        DayOfWeek::Monday => 1,
+        DayOfWeek::Tuesday => 1 << 1,
+        DayOfWeek::Wednesday => 1 << 2,
+        DayOfWeek::Thursday => 1 << 3,
+        DayOfWeek::Friday => 1 << 4,
+        DayOfWeek::Saturday => 1 << 5,
+        DayOfWeek::Sunday => 1 << 6,
+    }
+}
+
+/// The three letter English abbreviation of a day of the week, e.g. `Mon`
+pub const fn weekday_abbreviation(day: DayOfWeek) -> &'static str {
+    match day {
+        DayOfWeek::Monday => "Mon",
+        DayOfWeek::Tuesday => "Tue",
+        DayOfWeek::Wednesday => "Wed",
+        DayOfWeek::Thursday => "Thu",
+        DayOfWeek::Friday => "Fri",
+        DayOfWeek::Saturday => "Sat",
+        DayOfWeek::Sunday => "Sun",
+    }
+}
+
+/// Number of days from `now` until the time `hour:minute` next falls on one of the `weekdays`, a set of bits as given
+/// by `weekday_bit`. Today counts as long as the time has not passed yet. `None` if the set is empty.
+pub fn days_until_next(now: &DateTime, hour: u8, minute: u8, weekdays: u8) -> Option<i32> {
+    let today = DateTime {
+        hour,
+        minute,
+        second: 0,
+        ..now.clone()
+    };
+    let first_day = i32::from(compare(&today, now) != Ordering::Greater);
+    (first_day..first_day + 7).find(|&days| weekdays & weekday_bit(add_days(now, days).day_of_week) != 0)
+}
+
+/// Number of days since 1970-01-01 for a date
+fn days_from_civil(year: u16, month: u8, day: u8) -> i64 {
+    // shift the year to start in March, so that the leap day is the last day of the year
+    let year = i64::from(year) - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_from_march = (i64::from(month) + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * DAYS_PER_ERA + day_of_era - UNIX_EPOCH_DAYS
+}
+
+/// Date (year, month, day) for a number of days since 1970-01-01
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn civil_from_days(days: i64) -> (u16, u8, u8) {
+    let days = days + UNIX_EPOCH_DAYS;
+    let era = days.div_euclid(DAYS_PER_ERA);
+    let day_of_era = days.rem_euclid(DAYS_PER_ERA);
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year as u16, month as u8, day as u8)
+}
+
+/// Day of the week for a number of days since 1970-01-01
+const fn day_of_week_from_days(days: i64) -> DayOfWeek {
+    // 1970-01-01 was a Thursday
+    match (days + 4).rem_euclid(7) {
+        0 => DayOfWeek::Sunday,
+        1 => DayOfWeek::Monday,
+        2 => DayOfWeek::Tuesday,
+        3 => DayOfWeek::Wednesday,
+        4 => DayOfWeek::Thursday,
+        5 => DayOfWeek::Friday,
+        _ => DayOfWeek::Saturday,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    /// Seconds from 1970-01-01 to 0000-01-01 and to the end of 4095, the range the RTC can hold
+    const MIN_UNIX_TIME: i64 = -62_167_219_200;
+    /// See `MIN_UNIX_TIME`
+    const MAX_UNIX_TIME: i64 = 67_090_118_399;
+
+    /// A `DateTime` with the day of the week filled in
+    fn date_time(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> DateTime {
+        DateTime {
+            year,
+            month,
+            day,
+            day_of_week: day_of_week(year, month, day),
+            hour,
+            minute,
+            second,
+        }
+    }
+
+    /// Known dates around the epoch, leap days and the ends of the RTC range
+    #[test]
+    fn known_dates() {
+        assert_eq!(to_unix_time(&date_time(1970, 1, 1, 0, 0, 0)), 0);
+        assert_eq!(to_unix_time(&date_time(2000, 2, 29, 12, 0, 0)), 951_825_600);
+        assert_eq!(to_unix_time(&date_time(2024, 12, 31, 23, 59, 59)), 1_735_689_599);
+        assert_eq!(to_unix_time(&date_time(0, 1, 1, 0, 0, 0)), MIN_UNIX_TIME);
+        assert_eq!(to_unix_time(&date_time(4095, 12, 31, 23, 59, 59)), MAX_UNIX_TIME);
+        assert_eq!(day_of_week(1970, 1, 1), DayOfWeek::Thursday);
+        assert_eq!(day_of_week(2000, 2, 29), DayOfWeek::Tuesday);
+        assert_eq!(day_of_week(2025, 6, 1), DayOfWeek::Sunday);
+    }
+
+    /// Leap years and month lengths, including the century rules
+    #[test]
+    fn leap_years_and_month_lengths() {
+        assert!(is_leap_year(2024));
+        assert!(is_leap_year(2000));
+        assert!(!is_leap_year(1900));
+        assert!(!is_leap_year(2025));
+        assert_eq!(days_in_month(2, 2024), 29);
+        assert_eq!(days_in_month(2, 2100), 28);
+        assert_eq!(days_in_month(4, 2025), 30);
+        assert_eq!(days_in_month(12, 2025), 31);
+        let days_of_year = |year| (1..=12).map(|month| u32::from(days_in_month(month, year))).sum::<u32>();
+        assert_eq!(days_of_year(2024), 366);
+        assert_eq!(days_of_year(2025), 365);
+    }
+
+    /// Times outside of what the RTC can hold are clamped to its range
+    #[test]
+    fn from_unix_time_clamps_to_the_rtc_range() {
+        assert_eq!(from_unix_time(i64::MIN), date_time(0, 1, 1, 0, 0, 0));
+        assert_eq!(from_unix_time(i64::MAX), date_time(4095, 12, 31, 23, 59, 59));
+    }
+
+    /// Adding minutes crosses days, months, years and leap days
+    #[test]
+    fn add_minutes_crosses_boundaries() {
+        assert_eq!(
+            add_minutes(&date_time(2024, 12, 31, 23, 30, 15), 45),
+            date_time(2025, 1, 1, 0, 15, 15)
+        );
+        assert_eq!(
+            add_minutes(&date_time(2025, 1, 1, 0, 15, 0), -45),
+            date_time(2024, 12, 31, 23, 30, 0)
+        );
+        assert_eq!(
+            add_minutes(&date_time(2024, 2, 28, 23, 0, 0), 60),
+            date_time(2024, 2, 29, 0, 0, 0)
+        );
+        assert_eq!(
+            add_minutes(&date_time(2023, 2, 28, 23, 0, 0), 60),
+            date_time(2023, 3, 1, 0, 0, 0)
+        );
+    }
+
+    /// Adding days keeps the time and crosses months, years and leap days
+    #[test]
+    fn add_days_keeps_the_time() {
+        assert_eq!(
+            add_days(&date_time(2024, 2, 28, 6, 30, 0), 1),
+            date_time(2024, 2, 29, 6, 30, 0)
+        );
+        assert_eq!(
+            add_days(&date_time(2025, 1, 1, 6, 30, 0), -1),
+            date_time(2024, 12, 31, 6, 30, 0)
+        );
+        assert_eq!(
+            add_days(&date_time(2025, 1, 31, 0, 0, 0), 30),
+            date_time(2025, 3, 2, 0, 0, 0)
+        );
+    }
+
+    /// Comparing goes by date and time, days between go by date only
+    #[test]
+    fn compare_and_days_between() {
+        let morning = date_time(2025, 6, 2, 6, 0, 0);
+        let evening = date_time(2025, 6, 1, 22, 0, 0);
+        assert_eq!(compare(&morning, &evening), Ordering::Greater);
+        assert_eq!(compare(&evening, &morning), Ordering::Less);
+        assert_eq!(compare(&morning, &morning.clone()), Ordering::Equal);
+        assert_eq!(days_between(&evening, &morning), 1);
+        assert_eq!(days_between(&morning, &evening), -1);
+        assert_eq!(
+            days_between(&date_time(2024, 1, 1, 0, 0, 0), &date_time(2025, 1, 1, 0, 0, 0)),
+            366
+        );
+        assert_eq!(start_of_day(&morning), date_time(2025, 6, 2, 0, 0, 0));
+    }
+
+    /// The next day of a set of weekdays, today only while the time has not passed
+    #[test]
+    fn days_until_next_weekday() {
+        // 2025-06-02 is a Monday
+        let now = date_time(2025, 6, 2, 7, 0, 0);
+        let monday = weekday_bit(DayOfWeek::Monday);
+        let friday = weekday_bit(DayOfWeek::Friday);
+        assert_eq!(days_until_next(&now, 7, 30, monday), Some(0));
+        assert_eq!(days_until_next(&now, 7, 0, monday), Some(7));
+        assert_eq!(days_until_next(&now, 6, 0, monday | friday), Some(4));
+        assert_eq!(days_until_next(&date_time(2025, 6, 1, 23, 0, 0), 6, 0, monday), Some(1));
+        assert_eq!(days_until_next(&now, 7, 30, 0), None);
+    }
+
+    /// Each day of the week has its own bit and abbreviation
+    #[test]
+    fn weekday_bits_and_abbreviations() {
+        let days = [
+            DayOfWeek::Monday,
+            DayOfWeek::Tuesday,
+            DayOfWeek::Wednesday,
+            DayOfWeek::Thursday,
+            DayOfWeek::Friday,
+            DayOfWeek::Saturday,
+            DayOfWeek::Sunday,
+        ];
+        assert_eq!(days.iter().fold(0, |bits, day| bits | weekday_bit(*day)), 0x7f);
+        assert_eq!(weekday_abbreviation(DayOfWeek::Monday), "Mon");
+        assert_eq!(weekday_abbreviation(DayOfWeek::Sunday), "Sun");
+    }
+
+    proptest! {
+        /// Every second the RTC can hold converts to a valid `DateTime` and back
+        #[test]
+        fn unix_time_round_trips(seconds in MIN_UNIX_TIME..=MAX_UNIX_TIME) {
+            let dt = from_unix_time(seconds);
+            prop_assert!((1..=12).contains(&dt.month));
+            prop_assert!((1..=days_in_month(dt.month, dt.year)).contains(&dt.day));
+            prop_assert!(dt.hour < 24 && dt.minute < 60 && dt.second < 60);
+            prop_assert_eq!(dt.day_of_week, day_of_week(dt.year, dt.month, dt.day));
+            prop_assert_eq!(to_unix_time(&dt), seconds);
+        }
+
+        /// Every valid date converts to days and back, and the next day follows it in the calendar
+        #[test]
+        fn civil_days_round_trip(year in 0_u16..4095, month in 1_u8..=12, day in 1_u8..=31) {
+            let day = day.min(days_in_month(month, year));
+            let days = days_from_civil(year, month, day);
+            prop_assert_eq!(civil_from_days(days), (year, month, day));
+            let next = civil_from_days(days + 1);
+            let expected = if day < days_in_month(month, year) {
+                (year, month, day + 1)
+            } else if month < 12 {
+                (year, month + 1, 1)
+            } else {
+                (year + 1, 1, 1)
+            };
+            prop_assert_eq!(next, expected);
+        }
+
+        /// Consecutive days have consecutive days of the week
+        #[test]
+        fn days_of_the_week_cycle(days in -700_000_i64..700_000) {
+            let bit = weekday_bit(day_of_week_from_days(days));
+            let next = weekday_bit(day_of_week_from_days(days + 1));
+            prop_assert_eq!(if bit == 1 << 6 { 1 } else { bit << 1 }, next);
+        }
+
+        /// Adding minutes and taking them away again gets back to the start
+        #[test]
+        fn add_minutes_is_reversible(seconds in -30_000_000_000_i64..30_000_000_000, minutes in -2_000_000..2_000_000) {
+            let dt = from_unix_time(seconds);
+            let later = add_minutes(&dt, minutes);
+            prop_assert_eq!(to_unix_time(&later) - to_unix_time(&dt), i64::from(minutes) * 60);
+            prop_assert_eq!(add_minutes(&later, -minutes), dt);
+        }
+    }
+}
@@ -0,0 +1,479 @@
+//! # Console
+//! This module reads the command lines of the serial console on USB and parses them, without doing any of it. The
+//! console task answers the queries from the system state and turns the commands into events, so that they go
+//! through the orchestrator like button presses.
+//!
+//! | Command                        | What it does                                                                    |
+//! |--------------------------------|---------------------------------------------------------------------------------|
+//! | `help`                         | list the commands                                                               |
+//! | `status`                       | operation mode, alarm, time sync, power and network                             |
+//! | `alarm set HH:MM`              | set the alarm time                                                              |
+//! | `alarm on` / `alarm off`       | enable or disable the alarm                                                     |
+//! | `clock 12h` / `clock 24h`      | show the time in 12-hour format with AM and PM, or in 24-hour format            |
+//! | `time set YYYY-MM-DD HH:MM:SS` | set the RTC to local time until the next time sync, the seconds may be left out |
+//! | `wifi set <ssid> <password>`   | store `WiFi` credentials in flash, used instead of those in `wifi_config.json`  |
+//! | `log`                          | the last error of every subsystem                                               |
+//! | `reboot`                       | restart the clock                                                               |
+//! | `factory-reset`                | erase the settings and credentials in flash and restart                         |
+//!
+//! Words are separated by spaces, an SSID or password holding spaces is put in double quotes. Only printable ASCII
+//! is taken, backspace erases the last character.
+
+use core::fmt;
+
+use heapless::{String, Vec};
+
+use crate::utility::{calendar::DateTime, string_utils::StringUtils};
+
+/// Capacity of a command line, longer lines are cut off
+pub const LINE_CAPACITY: usize = 128;
+
+/// Capacity of an SSID, 32 bytes as of IEEE 802.11
+pub const SSID_CAPACITY: usize = 32;
+
+/// Capacity of a WPA2 passphrase, 8 to 63 characters
+pub const PASSWORD_CAPACITY: usize = 63;
+
+/// Shortest WPA2 passphrase
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+/// Most words of a command line, `wifi set <ssid> <password>` and `time set <date> <time>`
+const MAX_WORDS: usize = 4;
+
+/// Backspace, sent by some terminals instead of delete
+const BACKSPACE: u8 = 0x08;
+
+/// Delete, sent by most terminals for the backspace key
+const DELETE: u8 = 0x7F;
+
+/// The help text, one command per line
+pub const HELP: &str = "\
+help                           list the commands\r
+status                         mode, alarm, time sync, power and network\r
+alarm set HH:MM                set the alarm time\r
+alarm on|off                   enable or disable the alarm\r
+clock 12h|24h                  show the time in 12-hour or 24-hour format\r
+time set YYYY-MM-DD HH:MM[:SS] set the clock to local time\r
+wifi set <ssid> <password>     store WiFi credentials, quote words with spaces\r
+log                            the last error of every subsystem\r
+reboot                         restart the clock\r
+factory-reset                  erase settings and credentials, then restart\r
+";
+
+/// `WiFi` credentials set with the console, replacing those of `wifi_config.json`. The password is left out of
+/// `Debug` and `Format`, so it never ends up in a log.
+#[derive(Clone, PartialEq, Eq)]
+pub struct WifiCredentials {
+    /// The SSID of the network
+    pub ssid: String<SSID_CAPACITY>,
+    /// The WPA2 passphrase of the network
+    pub password: String<PASSWORD_CAPACITY>,
+}
+
+impl WifiCredentials {
+    /// Size of the credentials in flash: the length of the SSID, the SSID and the password
+    pub const ENCODED_CAPACITY: usize = 1 + SSID_CAPACITY + PASSWORD_CAPACITY;
+
+    /// Encode the credentials for the flash into the buffer, returning the bytes written
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn encode<'a>(&self, buffer: &'a mut [u8; Self::ENCODED_CAPACITY]) -> &'a [u8] {
+        let ssid = self.ssid.as_bytes();
+        let password = self.password.as_bytes();
+        // the SSID is at most 32 bytes, so its length fits a byte
+        buffer[0] = ssid.len() as u8;
+        buffer[1..=ssid.len()].copy_from_slice(ssid);
+        let end = 1 + ssid.len() + password.len();
+        buffer[1 + ssid.len()..end].copy_from_slice(password);
+        &buffer[..end]
+    }
+
+    /// Decode credentials read from flash, `None` if they are not valid
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let (&ssid_length, rest) = bytes.split_first()?;
+        let (ssid, password) = rest.split_at_checked(usize::from(ssid_length))?;
+        Self::new(core::str::from_utf8(ssid).ok()?, core::str::from_utf8(password).ok()?).ok()
+    }
+
+    /// Check and take the SSID and password
+    fn new(ssid: &str, password: &str) -> Result<Self, CommandError> {
+        if ssid.is_empty() || password.len() < MIN_PASSWORD_LENGTH {
+            return Err(CommandError::InvalidArgument);
+        }
+        Ok(Self {
+            ssid: ssid.try_into().map_err(|_| CommandError::InvalidArgument)?,
+            password: password.try_into().map_err(|_| CommandError::InvalidArgument)?,
+        })
+    }
+}
+
+impl fmt::Debug for WifiCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WifiCredentials")
+            .field("ssid", &self.ssid)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for WifiCredentials {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "WifiCredentials {{ ssid: {} }}", self.ssid.as_str());
+    }
+}
+
+/// A command of the console
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// List the commands
+    Help,
+    /// Show the state of the clock
+    Status,
+    /// Set the alarm time
+    SetAlarmTime {
+        /// The new alarm hour
+        hour: u8,
+        /// The new alarm minute
+        minute: u8,
+    },
+    /// Enable or disable the alarm
+    EnableAlarm(bool),
+    /// Show the time in 12-hour format or in 24-hour format
+    SetTwelveHour(bool),
+    /// Set the RTC to the given local time
+    SetTime(DateTime),
+    /// Store `WiFi` credentials and join with them
+    SetWifi(WifiCredentials),
+    /// Show the last error of every subsystem
+    Log,
+    /// Restart the clock
+    Reboot,
+    /// Erase the flash and restart the clock
+    FactoryReset,
+}
+
+/// Errors when parsing a command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CommandError {
+    /// The line holds nothing but spaces
+    Empty,
+    /// No such command
+    UnknownCommand,
+    /// The command needs more words
+    MissingArgument,
+    /// The command takes fewer words
+    TooManyArguments,
+    /// A word is not what the command expects, e.g. an hour of 24 or an unterminated quote
+    InvalidArgument,
+}
+
+impl CommandError {
+    /// A short message to answer with
+    pub const fn message(self) -> &'static str {
+        match self {
+            Self::Empty => "",
+            Self::UnknownCommand => "unknown command, try help",
+            Self::MissingArgument => "missing argument, try help",
+            Self::TooManyArguments => "too many arguments, try help",
+            Self::InvalidArgument => "invalid argument, try help",
+        }
+    }
+}
+
+/// Parse a command line
+pub fn parse_command(line: &str) -> Result<Command, CommandError> {
+    let words = split_words(line)?;
+    let command = match words.as_slice() {
+        [] => return Err(CommandError::Empty),
+        ["help"] => Command::Help,
+        ["status"] => Command::Status,
+        ["alarm", "set", time] => {
+            let (hour, minute) = parse_hour_minute(time)?;
+            Command::SetAlarmTime { hour, minute }
+        }
+        ["alarm", "on"] => Command::EnableAlarm(true),
+        ["alarm", "off"] => Command::EnableAlarm(false),
+        ["clock", "12h"] => Command::SetTwelveHour(true),
+        ["clock", "24h"] => Command::SetTwelveHour(false),
+        ["time", "set", date, time] => Command::SetTime(parse_local_datetime(date, time)?),
+        ["wifi", "set", ssid, password] => Command::SetWifi(WifiCredentials::new(ssid, password)?),
+        ["log"] => Command::Log,
+        ["reboot"] => Command::Reboot,
+        ["factory-reset"] => Command::FactoryReset,
+        ["alarm" | "clock" | "time" | "wifi"] | ["alarm" | "time" | "wifi", "set"] | ["time" | "wifi", "set", _] => {
+            return Err(CommandError::MissingArgument);
+        }
+        ["help" | "status" | "log" | "reboot" | "factory-reset", ..]
+        | ["alarm", "set" | "on" | "off", ..]
+        | ["clock", "12h" | "24h", ..]
+        | ["time" | "wifi", "set", ..] => return Err(CommandError::TooManyArguments),
+        ["alarm" | "clock" | "time" | "wifi", ..] => return Err(CommandError::InvalidArgument),
+        _ => return Err(CommandError::UnknownCommand),
+    };
+    Ok(command)
+}
+
+/// Split a command line into words at spaces, a word in double quotes may hold spaces
+fn split_words(line: &str) -> Result<Vec<&str, MAX_WORDS>, CommandError> {
+    let mut words = Vec::new();
+    let mut rest = line.trim_start();
+    while !rest.is_empty() {
+        let (word, after) = if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"').ok_or(CommandError::InvalidArgument)?;
+            let after = &quoted[end + 1..];
+            // a closing quote must end the word
+            if !after.is_empty() && !after.starts_with(' ') {
+                return Err(CommandError::InvalidArgument);
+            }
+            (&quoted[..end], after)
+        } else {
+            rest.split_at(rest.find(' ').unwrap_or(rest.len()))
+        };
+        words.push(word).map_err(|_| CommandError::TooManyArguments)?;
+        rest = after.trim_start();
+    }
+    Ok(words)
+}
+
+/// Parse a time of day as `HH:MM` or `H:MM`
+fn parse_hour_minute(time: &str) -> Result<(u8, u8), CommandError> {
+    let (hour, minute) = time.split_once(':').ok_or(CommandError::InvalidArgument)?;
+    if !(1..=2).contains(&hour.len()) || minute.len() != 2 {
+        return Err(CommandError::InvalidArgument);
+    }
+    let hour = parse_number(hour)?;
+    let minute = parse_number(minute)?;
+    if hour > 23 || minute > 59 {
+        return Err(CommandError::InvalidArgument);
+    }
+    Ok((hour, minute))
+}
+
+/// Parse a number of digits only, no sign
+fn parse_number(digits: &str) -> Result<u8, CommandError> {
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(CommandError::InvalidArgument);
+    }
+    digits.parse().map_err(|_| CommandError::InvalidArgument)
+}
+
+/// Parse a local date `YYYY-MM-DD` and time `HH:MM:SS` or `HH:MM`, the RTC holds local time so no offset is taken
+fn parse_local_datetime(date: &str, time: &str) -> Result<DateTime, CommandError> {
+    let mut iso: String<19> = String::new();
+    let seconds = if time.len() == 5 { ":00" } else { "" };
+    for part in [date, "T", time, seconds] {
+        iso.push_str(part).map_err(|_| CommandError::InvalidArgument)?;
+    }
+    match StringUtils::parse_datetime(&iso) {
+        Ok(parsed) if parsed.utc_offset_minutes.is_none() => Ok(parsed.datetime),
+        _ => Err(CommandError::InvalidArgument),
+    }
+}
+
+/// What a received byte did to the line being edited
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LineEdit {
+    /// The character was appended, it is echoed
+    Inserted(u8),
+    /// The last character was erased
+    Erased,
+    /// The line is complete, it can be taken with `LineEditor::line`
+    Completed,
+    /// The byte was ignored, e.g. a control character or a character that does not fit
+    Ignored,
+}
+
+/// Collects the bytes received from the terminal into a command line
+pub struct LineEditor {
+    /// The line so far
+    line: String<LINE_CAPACITY>,
+    /// The last byte was a carriage return, a line feed right after it does not complete another line
+    after_carriage_return: bool,
+}
+
+impl LineEditor {
+    /// Create a new `LineEditor` with an empty line
+    pub const fn new() -> Self {
+        Self {
+            line: String::new(),
+            after_carriage_return: false,
+        }
+    }
+
+    /// Take a byte received from the terminal
+    pub fn feed(&mut self, byte: u8) -> LineEdit {
+        let after_carriage_return = core::mem::replace(&mut self.after_carriage_return, byte == b'\r');
+        match byte {
+            b'\n' if after_carriage_return => LineEdit::Ignored,
+            b'\r' | b'\n' => LineEdit::Completed,
+            BACKSPACE | DELETE => {
+                if self.line.pop().is_some() {
+                    LineEdit::Erased
+                } else {
+                    LineEdit::Ignored
+                }
+            }
+            b' '..=b'~' => {
+                if self.line.push(char::from(byte)).is_ok() {
+                    LineEdit::Inserted(byte)
+                } else {
+                    LineEdit::Ignored
+                }
+            }
+            _ => LineEdit::Ignored,
+        }
+    }
+
+    /// The line so far, complete after `LineEdit::Completed`
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+
+    /// Start a new line
+    pub fn clear(&mut self) {
+        self.line.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utility::calendar::DayOfWeek;
+
+    /// Feed the bytes to the editor, one at a time
+    fn feed(editor: &mut LineEditor, bytes: &[u8]) -> std::vec::Vec<LineEdit> {
+        bytes.iter().map(|&byte| editor.feed(byte)).collect()
+    }
+
+    /// Every command, with extra spaces and the forms of its arguments
+    #[test]
+    fn parses_commands() {
+        assert_eq!(parse_command("  status "), Ok(Command::Status));
+        assert_eq!(parse_command("help"), Ok(Command::Help));
+        assert_eq!(
+            parse_command("alarm set 06:30"),
+            Ok(Command::SetAlarmTime { hour: 6, minute: 30 })
+        );
+        assert_eq!(
+            parse_command("alarm set 6:05"),
+            Ok(Command::SetAlarmTime { hour: 6, minute: 5 })
+        );
+        assert_eq!(parse_command("alarm   on"), Ok(Command::EnableAlarm(true)));
+        assert_eq!(parse_command("alarm off"), Ok(Command::EnableAlarm(false)));
+        assert_eq!(parse_command("clock 12h"), Ok(Command::SetTwelveHour(true)));
+        assert_eq!(parse_command("clock 24h"), Ok(Command::SetTwelveHour(false)));
+        assert_eq!(parse_command("log"), Ok(Command::Log));
+        assert_eq!(parse_command("reboot"), Ok(Command::Reboot));
+        assert_eq!(parse_command("factory-reset"), Ok(Command::FactoryReset));
+    }
+
+    /// Local time with and without seconds, the day of the week is filled in
+    #[test]
+    fn parses_the_time() {
+        let Ok(Command::SetTime(dt)) = parse_command("time set 2024-02-29 23:59") else {
+            panic!("not a time");
+        };
+        assert_eq!(
+            (dt.year, dt.month, dt.day, dt.hour, dt.minute, dt.second),
+            (2024, 2, 29, 23, 59, 0)
+        );
+        assert_eq!(dt.day_of_week, DayOfWeek::Thursday);
+        let Ok(Command::SetTime(dt)) = parse_command("time set 2024-05-01 06:30:15") else {
+            panic!("not a time");
+        };
+        assert_eq!(dt.second, 15);
+    }
+
+    /// Quoted credentials with spaces, kept out of `Debug`, and their round trip through the flash encoding
+    #[test]
+    fn parses_and_encodes_wifi_credentials() {
+        let Ok(Command::SetWifi(credentials)) = parse_command(r#"wifi set "My Home Net" "pass word 1""#) else {
+            panic!("no credentials");
+        };
+        assert_eq!(credentials.ssid.as_str(), "My Home Net");
+        assert_eq!(credentials.password.as_str(), "pass word 1");
+        assert_eq!(
+            format!("{credentials:?}"),
+            r#"WifiCredentials { ssid: "My Home Net", .. }"#
+        );
+
+        let mut buffer = [0; WifiCredentials::ENCODED_CAPACITY];
+        assert_eq!(
+            WifiCredentials::decode(credentials.encode(&mut buffer)),
+            Some(credentials)
+        );
+        let longest = WifiCredentials::new(&"a".repeat(SSID_CAPACITY), &"b".repeat(PASSWORD_CAPACITY)).unwrap();
+        assert_eq!(WifiCredentials::decode(longest.encode(&mut buffer)), Some(longest));
+        assert_eq!(WifiCredentials::decode(&[]), None);
+        assert_eq!(WifiCredentials::decode(&[5, b'a']), None);
+        assert_eq!(WifiCredentials::decode(&[1, b'a', b'x']), None);
+    }
+
+    /// Each error for the lines that cause it
+    #[test]
+    fn reports_errors() {
+        assert_eq!(parse_command("   "), Err(CommandError::Empty));
+        assert_eq!(parse_command("alarms"), Err(CommandError::UnknownCommand));
+        for line in ["alarm", "alarm set", "clock", "time set 2024-05-01", "wifi set net"] {
+            assert_eq!(parse_command(line), Err(CommandError::MissingArgument), "{line}");
+        }
+        for line in ["alarm set 6:30 x", "status now", "clock 12h please", "a b c d e"] {
+            assert_eq!(parse_command(line), Err(CommandError::TooManyArguments), "{line}");
+        }
+        for line in [
+            "alarm maybe",
+            "alarm set 24:00",
+            "alarm set 6:60",
+            "alarm set 630",
+            "alarm set +6:30",
+            "clock 13h",
+            "time set 2023-02-29 06:30",
+            "time set 2024-05-01 06:30:00Z",
+            "time set 2024-05-01 6:30",
+            "wifi set net short",
+            r#"wifi set "" password"#,
+            r#"wifi set "net password"#,
+            r#"wifi set "net"x password"#,
+        ] {
+            assert_eq!(parse_command(line), Err(CommandError::InvalidArgument), "{line}");
+        }
+        let long_ssid = format!("wifi set {} password", "a".repeat(SSID_CAPACITY + 1));
+        assert_eq!(parse_command(&long_ssid), Err(CommandError::InvalidArgument));
+    }
+
+    /// Backspace and delete erase, CR LF completes one line, control characters and overflow are ignored
+    #[test]
+    fn edits_the_line() {
+        let mut editor = LineEditor::new();
+        assert_eq!(
+            feed(&mut editor, b"lox\x7f\x08g\r\n"),
+            [
+                LineEdit::Inserted(b'l'),
+                LineEdit::Inserted(b'o'),
+                LineEdit::Inserted(b'x'),
+                LineEdit::Erased,
+                LineEdit::Erased,
+                LineEdit::Inserted(b'g'),
+                LineEdit::Completed,
+                LineEdit::Ignored
+            ]
+        );
+        assert_eq!(editor.line(), "lg");
+        editor.clear();
+        assert_eq!(
+            feed(&mut editor, b"\x7f\x1b\n\n"),
+            [
+                LineEdit::Ignored,
+                LineEdit::Ignored,
+                LineEdit::Completed,
+                LineEdit::Completed
+            ]
+        );
+        for _ in 0..LINE_CAPACITY {
+            editor.feed(b'a');
+        }
+        assert_eq!(editor.feed(b'a'), LineEdit::Ignored);
+        assert_eq!(editor.line().len(), LINE_CAPACITY);
+    }
+}
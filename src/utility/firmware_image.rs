@@ -0,0 +1,324 @@
+//! # Firmware Image
+//! This module reads and checks the firmware images of an over-the-air update, see `task::firmware_update`.
+//!
+//! An image is the firmware as flat binary, starting at the active slot, behind a header of 116 bytes made by
+//! `firmware_image.py`. All numbers are little endian:
+//!
+//! | Offset | Size | Content                                                                 |
+//! |--------|------|-------------------------------------------------------------------------|
+//! | 0      | 4    | Magic `ACFW`                                                            |
+//! | 4      | 6    | Version of the firmware: major, minor and patch as `u16`                |
+//! | 10     | 2    | Reserved, zero                                                          |
+//! | 12     | 4    | Length of the firmware in bytes                                         |
+//! | 16     | 32   | SHA-256 hash of the firmware                                            |
+//! | 48     | 64   | Ed25519 signature of the SHA-512 hash of the firmware, zero if unsigned |
+//! | 112    | 4    | CRC-32 of the 112 bytes before                                          |
+//!
+//! The CRC rejects anything that is not an image, e.g. an error page of the server, before a single byte is written
+//! to the flash. The SHA-256 hash is checked while the firmware streams in and again on what ended up in the flash.
+//! The signature is checked by the bootloader library, it covers the same bytes and is made with the key the
+//! firmware is built with, see the `ota-signature` feature.
+
+use sha2::{Digest, Sha256};
+
+/// Size of the header in front of the firmware
+pub const HEADER_SIZE: usize = 116;
+
+/// The magic bytes every image starts with
+const MAGIC: [u8; 4] = *b"ACFW";
+
+/// Offset of the CRC-32 in the header, it covers everything before
+const CRC_OFFSET: usize = 112;
+
+/// Reversed polynomial of the CRC-32 used by Ethernet, zlib and PNG
+const CRC32_POLYNOMIAL: u32 = 0xEDB8_8320;
+
+/// Version of a firmware, ordered by major, minor and patch
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Version {
+    /// Major version
+    pub major: u16,
+    /// Minor version
+    pub minor: u16,
+    /// Patch version
+    pub patch: u16,
+}
+
+impl Version {
+    /// Parse a version like `0.6.1`, anything after the patch number (e.g. `-beta`) is ignored
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut numbers = text.splitn(3, '.');
+        let major = numbers.next()?.parse().ok()?;
+        let minor = numbers.next()?.parse().ok()?;
+        let patch = numbers.next()?;
+        let digits = patch.find(|c: char| !c.is_ascii_digit()).unwrap_or(patch.len());
+        let patch = patch[..digits].parse().ok()?;
+        Some(Self { major, minor, patch })
+    }
+
+    /// The version of the running firmware
+    pub fn running() -> Self {
+        Self::parse(env!("CARGO_PKG_VERSION")).unwrap_or(Self {
+            major: 0,
+            minor: 0,
+            patch: 0,
+        })
+    }
+}
+
+/// Errors when checking a firmware image
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ImageError {
+    /// The data does not start with the magic bytes, it is not an image
+    Magic,
+    /// The CRC of the header does not match, it is damaged
+    HeaderCrc,
+    /// The image holds no firmware
+    Empty,
+    /// The firmware does not fit into the update slot
+    TooLarge {
+        /// Length of the firmware in bytes
+        length: u32,
+    },
+    /// The image is not signed, but the firmware only accepts signed images
+    Unsigned,
+    /// More bytes arrived than the header announced
+    TooLong,
+    /// Fewer bytes arrived than the header announced
+    Truncated {
+        /// Number of bytes that arrived
+        received: u32,
+    },
+    /// The SHA-256 hash of the firmware does not match the header
+    Digest,
+}
+
+/// The header in front of the firmware
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ImageHeader {
+    /// Version of the firmware
+    pub version: Version,
+    /// Length of the firmware in bytes
+    pub length: u32,
+    /// SHA-256 hash of the firmware
+    pub sha256: [u8; 32],
+    /// Ed25519 signature of the SHA-512 hash of the firmware, all zero if the image is not signed
+    pub signature: [u8; 64],
+}
+
+impl ImageHeader {
+    /// Read the header and check it, the firmware must fit into `capacity` bytes
+    pub fn parse(bytes: &[u8; HEADER_SIZE], capacity: u32) -> Result<Self, ImageError> {
+        if bytes[..4] != MAGIC {
+            return Err(ImageError::Magic);
+        }
+        if crc32(&bytes[..CRC_OFFSET]) != read_u32(bytes, CRC_OFFSET) {
+            return Err(ImageError::HeaderCrc);
+        }
+
+        let version = Version {
+            major: read_u16(bytes, 4),
+            minor: read_u16(bytes, 6),
+            patch: read_u16(bytes, 8),
+        };
+        let length = read_u32(bytes, 12);
+        if length == 0 {
+            return Err(ImageError::Empty);
+        }
+        if length > capacity {
+            return Err(ImageError::TooLarge { length });
+        }
+
+        let mut sha256 = [0; 32];
+        sha256.copy_from_slice(&bytes[16..48]);
+        let mut signature = [0; 64];
+        signature.copy_from_slice(&bytes[48..CRC_OFFSET]);
+        Ok(Self {
+            version,
+            length,
+            sha256,
+            signature,
+        })
+    }
+
+    /// Whether the image carries a signature
+    pub fn is_signed(&self) -> bool {
+        self.signature.iter().any(|&byte| byte != 0)
+    }
+}
+
+/// Checks the firmware behind a header while it streams in
+pub struct ImageVerifier {
+    /// The length announced by the header
+    expected_length: u32,
+    /// The hash announced by the header
+    expected_sha256: [u8; 32],
+    /// Number of bytes seen so far
+    received: u32,
+    /// Hash of the bytes seen so far
+    hasher: Sha256,
+}
+
+impl ImageVerifier {
+    /// Start checking the firmware announced by the header
+    pub fn new(header: &ImageHeader) -> Self {
+        Self {
+            expected_length: header.length,
+            expected_sha256: header.sha256,
+            received: 0,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Take the next bytes of the firmware
+    pub fn update(&mut self, chunk: &[u8]) -> Result<(), ImageError> {
+        let received = u32::try_from(chunk.len())
+            .ok()
+            .and_then(|length| self.received.checked_add(length))
+            .filter(|&received| received <= self.expected_length)
+            .ok_or(ImageError::TooLong)?;
+        self.received = received;
+        self.hasher.update(chunk);
+        Ok(())
+    }
+
+    /// Number of bytes seen so far
+    pub const fn received(&self) -> u32 {
+        self.received
+    }
+
+    /// Check that the firmware is complete and matches the hash of the header
+    pub fn finish(self) -> Result<(), ImageError> {
+        if self.received < self.expected_length {
+            return Err(ImageError::Truncated {
+                received: self.received,
+            });
+        }
+        if self.hasher.finalize().as_slice() == self.expected_sha256 {
+            Ok(())
+        } else {
+            Err(ImageError::Digest)
+        }
+    }
+}
+
+/// CRC-32 as used by Ethernet, zlib and PNG, computed bit by bit since only the header is checked with it
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (CRC32_POLYNOMIAL & mask);
+        }
+    }
+    !crc
+}
+
+/// Read a little endian `u16` at the offset
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+/// Read a little endian `u32` at the offset
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An unsigned image of version 0.7.2 with 10000 bytes of firmware
+    const UNSIGNED: &[u8] = include_bytes!("../../host-tests/fixtures/firmware_image/unsigned.img");
+
+    /// The same firmware as a signed image of version 1.0.0
+    const SIGNED: &[u8] = include_bytes!("../../host-tests/fixtures/firmware_image/signed.img");
+
+    /// Size of the active slot of the firmware
+    const CAPACITY: u32 = 992 * 1024;
+
+    /// The header of an image
+    fn header(image: &[u8]) -> [u8; HEADER_SIZE] {
+        image[..HEADER_SIZE].try_into().unwrap()
+    }
+
+    /// The check value of the CRC-32 catalogue
+    #[test]
+    fn computes_the_crc() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    /// Versions are read from text and ordered by major, minor and patch
+    #[test]
+    fn parses_and_orders_versions() {
+        let version = |major, minor, patch| Version { major, minor, patch };
+        assert_eq!(Version::parse("0.6.1"), Some(version(0, 6, 1)));
+        assert_eq!(Version::parse("1.2.3-beta"), Some(version(1, 2, 3)));
+        assert_eq!(Version::parse("1.2"), None);
+        assert_eq!(Version::parse("1.x.3"), None);
+        assert!(version(0, 7, 2) > version(0, 6, 9));
+        assert!(version(1, 0, 0) > version(0, 10, 10));
+    }
+
+    /// The images of `firmware_image.py` are read and their firmware verifies, in any chunks
+    #[test]
+    fn reads_and_verifies_images() {
+        let unsigned = ImageHeader::parse(&header(UNSIGNED), CAPACITY).unwrap();
+        assert_eq!(unsigned.version, Version::parse("0.7.2").unwrap());
+        assert_eq!(unsigned.length, 10_000);
+        assert!(!unsigned.is_signed());
+        let signed = ImageHeader::parse(&header(SIGNED), CAPACITY).unwrap();
+        assert_eq!(signed.version, Version::parse("1.0.0").unwrap());
+        assert_eq!(signed.sha256, unsigned.sha256);
+        assert!(signed.is_signed());
+
+        for chunk in [1, 333, 4096, 10_000] {
+            let mut verifier = ImageVerifier::new(&unsigned);
+            for part in UNSIGNED[HEADER_SIZE..].chunks(chunk) {
+                verifier.update(part).unwrap();
+            }
+            assert_eq!(verifier.received(), 10_000);
+            assert_eq!(verifier.finish(), Ok(()));
+        }
+    }
+
+    /// Anything that is not an image, a damaged header and a firmware too large for the slot are refused
+    #[test]
+    fn refuses_bad_headers() {
+        let mut damaged = header(UNSIGNED);
+        damaged[20] ^= 1;
+        assert_eq!(ImageHeader::parse(&damaged, CAPACITY), Err(ImageError::HeaderCrc));
+        let mut html = [b' '; HEADER_SIZE];
+        html[..15].copy_from_slice(b"<!DOCTYPE html>");
+        assert_eq!(ImageHeader::parse(&html, CAPACITY), Err(ImageError::Magic));
+        assert_eq!(
+            ImageHeader::parse(&header(UNSIGNED), 9_999),
+            Err(ImageError::TooLarge { length: 10_000 })
+        );
+    }
+
+    /// Firmware that is cut off, too long or changed does not verify
+    #[test]
+    fn refuses_bad_firmware() {
+        let header = ImageHeader::parse(&header(UNSIGNED), CAPACITY).unwrap();
+        let firmware = &UNSIGNED[HEADER_SIZE..];
+
+        let mut verifier = ImageVerifier::new(&header);
+        verifier.update(&firmware[..firmware.len() - 1]).unwrap();
+        assert_eq!(verifier.finish(), Err(ImageError::Truncated { received: 9_999 }));
+
+        let mut verifier = ImageVerifier::new(&header);
+        verifier.update(firmware).unwrap();
+        assert_eq!(verifier.update(&[0]), Err(ImageError::TooLong));
+
+        let mut changed = firmware.to_vec();
+        changed[5_000] ^= 0x80;
+        let mut verifier = ImageVerifier::new(&header);
+        verifier.update(&changed).unwrap();
+        assert_eq!(verifier.finish(), Err(ImageError::Digest));
+    }
+}
@@ -0,0 +1,296 @@
+//! # Home Assistant
+//! This module describes the clock to Home Assistant by MQTT discovery: the entities the clock has, the topics their
+//! states are published on and commands are received on, and the discovery payloads that announce them.
+//!
+//! All topics of a clock start with its topic prefix, e.g. `alarmclock/alarm/set`. The discovery payload of an
+//! entity is published retained to `homeassistant/<component>/<node id>/<object id>/config`, Home Assistant then
+//! adds the clock as a device with all its entities. The clock publishes `online` to `<prefix>/availability` after
+//! connecting and the broker publishes the last will `offline` when the connection is lost.
+
+use core::fmt::{self, Write};
+
+/// The topic Home Assistant listens for discovery payloads on
+pub const DISCOVERY_PREFIX: &str = "homeassistant";
+
+/// The topic suffix of the availability
+pub const AVAILABILITY: &str = "availability";
+
+/// The availability payload while the clock is connected
+pub const ONLINE: &str = "online";
+
+/// The availability payload the broker publishes when the connection is lost
+pub const OFFLINE: &str = "offline";
+
+/// An entity of the clock in Home Assistant
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Entity {
+    /// Switch to enable and disable the alarm, `ON` or `OFF`
+    Alarm,
+    /// Button to snooze the ringing alarm
+    Snooze,
+    /// Button to stop the ringing alarm
+    Stop,
+    /// The state of the alarm, `none`, `sunrise` or `noise`
+    AlarmState,
+    /// The next time the alarm rings, e.g. `Mon 06:30`, or `off`
+    NextAlarm,
+    /// The voltage of the system power supply in volts
+    BatteryVoltage,
+    /// The battery level in percent, not published while charging
+    BatteryLevel,
+    /// Whether the clock runs on usb power, `ON` or `OFF`
+    UsbPower,
+    /// The operation mode, e.g. `normal`
+    OperationMode,
+}
+
+impl Entity {
+    /// All entities of the clock
+    pub const ALL: [Self; 9] = [
+        Self::Alarm,
+        Self::Snooze,
+        Self::Stop,
+        Self::AlarmState,
+        Self::NextAlarm,
+        Self::BatteryVoltage,
+        Self::BatteryLevel,
+        Self::UsbPower,
+        Self::OperationMode,
+    ];
+
+    /// The Home Assistant component of the entity
+    pub const fn component(self) -> &'static str {
+        match self {
+            Self::Alarm => "switch",
+            Self::Snooze | Self::Stop => "button",
+            Self::UsbPower => "binary_sensor",
+            Self::AlarmState | Self::NextAlarm | Self::BatteryVoltage | Self::BatteryLevel | Self::OperationMode => {
+                "sensor"
+            }
+        }
+    }
+
+    /// The object id of the entity, also the topic suffix of its state
+    pub const fn object_id(self) -> &'static str {
+        match self {
+            Self::Alarm => "alarm",
+            Self::Snooze => "snooze",
+            Self::Stop => "stop",
+            Self::AlarmState => "alarm_state",
+            Self::NextAlarm => "next_alarm",
+            Self::BatteryVoltage => "battery_voltage",
+            Self::BatteryLevel => "battery_level",
+            Self::UsbPower => "usb_power",
+            Self::OperationMode => "operation_mode",
+        }
+    }
+
+    /// The name of the entity shown in Home Assistant
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Alarm => "Alarm",
+            Self::Snooze => "Snooze",
+            Self::Stop => "Stop alarm",
+            Self::AlarmState => "Alarm state",
+            Self::NextAlarm => "Next alarm",
+            Self::BatteryVoltage => "Battery voltage",
+            Self::BatteryLevel => "Battery level",
+            Self::UsbPower => "USB power",
+            Self::OperationMode => "Operation mode",
+        }
+    }
+
+    /// Whether the entity has a state, buttons only take commands
+    pub const fn has_state(self) -> bool {
+        !matches!(self, Self::Snooze | Self::Stop)
+    }
+
+    /// The topic suffix of the commands the entity takes, `None` for sensors
+    pub const fn command(self) -> Option<&'static str> {
+        match self {
+            Self::Alarm => Some("alarm/set"),
+            Self::Snooze => Some("alarm/snooze"),
+            Self::Stop => Some("alarm/stop"),
+            _ => None,
+        }
+    }
+
+    /// Further discovery fields of the entity, each starting with a comma
+    const fn extra_fields(self) -> &'static str {
+        match self {
+            Self::Alarm => r#","icon":"mdi:alarm""#,
+            Self::Snooze => r#","icon":"mdi:alarm-snooze""#,
+            Self::Stop => r#","icon":"mdi:alarm-off""#,
+            Self::BatteryVoltage => {
+                r#","device_class":"voltage","unit_of_measurement":"V","state_class":"measurement","entity_category":"diagnostic""#
+            }
+            Self::BatteryLevel => r#","device_class":"battery","unit_of_measurement":"%","state_class":"measurement""#,
+            Self::UsbPower => r#","device_class":"plug","entity_category":"diagnostic""#,
+            Self::AlarmState | Self::NextAlarm | Self::OperationMode => "",
+        }
+    }
+
+    /// Find the entity taking commands on the given topic suffix
+    pub fn from_command(suffix: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|entity| entity.command() == Some(suffix))
+    }
+}
+
+/// Write the discovery topic of the entity, e.g. `homeassistant/switch/alarmclock/alarm/config`
+pub fn write_discovery_topic<W: Write>(out: &mut W, node_id: &str, entity: Entity) -> fmt::Result {
+    write!(
+        out,
+        "{DISCOVERY_PREFIX}/{}/{node_id}/{}/config",
+        entity.component(),
+        entity.object_id()
+    )
+}
+
+/// Write the discovery payload of the entity.
+/// The node id identifies the clock as a device, the topic prefix is the start of all its topics.
+pub fn write_discovery_payload<W: Write>(
+    out: &mut W,
+    node_id: &str,
+    topic_prefix: &str,
+    version: &str,
+    entity: Entity,
+) -> fmt::Result {
+    write!(
+        out,
+        r#"{{"name":"{}","unique_id":"{node_id}_{}","availability_topic":"{topic_prefix}/{AVAILABILITY}""#,
+        entity.name(),
+        entity.object_id()
+    )?;
+    if entity.has_state() {
+        write!(out, r#","state_topic":"{topic_prefix}/{}""#, entity.object_id())?;
+    }
+    if let Some(command) = entity.command() {
+        write!(out, r#","command_topic":"{topic_prefix}/{command}""#)?;
+    }
+    write!(
+        out,
+        r#"{},"device":{{"identifiers":["{node_id}"],"name":"{node_id}","model":"Pi Pico W alarm clock","sw_version":"{version}"}}}}"#,
+        entity.extra_fields()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use heapless::String;
+    use serde::Deserialize;
+
+    use super::*;
+
+    /// The fields of a discovery payload the tests look at
+    #[derive(Deserialize)]
+    struct Discovery<'a> {
+        /// The name shown in Home Assistant
+        name: &'a str,
+        /// The unique id of the entity
+        unique_id: &'a str,
+        /// The availability topic
+        availability_topic: &'a str,
+        /// The state topic, none for buttons
+        state_topic: Option<&'a str>,
+        /// The command topic, none for sensors
+        command_topic: Option<&'a str>,
+        /// The device class, if any
+        device_class: Option<&'a str>,
+        /// The device the entity belongs to
+        device: Device<'a>,
+    }
+
+    /// The device of a discovery payload
+    #[derive(Deserialize)]
+    struct Device<'a> {
+        /// The identifiers of the device
+        identifiers: [&'a str; 1],
+        /// The firmware version
+        sw_version: &'a str,
+    }
+
+    /// The discovery payload of an entity of the clock `alarmclock`
+    fn payload(entity: Entity) -> String<512> {
+        let mut out = String::new();
+        write_discovery_payload(&mut out, "alarmclock", "alarmclock", "0.7.2", entity).unwrap();
+        out
+    }
+
+    /// The discovery topics follow the component and the object id
+    #[test]
+    fn writes_discovery_topics() {
+        let topic = |entity| {
+            let mut out: String<64> = String::new();
+            write_discovery_topic(&mut out, "alarmclock", entity).unwrap();
+            out
+        };
+        assert_eq!(topic(Entity::Alarm), "homeassistant/switch/alarmclock/alarm/config");
+        assert_eq!(topic(Entity::Stop), "homeassistant/button/alarmclock/stop/config");
+        assert_eq!(
+            topic(Entity::UsbPower),
+            "homeassistant/binary_sensor/alarmclock/usb_power/config"
+        );
+        assert_eq!(
+            topic(Entity::BatteryLevel),
+            "homeassistant/sensor/alarmclock/battery_level/config"
+        );
+    }
+
+    /// The switch has a state and takes commands, byte for byte
+    #[test]
+    fn writes_the_switch_payload() {
+        assert_eq!(
+            payload(Entity::Alarm),
+            r#"{"name":"Alarm","unique_id":"alarmclock_alarm","availability_topic":"alarmclock/availability","state_topic":"alarmclock/alarm","command_topic":"alarmclock/alarm/set","icon":"mdi:alarm","device":{"identifiers":["alarmclock"],"name":"alarmclock","model":"Pi Pico W alarm clock","sw_version":"0.7.2"}}"#
+        );
+    }
+
+    /// Every payload is JSON, buttons only take commands and sensors only have a state
+    #[test]
+    fn writes_json_payloads() {
+        for entity in Entity::ALL {
+            let payload = payload(entity);
+            let (discovery, _) = serde_json_core::from_str::<Discovery<'_>>(&payload).unwrap();
+            assert_eq!(
+                discovery.unique_id.strip_prefix("alarmclock_"),
+                Some(entity.object_id())
+            );
+            assert_eq!(discovery.availability_topic, "alarmclock/availability");
+            assert!(!discovery.name.is_empty());
+            assert_eq!(discovery.device.identifiers, ["alarmclock"]);
+            assert_eq!(discovery.device.sw_version, "0.7.2");
+            assert_eq!(discovery.state_topic.is_some(), entity.has_state());
+            assert_eq!(discovery.command_topic.is_some(), entity.command().is_some());
+            if let Some(state_topic) = discovery.state_topic {
+                assert_eq!(state_topic.strip_prefix("alarmclock/"), Some(entity.object_id()));
+            }
+        }
+        let battery_payload = payload(Entity::BatteryLevel);
+        let (battery, _) = serde_json_core::from_str::<Discovery<'_>>(&battery_payload).unwrap();
+        assert_eq!(battery.device_class, Some("battery"));
+        let snooze_payload = payload(Entity::Snooze);
+        let (snooze, _) = serde_json_core::from_str::<Discovery<'_>>(&snooze_payload).unwrap();
+        assert_eq!(snooze.command_topic, Some("alarmclock/alarm/snooze"));
+    }
+
+    /// Commands are found by their topic suffix, object ids are unique, a payload that does not fit is an error
+    #[test]
+    fn finds_entities() {
+        for entity in Entity::ALL {
+            if let Some(command) = entity.command() {
+                assert_eq!(Entity::from_command(command), Some(entity));
+            }
+            assert_eq!(
+                Entity::ALL
+                    .iter()
+                    .filter(|other| other.object_id() == entity.object_id())
+                    .count(),
+                1
+            );
+        }
+        assert_eq!(Entity::from_command("alarm"), None);
+        let mut small: String<64> = String::new();
+        assert!(write_discovery_payload(&mut small, "alarmclock", "alarmclock", "0.7.2", Entity::Alarm).is_err());
+    }
+}
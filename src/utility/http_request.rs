@@ -0,0 +1,190 @@
+//! # HTTP Request
+//! This module parses the HTTP requests the clock serves: the request line, the `Content-Length` and
+//! `Accept-Encoding` headers and the body.
+//!
+//! The request is parsed from the buffer it was received into, nothing is copied. As long as the buffer does not hold
+//! the complete request yet, parsing fails with `RequestError::Incomplete` and the caller reads more.
+
+/// Errors when parsing an HTTP request
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RequestError {
+    /// The request has not been received completely yet
+    Incomplete,
+    /// The request line or a header is not valid HTTP/1.x
+    Malformed,
+    /// The body is sent in chunks, which we do not support
+    ChunkedBody,
+}
+
+/// A parsed HTTP request
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Request<'a> {
+    /// The method, e.g. `GET`
+    pub method: &'a str,
+    /// The path of the target, without the query string
+    pub path: &'a str,
+    /// The body, as long as the `Content-Length` says
+    pub body: &'a [u8],
+    /// Whether the `Accept-Encoding` headers allow a gzip compressed body
+    pub accepts_gzip: bool,
+}
+
+/// Parse the request held in `buffer`
+pub fn parse_request(buffer: &[u8]) -> Result<Request<'_>, RequestError> {
+    let head_end = buffer
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or(RequestError::Incomplete)?;
+    let head = core::str::from_utf8(&buffer[..head_end]).map_err(|_| RequestError::Malformed)?;
+    let mut lines = head.split("\r\n");
+
+    // the request line, e.g. `GET /api/time HTTP/1.1`
+    let mut request_line = lines.next().ok_or(RequestError::Malformed)?.split(' ');
+    let (Some(method), Some(target), Some(version), None) = (
+        request_line.next(),
+        request_line.next(),
+        request_line.next(),
+        request_line.next(),
+    ) else {
+        return Err(RequestError::Malformed);
+    };
+    if method.is_empty() || !target.starts_with('/') || !version.starts_with("HTTP/1.") {
+        return Err(RequestError::Malformed);
+    }
+    let path = target.split_once('?').map_or(target, |(path, _)| path);
+
+    let mut content_length = 0;
+    let mut accepts_gzip = false;
+    for line in lines {
+        let (name, value) = line.split_once(':').ok_or(RequestError::Malformed)?;
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = value.parse::<usize>().map_err(|_| RequestError::Malformed)?;
+        } else if name.eq_ignore_ascii_case("accept-encoding") {
+            accepts_gzip |= accepts_gzip_encoding(value);
+        } else if name.eq_ignore_ascii_case("transfer-encoding") && !value.eq_ignore_ascii_case("identity") {
+            return Err(RequestError::ChunkedBody);
+        }
+    }
+
+    let body = &buffer[head_end + 4..];
+    let body = body.get(..content_length).ok_or(RequestError::Incomplete)?;
+    Ok(Request {
+        method,
+        path,
+        body,
+        accepts_gzip,
+    })
+}
+
+/// Whether the value of an `Accept-Encoding` header allows gzip, by name or by `*`, and not with a weight of 0
+fn accepts_gzip_encoding(value: &str) -> bool {
+    value.split(',').any(|coding| {
+        let mut parameters = coding.split(';');
+        let name = parameters.next().unwrap_or_default().trim();
+        let refused = parameters
+            .filter_map(|parameter| parameter.trim().strip_prefix("q="))
+            .any(|weight| weight.trim().bytes().all(|b| b == b'0' || b == b'.'));
+        (name.eq_ignore_ascii_case("gzip") || name == "*") && !refused
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A request as a browser sends it for the page of the web UI
+    const BROWSER_REQUEST: &[u8] =
+        b"GET / HTTP/1.1\r\nHost: alarmclck.local\r\nUser-Agent: Mozilla/5.0 (X11; Linux x86_64; \
+        rv:128.0) Gecko/20100101 Firefox/128.0\r\nAccept: text/html,application/xhtml+xml,application/xml;q=0.9,*/*;\
+        q=0.8\r\nAccept-Language: en-US,en;q=0.5\r\nAccept-Encoding: gzip, deflate\r\nConnection: keep-alive\r\n\
+        Upgrade-Insecure-Requests: 1\r\n\r\n";
+
+    /// A request with a body as `curl` sends it
+    const CURL_REQUEST: &[u8] = b"PUT /api/alarm?pretty HTTP/1.1\r\nHost: 192.168.1.20\r\nUser-Agent: curl/8.5.0\r\n\
+        Accept: */*\r\nContent-Type: application/json\r\nContent-Length: 25\r\n\r\n{\"hour\": 6, \"minute\": 30}";
+
+    /// The method, the path without the query and the body as long as the `Content-Length` says
+    #[test]
+    fn parses_a_request_with_a_body() {
+        assert_eq!(
+            parse_request(CURL_REQUEST),
+            Ok(Request {
+                method: "PUT",
+                path: "/api/alarm",
+                body: br#"{"hour": 6, "minute": 30}"#,
+                accepts_gzip: false,
+            })
+        );
+        assert_eq!(
+            parse_request(b"GET / HTTP/1.0\r\n\r\n").map(|request| request.body),
+            Ok(&b""[..])
+        );
+    }
+
+    /// The request is incomplete until the head and the whole body are in the buffer
+    #[test]
+    fn waits_for_the_complete_request() {
+        for len in 0..CURL_REQUEST.len() {
+            assert_eq!(
+                parse_request(&CURL_REQUEST[..len]),
+                Err(RequestError::Incomplete),
+                "{len}"
+            );
+        }
+        assert_eq!(
+            parse_request(BROWSER_REQUEST.strip_suffix(b"\r\n").unwrap_or_default()),
+            Err(RequestError::Incomplete)
+        );
+    }
+
+    /// Request lines and headers that are not HTTP/1.x, and bodies in chunks
+    #[test]
+    fn rejects_malformed_requests() {
+        assert_eq!(parse_request(b"GET x HTTP/1.1\r\n\r\n"), Err(RequestError::Malformed));
+        assert_eq!(
+            parse_request(b"GET /x HTTP/1.1 y\r\n\r\n"),
+            Err(RequestError::Malformed)
+        );
+        assert_eq!(parse_request(b"GET /x HTTP/2\r\n\r\n"), Err(RequestError::Malformed));
+        assert_eq!(parse_request(b" /x HTTP/1.1\r\n\r\n"), Err(RequestError::Malformed));
+        assert_eq!(
+            parse_request(b"GET /x HTTP/1.1\r\nbad\r\n\r\n"),
+            Err(RequestError::Malformed)
+        );
+        assert_eq!(
+            parse_request(b"PUT /x HTTP/1.1\r\nContent-Length: many\r\n\r\n"),
+            Err(RequestError::Malformed)
+        );
+        assert_eq!(
+            parse_request(b"GET /\xff HTTP/1.1\r\n\r\n"),
+            Err(RequestError::Malformed)
+        );
+        assert_eq!(
+            parse_request(b"POST /x HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n"),
+            Err(RequestError::ChunkedBody)
+        );
+    }
+
+    /// gzip is accepted by name or by `*`, unless its weight is 0
+    #[test]
+    fn reads_accept_encoding() {
+        assert_eq!(
+            parse_request(BROWSER_REQUEST).map(|request| request.accepts_gzip),
+            Ok(true)
+        );
+        let accepts = |value: &str| {
+            let request = format!("GET / HTTP/1.1\r\nAccept-Encoding: {value}\r\n\r\n");
+            parse_request(request.as_bytes()).map(|request| request.accepts_gzip)
+        };
+        assert_eq!(accepts("gzip"), Ok(true));
+        assert_eq!(accepts("br;q=1.0, GZIP;q=0.5"), Ok(true));
+        assert_eq!(accepts("*"), Ok(true));
+        assert_eq!(accepts("identity"), Ok(false));
+        assert_eq!(accepts("deflate, br"), Ok(false));
+        assert_eq!(accepts("gzip;q=0"), Ok(false));
+        assert_eq!(accepts("gzip; q=0.000, identity"), Ok(false));
+        assert_eq!(accepts("x-gzip"), Ok(false));
+    }
+}
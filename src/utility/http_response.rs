@@ -0,0 +1,296 @@
+//! # HTTP Response
+//! This module contains the decisions the time fetcher takes on the status line and headers of an HTTP response:
+//! which status codes are redirects, how long `Retry-After` asks us to wait, where a redirect leads and whether we
+//! follow it.
+//! For the responses the clock serves itself it provides the reason phrases.
+//!
+//! Like the other utilities this works on plain strings and numbers, the HTTP client itself stays in the task.
+
+use heapless::String;
+
+use crate::utility::{
+    calendar::{DateTime, to_unix_time},
+    string_utils::StringUtils,
+};
+
+/// Upper bound for the delay a server may ask for with `Retry-After`, one day
+pub const MAX_RETRY_AFTER_SECS: u64 = 86_400;
+
+/// Errors when following a redirect
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RedirectError {
+    /// The redirect has no `Location` header or it cannot be resolved against the current URL
+    InvalidLocation,
+    /// The resolved URL does not fit into our buffer
+    TooLong,
+}
+
+/// Check if a status code is a redirect we follow
+pub const fn is_redirect(status: u16) -> bool {
+    matches!(status, 301 | 302 | 303 | 307 | 308)
+}
+
+/// Check if a status code signals success
+pub const fn is_success(status: u16) -> bool {
+    status >= 200 && status < 300
+}
+
+/// The reason phrase of a status code the clock responds with
+pub const fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        413 => "Content Too Large",
+        422 => "Unprocessable Content",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+/// Parse the value of a `Retry-After` header into the number of seconds to wait.
+///
+/// The header either holds the number of seconds or an HTTP date. A date is taken relative to the `Date` header
+/// of the same response rather than to our own clock, which may be the very thing that is wrong.
+/// Longer waits than `MAX_RETRY_AFTER_SECS` are cut to it.
+/// Returns `None` if the value cannot be understood or a date is given without a `Date` header.
+pub fn parse_retry_after(value: &str, date: Option<&DateTime>) -> Option<u64> {
+    let value = value.trim();
+    let seconds = if let Ok(seconds) = value.parse::<u64>() {
+        seconds
+    } else {
+        let retry_at = StringUtils::parse_http_date(value).ok()?;
+        let now = date?;
+        // a date in the past means we may retry right away
+        u64::try_from(to_unix_time(&retry_at) - to_unix_time(now)).unwrap_or(0)
+    };
+    Some(seconds.min(MAX_RETRY_AFTER_SECS))
+}
+
+/// Resolve the `Location` of a redirect against the URL that was requested.
+/// The location may be an absolute URL, scheme relative (`//host/path`), absolute path or relative path.
+pub fn resolve_redirect<const N: usize>(url: &str, location: &str) -> Result<String<N>, RedirectError> {
+    let location = location.trim();
+    let (origin, path) = split_origin(url).ok_or(RedirectError::InvalidLocation)?;
+    if location.is_empty() {
+        return Err(RedirectError::InvalidLocation);
+    }
+
+    // the part of the current URL that the location is relative to
+    let (base, separator) = if split_origin(location).is_some() {
+        ("", "")
+    } else if location.starts_with("//") {
+        // the scheme including its colon
+        (&origin[..origin.find("//").ok_or(RedirectError::InvalidLocation)?], "")
+    } else if location.starts_with('/') {
+        (origin, "")
+    } else {
+        // the directory of the current path
+        path.rfind('/')
+            .map_or((origin, "/"), |end| (&url[..=origin.len() + end], ""))
+    };
+
+    let mut resolved = String::new();
+    for part in [base, separator, location] {
+        resolved.push_str(part).map_err(|_| RedirectError::TooLong)?;
+    }
+    Ok(resolved)
+}
+
+/// Check if two URLs have the same scheme, host and port
+pub fn same_origin(a: &str, b: &str) -> bool {
+    match (split_origin(a), split_origin(b)) {
+        (Some((a, _)), Some((b, _))) => a.eq_ignore_ascii_case(b),
+        _ => false,
+    }
+}
+
+/// Check if we may follow a redirect from the URL to the target.
+/// A pin only vouches for its own server, so with a pin we stay on the same origin, which also keeps us on HTTPS.
+/// Without a pin we stay on plain HTTP, as there is nothing to verify an HTTPS server against.
+pub fn redirect_allowed(url: &str, target: &str, pinned: bool) -> bool {
+    if pinned {
+        same_origin(url, target)
+    } else {
+        split_origin(target).is_some_and(|(origin, _)| origin[..5].eq_ignore_ascii_case("http:"))
+    }
+}
+
+/// Split an absolute URL into its origin (`scheme://host[:port]`) and its path
+fn split_origin(url: &str) -> Option<(&str, &str)> {
+    let scheme_end = url.find("://")?;
+    let scheme = &url[..scheme_end];
+    if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+        return None;
+    }
+
+    let host_start = scheme_end + 3;
+    let path_start = url[host_start..].find('/').map_or(url.len(), |end| host_start + end);
+    if path_start == host_start {
+        return None;
+    }
+    Some(url.split_at(path_start))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The `Date` of the response, Wed, 01 May 2024 06:30:00 GMT
+    fn date() -> DateTime {
+        StringUtils::parse_http_date("Wed, 01 May 2024 06:30:00 GMT").unwrap()
+    }
+
+    /// A redirect of the URL to the location, with room for 64 bytes
+    fn resolve(url: &str, location: &str) -> Result<String<64>, RedirectError> {
+        resolve_redirect(url, location)
+    }
+
+    /// The redirects we follow and the success codes
+    #[test]
+    fn classifies_status_codes() {
+        for status in [301, 302, 303, 307, 308] {
+            assert!(is_redirect(status));
+        }
+        for status in [200, 300, 304, 305, 306, 404] {
+            assert!(!is_redirect(status));
+        }
+        assert!(is_success(200) && is_success(204) && is_success(299));
+        assert!(!is_success(199) && !is_success(300) && !is_success(503));
+        assert_eq!(reason_phrase(422), "Unprocessable Content");
+    }
+
+    /// `Retry-After` in delta-seconds, with or without a `Date` header
+    #[test]
+    fn parses_retry_after_seconds() {
+        assert_eq!(parse_retry_after("120", None), Some(120));
+        assert_eq!(parse_retry_after(" 0 ", Some(&date())), Some(0));
+        assert_eq!(parse_retry_after("-5", None), None);
+        assert_eq!(parse_retry_after("1.5", None), None);
+        assert_eq!(parse_retry_after("soon", None), None);
+    }
+
+    /// `Retry-After` as HTTP date, relative to the `Date` of the response and not to our clock
+    #[test]
+    fn parses_retry_after_dates() {
+        assert_eq!(
+            parse_retry_after("Wed, 01 May 2024 06:32:30 GMT", Some(&date())),
+            Some(150)
+        );
+        assert_eq!(
+            parse_retry_after("Thu, 02 May 2024 00:00:00 GMT", Some(&date())),
+            Some(63_000)
+        );
+        // a date in the past allows to retry right away
+        assert_eq!(
+            parse_retry_after("Tue, 30 Apr 2024 06:30:00 GMT", Some(&date())),
+            Some(0)
+        );
+        assert_eq!(parse_retry_after("Wed, 01 May 2024 06:32:30 GMT", None), None);
+        assert_eq!(
+            parse_retry_after("Wednesday, 01-May-24 06:32:30 GMT", Some(&date())),
+            None
+        );
+    }
+
+    /// A server cannot make us wait longer than a day
+    #[test]
+    fn caps_retry_after() {
+        assert_eq!(parse_retry_after("86400", None), Some(MAX_RETRY_AFTER_SECS));
+        assert_eq!(parse_retry_after("86401", None), Some(MAX_RETRY_AFTER_SECS));
+        assert_eq!(
+            parse_retry_after("18446744073709551615", None),
+            Some(MAX_RETRY_AFTER_SECS)
+        );
+        assert_eq!(
+            parse_retry_after("Fri, 01 May 2026 06:30:00 GMT", Some(&date())),
+            Some(MAX_RETRY_AFTER_SECS)
+        );
+    }
+
+    /// Absolute, scheme relative, root relative and path relative locations
+    #[test]
+    fn resolves_locations() {
+        let url = "http://time.example.org:8080/api/v1/time?tz=UTC";
+        assert_eq!(
+            resolve(url, "https://other.example.org/now").unwrap(),
+            "https://other.example.org/now"
+        );
+        assert_eq!(
+            resolve(url, "//mirror.example.org/time").unwrap(),
+            "http://mirror.example.org/time"
+        );
+        assert_eq!(
+            resolve(url, "/v2/time").unwrap(),
+            "http://time.example.org:8080/v2/time"
+        );
+        assert_eq!(
+            resolve(url, "time2 ").unwrap(),
+            "http://time.example.org:8080/api/v1/time2"
+        );
+        assert_eq!(
+            resolve("http://time.example.org", "now").unwrap(),
+            "http://time.example.org/now"
+        );
+        assert_eq!(
+            resolve("http://time.example.org/", "now").unwrap(),
+            "http://time.example.org/now"
+        );
+    }
+
+    /// Locations that cannot be resolved and URLs that do not fit
+    #[test]
+    fn refuses_invalid_locations() {
+        assert_eq!(
+            resolve("http://time.example.org/a", " "),
+            Err(RedirectError::InvalidLocation)
+        );
+        assert_eq!(resolve("/relative/base", "/time"), Err(RedirectError::InvalidLocation));
+        assert_eq!(
+            resolve("ftp://time.example.org/a", "/time"),
+            Err(RedirectError::InvalidLocation)
+        );
+        assert_eq!(resolve("http:///path", "/time"), Err(RedirectError::InvalidLocation));
+        assert_eq!(
+            resolve_redirect::<24>("http://time.example.org/a", "/time"),
+            Err(RedirectError::TooLong)
+        );
+    }
+
+    /// Scheme, host and port make the origin, the host in any case
+    #[test]
+    fn compares_origins() {
+        assert!(same_origin(
+            "https://Time.Example.org/a",
+            "https://time.example.org/b?c"
+        ));
+        assert!(!same_origin("https://time.example.org/a", "http://time.example.org/a"));
+        assert!(!same_origin(
+            "https://time.example.org/a",
+            "https://time.example.org:8443/a"
+        ));
+        assert!(!same_origin("https://time.example.org/a", "https://evil.example.org/a"));
+        assert!(!same_origin("https://time.example.org/a", "/a"));
+    }
+
+    /// With a pin only the same origin is followed, never down to plain HTTP, without one only plain HTTP
+    #[test]
+    fn allows_redirects() {
+        let pinned = "https://time.example.org/api/time";
+        assert!(redirect_allowed(pinned, "https://time.example.org/v2/time", true));
+        assert!(!redirect_allowed(pinned, "http://time.example.org/api/time", true));
+        assert!(!redirect_allowed(pinned, "https://mirror.example.org/api/time", true));
+        assert!(!redirect_allowed(pinned, "https://time.example.org:444/api/time", true));
+
+        let plain = "http://time.example.org/api/time";
+        assert!(redirect_allowed(plain, "http://mirror.example.org/time", false));
+        assert!(!redirect_allowed(plain, "https://time.example.org/api/time", false));
+        assert!(!redirect_allowed(plain, "ftp://time.example.org/api/time", false));
+    }
+}
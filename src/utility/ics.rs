@@ -0,0 +1,636 @@
+//! # iCalendar
+//! This module reads the events of an iCalendar (`.ics`) document while it streams in, chunk by chunk, so that a
+//! calendar of any size is read with a fixed amount of memory: one content line and the event being read.
+//!
+//! Only what decides about the alarm is read from a `VEVENT`:
+//! - `DTSTART`: the day and, unless it is an all-day event, the time. A time in UTC (`Z`) is converted to local time
+//!   with the given UTC offset, a time with `TZID` or without zone is taken as local time of the clock.
+//! - `DTEND`: the number of days of an all-day event, the end of other events does not matter.
+//! - `RRULE`: simple recurrences, see `Recurrence`. A rule we cannot follow leaves the event as a single one.
+//! - `EXDATE`: days the recurrence skips, a few of them.
+//! - `SUMMARY` and `CATEGORIES`: whether the event is tagged with the keyword, as a whole word in any case.
+//! - `STATUS`: cancelled events are dropped.
+//!
+//! Components inside an event, like `VALARM`, are skipped. Moved occurrences of a recurring event
+//! (`RECURRENCE-ID`) are read as events of their own, the occurrence they replace stays in the recurrence.
+//! Lines longer than the line buffer are cut off, only `SUMMARY` and `CATEGORIES` are read from what is left.
+
+use core::cmp::Ordering;
+
+use heapless::Vec;
+
+use crate::utility::calendar::{self, DateTime};
+
+/// Capacity of the buffer for a content line, unfolded
+const LINE_CAPACITY: usize = 256;
+
+/// Number of days excluded from a recurrence that are kept, further ones are dropped
+const MAX_EXCLUDED_DAYS: usize = 8;
+
+/// Errors when reading an iCalendar document
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum IcsError {
+    /// The document has no `VCALENDAR`, it is not an iCalendar document
+    NotACalendar,
+}
+
+/// How often a recurrence repeats
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Frequency {
+    /// Every `interval` days
+    Daily,
+    /// Every `interval` weeks on the weekdays of the rule, weeks start on Monday
+    Weekly,
+    /// Every `interval` months on the day of the month of the start
+    Monthly,
+    /// Every `interval` years on the day of the start
+    Yearly,
+}
+
+/// A recurrence rule of the form `FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,TU;COUNT=10`, `UNTIL` ends it on a day
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Recurrence {
+    /// How often the event repeats
+    pub frequency: Frequency,
+    /// Every how many days, weeks, months or years the event repeats, at least 1
+    pub interval: u16,
+    /// The number of occurrences, `None` for no limit
+    pub count: Option<u16>,
+    /// The last day an occurrence may start on, at the start of the day, `None` for no limit
+    pub until: Option<DateTime>,
+    /// The weekdays of a weekly rule as given by `calendar::weekday_bit`, 0 for the weekday of the start
+    pub weekdays: u8,
+}
+
+/// An event of the calendar, as far as it matters for the alarm
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CalendarEvent {
+    /// The first day of the event at the start of the day, local time
+    pub day: DateTime,
+    /// The start time in (hour, minute), local time, `None` for an all-day event
+    pub time: Option<(u8, u8)>,
+    /// The number of days an all-day event lasts, 1 for other events
+    pub days: u16,
+    /// The summary or the categories hold the keyword
+    pub tagged: bool,
+    /// How the event repeats, `None` for a single event
+    pub recurrence: Option<Recurrence>,
+    /// Days the recurrence skips, at the start of the day
+    pub excluded_days: Vec<DateTime, MAX_EXCLUDED_DAYS>,
+}
+
+impl CalendarEvent {
+    /// Whether an occurrence of the event starts on the day, given at its start
+    pub fn starts_on(&self, day: &DateTime) -> bool {
+        if calendar::compare(day, &self.day) == Ordering::Less
+            || self
+                .excluded_days
+                .iter()
+                .any(|excluded| calendar::days_between(excluded, day) == 0)
+        {
+            return false;
+        }
+        let Some(rule) = &self.recurrence else {
+            return calendar::days_between(&self.day, day) == 0;
+        };
+        if rule
+            .until
+            .as_ref()
+            .is_some_and(|until| calendar::compare(day, until) == Ordering::Greater)
+        {
+            return false;
+        }
+        let Some(occurrence) = occurrence_number(&self.day, day, rule) else {
+            return false;
+        };
+        rule.count.is_none_or(|count| occurrence <= i64::from(count))
+    }
+
+    /// Whether the event takes place on the day, given at its start: it starts on it or, for an all-day event over
+    /// several days, on one of the days before
+    pub fn covers(&self, day: &DateTime) -> bool {
+        (0..i32::from(self.days)).any(|offset| self.starts_on(&calendar::add_days(day, -offset)))
+    }
+}
+
+/// The number of the occurrence of a recurrence starting on `first` that falls on `day`, counting from 1.
+/// `None` if the recurrence does not fall on the day. The start is always the first occurrence, even if the rule
+/// does not hold on it. Days that do not exist in a month or year, like the 31st of April, have no occurrence and
+/// do not count.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn occurrence_number(first: &DateTime, day: &DateTime, rule: &Recurrence) -> Option<i64> {
+    let interval = i64::from(rule.interval);
+    let days = calendar::days_between(first, day);
+    if days == 0 {
+        return Some(1);
+    }
+    match rule.frequency {
+        Frequency::Daily => (days % interval == 0).then_some(days / interval + 1),
+        Frequency::Weekly => {
+            // weekday index with Monday as 0, and the Monday of the week of the first occurrence
+            let first_weekday = calendar::weekday_bit(first.day_of_week).trailing_zeros();
+            let weekday = calendar::weekday_bit(day.day_of_week).trailing_zeros();
+            let weekdays = if rule.weekdays == 0 {
+                calendar::weekday_bit(first.day_of_week)
+            } else {
+                rule.weekdays
+            };
+            let weeks = (days - i64::from(weekday) + i64::from(first_weekday)) / 7;
+            if weeks % interval != 0 || weekdays & (1 << weekday) == 0 {
+                return None;
+            }
+            // the start, the occurrences of the rest of its week, of the full weeks in between, and of this week
+            let per_week = i64::from(weekdays.count_ones());
+            let rest_of_first_week = i64::from((weekdays >> first_weekday >> 1).count_ones());
+            let this_week = i64::from((weekdays & ((2 << weekday) - 1)).count_ones());
+            Some(if weeks == 0 {
+                1 + this_week - (per_week - rest_of_first_week)
+            } else {
+                1 + rest_of_first_week + (weeks / interval - 1) * per_week + this_week
+            })
+        }
+        Frequency::Monthly => {
+            let first_month = i64::from(first.year) * 12 + i64::from(first.month) - 1;
+            let months = i64::from(day.year) * 12 + i64::from(day.month) - 1 - first_month;
+            if day.day != first.day || months % interval != 0 {
+                return None;
+            }
+            if first.day <= 28 {
+                return Some(months / interval + 1);
+            }
+            // the month of each step, as months since year 0, is within the range of the calendar
+            count_occurrences(months / interval, |step| {
+                let month = first_month + step * interval;
+                calendar::days_in_month((month % 12 + 1) as u8, (month / 12) as u16) >= first.day
+            })
+        }
+        Frequency::Yearly => {
+            let years = i64::from(day.year) - i64::from(first.year);
+            if day.month != first.month || day.day != first.day || years % interval != 0 {
+                return None;
+            }
+            if first.month != 2 || first.day != 29 {
+                return Some(years / interval + 1);
+            }
+            // the year of each step is within the range of the calendar
+            count_occurrences(years / interval, |step| {
+                calendar::is_leap_year((i64::from(first.year) + step * interval) as u16)
+            })
+        }
+    }
+}
+
+/// The number of steps from 0 to `last` of a recurrence on which the day of the start exists
+fn count_occurrences(last: i64, exists: impl Fn(i64) -> bool) -> Option<i64> {
+    i64::try_from((0..=last).filter(|&step| exists(step)).count()).ok()
+}
+
+/// The event being read, before it is complete
+#[derive(Debug, Clone, Default)]
+struct EventBuilder {
+    /// The first day and the start time
+    start: Option<(DateTime, Option<(u8, u8)>)>,
+    /// The day of `DTEND` for an all-day event
+    end_day: Option<DateTime>,
+    /// The summary or the categories hold the keyword
+    tagged: bool,
+    /// The recurrence rule
+    recurrence: Option<Recurrence>,
+    /// The days the recurrence skips
+    excluded_days: Vec<DateTime, MAX_EXCLUDED_DAYS>,
+    /// The event is cancelled
+    cancelled: bool,
+}
+
+impl EventBuilder {
+    /// The complete event, `None` if it has no start or is cancelled
+    fn build(self) -> Option<CalendarEvent> {
+        let (day, time) = self.start?;
+        if self.cancelled {
+            return None;
+        }
+        let days = match (time, self.end_day) {
+            (None, Some(end_day)) => u16::try_from(calendar::days_between(&day, &end_day))
+                .unwrap_or(1)
+                .max(1),
+            _ => 1,
+        };
+        Some(CalendarEvent {
+            day,
+            time,
+            days,
+            tagged: self.tagged,
+            recurrence: self.recurrence,
+            excluded_days: self.excluded_days,
+        })
+    }
+}
+
+/// Reads the events of an iCalendar document while it streams in
+pub struct IcsReader<'a> {
+    /// The keyword events are tagged with, `None` if no event is tagged
+    keyword: Option<&'a str>,
+    /// The UTC offset of the local time in minutes, for times given in UTC
+    utc_offset_minutes: i16,
+    /// The content line being read
+    line: Vec<u8, LINE_CAPACITY>,
+    /// The content line did not fit the buffer and is cut off
+    truncated: bool,
+    /// The content line has ended, unless the next line continues it
+    line_ended: bool,
+    /// A `VCALENDAR` has been seen
+    calendar: bool,
+    /// The event being read, `None` outside of events
+    event: Option<EventBuilder>,
+    /// Nesting depth of the components inside the event being read
+    nested: u8,
+}
+
+impl<'a> IcsReader<'a> {
+    /// Create a new `IcsReader`, tagging events with the keyword and converting UTC times with the offset
+    pub const fn new(keyword: Option<&'a str>, utc_offset_minutes: i16) -> Self {
+        Self {
+            keyword,
+            utc_offset_minutes,
+            line: Vec::new(),
+            truncated: false,
+            line_ended: false,
+            calendar: false,
+            event: None,
+            nested: 0,
+        }
+    }
+
+    /// Feed the next chunk of the document, `on_event` is called with every event that has been read completely
+    pub fn feed(&mut self, chunk: &[u8], on_event: &mut impl FnMut(&CalendarEvent)) {
+        for &byte in chunk {
+            match byte {
+                b'\r' => {}
+                b'\n' => self.line_ended = true,
+                // a line starting with a space or tab continues the previous one
+                b' ' | b'\t' if self.line_ended => self.line_ended = false,
+                _ => {
+                    if self.line_ended {
+                        self.end_line(on_event);
+                    }
+                    if self.line.push(byte).is_err() {
+                        self.truncated = true;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Finish reading the document, `on_event` is called with the last event if it is complete
+    pub fn finish(mut self, on_event: &mut impl FnMut(&CalendarEvent)) -> Result<(), IcsError> {
+        self.end_line(on_event);
+        if self.calendar {
+            Ok(())
+        } else {
+            Err(IcsError::NotACalendar)
+        }
+    }
+
+    /// Handle the content line that has been read and start the next one
+    fn end_line(&mut self, on_event: &mut impl FnMut(&CalendarEvent)) {
+        let line = core::mem::take(&mut self.line);
+        let truncated = core::mem::replace(&mut self.truncated, false);
+        self.line_ended = false;
+        // a line cut off within a character is cut back to the last whole character
+        let line = match core::str::from_utf8(&line) {
+            Ok(line) => line,
+            Err(error) if truncated => core::str::from_utf8(&line[..error.valid_up_to()]).unwrap_or_default(),
+            Err(_) => return,
+        };
+        let Some((name, params, value)) = split_content_line(line) else {
+            return;
+        };
+
+        if name.eq_ignore_ascii_case("BEGIN") {
+            self.begin(value);
+        } else if name.eq_ignore_ascii_case("END") {
+            self.end(value, on_event);
+        } else if self.nested == 0
+            && let Some(event) = self.event.as_mut()
+        {
+            let keyword = self.keyword;
+            let utc_offset_minutes = self.utc_offset_minutes;
+            if name.eq_ignore_ascii_case("SUMMARY") || name.eq_ignore_ascii_case("CATEGORIES") {
+                event.tagged |= keyword.is_some_and(|keyword| contains_word(value, keyword));
+            } else if truncated {
+                // the value is incomplete, better ignore it than misread it
+            } else if name.eq_ignore_ascii_case("DTSTART") {
+                event.start = parse_date_time(value, params, utc_offset_minutes);
+            } else if name.eq_ignore_ascii_case("DTEND") {
+                event.end_day = parse_date_time(value, params, utc_offset_minutes)
+                    .filter(|(_, time)| time.is_none())
+                    .map(|(day, _)| day);
+            } else if name.eq_ignore_ascii_case("RRULE") {
+                event.recurrence = parse_recurrence(value, utc_offset_minutes);
+            } else if name.eq_ignore_ascii_case("EXDATE") {
+                for item in value.split(',') {
+                    if let Some((day, _)) = parse_date_time(item, params, utc_offset_minutes) {
+                        let _ = event.excluded_days.push(day);
+                    }
+                }
+            } else if name.eq_ignore_ascii_case("STATUS") {
+                event.cancelled = value.eq_ignore_ascii_case("CANCELLED");
+            }
+        }
+    }
+
+    /// Handle the start of a component
+    fn begin(&mut self, component: &str) {
+        if self.event.is_some() {
+            self.nested = self.nested.saturating_add(1);
+        } else if component.eq_ignore_ascii_case("VEVENT") {
+            self.event = Some(EventBuilder::default());
+        } else if component.eq_ignore_ascii_case("VCALENDAR") {
+            self.calendar = true;
+        }
+    }
+
+    /// Handle the end of a component, hand over the event if it is the end of one
+    fn end(&mut self, component: &str, on_event: &mut impl FnMut(&CalendarEvent)) {
+        if self.nested > 0 {
+            self.nested -= 1;
+        } else if component.eq_ignore_ascii_case("VEVENT")
+            && let Some(event) = self.event.take().and_then(EventBuilder::build)
+        {
+            on_event(&event);
+        }
+    }
+}
+
+/// Split a content line `NAME;PARAM=VALUE;PARAM=VALUE:VALUE` into name, parameters and value.
+/// The value starts after the first colon that is not inside a quoted parameter value.
+fn split_content_line(line: &str) -> Option<(&str, &str, &str)> {
+    let mut quoted = false;
+    let colon = line.char_indices().find_map(|(index, c)| {
+        if c == '"' {
+            quoted = !quoted;
+        }
+        (c == ':' && !quoted).then_some(index)
+    })?;
+    let (head, value) = (&line[..colon], &line[colon + 1..]);
+    let (name, params) = head.split_once(';').unwrap_or((head, ""));
+    Some((name, params, value))
+}
+
+/// Parse a date `20240501` or a date-time `20240501T083000` or `20240501T063000Z` into the day, at its start, and
+/// the time in (hour, minute), `None` for a date. UTC is converted to local time with the offset.
+fn parse_date_time(value: &str, params: &str, utc_offset_minutes: i16) -> Option<(DateTime, Option<(u8, u8)>)> {
+    let value = value.trim();
+    let date_only = params.split(';').any(|param| param.eq_ignore_ascii_case("VALUE=DATE"));
+    let year = value.get(0..4)?.parse().ok()?;
+    let month = value.get(4..6)?.parse().ok()?;
+    let day = value.get(6..8)?.parse().ok()?;
+    if !(1..=12).contains(&month) || day == 0 || day > calendar::days_in_month(month, year) {
+        return None;
+    }
+    let date = DateTime {
+        year,
+        month,
+        day,
+        day_of_week: calendar::day_of_week(year, month, day),
+        hour: 0,
+        minute: 0,
+        second: 0,
+    };
+    if date_only || value.len() == 8 {
+        return Some((date, None));
+    }
+
+    let time = value.get(8..)?.strip_prefix(['T', 't'])?;
+    let hour: u8 = time.get(0..2)?.parse().ok()?;
+    let minute: u8 = time.get(2..4)?.parse().ok()?;
+    if hour > 23 || minute > 59 {
+        return None;
+    }
+    if !time.ends_with(['Z', 'z']) {
+        return Some((date, Some((hour, minute))));
+    }
+    let local = calendar::add_minutes(&DateTime { hour, minute, ..date }, i32::from(utc_offset_minutes));
+    Some((calendar::start_of_day(&local), Some((local.hour, local.minute))))
+}
+
+/// Parse a recurrence rule, `None` if it uses anything beyond `FREQ`, `INTERVAL`, `COUNT`, `UNTIL`, `WKST` and
+/// plain weekdays in `BYDAY` of a weekly rule
+fn parse_recurrence(value: &str, utc_offset_minutes: i16) -> Option<Recurrence> {
+    let mut frequency = None;
+    let mut rule = Recurrence {
+        frequency: Frequency::Daily,
+        interval: 1,
+        count: None,
+        until: None,
+        weekdays: 0,
+    };
+    for part in value.split(';') {
+        let (key, part_value) = part.split_once('=')?;
+        if key.eq_ignore_ascii_case("FREQ") {
+            const FREQUENCIES: [(&str, Frequency); 4] = [
+                ("DAILY", Frequency::Daily),
+                ("WEEKLY", Frequency::Weekly),
+                ("MONTHLY", Frequency::Monthly),
+                ("YEARLY", Frequency::Yearly),
+            ];
+            frequency = Some(
+                FREQUENCIES
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(part_value))
+                    .map(|&(_, frequency)| frequency)?,
+            );
+        } else if key.eq_ignore_ascii_case("INTERVAL") {
+            rule.interval = part_value.parse().ok().filter(|&interval| interval > 0)?;
+        } else if key.eq_ignore_ascii_case("COUNT") {
+            rule.count = Some(part_value.parse().ok()?);
+        } else if key.eq_ignore_ascii_case("UNTIL") {
+            rule.until = Some(parse_date_time(part_value, "", utc_offset_minutes)?.0);
+        } else if key.eq_ignore_ascii_case("BYDAY") {
+            for weekday in part_value.split(',') {
+                rule.weekdays |= weekday_bit(weekday)?;
+            }
+        } else if !key.eq_ignore_ascii_case("WKST") {
+            return None;
+        }
+    }
+    rule.frequency = frequency?;
+    (rule.weekdays == 0 || rule.frequency == Frequency::Weekly).then_some(rule)
+}
+
+/// The bit of a weekday `MO` to `SU`, as given by `calendar::weekday_bit`. `None` for other values, like the
+/// ordinal weekdays `1MO` or `-1FR` of monthly rules.
+fn weekday_bit(weekday: &str) -> Option<u8> {
+    const WEEKDAYS: [&str; 7] = ["MO", "TU", "WE", "TH", "FR", "SA", "SU"];
+    WEEKDAYS
+        .iter()
+        .position(|name| name.eq_ignore_ascii_case(weekday.trim()))
+        .map(|index| 1 << index)
+}
+
+/// Whether the text holds the word, in any case and not as part of a longer word
+fn contains_word(text: &str, word: &str) -> bool {
+    let (text, word) = (text.as_bytes(), word.as_bytes());
+    if word.is_empty() || text.len() < word.len() {
+        return false;
+    }
+    (0..=text.len() - word.len()).any(|start| {
+        let end = start + word.len();
+        text[start..end].eq_ignore_ascii_case(word)
+            && (start == 0 || !text[start - 1].is_ascii_alphanumeric())
+            && text.get(end).is_none_or(|byte| !byte.is_ascii_alphanumeric())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Read the events of a document fed in chunks of the given size, tagged with `OFF`
+    fn read(document: &[u8], chunk: usize, utc_offset_minutes: i16) -> std::vec::Vec<CalendarEvent> {
+        let mut events = std::vec::Vec::new();
+        let mut reader = IcsReader::new(Some("OFF"), utc_offset_minutes);
+        for part in document.chunks(chunk) {
+            reader.feed(part, &mut |event| events.push(event.clone()));
+        }
+        reader.finish(&mut |event| events.push(event.clone())).unwrap();
+        events
+    }
+
+    /// The start of a day
+    fn date(year: u16, month: u8, day: u8) -> DateTime {
+        DateTime {
+            year,
+            month,
+            day,
+            day_of_week: calendar::day_of_week(year, month, day),
+            hour: 0,
+            minute: 0,
+            second: 0,
+        }
+    }
+
+    /// The days from `from` on, for the given number of days, an occurrence of the event starts on
+    fn starts(event: &CalendarEvent, from: &DateTime, days: i32) -> std::vec::Vec<(u16, u8, u8)> {
+        (0..days)
+            .map(|offset| calendar::add_days(from, offset))
+            .filter(|day| event.starts_on(day))
+            .map(|day| (day.year, day.month, day.day))
+            .collect()
+    }
+
+    /// Shifts with a count and an excluded day, a day off over two days and a night shift in UTC, in any chunks
+    #[test]
+    fn reads_shifts() {
+        let document = include_bytes!("../../host-tests/fixtures/ics/shifts.ics");
+        for chunk in [1, 3, 7, 64, 4096] {
+            let events = read(document, chunk, 120);
+            assert_eq!(events.len(), 4, "chunk {chunk}");
+            let shift = &events[0];
+            assert_eq!(shift.time, Some((7, 0)));
+            assert!(!shift.tagged, "the keyword of the alarm component does not count");
+            assert_eq!(
+                starts(shift, &date(2024, 5, 1), 31),
+                [(2024, 5, 6), (2024, 5, 8), (2024, 5, 13), (2024, 5, 14)]
+            );
+            let off = &events[1];
+            assert!(off.tagged);
+            assert_eq!(off.days, 2);
+            assert!(off.covers(&date(2024, 5, 9)) && off.covers(&date(2024, 5, 10)) && !off.covers(&date(2024, 5, 11)));
+            let night = &events[2];
+            assert_eq!((&night.day, night.time), (&date(2024, 5, 12), Some((0, 30))));
+            assert_eq!(events[3].recurrence, None, "ordinal weekdays are not followed");
+        }
+    }
+
+    /// Daily, yearly and monthly rules, the monthly one skips the months without a 31st
+    #[test]
+    fn reads_recurrences() {
+        let events = read(include_bytes!("../../host-tests/fixtures/ics/recurring.ics"), 5, 0);
+        assert_eq!(
+            starts(&events[0], &date(2024, 4, 1), 61),
+            [(2024, 5, 1), (2024, 5, 3), (2024, 5, 5), (2024, 5, 7), (2024, 5, 9)]
+        );
+        assert!(events[1].starts_on(&date(2025, 12, 31)) && !events[1].starts_on(&date(2025, 12, 30)));
+        assert!(events[2].tagged);
+        assert_eq!(
+            starts(&events[2], &date(2024, 1, 1), 182),
+            [(2024, 1, 31), (2024, 3, 31), (2024, 5, 31)]
+        );
+    }
+
+    /// Dates that do not exist are not counted, and the start counts even if the rule does not hold on it
+    #[test]
+    fn counts_occurrences_like_rfc_5545() {
+        let events = read(include_bytes!("../../host-tests/fixtures/ics/rule_edges.ics"), 4096, 0);
+        assert_eq!(
+            starts(&events[0], &date(2024, 1, 1), 366),
+            [(2024, 1, 31), (2024, 3, 31), (2024, 5, 31), (2024, 7, 31)]
+        );
+        assert!(events[1].tagged);
+        let leap_days: std::vec::Vec<u16> = (2020..2040)
+            .filter(|&year| calendar::is_leap_year(year) && events[1].starts_on(&date(year, 2, 29)))
+            .collect();
+        assert_eq!(leap_days, [2020, 2024, 2028]);
+        assert!(!events[1].starts_on(&date(2021, 2, 28)) && !events[1].starts_on(&date(2021, 3, 1)));
+        assert_eq!(
+            starts(&events[2], &date(2024, 5, 1), 31),
+            [(2024, 5, 8), (2024, 5, 9), (2024, 5, 14)]
+        );
+    }
+
+    /// A weekly rule with an interval and a count, starting in the middle of the week
+    #[test]
+    fn counts_weekly_occurrences_with_an_interval() {
+        let document = b"BEGIN:VCALENDAR\nBEGIN:VEVENT\nDTSTART:20240508T060000\n\
+            RRULE:FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,WE;COUNT=4;WKST=MO\nEND:VEVENT\nEND:VCALENDAR\n";
+        let events = read(document, 10, 0);
+        assert_eq!(
+            starts(&events[0], &date(2024, 5, 1), 61),
+            [(2024, 5, 8), (2024, 5, 20), (2024, 5, 22), (2024, 6, 3)]
+        );
+    }
+
+    /// Times in UTC move to the local day, forwards and backwards
+    #[test]
+    fn converts_utc_to_local_time() {
+        assert_eq!(
+            parse_date_time("20240101T003000Z", "", -60),
+            Some((date(2023, 12, 31), Some((23, 30))))
+        );
+        assert_eq!(
+            parse_date_time("20240229T230000Z", "", 120),
+            Some((date(2024, 3, 1), Some((1, 0))))
+        );
+        assert_eq!(
+            parse_date_time("20240229T230000", "TZID=Europe/Berlin", 120),
+            Some((date(2024, 2, 29), Some((23, 0))))
+        );
+        assert_eq!(parse_date_time("20240230", "VALUE=DATE", 0), None);
+    }
+
+    /// Anything without a `VCALENDAR` is refused
+    #[test]
+    fn refuses_other_documents() {
+        let mut reader = IcsReader::new(None, 0);
+        reader.feed(b"<html>Not found</html>", &mut |_| {});
+        assert_eq!(reader.finish(&mut |_| {}), Err(IcsError::NotACalendar));
+    }
+
+    /// Lines longer than the buffer are cut off, also within a character, and the keyword is still found
+    #[test]
+    fn cuts_off_long_lines() {
+        let document = format!(
+            "BEGIN:VCALENDAR\nBEGIN:VEVENT\nDTSTART:20240508T060000\nDESCRIPTION:{}\nSUMMARY:OFF {}\n\
+             END:VEVENT\nEND:VCALENDAR",
+            "x".repeat(1000),
+            "ü".repeat(300)
+        );
+        let events = read(document.as_bytes(), 13, 0);
+        assert_eq!(events.len(), 1);
+        assert!(events[0].tagged);
+    }
+}
@@ -0,0 +1,306 @@
+//! # JSON Stream
+//! This module extracts the value of a single string field from a JSON object while the document streams in,
+//! chunk by chunk, so that a response never has to be held in memory as a whole.
+//!
+//! Only the top level of the object is looked at, nested objects and arrays are skipped by counting brackets.
+//! Only the bytes of the wanted value are kept, everything else is dropped as soon as it has been read.
+//! The document is not validated, a response that is not JSON simply does not contain the field.
+
+use heapless::{String, Vec};
+
+/// Errors when extracting a field from a JSON stream
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum JsonStreamError {
+    /// The document ended without containing the field
+    FieldNotFound,
+    /// The field is there, but its value is not a string
+    NotAString,
+    /// The value of the field is longer than the capacity of the extractor
+    ValueTooLong,
+    /// The value contains an escape sequence other than `\"`, `\\` and `\/`
+    UnsupportedEscape,
+    /// The value is not valid UTF-8
+    InvalidUtf8,
+}
+
+/// What the string currently being read is
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum StringRole {
+    /// A key on the top level, with the number of bytes that matched the wanted key so far, `None` if it differs
+    Key(Option<usize>),
+    /// The value of the wanted field
+    Value,
+    /// Any other string
+    Other,
+}
+
+/// What comes next on the top level of the object
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Next {
+    /// The next string is a key
+    Key,
+    /// The wanted key was just read, the next value is the wanted one
+    WantedValue,
+    /// Anything else
+    Other,
+}
+
+/// Extracts the string value of the top level field `key`, keeping at most `N` bytes of it
+pub struct JsonFieldExtractor<'a, const N: usize> {
+    /// The key of the wanted field
+    key: &'a [u8],
+    /// Nesting depth of objects and arrays, 1 is the top level object
+    depth: u8,
+    /// The role of the string being read, `None` outside of strings
+    string: Option<StringRole>,
+    /// The previous byte in the string was a backslash
+    escaped: bool,
+    /// What comes next on the top level
+    next: Next,
+    /// The bytes of the wanted value read so far
+    value: Vec<u8, N>,
+    /// The wanted value has been read completely
+    complete: bool,
+}
+
+impl<'a, const N: usize> JsonFieldExtractor<'a, N> {
+    /// Create a new `JsonFieldExtractor` for the top level field `key`
+    pub const fn new(key: &'a str) -> Self {
+        Self {
+            key: key.as_bytes(),
+            depth: 0,
+            string: None,
+            escaped: false,
+            next: Next::Other,
+            value: Vec::new(),
+            complete: false,
+        }
+    }
+
+    /// Feed the next chunk of the document.
+    /// Returns `true` once the value has been read completely, the rest of the document does not need to be fed.
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<bool, JsonStreamError> {
+        for &byte in chunk {
+            if self.complete {
+                break;
+            }
+            match self.string {
+                Some(role) => self.string_byte(role, byte)?,
+                None => self.structure_byte(byte)?,
+            }
+        }
+        Ok(self.complete)
+    }
+
+    /// Finish extracting and return the value of the field
+    pub fn finish(self) -> Result<String<N>, JsonStreamError> {
+        if !self.complete {
+            return Err(JsonStreamError::FieldNotFound);
+        }
+        String::from_utf8(self.value).map_err(|_| JsonStreamError::InvalidUtf8)
+    }
+
+    /// Handle a byte outside of strings
+    fn structure_byte(&mut self, byte: u8) -> Result<(), JsonStreamError> {
+        let top_level = self.depth == 1;
+        match byte {
+            b'"' => {
+                let role = match self.next {
+                    Next::Key if top_level => StringRole::Key(Some(0)),
+                    Next::WantedValue if top_level => StringRole::Value,
+                    _ => StringRole::Other,
+                };
+                if top_level {
+                    self.next = Next::Other;
+                }
+                self.string = Some(role);
+            }
+            b'{' | b'[' => {
+                self.check_not_wanted(top_level)?;
+                self.depth = self.depth.saturating_add(1);
+                if self.depth == 1 && byte == b'{' {
+                    self.next = Next::Key;
+                }
+            }
+            b'}' | b']' => self.depth = self.depth.saturating_sub(1),
+            b',' if top_level => self.next = Next::Key,
+            b',' | b':' | b' ' | b'\t' | b'\r' | b'\n' => {}
+            // the start of a number, `true`, `false` or `null`
+            _ => self.check_not_wanted(top_level)?,
+        }
+        Ok(())
+    }
+
+    /// Handle a byte inside of a string
+    fn string_byte(&mut self, role: StringRole, byte: u8) -> Result<(), JsonStreamError> {
+        if self.escaped {
+            self.escaped = false;
+            return self.string_content(role, byte, true);
+        }
+        match byte {
+            b'\\' => self.escaped = true,
+            b'"' => {
+                self.string = None;
+                match role {
+                    StringRole::Key(Some(matched)) if matched == self.key.len() => self.next = Next::WantedValue,
+                    StringRole::Value => self.complete = true,
+                    StringRole::Key(_) | StringRole::Other => {}
+                }
+            }
+            _ => self.string_content(role, byte, false)?,
+        }
+        Ok(())
+    }
+
+    /// Handle a content byte of a string, `escaped` if it followed a backslash
+    fn string_content(&mut self, role: StringRole, byte: u8, escaped: bool) -> Result<(), JsonStreamError> {
+        match role {
+            StringRole::Key(matched) => {
+                let matched = matched.filter(|&matched| self.key.get(matched) == Some(&byte));
+                self.string = Some(StringRole::Key(matched.map(|matched| matched + 1)));
+            }
+            StringRole::Value => {
+                if escaped && !matches!(byte, b'"' | b'\\' | b'/') {
+                    return Err(JsonStreamError::UnsupportedEscape);
+                }
+                self.value.push(byte).map_err(|_| JsonStreamError::ValueTooLong)?;
+            }
+            StringRole::Other => {}
+        }
+        Ok(())
+    }
+
+    /// Fail if the wanted value starts with something else than a string
+    fn check_not_wanted(&self, top_level: bool) -> Result<(), JsonStreamError> {
+        if top_level && self.next == Next::WantedValue {
+            Err(JsonStreamError::NotAString)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A response of the time API, with the wanted field behind nested objects and arrays holding the same key
+    const RESPONSE: &[u8] = br#"{"abbreviation":"CEST","client_ip":"203.0.113.7","meta":{"datetime":"nested"},"list":[{"datetime":"in array"},"datetime"],"datetime":"2024-05-01T06:30:00.123456+02:00","day_of_week":3,"dst":true,"utc_offset":"+02:00"}"#;
+
+    /// Extract the field from the document fed in one chunk
+    fn extract<const N: usize>(key: &str, document: &[u8]) -> Result<String<N>, JsonStreamError> {
+        let mut extractor = JsonFieldExtractor::<N>::new(key);
+        extractor.feed(document)?;
+        extractor.finish()
+    }
+
+    /// The value is found however the document is split, also with the split inside the key or the value
+    #[test]
+    fn extracts_the_value_split_at_every_offset() {
+        for split in 0..=RESPONSE.len() {
+            let mut extractor = JsonFieldExtractor::<40>::new("datetime");
+            let (first, second) = RESPONSE.split_at(split);
+            let done = extractor.feed(first).unwrap() || extractor.feed(second).unwrap();
+            assert!(done, "split at {split}");
+            assert_eq!(extractor.finish().unwrap(), "2024-05-01T06:30:00.123456+02:00");
+        }
+        let mut extractor = JsonFieldExtractor::<40>::new("datetime");
+        for byte in RESPONSE {
+            extractor.feed(&[*byte]).unwrap();
+        }
+        assert_eq!(extractor.finish().unwrap(), "2024-05-01T06:30:00.123456+02:00");
+    }
+
+    /// Keys in inner objects and arrays and strings that equal the key are not the field
+    #[test]
+    fn ignores_nested_keys() {
+        assert_eq!(
+            extract::<16>(
+                "datetime",
+                br#"{"meta":{"datetime":"nested"},"list":[{"datetime":"x"}]}"#
+            ),
+            Err(JsonStreamError::FieldNotFound)
+        );
+        assert_eq!(
+            extract::<16>("datetime", br#"{"note":"datetime","datetime":"top"}"#).unwrap(),
+            "top"
+        );
+        assert_eq!(
+            extract::<16>("datetime", br#"[{"datetime":"in array"}]"#),
+            Err(JsonStreamError::FieldNotFound)
+        );
+        assert_eq!(extract::<16>("abbreviation", RESPONSE).unwrap(), "CEST");
+        assert_eq!(extract::<16>("utc_offset", RESPONSE).unwrap(), "+02:00");
+    }
+
+    /// Keys that only start like the wanted key or that extend it are other fields
+    #[test]
+    fn matches_the_whole_key() {
+        let document = br#"{"date":"prefix","datetimes":"extension","datetim":"short","datetime":"wanted"}"#;
+        assert_eq!(extract::<16>("datetime", document).unwrap(), "wanted");
+        assert_eq!(extract::<16>("date", document).unwrap(), "prefix");
+        assert_eq!(
+            extract::<16>("datetime", br#"{"date":"a","datetimes":"b"}"#),
+            Err(JsonStreamError::FieldNotFound)
+        );
+    }
+
+    /// Escaped quotes, backslashes and slashes are kept, other escapes are refused, also in the key
+    #[test]
+    fn handles_escapes() {
+        assert_eq!(
+            extract::<16>("v", br#"{"x":"a\"b","v":"q\"\\\/q"}"#).unwrap(),
+            r#"q"\/q"#
+        );
+        assert_eq!(
+            extract::<16>("v", br#"{"v":"line\nbreak"}"#),
+            Err(JsonStreamError::UnsupportedEscape)
+        );
+        assert_eq!(
+            extract::<16>("v", br#"{"v":"\u0041"}"#),
+            Err(JsonStreamError::UnsupportedEscape)
+        );
+        // escapes in other strings do not matter
+        assert_eq!(extract::<16>("v", br#"{"x":"\nA\"","v":"ok"}"#).unwrap(), "ok");
+        assert_eq!(extract::<16>("v\"", br#"{"v\"":"quoted key"}"#).unwrap(), "quoted key");
+    }
+
+    /// A value that is a number, a literal, an object or an array is not a string
+    #[test]
+    fn refuses_non_string_values() {
+        for document in [
+            br#"{"v":3}"#.as_slice(),
+            br#"{"v": true}"#,
+            br#"{"v":null}"#,
+            br#"{"v":{"a":"b"}}"#,
+            br#"{"v":["a"]}"#,
+        ] {
+            assert_eq!(extract::<16>("v", document), Err(JsonStreamError::NotAString));
+        }
+        assert_eq!(extract::<16>("day_of_week", RESPONSE), Err(JsonStreamError::NotAString));
+    }
+
+    /// A value longer than the capacity, an unfinished value, an unknown field and invalid UTF-8 are errors
+    #[test]
+    fn reports_errors() {
+        assert_eq!(extract::<5>("v", br#"{"v":"06:30"}"#).unwrap(), "06:30");
+        assert_eq!(
+            extract::<4>("v", br#"{"v":"06:30"}"#),
+            Err(JsonStreamError::ValueTooLong)
+        );
+        assert_eq!(extract::<31>("datetime", RESPONSE), Err(JsonStreamError::ValueTooLong));
+        assert_eq!(
+            extract::<16>("v", br#"{"v":"unfinished"#),
+            Err(JsonStreamError::FieldNotFound)
+        );
+        assert_eq!(
+            extract::<16>("v", b"<html>v</html>"),
+            Err(JsonStreamError::FieldNotFound)
+        );
+        assert_eq!(
+            extract::<16>("v", b"{\"v\":\"\xFF\"}"),
+            Err(JsonStreamError::InvalidUtf8)
+        );
+    }
+}
@@ -0,0 +1,747 @@
+//! # mDNS
+//! This module answers multicast DNS queries (RFC 6762) for the clock: the address of `<hostname>.local` and, while
+//! the web interface is served, the `_http._tcp` service (DNS-SD, RFC 6763) as `<hostname>._http._tcp.local`.
+//!
+//! Queries are parsed from and responses written into buffers the caller owns, there is no I/O in here, so the
+//! responder can be fed captured query packets on the host. The responder is deliberately small:
+//! - only IPv4 addresses are answered, `AAAA` questions get no answer
+//! - responses are written without name compression, they are small anyway
+//! - known answers in the query are not used to suppress answers, the querier just gets them again
+//!
+//! Queries from port 5353 are answered by multicast, unless all questions ask for a unicast response. Queries from
+//! any other port come from simple resolvers (legacy unicast), they are answered directly with the query id, the
+//! questions repeated and short TTLs.
+
+/// The port mDNS queries and responses are sent from and to
+pub const MDNS_PORT: u16 = 5353;
+
+/// The IPv4 multicast group of mDNS
+pub const MDNS_GROUP: [u8; 4] = [224, 0, 0, 251];
+
+/// Length of the DNS header
+const HEADER_LEN: usize = 12;
+
+/// Flag of a response in the header
+const FLAG_RESPONSE: u16 = 0x8000;
+
+/// Flag of an authoritative answer in the header
+const FLAG_AUTHORITATIVE: u16 = 0x0400;
+
+/// Mask of the opcode in the header, only standard queries (opcode 0) are answered
+const OPCODE_MASK: u16 = 0x7800;
+
+/// Record type `A`, an IPv4 address
+const TYPE_A: u16 = 1;
+/// Record type `PTR`, a pointer to another name
+const TYPE_PTR: u16 = 12;
+/// Record type `TXT`, key value pairs of a service
+const TYPE_TXT: u16 = 16;
+/// Record type `SRV`, the host and port of a service
+const TYPE_SRV: u16 = 33;
+/// Question type `ANY`, all records of a name
+const TYPE_ANY: u16 = 255;
+
+/// Class `IN`, the internet
+const CLASS_IN: u16 = 1;
+
+/// Class `ANY` in a question
+const CLASS_ANY: u16 = 255;
+
+/// The top bit of the class: unicast response requested in a question, cache flush in a record
+const CLASS_TOP_BIT: u16 = 0x8000;
+
+/// TTL of records that hold the hostname or address, in seconds
+const HOST_TTL: u32 = 120;
+
+/// TTL of the other records, in seconds
+const OTHER_TTL: u32 = 4500;
+
+/// Upper bound of the TTL in answers to legacy unicast queries, in seconds
+const LEGACY_TTL: u32 = 10;
+
+/// The most labels of a name we compare, longer names are never ours
+const MAX_LABELS: usize = 8;
+
+/// The most compression pointers followed in a name, protects against loops
+const MAX_POINTERS: usize = 16;
+
+/// The most questions repeated in an answer to a legacy unicast query
+const MAX_QUESTIONS: usize = 4;
+
+/// The TXT record of the web interface, one length prefixed string
+const HTTP_TXT: &[u8] = b"\x06path=/";
+
+/// Errors when answering a query
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MdnsError {
+    /// The query is not a valid DNS message
+    Malformed,
+    /// The response does not fit into the buffer
+    BufferTooSmall,
+}
+
+/// A name the clock answers for
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Name {
+    /// `<hostname>.local`
+    Host,
+    /// `_http._tcp.local`, the service type
+    ServiceType,
+    /// `<hostname>._http._tcp.local`, the service instance
+    ServiceInstance,
+    /// `_services._dns-sd._udp.local`, the enumeration of service types
+    ServiceEnumeration,
+}
+
+impl Name {
+    /// All names the clock answers for
+    const ALL: [Self; 4] = [
+        Self::Host,
+        Self::ServiceType,
+        Self::ServiceInstance,
+        Self::ServiceEnumeration,
+    ];
+
+    /// Whether the name starts with the hostname, and the labels after it
+    const fn labels(self) -> (bool, &'static [&'static str]) {
+        match self {
+            Self::Host => (true, &["local"]),
+            Self::ServiceType => (false, &["_http", "_tcp", "local"]),
+            Self::ServiceInstance => (true, &["_http", "_tcp", "local"]),
+            Self::ServiceEnumeration => (false, &["_services", "_dns-sd", "_udp", "local"]),
+        }
+    }
+
+    /// Check if the labels read from a packet are this name, labels are compared ignoring ASCII case
+    fn matches(self, hostname: &str, labels: &[&[u8]]) -> bool {
+        let (with_host, rest) = self.labels();
+        let expected = with_host.then_some(hostname).into_iter().chain(rest.iter().copied());
+        labels.len() == usize::from(with_host) + rest.len()
+            && expected
+                .zip(labels)
+                .all(|(expected, label)| expected.as_bytes().eq_ignore_ascii_case(label))
+    }
+}
+
+/// A record the clock answers with
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Record {
+    /// `A` record of the host
+    HostAddress,
+    /// `PTR` record from the service type to the service instance
+    ServicePointer,
+    /// `SRV` record of the service instance
+    ServiceLocation,
+    /// `TXT` record of the service instance
+    ServiceText,
+    /// `PTR` record from the service enumeration to the service type
+    ServiceEnumeration,
+}
+
+impl Record {
+    /// All records, in the order they are written
+    const ALL: [Self; 5] = [
+        Self::ServiceEnumeration,
+        Self::ServicePointer,
+        Self::ServiceLocation,
+        Self::ServiceText,
+        Self::HostAddress,
+    ];
+
+    /// The bit of the record in a `RecordSet`
+    const fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// A set of records
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+struct RecordSet(u8);
+
+impl RecordSet {
+    /// Add a record to the set
+    const fn insert(&mut self, record: Record) {
+        self.0 |= record.bit();
+    }
+
+    /// Check if the set holds the record
+    const fn contains(self, record: Record) -> bool {
+        self.0 & record.bit() != 0
+    }
+
+    /// The records in this set that are not in the other set
+    const fn without(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    /// Check if the set is empty
+    const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// The number of records in the set, at most five
+    #[allow(clippy::cast_possible_truncation)]
+    const fn len(self) -> u16 {
+        self.0.count_ones() as u16
+    }
+
+    /// The records in the set, in the order they are written
+    fn iter(self) -> impl Iterator<Item = Record> {
+        Record::ALL.into_iter().filter(move |record| self.contains(*record))
+    }
+}
+
+/// A response written by the responder
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Response {
+    /// The length of the response in the buffer
+    pub len: usize,
+    /// Whether the response goes directly to the querier instead of the multicast group
+    pub unicast: bool,
+}
+
+/// Answers the queries for one host, and optionally its web interface
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Responder<'a> {
+    /// The hostname, without `.local`
+    pub hostname: &'a str,
+    /// The IPv4 address of the host
+    pub address: [u8; 4],
+    /// The port of the web interface, `None` if it is not served
+    pub http_port: Option<u16>,
+}
+
+impl Responder<'_> {
+    /// Answer a query. `legacy` is set for queries from a port other than 5353.
+    /// Returns `None` if the message is not a query or none of its questions is about this host.
+    pub fn respond(&self, query: &[u8], legacy: bool, out: &mut [u8]) -> Result<Option<Response>, MdnsError> {
+        let header = query.get(..HEADER_LEN).ok_or(MdnsError::Malformed)?;
+        let id = u16::from_be_bytes([header[0], header[1]]);
+        let flags = u16::from_be_bytes([header[2], header[3]]);
+        let question_count = u16::from_be_bytes([header[4], header[5]]);
+        if flags & (FLAG_RESPONSE | OPCODE_MASK) != 0 {
+            return Ok(None);
+        }
+
+        let mut answers = RecordSet::default();
+        let mut additionals = RecordSet::default();
+        let mut questions: heapless::Vec<(Name, u16), MAX_QUESTIONS> = heapless::Vec::new();
+        let mut all_unicast = true;
+        let mut offset = HEADER_LEN;
+        for _ in 0..question_count {
+            let (name, end) = self.read_name(query, offset)?;
+            let fields = query.get(end..end + 4).ok_or(MdnsError::Malformed)?;
+            offset = end + 4;
+            let question_type = u16::from_be_bytes([fields[0], fields[1]]);
+            let question_class = u16::from_be_bytes([fields[2], fields[3]]);
+            if !matches!(question_class & !CLASS_TOP_BIT, CLASS_IN | CLASS_ANY) {
+                continue;
+            }
+            let Some(name) = name else {
+                continue;
+            };
+            let before = answers;
+            self.answer_question(name, question_type, &mut answers, &mut additionals);
+            if answers != before {
+                all_unicast &= question_class & CLASS_TOP_BIT != 0;
+                // the questions are only repeated for legacy queries, ignore those that do not fit
+                let _ = questions.push((name, question_type));
+            }
+        }
+
+        if answers.is_empty() {
+            return Ok(None);
+        }
+        let additionals = additionals.without(answers);
+        let id = if legacy { id } else { 0 };
+        let questions = if legacy { questions.as_slice() } else { &[] };
+        let len = self.write_response(out, id, questions, answers, additionals, legacy)?;
+        Ok(Some(Response {
+            len,
+            unicast: legacy || all_unicast,
+        }))
+    }
+
+    /// Write an unsolicited response with all records, sent when the host comes up
+    pub fn announce(&self, out: &mut [u8]) -> Result<usize, MdnsError> {
+        let mut records = RecordSet::default();
+        records.insert(Record::HostAddress);
+        if self.http_port.is_some() {
+            records.insert(Record::ServicePointer);
+            records.insert(Record::ServiceLocation);
+            records.insert(Record::ServiceText);
+        }
+        self.write_response(out, 0, &[], records, RecordSet::default(), false)
+    }
+
+    /// Add the records that answer a question, and the records that help the querier, to the sets
+    fn answer_question(&self, name: Name, question_type: u16, answers: &mut RecordSet, additionals: &mut RecordSet) {
+        let wants = |record_type| question_type == record_type || question_type == TYPE_ANY;
+        let service = self.http_port.is_some();
+        match name {
+            Name::Host if wants(TYPE_A) => answers.insert(Record::HostAddress),
+            Name::ServiceType if service && wants(TYPE_PTR) => {
+                answers.insert(Record::ServicePointer);
+                additionals.insert(Record::ServiceLocation);
+                additionals.insert(Record::ServiceText);
+                additionals.insert(Record::HostAddress);
+            }
+            Name::ServiceInstance if service => {
+                if wants(TYPE_SRV) {
+                    answers.insert(Record::ServiceLocation);
+                    additionals.insert(Record::HostAddress);
+                }
+                if wants(TYPE_TXT) {
+                    answers.insert(Record::ServiceText);
+                }
+            }
+            Name::ServiceEnumeration if service && wants(TYPE_PTR) => answers.insert(Record::ServiceEnumeration),
+            _ => {}
+        }
+    }
+
+    /// Read the name at `offset`, following compression pointers.
+    /// Returns the name if it is one of ours, and the offset after the name.
+    fn read_name(&self, packet: &[u8], mut offset: usize) -> Result<(Option<Name>, usize), MdnsError> {
+        let mut labels: heapless::Vec<&[u8], MAX_LABELS> = heapless::Vec::new();
+        let mut too_long = false;
+        let mut end = None;
+        let mut pointers = 0;
+        loop {
+            let len = *packet.get(offset).ok_or(MdnsError::Malformed)?;
+            match len & 0xC0 {
+                0x00 if len == 0 => {
+                    offset += 1;
+                    break;
+                }
+                0x00 => {
+                    let label = packet
+                        .get(offset + 1..offset + 1 + usize::from(len))
+                        .ok_or(MdnsError::Malformed)?;
+                    too_long |= labels.push(label).is_err();
+                    offset += 1 + usize::from(len);
+                }
+                0xC0 => {
+                    let low = *packet.get(offset + 1).ok_or(MdnsError::Malformed)?;
+                    pointers += 1;
+                    if pointers > MAX_POINTERS {
+                        return Err(MdnsError::Malformed);
+                    }
+                    end.get_or_insert(offset + 2);
+                    offset = usize::from(u16::from_be_bytes([len & 0x3F, low]));
+                }
+                _ => return Err(MdnsError::Malformed),
+            }
+        }
+
+        let name = if too_long {
+            None
+        } else {
+            Name::ALL.into_iter().find(|name| name.matches(self.hostname, &labels))
+        };
+        Ok((name, end.unwrap_or(offset)))
+    }
+
+    /// Write a response with the questions, answers and additional records
+    fn write_response(
+        &self,
+        out: &mut [u8],
+        id: u16,
+        questions: &[(Name, u16)],
+        answers: RecordSet,
+        additionals: RecordSet,
+        legacy: bool,
+    ) -> Result<usize, MdnsError> {
+        let mut writer = Writer {
+            buffer: out,
+            position: 0,
+        };
+        writer.put_u16(id)?;
+        writer.put_u16(FLAG_RESPONSE | FLAG_AUTHORITATIVE)?;
+        // at most four questions are kept
+        #[allow(clippy::cast_possible_truncation)]
+        writer.put_u16(questions.len() as u16)?;
+        writer.put_u16(answers.len())?;
+        writer.put_u16(0)?;
+        writer.put_u16(additionals.len())?;
+
+        for (name, question_type) in questions {
+            self.write_name(&mut writer, *name)?;
+            writer.put_u16(*question_type)?;
+            writer.put_u16(CLASS_IN)?;
+        }
+        for record in answers.iter().chain(additionals.iter()) {
+            self.write_record(&mut writer, record, legacy)?;
+        }
+        Ok(writer.position)
+    }
+
+    /// Write a name as labels without compression
+    fn write_name(&self, writer: &mut Writer<'_>, name: Name) -> Result<(), MdnsError> {
+        let (with_host, rest) = name.labels();
+        for label in with_host
+            .then_some(self.hostname)
+            .into_iter()
+            .chain(rest.iter().copied())
+        {
+            let len = u8::try_from(label.len())
+                .ok()
+                .filter(|len| *len < 64)
+                .ok_or(MdnsError::BufferTooSmall)?;
+            writer.put_u8(len)?;
+            writer.put_bytes(label.as_bytes())?;
+        }
+        writer.put_u8(0)
+    }
+
+    /// Write a resource record
+    fn write_record(&self, writer: &mut Writer<'_>, record: Record, legacy: bool) -> Result<(), MdnsError> {
+        let (name, record_type, unique, ttl) = match record {
+            Record::HostAddress => (Name::Host, TYPE_A, true, HOST_TTL),
+            Record::ServicePointer => (Name::ServiceType, TYPE_PTR, false, OTHER_TTL),
+            Record::ServiceLocation => (Name::ServiceInstance, TYPE_SRV, true, HOST_TTL),
+            Record::ServiceText => (Name::ServiceInstance, TYPE_TXT, true, OTHER_TTL),
+            Record::ServiceEnumeration => (Name::ServiceEnumeration, TYPE_PTR, false, OTHER_TTL),
+        };
+        self.write_name(writer, name)?;
+        writer.put_u16(record_type)?;
+        // the cache flush bit is not understood by legacy resolvers
+        writer.put_u16(if unique && !legacy {
+            CLASS_IN | CLASS_TOP_BIT
+        } else {
+            CLASS_IN
+        })?;
+        writer.put_u32(if legacy { ttl.min(LEGACY_TTL) } else { ttl })?;
+
+        // the length of the data is filled in after the data is written
+        let length_position = writer.position;
+        writer.put_u16(0)?;
+        match record {
+            Record::HostAddress => writer.put_bytes(&self.address)?,
+            Record::ServicePointer => self.write_name(writer, Name::ServiceInstance)?,
+            Record::ServiceLocation => {
+                writer.put_u16(0)?; // priority
+                writer.put_u16(0)?; // weight
+                writer.put_u16(self.http_port.unwrap_or_default())?;
+                self.write_name(writer, Name::Host)?;
+            }
+            Record::ServiceText => writer.put_bytes(HTTP_TXT)?,
+            Record::ServiceEnumeration => self.write_name(writer, Name::ServiceType)?,
+        }
+        let data_len = u16::try_from(writer.position - length_position - 2).map_err(|_| MdnsError::BufferTooSmall)?;
+        writer.buffer[length_position..length_position + 2].copy_from_slice(&data_len.to_be_bytes());
+        Ok(())
+    }
+}
+
+/// Writes the fields of a response into a buffer
+struct Writer<'b> {
+    /// The buffer written into
+    buffer: &'b mut [u8],
+    /// The number of bytes written
+    position: usize,
+}
+
+impl Writer<'_> {
+    /// Write raw bytes
+    fn put_bytes(&mut self, bytes: &[u8]) -> Result<(), MdnsError> {
+        let end = self.position + bytes.len();
+        self.buffer
+            .get_mut(self.position..end)
+            .ok_or(MdnsError::BufferTooSmall)?
+            .copy_from_slice(bytes);
+        self.position = end;
+        Ok(())
+    }
+
+    /// Write a byte
+    fn put_u8(&mut self, value: u8) -> Result<(), MdnsError> {
+        self.put_bytes(&[value])
+    }
+
+    /// Write a big endian two byte integer
+    fn put_u16(&mut self, value: u16) -> Result<(), MdnsError> {
+        self.put_bytes(&value.to_be_bytes())
+    }
+
+    /// Write a big endian four byte integer
+    fn put_u32(&mut self, value: u32) -> Result<(), MdnsError> {
+        self.put_bytes(&value.to_be_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The responder of the tests, with the web interface
+    const RESPONDER: Responder<'static> = Responder {
+        hostname: "clock-1",
+        address: [192, 168, 1, 50],
+        http_port: Some(80),
+    };
+
+    /// A record of a response: name, type, class, TTL and data
+    type ParsedRecord = (String, u16, u16, u32, Vec<u8>);
+
+    /// A response as a resolver reads it: id, number of questions, number of answers and all records
+    struct ParsedResponse {
+        /// The id of the response
+        id: u16,
+        /// The number of questions repeated
+        questions: u16,
+        /// The number of answers, the records after them are additional
+        answers: u16,
+        /// The answers and the additional records
+        records: Vec<ParsedRecord>,
+    }
+
+    /// Read a big endian two byte integer
+    fn u16_at(packet: &[u8], offset: usize) -> u16 {
+        u16::from_be_bytes([packet[offset], packet[offset + 1]])
+    }
+
+    /// Read a name as dotted string, following compression pointers, and the offset after it
+    fn name_at(packet: &[u8], mut offset: usize) -> (String, usize) {
+        let mut name = String::new();
+        let mut end = None;
+        loop {
+            let len = usize::from(packet[offset]);
+            if len == 0 {
+                return (name, end.unwrap_or(offset + 1));
+            }
+            if len & 0xC0 == 0xC0 {
+                end.get_or_insert(offset + 2);
+                offset = usize::from(u16_at(packet, offset) & 0x3FFF);
+                continue;
+            }
+            name.push_str(std::str::from_utf8(&packet[offset + 1..offset + 1 + len]).unwrap());
+            name.push('.');
+            offset += 1 + len;
+        }
+    }
+
+    /// Parse a response of the responder, checking the header and that nothing follows the records
+    fn parse(packet: &[u8]) -> ParsedResponse {
+        assert_eq!(u16_at(packet, 2), FLAG_RESPONSE | FLAG_AUTHORITATIVE);
+        assert_eq!(u16_at(packet, 8), 0);
+        let (questions, answers, additionals) = (u16_at(packet, 4), u16_at(packet, 6), u16_at(packet, 10));
+        let mut offset = HEADER_LEN;
+        for _ in 0..questions {
+            offset = name_at(packet, offset).1 + 4;
+        }
+        let mut records = Vec::new();
+        for _ in 0..answers + additionals {
+            let (name, end) = name_at(packet, offset);
+            let ttl = u32::from_be_bytes(packet[end + 4..end + 8].try_into().unwrap());
+            let data_end = end + 10 + usize::from(u16_at(packet, end + 8));
+            records.push((
+                name,
+                u16_at(packet, end),
+                u16_at(packet, end + 2),
+                ttl,
+                packet[end + 10..data_end].to_vec(),
+            ));
+            offset = data_end;
+        }
+        assert_eq!(offset, packet.len());
+        ParsedResponse {
+            id: u16_at(packet, 0),
+            questions,
+            answers,
+            records,
+        }
+    }
+
+    /// Answer a query, returning whether it goes by unicast and the parsed response
+    fn respond(responder: &Responder<'_>, query: &[u8], legacy: bool) -> Option<(bool, ParsedResponse)> {
+        let mut out = [0; 512];
+        let response = responder.respond(query, legacy, &mut out).unwrap()?;
+        Some((response.unicast, parse(&out[..response.len])))
+    }
+
+    /// The address of the host is answered by multicast, with the cache flush bit
+    #[test]
+    fn answers_the_host_address() {
+        let (unicast, response) = respond(
+            &RESPONDER,
+            include_bytes!("../../host-tests/fixtures/mdns/avahi_resolve_host.bin"),
+            false,
+        )
+        .unwrap();
+        assert!(!unicast);
+        assert_eq!((response.id, response.questions, response.answers), (0, 0, 1));
+        assert_eq!(
+            response.records,
+            vec![("clock-1.local.".into(), TYPE_A, 0x8001, 120, vec![192, 168, 1, 50])]
+        );
+    }
+
+    /// Names are compared ignoring ASCII case
+    #[test]
+    fn ignores_the_case_of_names() {
+        let responder = Responder {
+            hostname: "CLOCK-1",
+            ..RESPONDER
+        };
+        let query = include_bytes!("../../host-tests/fixtures/mdns/avahi_resolve_host.bin");
+        assert!(respond(&responder, query, false).is_some());
+    }
+
+    /// `AAAA` questions, other hosts and responses get no answer
+    #[test]
+    fn ignores_what_is_not_ours() {
+        let aaaa = include_bytes!("../../host-tests/fixtures/mdns/avahi_resolve_host_aaaa.bin");
+        let announcement = include_bytes!("../../host-tests/fixtures/mdns/other_clock_announcement.bin");
+        let other_host = Responder {
+            hostname: "clock-2",
+            ..RESPONDER
+        };
+        let mut out = [0; 512];
+        assert_eq!(RESPONDER.respond(aaaa, false, &mut out), Ok(None));
+        assert_eq!(RESPONDER.respond(announcement, false, &mut out), Ok(None));
+        assert_eq!(
+            other_host.respond(
+                include_bytes!("../../host-tests/fixtures/mdns/avahi_resolve_host.bin"),
+                false,
+                &mut out
+            ),
+            Ok(None)
+        );
+    }
+
+    /// A legacy query is answered directly with its id, its question and short TTLs without cache flush
+    #[test]
+    fn answers_legacy_queries() {
+        let (unicast, response) = respond(
+            &RESPONDER,
+            include_bytes!("../../host-tests/fixtures/mdns/dig_host.bin"),
+            true,
+        )
+        .unwrap();
+        assert!(unicast);
+        assert_eq!((response.id, response.questions, response.answers), (0x5B1E, 1, 1));
+        assert_eq!(
+            response.records,
+            vec![(
+                "clock-1.local.".into(),
+                TYPE_A,
+                CLASS_IN,
+                LEGACY_TTL,
+                vec![192, 168, 1, 50]
+            )]
+        );
+    }
+
+    /// Browsing for web servers finds the service with its location, text and address, by unicast as asked
+    #[test]
+    fn answers_browsing_for_the_service() {
+        let (unicast, response) = respond(
+            &RESPONDER,
+            include_bytes!("../../host-tests/fixtures/mdns/dns_sd_browse_http.bin"),
+            false,
+        )
+        .unwrap();
+        assert!(unicast);
+        assert_eq!(response.answers, 1);
+        let records: Vec<_> = response
+            .records
+            .iter()
+            .map(|record| (record.0.as_str(), record.1))
+            .collect();
+        assert_eq!(
+            records,
+            [
+                ("_http._tcp.local.", TYPE_PTR),
+                ("clock-1._http._tcp.local.", TYPE_SRV),
+                ("clock-1._http._tcp.local.", TYPE_TXT),
+                ("clock-1.local.", TYPE_A),
+            ]
+        );
+        assert_eq!(name_at(&response.records[0].4, 0).0, "clock-1._http._tcp.local.");
+    }
+
+    /// Resolving the service instance, the second question is a compressed name
+    #[test]
+    fn answers_resolving_the_service() {
+        let (_, response) = respond(
+            &RESPONDER,
+            include_bytes!("../../host-tests/fixtures/mdns/dns_sd_resolve_instance.bin"),
+            false,
+        )
+        .unwrap();
+        assert_eq!(response.answers, 2);
+        let location = &response.records[0];
+        assert_eq!((location.1, location.3), (TYPE_SRV, HOST_TTL));
+        assert_eq!(location.4[..6], [0, 0, 0, 0, 0, 80]);
+        assert_eq!(name_at(&location.4, 6).0, "clock-1.local.");
+        assert_eq!(
+            (response.records[1].1, response.records[1].4.as_slice()),
+            (TYPE_TXT, HTTP_TXT)
+        );
+        assert_eq!(response.records[2].1, TYPE_A);
+    }
+
+    /// The service types are enumerated, only while the web interface is served
+    #[test]
+    fn enumerates_the_service_types() {
+        let query = include_bytes!("../../host-tests/fixtures/mdns/avahi_browse_services.bin");
+        let (_, response) = respond(&RESPONDER, query, false).unwrap();
+        assert_eq!(response.records[0].0, "_services._dns-sd._udp.local.");
+        assert_eq!(name_at(&response.records[0].4, 0).0, "_http._tcp.local.");
+
+        let without_http = Responder {
+            http_port: None,
+            ..RESPONDER
+        };
+        assert!(respond(&without_http, query, false).is_none());
+        let browse = include_bytes!("../../host-tests/fixtures/mdns/dns_sd_browse_http.bin");
+        assert!(respond(&without_http, browse, false).is_none());
+    }
+
+    /// The announcement holds all records, the service only with the web interface
+    #[test]
+    fn announces_all_records() {
+        let mut out = [0; 512];
+        let len = RESPONDER.announce(&mut out).unwrap();
+        let response = parse(&out[..len]);
+        assert_eq!((response.answers, response.records.len()), (4, 4));
+
+        let len = Responder {
+            http_port: None,
+            ..RESPONDER
+        }
+        .announce(&mut out)
+        .unwrap();
+        assert_eq!(parse(&out[..len]).answers, 1);
+    }
+
+    /// Cut off packets and pointer loops are malformed, a response that does not fit is refused
+    #[test]
+    fn rejects_bad_packets() {
+        let query = include_bytes!("../../host-tests/fixtures/mdns/dns_sd_resolve_instance.bin");
+        let mut out = [0; 512];
+        assert_eq!(
+            RESPONDER.respond(&query[..20], false, &mut out),
+            Err(MdnsError::Malformed)
+        );
+        assert_eq!(
+            RESPONDER.respond(&query[..8], false, &mut out),
+            Err(MdnsError::Malformed)
+        );
+        assert_eq!(
+            RESPONDER.respond(query, false, &mut out[..40]),
+            Err(MdnsError::BufferTooSmall)
+        );
+
+        let mut pointer_loop = query[..HEADER_LEN].to_vec();
+        pointer_loop[5] = 1;
+        pointer_loop.extend_from_slice(&[0xC0, 12, 0, 1, 0, 1]);
+        assert_eq!(
+            RESPONDER.respond(&pointer_loop, false, &mut out),
+            Err(MdnsError::Malformed)
+        );
+    }
+}
@@ -1,2 +1,24 @@
 //! Utility functions and modules for the project.
+pub mod alarm_limits;
+pub mod alarm_plan;
+pub mod api_router;
+pub mod backoff;
+pub mod calendar;
+pub mod console;
+pub mod firmware_image;
+pub mod home_assistant;
+pub mod http_request;
+pub mod http_response;
+pub mod ics;
+pub mod json_stream;
+pub mod mdns;
+pub mod mqtt;
+pub mod night;
+pub mod peer_sync;
+pub mod pinned_tls;
+pub mod screen;
 pub mod string_utils;
+pub mod syslog;
+pub mod tls_pin;
+pub mod weather;
+pub mod webhook;
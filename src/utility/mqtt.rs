@@ -0,0 +1,571 @@
+//! # MQTT
+//! This module encodes and decodes the MQTT 3.1.1 packets the clock needs as a client: connecting with a last will,
+//! publishing and subscribing at `QoS` 0, and keeping the connection alive.
+//!
+//! Packets are encoded into and decoded from buffers the caller owns, there is no I/O in here, so the whole protocol
+//! can be exercised on the host, the tests hold a session with an in-process fake broker. Decoding borrows topic and
+//! payload from the buffer.
+
+/// The protocol level of MQTT 3.1.1
+const PROTOCOL_LEVEL: u8 = 4;
+
+/// Packet type `CONNECT`
+const CONNECT: u8 = 1;
+/// Packet type `CONNACK`
+const CONNACK: u8 = 2;
+/// Packet type `PUBLISH`
+const PUBLISH: u8 = 3;
+/// Packet type `SUBSCRIBE`
+const SUBSCRIBE: u8 = 8;
+/// Packet type `SUBACK`
+const SUBACK: u8 = 9;
+/// Packet type `PINGREQ`
+const PINGREQ: u8 = 12;
+/// Packet type `PINGRESP`
+const PINGRESP: u8 = 13;
+/// Packet type `DISCONNECT`
+const DISCONNECT: u8 = 14;
+
+/// The largest remaining length MQTT can express
+const MAX_REMAINING_LENGTH: usize = 268_435_455;
+
+/// Errors when encoding or decoding a packet
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PacketError {
+    /// The packet does not fit into the buffer
+    BufferTooSmall,
+    /// The buffer does not hold the complete packet yet
+    Incomplete,
+    /// The packet is not valid MQTT 3.1.1
+    Malformed,
+}
+
+/// The last will, published by the broker when the connection is lost
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Will<'a> {
+    /// The topic of the will
+    pub topic: &'a str,
+    /// The payload of the will
+    pub payload: &'a [u8],
+    /// Whether the broker retains the will
+    pub retain: bool,
+}
+
+/// The options of a `CONNECT` packet
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Connect<'a> {
+    /// The client identifier, unique on the broker
+    pub client_id: &'a str,
+    /// The user name, if the broker wants one
+    pub username: Option<&'a str>,
+    /// The password, only sent together with a user name
+    pub password: Option<&'a str>,
+    /// The keep alive interval in seconds, the broker drops the connection after one and a half of it without packets
+    pub keep_alive_secs: u16,
+    /// The last will
+    pub will: Option<Will<'a>>,
+}
+
+/// A packet received from the broker
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Packet<'a> {
+    /// The answer to `CONNECT`, a return code of 0 means accepted
+    ConnAck {
+        /// Whether the broker kept a session from an earlier connection
+        session_present: bool,
+        /// The return code, 0 is accepted, everything else is refused
+        return_code: u8,
+    },
+    /// The answer to `SUBSCRIBE`
+    SubAck {
+        /// The packet identifier of the subscription
+        packet_id: u16,
+        /// Whether the broker refused any of the topics
+        refused: bool,
+    },
+    /// A message on a subscribed topic
+    Publish {
+        /// The topic of the message
+        topic: &'a str,
+        /// The payload of the message
+        payload: &'a [u8],
+    },
+    /// The answer to `PINGREQ`
+    PingResp,
+    /// Any other packet, which a `QoS` 0 client can ignore
+    Other {
+        /// The packet type
+        packet_type: u8,
+    },
+}
+
+/// Writes the fields of a packet into a buffer
+struct Writer<'b> {
+    /// The buffer written into
+    buffer: &'b mut [u8],
+    /// The number of bytes written
+    position: usize,
+}
+
+impl<'b> Writer<'b> {
+    /// Start a packet of the given type and flags with the given remaining length
+    fn new(buffer: &'b mut [u8], first_byte: u8, remaining_length: usize) -> Result<Self, PacketError> {
+        if remaining_length > MAX_REMAINING_LENGTH {
+            return Err(PacketError::BufferTooSmall);
+        }
+        let mut writer = Self { buffer, position: 0 };
+        writer.put_u8(first_byte)?;
+        // the remaining length is written 7 bits at a time, the high bit marks that more bytes follow
+        let mut length = remaining_length;
+        loop {
+            #[allow(clippy::cast_possible_truncation)]
+            let mut byte = (length % 128) as u8;
+            length /= 128;
+            if length > 0 {
+                byte |= 0x80;
+            }
+            writer.put_u8(byte)?;
+            if length == 0 {
+                break;
+            }
+        }
+        Ok(writer)
+    }
+
+    /// Write raw bytes
+    fn put_bytes(&mut self, bytes: &[u8]) -> Result<(), PacketError> {
+        let end = self.position + bytes.len();
+        self.buffer
+            .get_mut(self.position..end)
+            .ok_or(PacketError::BufferTooSmall)?
+            .copy_from_slice(bytes);
+        self.position = end;
+        Ok(())
+    }
+
+    /// Write a byte
+    fn put_u8(&mut self, value: u8) -> Result<(), PacketError> {
+        self.put_bytes(&[value])
+    }
+
+    /// Write a big endian two byte integer
+    fn put_u16(&mut self, value: u16) -> Result<(), PacketError> {
+        self.put_bytes(&value.to_be_bytes())
+    }
+
+    /// Write bytes prefixed with their length
+    fn put_prefixed(&mut self, bytes: &[u8]) -> Result<(), PacketError> {
+        let length = u16::try_from(bytes.len()).map_err(|_| PacketError::BufferTooSmall)?;
+        self.put_u16(length)?;
+        self.put_bytes(bytes)
+    }
+
+    /// The number of bytes written
+    const fn finish(self) -> usize {
+        self.position
+    }
+}
+
+/// The length of bytes prefixed with their length
+const fn prefixed_len(bytes: &[u8]) -> usize {
+    2 + bytes.len()
+}
+
+/// Encode a `CONNECT` packet with a clean session, returns the length of the packet
+pub fn encode_connect(buffer: &mut [u8], connect: &Connect<'_>) -> Result<usize, PacketError> {
+    // the password is only allowed together with a user name
+    let password = connect.username.and(connect.password);
+
+    let mut flags = 0x02; // clean session
+    let mut remaining_length = prefixed_len(b"MQTT") + 4 + prefixed_len(connect.client_id.as_bytes());
+    if let Some(will) = &connect.will {
+        flags |= 0x04 | if will.retain { 0x20 } else { 0 };
+        remaining_length += prefixed_len(will.topic.as_bytes()) + prefixed_len(will.payload);
+    }
+    if let Some(username) = connect.username {
+        flags |= 0x80;
+        remaining_length += prefixed_len(username.as_bytes());
+    }
+    if let Some(password) = password {
+        flags |= 0x40;
+        remaining_length += prefixed_len(password.as_bytes());
+    }
+
+    let mut writer = Writer::new(buffer, CONNECT << 4, remaining_length)?;
+    writer.put_prefixed(b"MQTT")?;
+    writer.put_u8(PROTOCOL_LEVEL)?;
+    writer.put_u8(flags)?;
+    writer.put_u16(connect.keep_alive_secs)?;
+    writer.put_prefixed(connect.client_id.as_bytes())?;
+    if let Some(will) = &connect.will {
+        writer.put_prefixed(will.topic.as_bytes())?;
+        writer.put_prefixed(will.payload)?;
+    }
+    if let Some(username) = connect.username {
+        writer.put_prefixed(username.as_bytes())?;
+    }
+    if let Some(password) = password {
+        writer.put_prefixed(password.as_bytes())?;
+    }
+    Ok(writer.finish())
+}
+
+/// Encode a `PUBLISH` packet at `QoS` 0, returns the length of the packet
+pub fn encode_publish(buffer: &mut [u8], topic: &str, payload: &[u8], retain: bool) -> Result<usize, PacketError> {
+    let remaining_length = prefixed_len(topic.as_bytes()) + payload.len();
+    let mut writer = Writer::new(buffer, PUBLISH << 4 | u8::from(retain), remaining_length)?;
+    writer.put_prefixed(topic.as_bytes())?;
+    writer.put_bytes(payload)?;
+    Ok(writer.finish())
+}
+
+/// Encode a `SUBSCRIBE` packet for the topic filters at `QoS` 0, returns the length of the packet
+pub fn encode_subscribe(buffer: &mut [u8], packet_id: u16, filters: &[&str]) -> Result<usize, PacketError> {
+    let remaining_length = 2 + filters
+        .iter()
+        .map(|filter| prefixed_len(filter.as_bytes()) + 1)
+        .sum::<usize>();
+    let mut writer = Writer::new(buffer, SUBSCRIBE << 4 | 0x02, remaining_length)?;
+    writer.put_u16(packet_id)?;
+    for filter in filters {
+        writer.put_prefixed(filter.as_bytes())?;
+        writer.put_u8(0)?; // QoS 0
+    }
+    Ok(writer.finish())
+}
+
+/// Encode a `PINGREQ` packet, returns the length of the packet
+pub fn encode_pingreq(buffer: &mut [u8]) -> Result<usize, PacketError> {
+    Ok(Writer::new(buffer, PINGREQ << 4, 0)?.finish())
+}
+
+/// Encode a `DISCONNECT` packet, returns the length of the packet
+pub fn encode_disconnect(buffer: &mut [u8]) -> Result<usize, PacketError> {
+    Ok(Writer::new(buffer, DISCONNECT << 4, 0)?.finish())
+}
+
+/// Decode the first packet in the buffer, returns the packet and its length.
+/// Fails with `PacketError::Incomplete` as long as the buffer does not hold the complete packet.
+pub fn decode_packet(buffer: &[u8]) -> Result<(Packet<'_>, usize), PacketError> {
+    let first_byte = *buffer.first().ok_or(PacketError::Incomplete)?;
+
+    // the remaining length takes up to four bytes
+    let mut remaining_length = 0;
+    let mut header_length = 1;
+    loop {
+        if header_length > 4 {
+            return Err(PacketError::Malformed);
+        }
+        let byte = *buffer.get(header_length).ok_or(PacketError::Incomplete)?;
+        remaining_length += usize::from(byte & 0x7F) << (7 * (header_length - 1));
+        header_length += 1;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+
+    let length = header_length + remaining_length;
+    let body = buffer.get(header_length..length).ok_or(PacketError::Incomplete)?;
+    let packet = match first_byte >> 4 {
+        CONNACK => match body {
+            [acknowledge_flags, return_code] => Packet::ConnAck {
+                session_present: acknowledge_flags & 0x01 != 0,
+                return_code: *return_code,
+            },
+            _ => return Err(PacketError::Malformed),
+        },
+        SUBACK => match body {
+            [high, low, return_codes @ ..] if !return_codes.is_empty() => Packet::SubAck {
+                packet_id: u16::from_be_bytes([*high, *low]),
+                refused: return_codes.iter().any(|code| code & 0x80 != 0),
+            },
+            _ => return Err(PacketError::Malformed),
+        },
+        PUBLISH => decode_publish(first_byte, body)?,
+        PINGRESP => Packet::PingResp,
+        packet_type => Packet::Other { packet_type },
+    };
+    Ok((packet, length))
+}
+
+/// Decode the body of a `PUBLISH` packet
+fn decode_publish(first_byte: u8, body: &[u8]) -> Result<Packet<'_>, PacketError> {
+    let [high, low, rest @ ..] = body else {
+        return Err(PacketError::Malformed);
+    };
+    let topic_length = usize::from(u16::from_be_bytes([*high, *low]));
+    let topic = rest.get(..topic_length).ok_or(PacketError::Malformed)?;
+    let topic = core::str::from_utf8(topic).map_err(|_| PacketError::Malformed)?;
+
+    // above QoS 0 a packet identifier follows the topic
+    let qos = (first_byte >> 1) & 0x03;
+    let payload_start = topic_length + if qos > 0 { 2 } else { 0 };
+    let payload = rest.get(payload_start..).ok_or(PacketError::Malformed)?;
+    Ok(Packet::Publish { topic, payload })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{string::String, vec::Vec};
+
+    use super::*;
+
+    /// An in-process fake broker, it accepts every connection, refuses subscriptions to the `$SYS` topics and sends
+    /// publishes of other clients at `QoS` 1 to the subscribed topics
+    #[derive(Default)]
+    struct FakeBroker {
+        /// The client identifier of the connected client
+        client_id: Option<String>,
+        /// The topic filters the client subscribed to
+        subscriptions: Vec<String>,
+    }
+
+    impl FakeBroker {
+        /// Handle a packet of the client, returns the answer
+        fn receive(&mut self, packet: &[u8]) -> Vec<u8> {
+            let (_, length) = decode_packet(packet).unwrap();
+            assert_eq!(length, packet.len());
+            let body = &packet[length - remaining_length(packet)..];
+            match packet[0] >> 4 {
+                CONNECT => {
+                    assert_eq!(&body[..7], b"\x00\x04MQTT\x04");
+                    let client_id_length = usize::from(u16::from_be_bytes([body[10], body[11]]));
+                    self.client_id = Some(String::from_utf8(body[12..12 + client_id_length].to_vec()).unwrap());
+                    std::vec![CONNACK << 4, 2, 0, 0]
+                }
+                SUBSCRIBE => {
+                    assert_eq!(packet[0] & 0x0F, 0x02);
+                    let mut answer = std::vec![SUBACK << 4, 0, body[0], body[1]];
+                    let mut filters = &body[2..];
+                    while let [high, low, rest @ ..] = filters {
+                        let filter_length = usize::from(u16::from_be_bytes([*high, *low]));
+                        let filter = core::str::from_utf8(&rest[..filter_length]).unwrap();
+                        answer.push(if filter.starts_with("$SYS") {
+                            0x80
+                        } else {
+                            rest[filter_length]
+                        });
+                        self.subscriptions.push(filter.into());
+                        filters = &rest[filter_length + 1..];
+                    }
+                    answer[1] = u8::try_from(answer.len() - 2).unwrap();
+                    answer
+                }
+                PINGREQ => std::vec![PINGRESP << 4, 0],
+                packet_type => panic!("unexpected packet type {packet_type}"),
+            }
+        }
+
+        /// A publish of another client at `QoS` 1 with the packet identifier 7
+        fn publish(&self, topic: &str, payload: &[u8]) -> Vec<u8> {
+            assert!(self.subscriptions.iter().any(|filter| filter == topic));
+            let mut packet = std::vec![PUBLISH << 4 | 0x02, 0, 0, u8::try_from(topic.len()).unwrap()];
+            packet.extend(topic.as_bytes());
+            packet.extend([0, 7]);
+            packet.extend(payload);
+            packet[1] = u8::try_from(packet.len() - 2).unwrap();
+            packet
+        }
+    }
+
+    /// The remaining length of a complete packet, read the way a broker does
+    fn remaining_length(packet: &[u8]) -> usize {
+        packet[1..]
+            .iter()
+            .take(4)
+            .enumerate()
+            .take_while(|(i, _)| *i == 0 || packet[*i] & 0x80 != 0)
+            .map(|(i, byte)| usize::from(byte & 0x7F) << (7 * i))
+            .sum()
+    }
+
+    /// The connection of the clock, with a last will and credentials
+    const CONNECT_OPTIONS: Connect<'static> = Connect {
+        client_id: "clock",
+        username: Some("user"),
+        password: Some("pw"),
+        keep_alive_secs: 60,
+        will: Some(Will {
+            topic: "c/a",
+            payload: b"offline",
+            retain: true,
+        }),
+    };
+
+    /// The packet as the client encodes it
+    fn encoded(encode: impl FnOnce(&mut [u8]) -> Result<usize, PacketError>) -> Vec<u8> {
+        let mut buffer = std::vec![0; 20_000];
+        let length = encode(&mut buffer).unwrap();
+        buffer[..length].to_vec()
+    }
+
+    /// A `CONNECT` with a retained will, user name and password, byte for byte as in the MQTT 3.1.1 specification
+    #[test]
+    fn encodes_connect() {
+        let packet = encoded(|buffer| encode_connect(buffer, &CONNECT_OPTIONS));
+        let mut expected = std::vec![0x10, 41, 0, 4];
+        expected.extend(b"MQTT\x04\xE6\x00\x3C\x00\x05clock\x00\x03c/a\x00\x07offline\x00\x04user\x00\x02pw");
+        assert_eq!(packet, expected);
+
+        // a password without a user name is left out, as the specification demands
+        let anonymous = Connect {
+            username: None,
+            will: None,
+            ..CONNECT_OPTIONS
+        };
+        let packet = encoded(|buffer| encode_connect(buffer, &anonymous));
+        assert_eq!(packet, b"\x10\x11\x00\x04MQTT\x04\x02\x00\x3C\x00\x05clock");
+        assert_eq!(
+            encode_connect(&mut [0; 42], &CONNECT_OPTIONS),
+            Err(PacketError::BufferTooSmall)
+        );
+    }
+
+    /// Remaining lengths of 128 and more take more than one byte, the packets decode again
+    #[test]
+    fn encodes_multi_byte_remaining_lengths() {
+        let payload = [b'x'; 16_384];
+        for (payload_length, header) in [
+            (124, [0x30, 0x7F].as_slice()),
+            (125, &[0x30, 0x80, 0x01]),
+            (200, &[0x30, 0xCB, 0x01]),
+            (16_381, &[0x30, 0x80, 0x80, 0x01]),
+        ] {
+            let packet = encoded(|buffer| encode_publish(buffer, "t", &payload[..payload_length], false));
+            assert_eq!(&packet[..header.len()], header);
+            assert_eq!(packet.len(), header.len() + 3 + payload_length);
+            let (decoded, length) = decode_packet(&packet).unwrap();
+            assert_eq!(length, packet.len());
+            assert_eq!(
+                decoded,
+                Packet::Publish {
+                    topic: "t",
+                    payload: &payload[..payload_length]
+                }
+            );
+        }
+        assert_eq!(
+            encode_publish(&mut [0; 100], "t", &payload[..200], true),
+            Err(PacketError::BufferTooSmall)
+        );
+    }
+
+    /// Packets that are not complete yet wait for more bytes, a length of five bytes is not MQTT
+    #[test]
+    fn decodes_incomplete_and_over_long_lengths() {
+        assert_eq!(decode_packet(&[]), Err(PacketError::Incomplete));
+        assert_eq!(decode_packet(&[0x30]), Err(PacketError::Incomplete));
+        assert_eq!(decode_packet(&[0x30, 0x80]), Err(PacketError::Incomplete));
+        assert_eq!(decode_packet(&[0x30, 0xFF, 0xFF, 0xFF]), Err(PacketError::Incomplete));
+        assert_eq!(
+            decode_packet(&[0x30, 0x05, 0x00, 0x01, b't']),
+            Err(PacketError::Incomplete)
+        );
+        assert_eq!(
+            decode_packet(&[0x30, 0x80, 0x80, 0x80, 0x80, 0x01]),
+            Err(PacketError::Malformed)
+        );
+        assert_eq!(
+            decode_packet(&[0x30, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F]),
+            Err(PacketError::Malformed)
+        );
+    }
+
+    /// The packet identifier of a publish above `QoS` 0 is not part of the payload
+    #[test]
+    fn decodes_publish() {
+        let qos_0 = b"\x30\x07\x00\x03a/bON";
+        let qos_1 = b"\x32\x09\x00\x03a/b\x00\x07ON";
+        for packet in [qos_0.as_slice(), qos_1] {
+            assert_eq!(
+                decode_packet(packet),
+                Ok((
+                    Packet::Publish {
+                        topic: "a/b",
+                        payload: b"ON"
+                    },
+                    packet.len()
+                ))
+            );
+        }
+        assert_eq!(decode_packet(b"\x32\x04\x00\x03a/"), Err(PacketError::Malformed));
+        assert_eq!(decode_packet(b"\x32\x05\x00\x03a/b"), Err(PacketError::Malformed));
+        assert_eq!(decode_packet(b"\x30\x04\x00\x02\xFF\xFE"), Err(PacketError::Malformed));
+    }
+
+    /// A refused topic filter is reported, a subscription without return codes is not MQTT
+    #[test]
+    fn decodes_suback() {
+        let suback = |packet| decode_packet(packet).map(|(packet, _)| packet);
+        assert_eq!(
+            suback(b"\x90\x04\x00\x01\x00\x80"),
+            Ok(Packet::SubAck {
+                packet_id: 1,
+                refused: true
+            })
+        );
+        assert_eq!(
+            suback(b"\x90\x04\x01\x02\x00\x00"),
+            Ok(Packet::SubAck {
+                packet_id: 0x0102,
+                refused: false
+            })
+        );
+        assert_eq!(suback(b"\x90\x02\x00\x01"), Err(PacketError::Malformed));
+        assert_eq!(
+            suback(b"\x20\x02\x01\x05"),
+            Ok(Packet::ConnAck {
+                session_present: true,
+                return_code: 5
+            })
+        );
+        assert_eq!(suback(b"\x20\x01\x00"), Err(PacketError::Malformed));
+        assert_eq!(suback(b"\xB0\x02\x00\x01"), Ok(Packet::Other { packet_type: 11 }));
+    }
+
+    /// A whole session with the fake broker, the answers arrive in one stream and are decoded one after the other
+    #[test]
+    fn holds_a_session_with_a_fake_broker() {
+        let mut broker = FakeBroker::default();
+        let mut stream = broker.receive(&encoded(|buffer| encode_connect(buffer, &CONNECT_OPTIONS)));
+        assert_eq!(broker.client_id.as_deref(), Some("clock"));
+        stream.extend(broker.receive(&encoded(|buffer| {
+            encode_subscribe(buffer, 1, &["c/alarm/set", "c/alarm/snooze"])
+        })));
+        stream.extend(broker.receive(&encoded(|buffer| encode_subscribe(buffer, 2, &["$SYS/#"]))));
+        stream.extend(broker.publish("c/alarm/set", b"OFF"));
+        stream.extend(broker.receive(&encoded(encode_pingreq)));
+
+        let mut packets = Vec::new();
+        let mut rest = stream.as_slice();
+        while !rest.is_empty() {
+            let (packet, length) = decode_packet(rest).unwrap();
+            packets.push(packet);
+            rest = &rest[length..];
+        }
+        assert_eq!(
+            packets,
+            [
+                Packet::ConnAck {
+                    session_present: false,
+                    return_code: 0
+                },
+                Packet::SubAck {
+                    packet_id: 1,
+                    refused: false
+                },
+                Packet::SubAck {
+                    packet_id: 2,
+                    refused: true
+                },
+                Packet::Publish {
+                    topic: "c/alarm/set",
+                    payload: b"OFF"
+                },
+                Packet::PingResp,
+            ]
+        );
+        assert_eq!(encoded(encode_disconnect), [0xE0, 0]);
+    }
+}
@@ -0,0 +1,61 @@
+//! # Night
+//! This module holds the night schedule, the time of the day during which the display and the LEDs keep the bedroom
+//! dark. The schedule is configured in `config/night.json`, see `task::display`.
+//!
+//! The night may span midnight, e.g. from 22:30 to 06:30. It starts with its first minute and ends before its last.
+
+/// Minutes per day
+const DAY_MINUTES: u16 = 24 * 60;
+
+/// What the display shows at night while the alarm clock is left alone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NightDisplay {
+    /// The screens as during the day, with the lowest brightness
+    Dim,
+    /// Only the time, with the lowest brightness
+    Clock,
+    /// Nothing, the display is switched off
+    Off,
+}
+
+/// The brightness of the display during the day
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DayBrightness {
+    /// The lowest brightness, the same as at night
+    Dimmest,
+    /// Dimmer than normal
+    Dim,
+    /// The default brightness of the display
+    Normal,
+    /// Brighter than normal
+    Bright,
+    /// The highest brightness
+    Brightest,
+}
+
+/// The night schedule
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NightSchedule {
+    /// The first minute of the night, counted from midnight
+    pub start_minutes: u16,
+    /// The minute the night ends with, counted from midnight, before the start if the night spans midnight
+    pub end_minutes: u16,
+    /// What the display shows at night
+    pub display: NightDisplay,
+    /// How long a button press wakes the display at night, in seconds
+    pub wake_secs: u16,
+    /// The brightness of the display during the day
+    pub day_brightness: DayBrightness,
+}
+
+impl NightSchedule {
+    /// Whether the time lies within the night. The start and the end must differ, the build script makes sure.
+    pub fn contains(self, hour: u8, minute: u8) -> bool {
+        let minutes = (u16::from(hour) * 60 + u16::from(minute)) % DAY_MINUTES;
+        if self.start_minutes < self.end_minutes {
+            self.start_minutes <= minutes && minutes < self.end_minutes
+        } else {
+            self.start_minutes <= minutes || minutes < self.end_minutes
+        }
+    }
+}
@@ -0,0 +1,692 @@
+//! # Peer Sync
+//! This module holds the protocol clocks on the same LAN use to share their alarm, see `task::peer_sync`.
+//!
+//! Every clock sends small UDP datagrams to a multicast group. A `Hello` is sent when the network comes up and now
+//! and then after that, every clock of the group answers it with a `Snapshot` of its shared alarm. A `Snapshot` is
+//! also sent whenever the alarm is changed on a clock. All numbers are little endian:
+//!
+//! | Offset | Size | Content                                                                |
+//! |--------|------|------------------------------------------------------------------------|
+//! | 0      | 4    | Magic `ACPS`                                                           |
+//! | 4      | 1    | Protocol version, 1                                                    |
+//! | 5      | 1    | Kind: 1 `Hello`, 2 `Snapshot`                                          |
+//! | 6      | 4    | FNV-1a hash of the group name, clocks of other groups are ignored      |
+//! | 10     | 1    | Length of the hostname of the sender                                   |
+//! | 11     | n    | Hostname of the sender                                                 |
+//!
+//! A `Snapshot` continues with its version, a timestamp as `i64` and the origin as `u32`, the fields the sender
+//! shares as bit mask (see `SharedFields`), and one byte each for hour, minute, enabled, days, sunrise minutes and
+//! volume.
+//!
+//! Conflicts are resolved by last-writer-wins: the snapshot with the higher version is the alarm of the group. The
+//! version is the local time of the change in seconds since 1970, ties are broken by the origin, the hash of the
+//! hostname of the clock that made the change. A clock that is behind never goes back in time: a change gets a
+//! timestamp after the version it replaces. Each clock applies only the fields both the sender and itself share.
+
+use crate::utility::alarm_limits::alarm_values_in_range;
+
+/// The port the clocks send to and listen on
+pub const PEER_PORT: u16 = 5780;
+
+/// The IPv4 multicast group the clocks send to, in the range for organisation local use
+pub const PEER_GROUP: [u8; 4] = [239, 255, 57, 80];
+
+/// Size of the largest packet, a `Snapshot` with a hostname of 32 characters
+pub const MAX_PACKET_SIZE: usize = HEADER_SIZE + MAX_HOSTNAME_LEN + SNAPSHOT_SIZE;
+
+/// The magic bytes every packet starts with
+const MAGIC: [u8; 4] = *b"ACPS";
+
+/// The version of the protocol, packets of other versions are ignored
+const PROTOCOL_VERSION: u8 = 1;
+
+/// Kind of a `Hello`
+const KIND_HELLO: u8 = 1;
+
+/// Kind of a `Snapshot`
+const KIND_SNAPSHOT: u8 = 2;
+
+/// Size of the header up to the hostname
+const HEADER_SIZE: usize = 11;
+
+/// Size of the snapshot behind the hostname
+const SNAPSHOT_SIZE: usize = 19;
+
+/// The longest hostname, as allowed in `wifi_config.json`
+const MAX_HOSTNAME_LEN: usize = 32;
+
+/// Number of peers kept in the table, the oldest is forgotten for a new one
+pub const MAX_PEERS: usize = 8;
+
+/// The fields of the alarm a clock shares with its peers, as bit mask
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SharedFields(u8);
+
+impl SharedFields {
+    /// No field
+    pub const NONE: Self = Self(0);
+    /// Hour and minute of the alarm
+    pub const TIME: Self = Self(0x01);
+    /// Whether the alarm is enabled
+    pub const ENABLED: Self = Self(0x02);
+    /// The days of the week the alarm rings on
+    pub const DAYS: Self = Self(0x04);
+    /// The length of the sunrise effect
+    pub const SUNRISE: Self = Self(0x08);
+    /// The volume of the alarm sound
+    pub const VOLUME: Self = Self(0x10);
+    /// All fields
+    pub const ALL: Self = Self(0x1F);
+
+    /// The fields of a bit mask, unknown bits are dropped
+    pub const fn from_bits(bits: u8) -> Self {
+        Self(bits & Self::ALL.0)
+    }
+
+    /// The bit mask of the fields
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// The fields in both
+    pub const fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    /// Whether all fields of `other` are in these
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Whether there are no fields
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+/// The values of the alarm settings that can be shared
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AlarmValues {
+    /// Hour of the alarm
+    pub hour: u8,
+    /// Minute of the alarm
+    pub minute: u8,
+    /// Whether the alarm is enabled
+    pub enabled: bool,
+    /// The days of the week the alarm rings on, bit 0 is Monday and bit 6 is Sunday
+    pub days: u8,
+    /// The length of the sunrise effect in minutes
+    pub sunrise_minutes: u8,
+    /// The volume of the alarm sound
+    pub volume: u8,
+}
+
+impl AlarmValues {
+    /// These values with the given fields taken from `other`
+    pub const fn merged(self, other: Self, fields: SharedFields) -> Self {
+        let mut merged = self;
+        if fields.contains(SharedFields::TIME) {
+            merged.hour = other.hour;
+            merged.minute = other.minute;
+        }
+        if fields.contains(SharedFields::ENABLED) {
+            merged.enabled = other.enabled;
+        }
+        if fields.contains(SharedFields::DAYS) {
+            merged.days = other.days;
+        }
+        if fields.contains(SharedFields::SUNRISE) {
+            merged.sunrise_minutes = other.sunrise_minutes;
+        }
+        if fields.contains(SharedFields::VOLUME) {
+            merged.volume = other.volume;
+        }
+        merged
+    }
+
+    /// Whether the values are in range, by the same check as changes through the API
+    const fn is_valid(self) -> bool {
+        alarm_values_in_range(
+            Some(self.hour),
+            Some(self.minute),
+            Some(self.days),
+            Some(self.sunrise_minutes),
+            Some(self.volume),
+        )
+    }
+}
+
+/// The version of a snapshot, ordered by timestamp and then by origin
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SnapshotVersion {
+    /// Local time of the change in seconds since 1970
+    pub timestamp: i64,
+    /// Hash of the hostname of the clock that made the change
+    pub origin: u32,
+}
+
+/// The shared alarm of a clock at a version
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Snapshot {
+    /// The version of the alarm
+    pub version: SnapshotVersion,
+    /// The fields the sender shares, the others are just its own values
+    pub fields: SharedFields,
+    /// The values of the alarm
+    pub values: AlarmValues,
+}
+
+/// A packet between peers
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Message<'a> {
+    /// A clock joined, the peers answer with their snapshot
+    Hello {
+        /// Hostname of the sender
+        hostname: &'a str,
+    },
+    /// The shared alarm of the sender
+    Snapshot {
+        /// Hostname of the sender
+        hostname: &'a str,
+        /// The snapshot
+        snapshot: Snapshot,
+    },
+}
+
+/// Errors when encoding or decoding a packet
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PeerSyncError {
+    /// The buffer is too small for the packet, or the hostname is too long
+    BufferTooSmall,
+    /// The packet does not start with the magic bytes, it is not ours
+    NotPeerSync,
+    /// The packet is of another protocol version
+    UnsupportedVersion(u8),
+    /// The packet is from a clock of another group
+    OtherGroup,
+    /// The packet is cut off, of an unknown kind, or holds values out of range
+    Malformed,
+}
+
+impl Message<'_> {
+    /// The hostname of the sender
+    pub const fn hostname(&self) -> &str {
+        match self {
+            Self::Hello { hostname } | Self::Snapshot { hostname, .. } => hostname,
+        }
+    }
+
+    /// Write the packet for the group into the buffer, returns its length
+    pub fn encode(&self, group: u32, buffer: &mut [u8]) -> Result<usize, PeerSyncError> {
+        let hostname = self.hostname().as_bytes();
+        let snapshot_size = if matches!(self, Self::Snapshot { .. }) {
+            SNAPSHOT_SIZE
+        } else {
+            0
+        };
+        let hostname_len = u8::try_from(hostname.len())
+            .ok()
+            .filter(|&len| usize::from(len) <= MAX_HOSTNAME_LEN)
+            .ok_or(PeerSyncError::BufferTooSmall)?;
+        let len = HEADER_SIZE + hostname.len() + snapshot_size;
+        if buffer.len() < len {
+            return Err(PeerSyncError::BufferTooSmall);
+        }
+
+        buffer[..4].copy_from_slice(&MAGIC);
+        buffer[4] = PROTOCOL_VERSION;
+        buffer[6..10].copy_from_slice(&group.to_le_bytes());
+        buffer[10] = hostname_len;
+        buffer[HEADER_SIZE..HEADER_SIZE + hostname.len()].copy_from_slice(hostname);
+        match self {
+            Self::Hello { .. } => buffer[5] = KIND_HELLO,
+            Self::Snapshot { snapshot, .. } => {
+                buffer[5] = KIND_SNAPSHOT;
+                let out = &mut buffer[HEADER_SIZE + hostname.len()..len];
+                out[..8].copy_from_slice(&snapshot.version.timestamp.to_le_bytes());
+                out[8..12].copy_from_slice(&snapshot.version.origin.to_le_bytes());
+                let values = &snapshot.values;
+                out[12..].copy_from_slice(&[
+                    snapshot.fields.bits(),
+                    values.hour,
+                    values.minute,
+                    u8::from(values.enabled),
+                    values.days,
+                    values.sunrise_minutes,
+                    values.volume,
+                ]);
+            }
+        }
+        Ok(len)
+    }
+
+    /// Read a packet, only packets of the group are accepted
+    pub fn decode(packet: &[u8], group: u32) -> Result<Message<'_>, PeerSyncError> {
+        if packet.len() < HEADER_SIZE || packet[..4] != MAGIC {
+            return Err(PeerSyncError::NotPeerSync);
+        }
+        if packet[4] != PROTOCOL_VERSION {
+            return Err(PeerSyncError::UnsupportedVersion(packet[4]));
+        }
+        if packet[6..10] != group.to_le_bytes() {
+            return Err(PeerSyncError::OtherGroup);
+        }
+        let hostname_end = HEADER_SIZE + usize::from(packet[10]);
+        let hostname = packet
+            .get(HEADER_SIZE..hostname_end)
+            .and_then(|bytes| core::str::from_utf8(bytes).ok())
+            .filter(|hostname| !hostname.is_empty())
+            .ok_or(PeerSyncError::Malformed)?;
+
+        match packet[5] {
+            KIND_HELLO if packet.len() == hostname_end => Ok(Message::Hello { hostname }),
+            KIND_SNAPSHOT if packet.len() == hostname_end + SNAPSHOT_SIZE => {
+                let data = &packet[hostname_end..];
+                let mut timestamp = [0; 8];
+                timestamp.copy_from_slice(&data[..8]);
+                let mut origin = [0; 4];
+                origin.copy_from_slice(&data[8..12]);
+                let enabled = match data[15] {
+                    0 => false,
+                    1 => true,
+                    _ => return Err(PeerSyncError::Malformed),
+                };
+                let values = AlarmValues {
+                    hour: data[13],
+                    minute: data[14],
+                    enabled,
+                    days: data[16],
+                    sunrise_minutes: data[17],
+                    volume: data[18],
+                };
+                if !values.is_valid() {
+                    return Err(PeerSyncError::Malformed);
+                }
+                Ok(Message::Snapshot {
+                    hostname,
+                    snapshot: Snapshot {
+                        version: SnapshotVersion {
+                            timestamp: i64::from_le_bytes(timestamp),
+                            origin: u32::from_le_bytes(origin),
+                        },
+                        fields: SharedFields::from_bits(data[12]),
+                        values,
+                    },
+                })
+            }
+            _ => Err(PeerSyncError::Malformed),
+        }
+    }
+}
+
+/// What to do with a snapshot of a peer
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Received {
+    /// The snapshot is newer and changes the alarm, apply the values
+    Apply(AlarmValues),
+    /// The snapshot is newer or the same, but changes nothing here
+    UpToDate,
+    /// Our snapshot is newer, send it so the peer catches up
+    Stale,
+}
+
+/// The shared alarm of this clock and its version, deciding between local changes and snapshots of peers
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SyncState {
+    /// The fields this clock shares
+    shared: SharedFields,
+    /// The origin of the changes made on this clock
+    origin: u32,
+    /// The version of the alarm
+    version: SnapshotVersion,
+    /// The values of the alarm, all fields, not just the shared ones
+    values: AlarmValues,
+}
+
+impl SyncState {
+    /// Start with the alarm as read from the flash. The version is the oldest there is, so after a restart the alarm
+    /// of the peers wins.
+    pub const fn new(shared: SharedFields, origin: u32, values: AlarmValues) -> Self {
+        Self {
+            shared,
+            origin,
+            version: SnapshotVersion {
+                timestamp: i64::MIN,
+                origin,
+            },
+            values,
+        }
+    }
+
+    /// The snapshot to send to peers
+    pub const fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            version: self.version,
+            fields: self.shared,
+            values: self.values,
+        }
+    }
+
+    /// The alarm was changed on this clock at the given local time. Returns the snapshot to send if a shared field
+    /// changed.
+    pub fn local_change(&mut self, values: AlarmValues, timestamp: i64) -> Option<Snapshot> {
+        let changed = self.values.merged(values, self.shared) != self.values;
+        self.values = values;
+        if !changed {
+            return None;
+        }
+        // a clock that is behind must still win with a change made after the version it has
+        self.version = SnapshotVersion {
+            timestamp: timestamp.max(self.version.timestamp.saturating_add(1)),
+            origin: self.origin,
+        };
+        Some(self.snapshot())
+    }
+
+    /// A peer sent its snapshot, take it if it is newer
+    pub fn receive(&mut self, snapshot: &Snapshot) -> Received {
+        if snapshot.version < self.version {
+            return Received::Stale;
+        }
+        if snapshot.version == self.version {
+            return Received::UpToDate;
+        }
+        self.version = snapshot.version;
+        let merged = self
+            .values
+            .merged(snapshot.values, self.shared.intersection(snapshot.fields));
+        if merged == self.values {
+            Received::UpToDate
+        } else {
+            self.values = merged;
+            Received::Apply(merged)
+        }
+    }
+}
+
+/// A peer that was heard from
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+struct Peer {
+    /// Hash of the hostname of the peer
+    origin: u32,
+    /// The address the peer sent from
+    address: [u8; 4],
+    /// When the peer was last heard from, in seconds since startup
+    last_seen_secs: u64,
+}
+
+/// The peers that were heard from, the oldest is forgotten when the table is full
+#[derive(Debug, Clone, Default)]
+pub struct PeerTable {
+    /// The peers
+    peers: heapless::Vec<Peer, MAX_PEERS>,
+}
+
+impl PeerTable {
+    /// An empty table
+    pub const fn new() -> Self {
+        Self {
+            peers: heapless::Vec::new(),
+        }
+    }
+
+    /// A peer was heard from. Returns whether it is new or moved to another address.
+    pub fn seen(&mut self, hostname: &str, address: [u8; 4], now_secs: u64) -> bool {
+        let origin = origin(hostname);
+        if let Some(peer) = self.peers.iter_mut().find(|peer| peer.origin == origin) {
+            let moved = peer.address != address;
+            peer.address = address;
+            peer.last_seen_secs = now_secs;
+            return moved;
+        }
+        if self.peers.is_full()
+            && let Some(oldest) = (0..self.peers.len()).min_by_key(|&index| self.peers[index].last_seen_secs)
+        {
+            self.peers.swap_remove(oldest);
+        }
+        let _ = self.peers.push(Peer {
+            origin,
+            address,
+            last_seen_secs: now_secs,
+        });
+        true
+    }
+
+    /// Forget the peers not heard from since the given time
+    pub fn expire(&mut self, since_secs: u64) {
+        self.peers.retain(|peer| peer.last_seen_secs >= since_secs);
+    }
+
+    /// Number of peers
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    /// Whether no peer was heard from
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+}
+
+/// 32-bit FNV-1a hash, used for the group name and as origin of a hostname
+pub const fn fnv1a(text: &str) -> u32 {
+    let bytes = text.as_bytes();
+    let mut hash: u32 = 0x811C_9DC5;
+    let mut index = 0;
+    while index < bytes.len() {
+        hash ^= bytes[index] as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+        index += 1;
+    }
+    hash
+}
+
+/// The origin of the snapshots of a clock, the hash of its hostname
+pub const fn origin(hostname: &str) -> u32 {
+    fnv1a(hostname)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Alarm values with the given hour and volume
+    const fn values(hour: u8, volume: u8) -> AlarmValues {
+        AlarmValues {
+            hour,
+            minute: 30,
+            enabled: true,
+            days: 0x1F,
+            sunrise_minutes: 10,
+            volume,
+        }
+    }
+
+    /// A snapshot of clock `a` with the longest hostname
+    fn snapshot_message() -> Message<'static> {
+        Message::Snapshot {
+            hostname: "abcdefghijabcdefghijabcdefghij12",
+            snapshot: Snapshot {
+                version: SnapshotVersion {
+                    timestamp: 1_700_000_000,
+                    origin: origin("a"),
+                },
+                fields: SharedFields::TIME,
+                values: values(6, 20),
+            },
+        }
+    }
+
+    /// Packets decode to what was encoded, only within the group
+    #[test]
+    fn messages_round_trip() {
+        let group = fnv1a("bedrooms");
+        let mut buffer = [0; MAX_PACKET_SIZE];
+        let hello = Message::Hello { hostname: "kids-room" };
+        let len = hello.encode(group, &mut buffer).unwrap();
+        assert_eq!(Message::decode(&buffer[..len], group), Ok(hello));
+        assert_eq!(
+            Message::decode(&buffer[..len], fnv1a("kitchen")),
+            Err(PeerSyncError::OtherGroup)
+        );
+
+        let snapshot = snapshot_message();
+        let len = snapshot.encode(group, &mut buffer).unwrap();
+        assert_eq!(len, MAX_PACKET_SIZE);
+        assert_eq!(Message::decode(&buffer[..len], group), Ok(snapshot));
+    }
+
+    /// The layout of a `Snapshot` as documented at the top of the module
+    #[test]
+    fn encodes_the_documented_layout() {
+        let message = Message::Snapshot {
+            hostname: "a",
+            snapshot: Snapshot {
+                version: SnapshotVersion {
+                    timestamp: 0x0102_0304_0506_0708,
+                    origin: 0x0A0B_0C0D,
+                },
+                fields: SharedFields::ALL,
+                values: values(6, 20),
+            },
+        };
+        let mut buffer = [0; MAX_PACKET_SIZE];
+        let len = message.encode(0x1122_3344, &mut buffer).unwrap();
+        assert_eq!(
+            &buffer[..len],
+            &[
+                b'A', b'C', b'P', b'S', 1, 2, 0x44, 0x33, 0x22, 0x11, 1, b'a', 0x08, 0x07, 0x06, 0x05, 0x04, 0x03,
+                0x02, 0x01, 0x0D, 0x0C, 0x0B, 0x0A, 0x1F, 6, 30, 1, 0x1F, 10, 20
+            ]
+        );
+    }
+
+    /// Packets that are not ours, of another version, cut off or with values out of range are rejected
+    #[test]
+    fn rejects_bad_packets() {
+        let group = fnv1a("bedrooms");
+        let mut buffer = [0; MAX_PACKET_SIZE];
+        let len = snapshot_message().encode(group, &mut buffer).unwrap();
+        assert_eq!(
+            Message::decode(&buffer[..len - 1], group),
+            Err(PeerSyncError::Malformed)
+        );
+        assert_eq!(
+            Message::decode(b"\x00\x00 mdns garbage", group),
+            Err(PeerSyncError::NotPeerSync)
+        );
+
+        let mut other_version = buffer;
+        other_version[4] = 2;
+        assert_eq!(
+            Message::decode(&other_version[..len], group),
+            Err(PeerSyncError::UnsupportedVersion(2))
+        );
+
+        // hour 24, enabled 2, days 0, sunrise 0, volume 31
+        for (offset, value) in [(6, 24), (4, 2), (3, 0), (2, 0), (1, 31)] {
+            let mut bad = buffer;
+            bad[len - offset] = value;
+            assert_eq!(Message::decode(&bad[..len], group), Err(PeerSyncError::Malformed));
+        }
+    }
+
+    /// Buffers that are too small and hostnames that are too long are not encoded
+    #[test]
+    fn rejects_too_small_buffers() {
+        let group = fnv1a("bedrooms");
+        assert_eq!(
+            snapshot_message().encode(group, &mut [0; 20]),
+            Err(PeerSyncError::BufferTooSmall)
+        );
+        let long = Message::Hello {
+            hostname: "abcdefghijabcdefghijabcdefghij123",
+        };
+        assert_eq!(long.encode(group, &mut [0; 100]), Err(PeerSyncError::BufferTooSmall));
+    }
+
+    /// The newest change wins, each clock takes only the fields both share
+    #[test]
+    fn last_writer_wins() {
+        let shared = SharedFields::from_bits(SharedFields::TIME.bits() | SharedFields::ENABLED.bits());
+        let mut a = SyncState::new(shared, origin("a"), values(6, 20));
+        let mut b = SyncState::new(SharedFields::ALL, origin("b"), values(7, 5));
+
+        // a change of a field a does not share is not sent
+        assert_eq!(a.local_change(values(6, 25), 100), None);
+        let from_a = a.local_change(values(8, 25), 100).unwrap();
+        assert_eq!(
+            from_a.version,
+            SnapshotVersion {
+                timestamp: 100,
+                origin: origin("a"),
+            }
+        );
+
+        // b takes the time, but not the volume a does not share
+        let mut expected = values(8, 5);
+        assert_eq!(b.receive(&from_a), Received::Apply(expected));
+        assert_eq!(b.receive(&from_a), Received::UpToDate);
+
+        // the clock of b is behind, its change still wins
+        expected.hour = 9;
+        let from_b = b.local_change(expected, 50).unwrap();
+        assert_eq!(from_b.version.timestamp, 101);
+        assert_eq!(a.receive(&from_b), Received::Apply(values(9, 25)));
+
+        // an older snapshot is answered with ours
+        assert_eq!(a.receive(&from_a), Received::Stale);
+    }
+
+    /// The same timestamp is decided by the origin, both clocks end up with the same alarm
+    #[test]
+    fn ties_are_broken_by_the_origin() {
+        let mut c = SyncState::new(SharedFields::ALL, origin("c"), values(5, 5));
+        let mut d = SyncState::new(SharedFields::ALL, origin("d"), values(4, 4));
+        let (from_c, from_d) = (c.snapshot(), d.snapshot());
+        let received = (c.receive(&from_d), d.receive(&from_c));
+        assert!(matches!(
+            received,
+            (Received::Apply(_), Received::Stale) | (Received::Stale, Received::Apply(_))
+        ));
+        assert_eq!(c.snapshot().values, d.snapshot().values);
+    }
+
+    /// A newer snapshot that changes none of the shared fields is taken without applying anything
+    #[test]
+    fn newer_snapshots_without_changes_are_up_to_date() {
+        let mut a = SyncState::new(SharedFields::ALL, origin("a"), values(6, 20));
+        let from_a = a.local_change(values(8, 20), 100).unwrap();
+        let mut e = SyncState::new(SharedFields::VOLUME, origin("e"), values(1, 20));
+        assert_eq!(e.receive(&from_a), Received::UpToDate);
+        assert_eq!(e.snapshot().version, from_a.version);
+    }
+
+    /// Peers are tracked by hostname, the oldest is forgotten when the table is full
+    #[test]
+    fn peer_table_forgets_the_oldest() {
+        let mut peers = PeerTable::new();
+        assert!(peers.seen("a", [10, 0, 0, 1], 1));
+        assert!(!peers.seen("a", [10, 0, 0, 1], 2));
+        assert!(peers.seen("a", [10, 0, 0, 2], 3));
+        for index in 0..MAX_PEERS {
+            peers.seen(&format!("p{index}"), [10, 0, 1, 1], 10 + index as u64);
+        }
+        assert_eq!(peers.len(), MAX_PEERS);
+        // "a" was the oldest and was forgotten
+        assert!(peers.seen("a", [10, 0, 0, 2], 100));
+        peers.expire(12);
+        assert_eq!(peers.len(), MAX_PEERS - 1);
+        peers.expire(1_000);
+        assert!(peers.is_empty());
+    }
+}
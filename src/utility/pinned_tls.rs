@@ -0,0 +1,119 @@
+//! # Pinned TLS
+//! This module plugs the pin check into `embedded-tls`. The crypto provider hands out a verifier that accepts
+//! the server only if its certificate matches the pin, and then checks the server's `CertificateVerify` signature
+//! with the pinned key, proving that the server actually holds the private key.
+//!
+//! The server has to speak TLS 1.3 with `TLS_AES_128_GCM_SHA256` and present a P-256 key,
+//! those are the algorithms `embedded-tls` and we support.
+
+use defmt::warn;
+use embassy_rp::clocks::RoscRng;
+use embedded_tls::{
+    Aes128GcmSha256, CertificateEntryRef, CertificateRef, CertificateVerifyRef, CryptoProvider, SignatureScheme,
+    TlsError, TlsVerifier,
+};
+use p256::ecdsa::{DerSignature, VerifyingKey, signature::Verifier};
+use rand::rand_core::CryptoRngCore;
+use sha2::{Digest, Sha256};
+
+use crate::utility::tls_pin::{Pin, p256_public_key};
+
+/// Context string of the server signature in TLS 1.3, including its terminating zero byte (RFC 8446, 4.4.3)
+const SERVER_SIGNATURE_CONTEXT: &[u8; 34] = b"TLS 1.3, server CertificateVerify\0";
+
+/// Crypto provider for `embedded-tls` that verifies the server against a pin
+pub struct PinnedProvider {
+    /// Random number generator for the key exchange
+    rng: RoscRng,
+    /// Verifier checking the server certificate and signature
+    verifier: PinnedVerifier,
+}
+
+impl PinnedProvider {
+    /// Create a new `PinnedProvider` for the given pin
+    pub const fn new(pin: Pin) -> Self {
+        Self {
+            rng: RoscRng,
+            verifier: PinnedVerifier {
+                pin,
+                public_key: None,
+                transcript: None,
+            },
+        }
+    }
+}
+
+impl CryptoProvider for PinnedProvider {
+    type CipherSuite = Aes128GcmSha256;
+    type Signature = &'static [u8];
+
+    fn rng(&mut self) -> impl CryptoRngCore {
+        &mut self.rng
+    }
+
+    fn verifier(&mut self) -> Result<&mut impl TlsVerifier<Self::CipherSuite>, TlsError> {
+        Ok(&mut self.verifier)
+    }
+}
+
+/// Verifier for `embedded-tls` that accepts only the pinned server
+pub struct PinnedVerifier {
+    /// The pin the server certificate must match
+    pin: Pin,
+    /// Public key of the server, known once its certificate matched the pin
+    public_key: Option<VerifyingKey>,
+    /// Handshake transcript up to the server certificate, which the server signature covers
+    transcript: Option<Sha256>,
+}
+
+impl TlsVerifier<Aes128GcmSha256> for PinnedVerifier {
+    fn set_hostname_verification(&mut self, _hostname: &str) -> Result<(), TlsError> {
+        // the pin identifies the server, its name is not checked
+        Ok(())
+    }
+
+    fn verify_certificate(&mut self, transcript: &Sha256, cert: CertificateRef) -> Result<(), TlsError> {
+        // the leaf certificate comes first, any intermediates after it are of no interest to us
+        let Some(CertificateEntryRef::X509(certificate)) = cert.entries.first() else {
+            warn!("Server did not send an X.509 certificate");
+            return Err(TlsError::InvalidCertificate);
+        };
+
+        let public_key_info = self.pin.check(certificate).map_err(|e| {
+            warn!("Server certificate rejected: {:?}", e);
+            TlsError::InvalidCertificate
+        })?;
+        let public_key = p256_public_key(public_key_info)
+            .ok()
+            .and_then(|point| VerifyingKey::from_sec1_bytes(point).ok())
+            .ok_or_else(|| {
+                warn!("Server key is not a P-256 key");
+                TlsError::InvalidCertificate
+            })?;
+
+        self.public_key = Some(public_key);
+        self.transcript = Some(transcript.clone());
+        Ok(())
+    }
+
+    fn verify_signature(&mut self, verify: CertificateVerifyRef) -> Result<(), TlsError> {
+        if verify.signature_scheme != SignatureScheme::EcdsaSecp256r1Sha256 {
+            warn!("Server signature scheme is not ECDSA P-256 with SHA-256");
+            return Err(TlsError::InvalidSignatureScheme);
+        }
+        let (Some(public_key), Some(transcript)) = (self.public_key.take(), self.transcript.take()) else {
+            return Err(TlsError::InvalidCertificate);
+        };
+
+        // the signed content is 64 spaces, the context string and the transcript hash
+        let mut message = [0x20; 64 + SERVER_SIGNATURE_CONTEXT.len() + 32];
+        message[64..64 + SERVER_SIGNATURE_CONTEXT.len()].copy_from_slice(SERVER_SIGNATURE_CONTEXT);
+        message[64 + SERVER_SIGNATURE_CONTEXT.len()..].copy_from_slice(&transcript.finalize());
+
+        let signature = DerSignature::try_from(verify.signature).map_err(|_| TlsError::InvalidSignature)?;
+        public_key.verify(&message, &signature).map_err(|_| {
+            warn!("Server signature is invalid");
+            TlsError::InvalidSignature
+        })
+    }
+}
@@ -0,0 +1,611 @@
+//! # Screen
+//! This module draws the screens of the OLED display, see `task::display`.
+//!
+//! The display task composes a `Screen` from the system state and draws it into the buffer of the display. The
+//! drawing only needs a `DrawTarget`, so `simulator/` draws the same screens into a framebuffer on the host, writes
+//! them as images and compares them with golden images. The module uses nothing else of the crate for that reason.
+//!
+//! The display is 128×64 pixels: the state indicator and the battery share the top row, the time or three lines of
+//! content fill the middle and the date is at the bottom. Text that does not fit is cut off by the display, so a
+//! line holds more than fits rather than silently dropping what does not fit into its buffer.
+//!
+//! Against burn-in the layout moves by a pixel every `SHIFT_MINUTES`, see `pixel_shift`. Each region, the state
+//! indicator, the battery, the content and the date, moves as far as its lit pixels stay on the display, so a
+//! region at an edge stays there rather than being cut off. A screen can be drawn inverted as well.
+//!
+//! A time in 12-hour format has its AM or PM indicator at the end of the state indicator, which cuts the tip of the
+//! saber short.
+
+use core::fmt::Write;
+
+use embedded_graphics::{
+    image::Image,
+    mono_font::{
+        MonoTextStyle, MonoTextStyleBuilder,
+        ascii::{FONT_6X13, FONT_8X13_BOLD},
+    },
+    pixelcolor::{BinaryColor, Gray8},
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle},
+    text::{Baseline, Text},
+};
+use heapless::String;
+use tinybmp::Bmp;
+
+/// Width of the display in pixels
+pub const WIDTH: u32 = 128;
+
+/// Height of the display in pixels
+pub const HEIGHT: u32 = 64;
+
+/// Capacity of a line of content, long enough for a hostname of 32 characters
+pub const LINE_CAPACITY: usize = 40;
+
+/// Number of characters of the content font that fit on a line
+pub const LINE_CHARS: usize = 21;
+
+/// Number of characters of the state indicator font that fit on the top row, left of the battery
+pub const TITLE_CHARS: usize = 13;
+
+/// How far the layout moves at most from its place in each direction, in pixels
+pub const MAX_SHIFT: i32 = 2;
+
+/// Minutes the layout stays in place before it moves by a pixel
+pub const SHIFT_MINUTES: u16 = 3;
+
+/// Pixels cleared left of the AM or PM indicator, so that it stands apart from the saber
+const MERIDIEM_CLEARANCE: i32 = 3;
+
+/// The lines of content in the middle of the display
+pub type Lines = [String<LINE_CAPACITY>; 3];
+
+/// An image could not be loaded
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct AssetError {
+    /// The file name of the image
+    pub name: &'static str,
+}
+
+/// Loads and holds BMP images and Points for the display
+/// Holds some settings for composing the display
+pub struct Settings<'a> {
+    /// BMP image of the saber icon
+    saber: Bmp<'static, Gray8>,
+    /// BMP image of the colon icon
+    colon: Bmp<'static, Gray8>,
+    /// BMP images of the digits 0-9
+    digits: [Bmp<'static, Gray8>; 10],
+    /// BMP images of the battery status icons
+    bat: [Bmp<'static, Gray8>; 6],
+    /// BMP image of the battery mains icon
+    bat_mains: Bmp<'static, Gray8>,
+    /// BMP image of the settings icon
+    setup: Bmp<'static, Gray8>,
+    /// BMP images of the weather conditions, in the order of `WeatherCondition`
+    weather: [Bmp<'static, Gray8>; 7],
+    /// Position of the state indicator images, hight is 16
+    state_indicator_position: Point,
+    /// Position of the battery status images, hight is 11
+    bat_position: Point,
+    /// (Starting) Position of the time digits, hight is 24
+    time_digit_start_position: Point,
+    /// Position of the date text
+    date_position: Point,
+    /// Position of the AM or PM indicator, the state indicator is cleared from `MERIDIEM_CLEARANCE` to the left of it
+    meridiem_position: Point,
+    /// (Starting) Position of content
+    content_start_position: Point,
+    /// Position of the weather condition image, hight is 16
+    weather_icon_position: Point,
+    /// Style of the state area text
+    state_indicator_text_style: MonoTextStyle<'a, BinaryColor>,
+    /// Style of the date text
+    date_text_style: MonoTextStyle<'a, BinaryColor>,
+    /// Style of the menu and system info content text
+    content_text_style: MonoTextStyle<'a, BinaryColor>,
+}
+
+/// Load a BMP image that is compiled in
+fn bmp(name: &'static str, bytes: &'static [u8]) -> Result<Bmp<'static, Gray8>, AssetError> {
+    Bmp::from_slice(bytes).map_err(|_| AssetError { name })
+}
+
+impl Settings<'_> {
+    /// Creates a new Settings struct, loading all BMP images from the media folder
+    pub fn new() -> Result<Self, AssetError> {
+        Ok(Self {
+            saber: bmp("saber.bmp", include_bytes!("../media/saber.bmp"))?,
+            colon: bmp("colon.bmp", include_bytes!("../media/colon.bmp"))?,
+            digits: [
+                bmp("0.bmp", include_bytes!("../media/0.bmp"))?,
+                bmp("1.bmp", include_bytes!("../media/1.bmp"))?,
+                bmp("2.bmp", include_bytes!("../media/2.bmp"))?,
+                bmp("3.bmp", include_bytes!("../media/3.bmp"))?,
+                bmp("4.bmp", include_bytes!("../media/4.bmp"))?,
+                bmp("5.bmp", include_bytes!("../media/5.bmp"))?,
+                bmp("6.bmp", include_bytes!("../media/6.bmp"))?,
+                bmp("7.bmp", include_bytes!("../media/7.bmp"))?,
+                bmp("8.bmp", include_bytes!("../media/8.bmp"))?,
+                bmp("9.bmp", include_bytes!("../media/9.bmp"))?,
+            ],
+            bat: [
+                bmp("bat_000.bmp", include_bytes!("../media/bat_000.bmp"))?,
+                bmp("bat_020.bmp", include_bytes!("../media/bat_020.bmp"))?,
+                bmp("bat_040.bmp", include_bytes!("../media/bat_040.bmp"))?,
+                bmp("bat_060.bmp", include_bytes!("../media/bat_060.bmp"))?,
+                bmp("bat_080.bmp", include_bytes!("../media/bat_080.bmp"))?,
+                bmp("bat_100.bmp", include_bytes!("../media/bat_100.bmp"))?,
+            ],
+            bat_mains: bmp("bat_mains.bmp", include_bytes!("../media/bat_mains.bmp"))?,
+            setup: bmp("settings.bmp", include_bytes!("../media/settings.bmp"))?,
+            weather: [
+                bmp("weather_clear.bmp", include_bytes!("../media/weather_clear.bmp"))?,
+                bmp(
+                    "weather_partly_cloudy.bmp",
+                    include_bytes!("../media/weather_partly_cloudy.bmp"),
+                )?,
+                bmp("weather_cloudy.bmp", include_bytes!("../media/weather_cloudy.bmp"))?,
+                bmp("weather_fog.bmp", include_bytes!("../media/weather_fog.bmp"))?,
+                bmp("weather_rain.bmp", include_bytes!("../media/weather_rain.bmp"))?,
+                bmp("weather_snow.bmp", include_bytes!("../media/weather_snow.bmp"))?,
+                bmp(
+                    "weather_thunderstorm.bmp",
+                    include_bytes!("../media/weather_thunderstorm.bmp"),
+                )?,
+            ],
+            state_indicator_position: Point::new(0, 0),
+            bat_position: Point::new(108, 0),
+            time_digit_start_position: Point::new(13, 21),
+            content_start_position: Point::new(0, 19),
+            weather_icon_position: Point::new(108, 19),
+            date_position: Point::new(0, 51),
+            meridiem_position: Point::new(93, 1),
+            state_indicator_text_style: MonoTextStyleBuilder::new()
+                .font(&FONT_8X13_BOLD)
+                .text_color(BinaryColor::On)
+                .build(),
+            date_text_style: MonoTextStyleBuilder::new()
+                .font(&FONT_6X13)
+                .text_color(BinaryColor::On)
+                .build(),
+            content_text_style: MonoTextStyleBuilder::new()
+                .font(&FONT_6X13)
+                .text_color(BinaryColor::On)
+                .build(),
+        })
+    }
+}
+
+/// The state indicator in the top-left area of the display
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Header<'a> {
+    /// Nothing, e.g. in normal mode with the alarm disabled
+    None,
+    /// The saber, the alarm is enabled
+    Saber,
+    /// The settings icon, the alarm time is being set
+    Setup,
+    /// A title like the name of the page or the button to press
+    Title(&'a str),
+}
+
+/// The battery status indicator in the top-right area of the display
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Battery {
+    /// On battery, the charge in fifths from 0 to 5
+    Level(u8),
+    /// On USB power
+    Charging,
+}
+
+/// The content in the middle of the display
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Body<'a> {
+    /// The time in hours and minutes
+    Time {
+        /// The hours
+        hours: u8,
+        /// The minutes
+        minutes: u8,
+    },
+    /// The menu with what the buttons do
+    Menu,
+    /// Three lines of text with an icon on the right
+    Lines {
+        /// The lines
+        lines: &'a Lines,
+        /// The index of the weather icon, in the order of `WeatherCondition`
+        icon: Option<usize>,
+    },
+    /// A single message
+    Message(&'a str),
+}
+
+/// The half of the day of a time in 12-hour format
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Meridiem {
+    /// Before noon, from midnight
+    Am,
+    /// From noon
+    Pm,
+}
+
+impl Meridiem {
+    /// The indicator on the display
+    const fn label(self) -> &'static str {
+        match self {
+            Self::Am => "AM",
+            Self::Pm => "PM",
+        }
+    }
+}
+
+/// The hour in 12-hour format, from 1 to 12, and the half of the day of an hour from 0 to 23
+pub const fn twelve_hour(hour: u8) -> (u8, Meridiem) {
+    let meridiem = if hour < 12 { Meridiem::Am } else { Meridiem::Pm };
+    match hour % 12 {
+        0 => (12, meridiem),
+        hour => (hour, meridiem),
+    }
+}
+
+/// Everything the display shows at once
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Screen<'a> {
+    /// The state indicator
+    pub header: Header<'a>,
+    /// The battery status, not shown by the night clock
+    pub battery: Option<Battery>,
+    /// The content in the middle
+    pub body: Body<'a>,
+    /// The date at the bottom, if shown
+    pub date: Option<&'a str>,
+    /// The AM or PM indicator of a time in 12-hour format, at the end of the state indicator
+    pub meridiem: Option<Meridiem>,
+    /// How far the layout moves from its place, see `pixel_shift`
+    pub shift: Point,
+    /// Lit and dark pixels are swapped
+    pub inverted: bool,
+}
+
+/// A region of the screen, which moves as a whole
+#[derive(Clone, Copy)]
+enum Region<'a> {
+    /// The state indicator, with the AM or PM indicator
+    Header(&'a Header<'a>, Option<Meridiem>),
+    /// The battery status
+    Battery(Battery),
+    /// The content in the middle
+    Body(&'a Body<'a>),
+    /// The date at the bottom
+    Date(&'a str),
+}
+
+impl Region<'_> {
+    /// Draws the region at its place
+    fn draw<D>(self, display: &mut D, settings: &Settings)
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        match self {
+            Region::Header(header, meridiem) => {
+                draw_state_indicator(display, header, settings);
+                if let Some(meridiem) = meridiem {
+                    draw_meridiem(display, meridiem, settings);
+                }
+            }
+            Region::Battery(battery) => draw_battery_status(display, battery, settings),
+            Region::Body(body) => draw_body(display, body, settings),
+            Region::Date(date) => draw_date(display, date, settings),
+        }
+    }
+}
+
+/// A draw target that only records the area of the lit pixels drawn on it
+#[derive(Default)]
+struct Extent {
+    /// The top left and the bottom right lit pixel, `None` if none is lit
+    corners: Option<(Point, Point)>,
+}
+
+impl Extent {
+    /// The extent of the lit pixels of a region
+    fn of(region: Region, settings: &Settings) -> Self {
+        let mut extent = Self::default();
+        region.draw(&mut extent, settings);
+        extent
+    }
+
+    /// The shift limited to what keeps the lit pixels on the display. Pixels that are off the display already do not
+    /// move further off.
+    fn fit(&self, shift: Point) -> Point {
+        let Some((top_left, bottom_right)) = self.corners else {
+            return Point::zero();
+        };
+        let last = Point::zero() + Size::new(WIDTH, HEIGHT) - Point::new(1, 1);
+        Point::new(
+            shift.x.clamp((-top_left.x).min(0), (last.x - bottom_right.x).max(0)),
+            shift.y.clamp((-top_left.y).min(0), (last.y - bottom_right.y).max(0)),
+        )
+    }
+}
+
+impl OriginDimensions for Extent {
+    fn size(&self) -> Size {
+        Size::new(WIDTH, HEIGHT)
+    }
+}
+
+impl DrawTarget for Extent {
+    type Color = BinaryColor;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            if color.is_on() {
+                self.corners = Some(match self.corners {
+                    Some((top_left, bottom_right)) => {
+                        (top_left.component_min(point), bottom_right.component_max(point))
+                    }
+                    None => (point, point),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A draw target that draws every pixel in the opposite color
+struct Inverted<'a, D>(&'a mut D);
+
+impl<D: DrawTarget<Color = BinaryColor>> Dimensions for Inverted<'_, D> {
+    fn bounding_box(&self) -> Rectangle {
+        self.0.bounding_box()
+    }
+}
+
+impl<D: DrawTarget<Color = BinaryColor>> DrawTarget for Inverted<'_, D> {
+    type Color = BinaryColor;
+    type Error = D::Error;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        self.0.draw_iter(
+            pixels
+                .into_iter()
+                .map(|Pixel(point, color)| Pixel(point, color.invert())),
+        )
+    }
+}
+
+/// The shift of the layout at the time of the day. Every `SHIFT_MINUTES` the layout moves by a pixel, along rows of
+/// the square of `MAX_SHIFT` around its place, back and forth. A day holds a whole number of rounds, so the layout
+/// does not jump at midnight.
+pub fn pixel_shift(hour: u8, minute: u8) -> Point {
+    /// Number of shifts in a row of the square
+    const SIDE: i32 = 2 * MAX_SHIFT + 1;
+    /// Number of shifts of the square
+    const CELLS: i32 = SIDE * SIDE;
+    /// Number of steps of a round, there and back
+    const ROUND: i32 = 2 * (CELLS - 1);
+
+    let step = i32::from((u16::from(hour) * 60 + u16::from(minute)) / SHIFT_MINUTES) % ROUND;
+    let cell = if step < CELLS { step } else { ROUND - step };
+    let row = cell / SIDE;
+    // every other row is walked backwards, so the next row starts next to where the last one ended
+    let column = if row % 2 == 0 {
+        cell % SIDE
+    } else {
+        SIDE - 1 - cell % SIDE
+    };
+    Point::new(column - MAX_SHIFT, row - MAX_SHIFT)
+}
+
+/// Draws the whole screen, the display must be cleared before
+pub fn draw_screen<D>(display: &mut D, screen: &Screen, settings: &Settings)
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    if screen.inverted {
+        let _ = display.clear(BinaryColor::On);
+        draw_regions(&mut Inverted(display), screen, settings);
+    } else {
+        draw_regions(display, screen, settings);
+    }
+}
+
+/// Draws every region of the screen, each moved by the shift as far as it stays on the display
+fn draw_regions<D>(display: &mut D, screen: &Screen, settings: &Settings)
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let regions = [
+        Some(Region::Header(&screen.header, screen.meridiem)),
+        screen.battery.map(Region::Battery),
+        Some(Region::Body(&screen.body)),
+        screen.date.map(Region::Date),
+    ];
+    for region in regions.into_iter().flatten() {
+        let shift = Extent::of(region, settings).fit(screen.shift);
+        region.draw(&mut display.translated(shift), settings);
+    }
+}
+
+/// The lines of the system info page
+pub fn system_info_lines(vsys: f32, usb_power: bool, upper: f32, lower: f32) -> Lines {
+    let mut lines: Lines = [String::new(), String::new(), String::new()];
+    let _ = write!(lines[0], "Vsys:  {vsys:.2}V");
+    let _ = write!(lines[1], "USB:   {usb_power}");
+    let _ = write!(lines[2], "Bounds {lower:.2}-{upper:.2}V");
+    lines
+}
+
+/// Draws the state indicator in the top-left area of the display
+fn draw_state_indicator<D>(display: &mut D, header: &Header, settings: &Settings)
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    match header {
+        Header::None => {}
+        Header::Saber => {
+            let saber = Image::new(&settings.saber, settings.state_indicator_position);
+            let _ = saber.draw(&mut display.color_converted());
+        }
+        Header::Setup => {
+            let setup_img = Image::new(&settings.setup, settings.state_indicator_position);
+            let _ = setup_img.draw(&mut display.color_converted());
+        }
+        Header::Title(title) => {
+            let _ = Text::with_baseline(
+                title,
+                settings.state_indicator_position,
+                settings.state_indicator_text_style,
+                Baseline::Top,
+            )
+            .draw(display);
+        }
+    }
+}
+
+/// Draws the content in the middle of the display
+fn draw_body<D>(display: &mut D, body: &Body, settings: &Settings)
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    match *body {
+        Body::Time { hours, minutes } => draw_time_display(display, hours, minutes, settings),
+        Body::Menu => draw_menu_content(display, settings),
+        Body::Lines { lines, icon } => draw_lines_content(display, lines, icon, settings),
+        Body::Message(message) => {
+            let _ = Text::with_baseline(
+                message,
+                settings.content_start_position,
+                settings.content_text_style,
+                Baseline::Top,
+            )
+            .draw(display);
+        }
+    }
+}
+
+/// Draws the AM or PM indicator at the end of the state indicator, over what is drawn there
+fn draw_meridiem<D>(display: &mut D, meridiem: Meridiem, settings: &Settings)
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let clear_from = settings.meridiem_position.x - MERIDIEM_CLEARANCE;
+    let _ = Rectangle::with_corners(
+        Point::new(clear_from, settings.state_indicator_position.y),
+        Point::new(settings.bat_position.x - 1, settings.state_indicator_position.y + 15),
+    )
+    .into_styled(PrimitiveStyle::with_fill(BinaryColor::Off))
+    .draw(display);
+    let _ = Text::with_baseline(
+        meridiem.label(),
+        settings.meridiem_position,
+        settings.date_text_style,
+        Baseline::Top,
+    )
+    .draw(display);
+}
+
+/// Draws the battery status indicator in the top-right area of the display
+fn draw_battery_status<D>(display: &mut D, battery: Battery, settings: &Settings)
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let bmp = match battery {
+        Battery::Level(level) => &settings.bat[usize::from(level.min(5))],
+        Battery::Charging => &settings.bat_mains,
+    };
+    let _ = Image::new(bmp, settings.bat_position).draw(&mut display.color_converted());
+}
+
+/// Draws the time display in the center area of the display
+fn draw_time_display<D>(display: &mut D, hours: u8, minutes: u8, settings: &Settings)
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let mut digit_next_position = settings.time_digit_start_position;
+
+    let first_hour_digit = Image::new(&settings.digits[(hours / 10) as usize], digit_next_position);
+    digit_next_position.x += 24;
+
+    let second_hour_digit = Image::new(&settings.digits[(hours % 10) as usize], digit_next_position);
+    digit_next_position.x += 24;
+
+    let colon = Image::new(&settings.colon, digit_next_position);
+    digit_next_position.x += 11;
+
+    let first_minute_digit = Image::new(&settings.digits[(minutes / 10) as usize], digit_next_position);
+    digit_next_position.x += 24;
+
+    let second_minute_digit = Image::new(&settings.digits[(minutes % 10) as usize], digit_next_position);
+
+    let _ = first_hour_digit.draw(&mut display.color_converted());
+    let _ = second_hour_digit.draw(&mut display.color_converted());
+    let _ = colon.draw(&mut display.color_converted());
+    let _ = first_minute_digit.draw(&mut display.color_converted());
+    let _ = second_minute_digit.draw(&mut display.color_converted());
+}
+
+/// Draws the menu content in the center area of the display
+fn draw_menu_content<D>(display: &mut D, settings: &Settings)
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let mut content_next_position = settings.content_start_position;
+    let _ = Text::with_baseline(
+        "Green: Sys. Info",
+        content_next_position,
+        settings.content_text_style,
+        Baseline::Top,
+    )
+    .draw(display);
+    content_next_position.y += 15;
+    let _ = Text::with_baseline(
+        "Blue: Standby",
+        content_next_position,
+        settings.content_text_style,
+        Baseline::Top,
+    )
+    .draw(display);
+    content_next_position.y += 15;
+    let _ = Text::with_baseline(
+        "Yellow: Back",
+        content_next_position,
+        settings.content_text_style,
+        Baseline::Top,
+    )
+    .draw(display);
+}
+
+/// Draws lines of content in the center area of the display, with the weather icon on the right
+fn draw_lines_content<D>(display: &mut D, lines: &Lines, icon: Option<usize>, settings: &Settings)
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    if let Some(bmp) = icon.and_then(|index| settings.weather.get(index)) {
+        let _ = Image::new(bmp, settings.weather_icon_position).draw(&mut display.color_converted());
+    }
+
+    let mut content_next_position = settings.content_start_position;
+    for line in lines {
+        let _ =
+            Text::with_baseline(line, content_next_position, settings.content_text_style, Baseline::Top).draw(display);
+        content_next_position.y += 15;
+    }
+}
+
+/// Draws the date text at the bottom of the display
+fn draw_date<D>(display: &mut D, date: &str, settings: &Settings)
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let _ = Text::with_baseline(date, settings.date_position, settings.date_text_style, Baseline::Top).draw(display);
+}
@@ -3,59 +3,133 @@
 
 use core::fmt::Write;
 
-use embassy_rp::rtc::{DateTime, DayOfWeek};
-use heapless::{String, Vec};
+use heapless::String;
+
+use crate::utility::calendar::{DateTime, day_of_week, days_in_month};
+
+/// The earliest year we accept when parsing a datetime. Anything before is certainly not the current time.
+const MIN_YEAR: u16 = 2000;
+
+/// The latest year we accept when parsing a datetime, the RTC stores the year in 12 bits.
+const MAX_YEAR: u16 = 4095;
 
 /// A utility struct for string operations
 pub struct StringUtils;
 
+/// The reasons why a datetime string could not be parsed
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DateTimeParseError {
+    /// The string does not follow the "YYYY-MM-DDTHH:MM:SS" layout
+    InvalidFormat,
+    /// A field that should be a number contains something else
+    InvalidNumber,
+    /// The year is outside of the range we accept
+    YearOutOfRange,
+    /// The month is not within 1-12
+    MonthOutOfRange,
+    /// The day does not exist in the given month
+    DayOutOfRange,
+    /// The hour is not within 0-23
+    HourOutOfRange,
+    /// The minute is not within 0-59
+    MinuteOutOfRange,
+    /// The second is not within 0-59
+    SecondOutOfRange,
+    /// The UTC offset is neither `Z` nor `+HH:MM`/`-HH:MM` within +-18 hours
+    InvalidOffset,
+}
+
+/// A datetime parsed from a string, together with the UTC offset the string was given in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedDateTime {
+    /// The date and time as written in the string, i.e. local time at the given offset
+    pub datetime: DateTime,
+    /// The offset from UTC in minutes, `None` if the string did not specify one
+    pub utc_offset_minutes: Option<i16>,
+}
+
 impl StringUtils {
-    /// This function converts a &str to a `DateTime` struct
-    /// The input string should be in the format "YYYY-MM-DDTHH:MM:SS.ssssss+HH:MM"
+    /// This function parses an ISO 8601 datetime string into a `DateTime` struct and the UTC offset it was given in.
+    /// The input string should be in the format "YYYY-MM-DDTHH:MM:SS[.ssssss][Z|+HH:MM|-HH:MM]"
     /// one example being "2024-06-26T22:01:27.106426+02:00"
-    pub fn convert_str_to_datetime(s: &str, d: u8) -> DateTime {
-        const CAPACITY: usize = 10;
-
-        let mut dt = DateTime {
-            year: 0,
-            month: 0,
-            day: 0,
-            day_of_week: match d {
-                2 => DayOfWeek::Tuesday,
-                3 => DayOfWeek::Wednesday,
-                4 => DayOfWeek::Thursday,
-                5 => DayOfWeek::Friday,
-                6 => DayOfWeek::Saturday,
-                0 => DayOfWeek::Sunday, // as specified by worldtimeapi.org
-                _ => DayOfWeek::Monday,
-            },
-            hour: 0,
-            minute: 0,
-            second: 0,
-        };
-
-        // Split the input string into date and time components
-        let parts: Vec<&str, CAPACITY> = s.split('T').collect();
-        if parts.len() == 2 {
-            // Process the date part
-            let date_parts: Vec<&str, CAPACITY> = parts[0].split('-').collect();
-            if date_parts.len() == 3 {
-                dt.year = date_parts[0].parse::<u16>().unwrap_or_default();
-                dt.month = date_parts[1].parse::<u8>().unwrap_or_default();
-                dt.day = date_parts[2].parse::<u8>().unwrap_or_default();
-            }
+    ///
+    /// All fields are range checked, including the day against the length of the month in leap and common years.
+    /// The day of the week is computed from the date, so it does not need to be trusted from elsewhere.
+    pub fn parse_datetime(s: &str) -> Result<ParsedDateTime, DateTimeParseError> {
+        let bytes = s.as_bytes();
+        if bytes.len() < 19
+            || bytes[4] != b'-'
+            || bytes[7] != b'-'
+            || !matches!(bytes[10], b'T' | b't')
+            || bytes[13] != b':'
+            || bytes[16] != b':'
+        {
+            return Err(DateTimeParseError::InvalidFormat);
+        }
+
+        let datetime = validated_datetime(
+            parse_digits(&bytes[0..4])?,
+            parse_digits(&bytes[5..7])?,
+            parse_digits(&bytes[8..10])?,
+            parse_digits(&bytes[11..13])?,
+            parse_digits(&bytes[14..16])?,
+            parse_digits(&bytes[17..19])?,
+        )?;
 
-            // Process the time part, ignoring fractional seconds and timezone
-            let time_parts: Vec<&str, CAPACITY> = parts[1].split(':').collect();
-            if time_parts.len() >= 3 {
-                dt.hour = time_parts[0].parse::<u8>().unwrap_or_default();
-                dt.minute = time_parts[1].parse::<u8>().unwrap_or_default();
-                // Extract seconds, ignoring fractional part
-                let second_parts: Vec<&str, CAPACITY> = time_parts[2].split('.').collect();
-                dt.second = second_parts[0].parse::<u8>().unwrap_or_default();
+        // skip fractional seconds, we do not need them
+        let mut rest = &bytes[19..];
+        if let Some((b'.', fraction)) = rest.split_first() {
+            let digits = fraction.iter().take_while(|b| b.is_ascii_digit()).count();
+            if digits == 0 {
+                return Err(DateTimeParseError::InvalidFormat);
             }
+            rest = &fraction[digits..];
+        }
+
+        let utc_offset_minutes = parse_utc_offset(rest)?;
+
+        Ok(ParsedDateTime {
+            datetime,
+            utc_offset_minutes,
+        })
+    }
+
+    /// This function parses an HTTP date (RFC 9110, `IMF-fixdate`) into a `DateTime` struct in UTC.
+    /// The input string should be in the format "Day, DD Mon YYYY HH:MM:SS GMT"
+    /// one example being "Wed, 26 Jun 2024 20:01:27 GMT"
+    ///
+    /// The obsolete formats that servers must no longer send are not supported.
+    /// The day name is not checked, the day of the week is computed from the date.
+    pub fn parse_http_date(s: &str) -> Result<DateTime, DateTimeParseError> {
+        let bytes = s.trim().as_bytes();
+        if bytes.len() != 29
+            || &bytes[3..5] != b", "
+            || bytes[7] != b' '
+            || bytes[11] != b' '
+            || bytes[16] != b' '
+            || bytes[19] != b':'
+            || bytes[22] != b':'
+            || &bytes[25..] != b" GMT"
+        {
+            return Err(DateTimeParseError::InvalidFormat);
         }
-        dt
+
+        let month = MONTH_NAMES
+            .iter()
+            .position(|name| name.as_bytes() == &bytes[8..11])
+            .ok_or(DateTimeParseError::MonthOutOfRange)?;
+
+        // the position in the list of month names is below 12
+        #[allow(clippy::cast_possible_truncation)]
+        validated_datetime(
+            parse_digits(&bytes[12..16])?,
+            month as u16 + 1,
+            parse_digits(&bytes[5..7])?,
+            parse_digits(&bytes[17..19])?,
+            parse_digits(&bytes[20..22])?,
+            parse_digits(&bytes[23..25])?,
+        )
     }
 
     /// This function converts a `DateTime` struct to a string
@@ -92,3 +166,231 @@ impl StringUtils {
         padded_string
     }
 }
+
+/// Names of the months as used in HTTP dates
+const MONTH_NAMES: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Range check the fields of a datetime and build the `DateTime`, computing its day of the week
+fn validated_datetime(
+    year: u16,
+    month: u16,
+    day: u16,
+    hour: u16,
+    minute: u16,
+    second: u16,
+) -> Result<DateTime, DateTimeParseError> {
+    if !(MIN_YEAR..=MAX_YEAR).contains(&year) {
+        return Err(DateTimeParseError::YearOutOfRange);
+    }
+    // the casts below are safe, all values have at most two digits
+    #[allow(clippy::cast_possible_truncation)]
+    let (month, day, hour, minute, second) = (month as u8, day as u8, hour as u8, minute as u8, second as u8);
+    if !(1..=12).contains(&month) {
+        return Err(DateTimeParseError::MonthOutOfRange);
+    }
+    if day < 1 || day > days_in_month(month, year) {
+        return Err(DateTimeParseError::DayOutOfRange);
+    }
+    if hour > 23 {
+        return Err(DateTimeParseError::HourOutOfRange);
+    }
+    if minute > 59 {
+        return Err(DateTimeParseError::MinuteOutOfRange);
+    }
+    // leap seconds cannot be represented in the RTC
+    if second > 59 {
+        return Err(DateTimeParseError::SecondOutOfRange);
+    }
+
+    Ok(DateTime {
+        year,
+        month,
+        day,
+        day_of_week: day_of_week(year, month, day),
+        hour,
+        minute,
+        second,
+    })
+}
+
+/// Parse a fixed number of ASCII digits into a number
+fn parse_digits(digits: &[u8]) -> Result<u16, DateTimeParseError> {
+    digits.iter().try_fold(0u16, |acc, &b| {
+        if b.is_ascii_digit() {
+            Ok(acc * 10 + u16::from(b - b'0'))
+        } else {
+            Err(DateTimeParseError::InvalidNumber)
+        }
+    })
+}
+
+/// Parse the UTC offset at the end of a datetime string: nothing, `Z`, or `+HH:MM`/`-HH:MM`
+fn parse_utc_offset(offset: &[u8]) -> Result<Option<i16>, DateTimeParseError> {
+    match offset {
+        [] => Ok(None),
+        [b'Z' | b'z'] => Ok(Some(0)),
+        [sign @ (b'+' | b'-'), h1, h2, b':', m1, m2] => {
+            let hours = parse_digits(&[*h1, *h2]).map_err(|_| DateTimeParseError::InvalidOffset)?;
+            let minutes = parse_digits(&[*m1, *m2]).map_err(|_| DateTimeParseError::InvalidOffset)?;
+            if hours > 18 || minutes > 59 {
+                return Err(DateTimeParseError::InvalidOffset);
+            }
+            // at most 18 * 60 + 59, fits easily
+            #[allow(clippy::cast_possible_wrap)]
+            let total = (hours * 60 + minutes) as i16;
+            Ok(Some(if *sign == b'-' { -total } else { total }))
+        }
+        _ => Err(DateTimeParseError::InvalidOffset),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utility::calendar::DayOfWeek;
+
+    /// The datetime of a string that must parse
+    fn datetime(s: &str) -> DateTime {
+        StringUtils::parse_datetime(s).unwrap().datetime
+    }
+
+    /// A full response of the time `API` with fractional seconds and an offset
+    #[test]
+    fn parses_an_api_datetime() {
+        assert_eq!(
+            StringUtils::parse_datetime("2024-06-26T22:01:27.106426+02:00"),
+            Ok(ParsedDateTime {
+                datetime: DateTime {
+                    year: 2024,
+                    month: 6,
+                    day: 26,
+                    day_of_week: DayOfWeek::Wednesday,
+                    hour: 22,
+                    minute: 1,
+                    second: 27,
+                },
+                utc_offset_minutes: Some(120),
+            })
+        );
+    }
+
+    /// No offset, `Z`, and negative and half hour offsets
+    #[test]
+    fn parses_utc_offsets() {
+        let offset = |s| StringUtils::parse_datetime(s).map(|parsed| parsed.utc_offset_minutes);
+        assert_eq!(offset("2024-06-26T22:01:27"), Ok(None));
+        assert_eq!(offset("2024-06-26T22:01:27Z"), Ok(Some(0)));
+        assert_eq!(offset("2024-06-26t22:01:27.5z"), Ok(Some(0)));
+        assert_eq!(offset("2024-06-26T22:01:27-05:00"), Ok(Some(-300)));
+        assert_eq!(offset("2024-06-26T22:01:27+05:30"), Ok(Some(330)));
+        assert_eq!(offset("2024-06-26T22:01:27+18:00"), Ok(Some(1_080)));
+        assert_eq!(
+            offset("2024-06-26T22:01:27+19:00"),
+            Err(DateTimeParseError::InvalidOffset)
+        );
+        assert_eq!(offset("2024-06-26T22:01:27+02"), Err(DateTimeParseError::InvalidOffset));
+        assert_eq!(
+            offset("2024-06-26T22:01:27 +02:00"),
+            Err(DateTimeParseError::InvalidOffset)
+        );
+    }
+
+    /// The day of the week is computed from the date, across leap days and centuries
+    #[test]
+    fn computes_the_day_of_the_week() {
+        assert_eq!(datetime("2024-02-29T00:00:00Z").day_of_week, DayOfWeek::Thursday);
+        assert_eq!(datetime("2000-01-01T00:00:00").day_of_week, DayOfWeek::Saturday);
+        assert_eq!(datetime("2100-03-01T00:00:00").day_of_week, DayOfWeek::Monday);
+    }
+
+    /// Every field is range checked, the day against the month of the year
+    #[test]
+    fn rejects_out_of_range_fields() {
+        let error = |s| StringUtils::parse_datetime(s).err();
+        assert_eq!(error("1999-12-31T23:59:59"), Some(DateTimeParseError::YearOutOfRange));
+        assert_eq!(error("0000-01-01T00:00:00"), Some(DateTimeParseError::YearOutOfRange));
+        assert_eq!(error("2024-13-01T00:00:00"), Some(DateTimeParseError::MonthOutOfRange));
+        assert_eq!(error("2024-00-01T00:00:00"), Some(DateTimeParseError::MonthOutOfRange));
+        assert_eq!(error("2024-04-31T00:00:00"), Some(DateTimeParseError::DayOutOfRange));
+        assert_eq!(error("2023-02-29T00:00:00"), Some(DateTimeParseError::DayOutOfRange));
+        assert_eq!(error("2100-02-29T00:00:00"), Some(DateTimeParseError::DayOutOfRange));
+        assert_eq!(error("2024-01-00T00:00:00"), Some(DateTimeParseError::DayOutOfRange));
+        assert_eq!(error("2024-01-01T24:00:00"), Some(DateTimeParseError::HourOutOfRange));
+        assert_eq!(error("2024-01-01T00:60:00"), Some(DateTimeParseError::MinuteOutOfRange));
+        assert_eq!(
+            error("2024-06-30T23:59:60Z"),
+            Some(DateTimeParseError::SecondOutOfRange)
+        );
+        assert_eq!(error("2000-02-29T00:00:00"), None);
+    }
+
+    /// Anything that does not have the layout of a datetime is rejected, not read as zeros
+    #[test]
+    fn rejects_malformed_strings() {
+        let error = |s| StringUtils::parse_datetime(s).err();
+        assert_eq!(error(""), Some(DateTimeParseError::InvalidFormat));
+        assert_eq!(error("2024-06-26"), Some(DateTimeParseError::InvalidFormat));
+        assert_eq!(error("2024/06/26T22:01:27"), Some(DateTimeParseError::InvalidFormat));
+        assert_eq!(error("2024-06-26 22:01:27"), Some(DateTimeParseError::InvalidFormat));
+        assert_eq!(error("2024-06-26T22:01:27."), Some(DateTimeParseError::InvalidFormat));
+        assert_eq!(error("20x4-06-26T22:01:27"), Some(DateTimeParseError::InvalidNumber));
+        assert_eq!(error("2024-06-26T22:0a:27"), Some(DateTimeParseError::InvalidNumber));
+    }
+
+    /// HTTP dates are read in `IMF-fixdate` only
+    #[test]
+    fn parses_http_dates() {
+        assert_eq!(
+            StringUtils::parse_http_date(" Wed, 26 Jun 2024 20:01:27 GMT "),
+            Ok(DateTime {
+                year: 2024,
+                month: 6,
+                day: 26,
+                day_of_week: DayOfWeek::Wednesday,
+                hour: 20,
+                minute: 1,
+                second: 27,
+            })
+        );
+        let error = |s| StringUtils::parse_http_date(s).err();
+        assert_eq!(
+            error("Wed, 26 Foo 2024 20:01:27 GMT"),
+            Some(DateTimeParseError::MonthOutOfRange)
+        );
+        assert_eq!(
+            error("Wednesday, 26-Jun-24 20:01:27 GMT"),
+            Some(DateTimeParseError::InvalidFormat)
+        );
+        assert_eq!(
+            error("Wed Jun 26 20:01:27 2024"),
+            Some(DateTimeParseError::InvalidFormat)
+        );
+        assert_eq!(
+            error("Wed, 26 Jun 2024 20:01:27 UTC"),
+            Some(DateTimeParseError::InvalidFormat)
+        );
+        assert_eq!(
+            error("Thu, 29 Feb 2024 24:00:00 GMT"),
+            Some(DateTimeParseError::HourOutOfRange)
+        );
+        assert_eq!(
+            error("Thu, 30 Feb 2024 00:00:00 GMT"),
+            Some(DateTimeParseError::DayOutOfRange)
+        );
+    }
+
+    /// The date line of the display is centered in 22 characters
+    #[test]
+    fn converts_a_datetime_to_the_date_line() {
+        assert_eq!(
+            StringUtils::convert_datetime_to_str(&datetime("2024-06-26T22:01:27")).as_str(),
+            " Wednesday 26.06.2024 "
+        );
+        assert_eq!(
+            StringUtils::convert_datetime_to_str(&datetime("2024-06-01T00:00:00")).as_str(),
+            " Saturday 01.06.2024  "
+        );
+    }
+}
@@ -0,0 +1,204 @@
+//! # Syslog
+//! This module formats log messages as syslog messages of RFC 5424, to be sent over UDP as of RFC 5426:
+//!```text
+//! <134>1 2024-05-01T06:30:00+02:00 alarmclck alarmclock - event - Alarm
+//! ```
+//! That is the priority of facility and severity, the version, the timestamp, the hostname, the app name, no process
+//! id, the message id, no structured data and the message. Without a timestamp, e.g. before the first time sync, the
+//! collector stamps the message on receipt.
+//!
+//! The messages are formatted with `core::fmt`, next to the `defmt` logs, which never leave the debug probe.
+
+use core::fmt::{self, Write};
+
+use heapless::String;
+
+use crate::utility::calendar::DateTime;
+
+/// The facility of all messages, `local0`
+const FACILITY_LOCAL0: u8 = 16;
+
+/// The app name of all messages
+pub const APP_NAME: &str = "alarmclock";
+
+/// The severity of a syslog message, only those the clock uses
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Severity {
+    /// A subsystem failed
+    Error = 3,
+    /// Something went wrong, the clock copes with it
+    Warning = 4,
+    /// Something worth noting, e.g. dropped messages
+    Notice = 5,
+    /// Regular operation, e.g. an event of the orchestrator
+    Informational = 6,
+}
+
+/// The time of a message: the local time and its UTC offset in minutes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timestamp<'a> {
+    /// The local time
+    pub datetime: &'a DateTime,
+    /// The UTC offset of the local time in minutes
+    pub utc_offset_minutes: i16,
+}
+
+/// A `fmt::Write` into a string that cuts off what does not fit instead of failing
+pub struct Truncating<'a, const N: usize>(pub &'a mut String<N>);
+
+impl<const N: usize> Write for Truncating<'_, N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if self.0.push(c).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Format a syslog message into the buffer, `Err` if it does not fit
+pub fn format_message<const N: usize>(
+    buffer: &mut String<N>,
+    severity: Severity,
+    timestamp: Option<Timestamp>,
+    hostname: &str,
+    message_id: &str,
+    message: &str,
+) -> fmt::Result {
+    buffer.clear();
+    write!(buffer, "<{}>1 ", FACILITY_LOCAL0 * 8 + severity as u8)?;
+    match timestamp {
+        Some(Timestamp {
+            datetime: dt,
+            utc_offset_minutes,
+        }) => {
+            let sign = if utc_offset_minutes < 0 { '-' } else { '+' };
+            let offset = utc_offset_minutes.unsigned_abs();
+            write!(
+                buffer,
+                "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{sign}{:02}:{:02}",
+                dt.year,
+                dt.month,
+                dt.day,
+                dt.hour,
+                dt.minute,
+                dt.second,
+                offset / 60,
+                offset % 60
+            )?;
+        }
+        None => buffer.push('-').map_err(|_| fmt::Error)?,
+    }
+    write!(buffer, " {hostname} {APP_NAME} - {message_id} - {message}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utility::calendar;
+
+    /// Format a message into a buffer as large as the one of the syslog task
+    fn format(severity: Severity, timestamp: Option<Timestamp>, message_id: &str, message: &str) -> String<224> {
+        let mut buffer = String::new();
+        format_message(&mut buffer, severity, timestamp, "alarmclck", message_id, message).unwrap();
+        buffer
+    }
+
+    /// The example of the module documentation
+    #[test]
+    fn formats_the_documented_example() {
+        let datetime = calendar::from_unix_time(1_714_545_000);
+        let timestamp = Timestamp {
+            datetime: &datetime,
+            utc_offset_minutes: 120,
+        };
+        assert_eq!(
+            format(Severity::Informational, Some(timestamp), "event", "Alarm"),
+            "<134>1 2024-05-01T06:30:00+02:00 alarmclck alarmclock - event - Alarm"
+        );
+    }
+
+    /// Example 2 of RFC 5424, section 6.5, in facility `local0` and without the fraction of the second and the
+    /// process id, which the clock does not know: a negative offset and no structured data
+    #[test]
+    fn formats_the_rfc_example_with_offset() {
+        let datetime = calendar::from_unix_time(1_061_702_055);
+        let timestamp = Timestamp {
+            datetime: &datetime,
+            utc_offset_minutes: -7 * 60,
+        };
+        assert_eq!(
+            format(
+                Severity::Notice,
+                Some(timestamp),
+                "-",
+                "%% It's time to make the do-nuts."
+            ),
+            "<133>1 2003-08-24T05:14:15-07:00 alarmclck alarmclock - - - %% It's time to make the do-nuts."
+        );
+
+        let timestamp = Timestamp {
+            datetime: &datetime,
+            utc_offset_minutes: -210,
+        };
+        assert!(format(Severity::Notice, Some(timestamp), "-", "").starts_with("<133>1 2003-08-24T05:14:15-03:30 "));
+        let timestamp = Timestamp {
+            datetime: &datetime,
+            utc_offset_minutes: 0,
+        };
+        assert!(format(Severity::Notice, Some(timestamp), "-", "").starts_with("<133>1 2003-08-24T05:14:15+00:00 "));
+    }
+
+    /// The priority is the facility `local0` times 8 plus the severity
+    #[test]
+    fn computes_the_priority() {
+        for (severity, priority) in [
+            (Severity::Error, "<131>"),
+            (Severity::Warning, "<132>"),
+            (Severity::Notice, "<133>"),
+            (Severity::Informational, "<134>"),
+        ] {
+            assert!(format(severity, None, "event", "").starts_with(priority));
+        }
+    }
+
+    /// Without a known time the timestamp is the NILVALUE, like example 1 of RFC 5424 without its timestamp
+    #[test]
+    fn formats_the_nilvalue_without_time() {
+        assert_eq!(
+            format(
+                Severity::Error,
+                None,
+                "ID47",
+                "'su root' failed for lonvick on /dev/pts/8"
+            ),
+            "<131>1 - alarmclck alarmclock - ID47 - 'su root' failed for lonvick on /dev/pts/8"
+        );
+    }
+
+    /// A message that does not fit the buffer is an error
+    #[test]
+    fn refuses_messages_that_do_not_fit() {
+        let mut buffer: String<44> = String::new();
+        assert!(format_message(&mut buffer, Severity::Error, None, "alarmclck", "event", "fits").is_ok());
+        assert_eq!(buffer.len(), 44);
+        assert!(format_message(&mut buffer, Severity::Error, None, "alarmclck", "event", "fits!").is_err());
+    }
+
+    /// `Truncating` cuts off what does not fit at a character boundary and keeps the writer going
+    #[test]
+    fn truncates_at_character_boundaries() {
+        let mut text: String<8> = String::new();
+        let state = "läuft";
+        write!(Truncating(&mut text), "Wecker {state}").unwrap();
+        assert_eq!(text, "Wecker l");
+
+        let mut text: String<8> = String::new();
+        write!(Truncating(&mut text), "Weckerlä{}", 1).unwrap();
+        assert_eq!(text, "Weckerl");
+        write!(Truncating(&mut text), "!").unwrap();
+        assert_eq!(text, "Weckerl!");
+    }
+}
@@ -0,0 +1,271 @@
+//! # TLS Pin
+//! This module checks the certificate presented by the time server against a pin configured at build time.
+//!
+//! We cannot validate a certificate chain the usual way: checking the validity period needs the current time,
+//! and getting the current time is the whole point of talking to the server. Instead the server is identified by
+//! the SHA-256 hash of either its complete certificate or its public key (the DER encoded `SubjectPublicKeyInfo`).
+//! Pinning the public key survives certificate renewals as long as the server keeps its key.
+//!
+//! Only the few DER structures needed to find the public key in an X.509 certificate are parsed here,
+//! everything else is skipped without looking at it.
+
+use sha2::{Digest, Sha256};
+
+/// DER tag of a `SEQUENCE`
+const TAG_SEQUENCE: u8 = 0x30;
+
+/// DER tag of a `BIT STRING`
+const TAG_BIT_STRING: u8 = 0x03;
+
+/// DER tag of the explicit `[0]` version field of a certificate
+const TAG_VERSION: u8 = 0xA0;
+
+/// DER encoded algorithm parameters of an EC public key on the P-256 curve:
+/// the OIDs `id-ecPublicKey` (1.2.840.10045.2.1) and `prime256v1` (1.2.840.10045.3.1.7)
+const P256_ALGORITHM: [u8; 19] = [
+    0x06, 0x07, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x02, 0x01, 0x06, 0x08, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x03, 0x01, 0x07,
+];
+
+/// What the server is pinned to, each holding the expected SHA-256 hash
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Pin {
+    /// Hash of the complete DER encoded certificate
+    Certificate([u8; 32]),
+    /// Hash of the DER encoded `SubjectPublicKeyInfo` of the certificate
+    PublicKey([u8; 32]),
+}
+
+/// Errors when checking a certificate against the pin
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PinError {
+    /// The certificate is not the DER structure we expect
+    Malformed,
+    /// The certificate does not match the pin
+    Mismatch,
+    /// The public key is not an uncompressed P-256 key, which is the only kind we can verify signatures with
+    UnsupportedKey,
+}
+
+impl Pin {
+    /// Check a DER encoded certificate against the pin and return its `SubjectPublicKeyInfo`
+    pub fn check<'a>(&self, certificate: &'a [u8]) -> Result<&'a [u8], PinError> {
+        let public_key_info = subject_public_key_info(certificate)?;
+        let (expected, hashed) = match self {
+            Self::Certificate(expected) => (expected, certificate),
+            Self::PublicKey(expected) => (expected, public_key_info),
+        };
+
+        if Sha256::digest(hashed).as_slice() == expected {
+            Ok(public_key_info)
+        } else {
+            Err(PinError::Mismatch)
+        }
+    }
+}
+
+/// Find the DER encoded `SubjectPublicKeyInfo` (tag and length included) in a DER encoded X.509 certificate
+pub fn subject_public_key_info(certificate: &[u8]) -> Result<&[u8], PinError> {
+    let (certificate, _) = read_element(certificate, TAG_SEQUENCE)?;
+    let (tbs_certificate, _) = read_element(certificate.content, TAG_SEQUENCE)?;
+
+    let mut fields = tbs_certificate.content;
+    if fields.first() == Some(&TAG_VERSION) {
+        fields = read_any(fields)?.1;
+    }
+    // serial number, signature algorithm, issuer, validity and subject come before the public key
+    for _ in 0..5 {
+        fields = read_any(fields)?.1;
+    }
+
+    let (public_key_info, _) = read_element(fields, TAG_SEQUENCE)?;
+    Ok(public_key_info.encoded)
+}
+
+/// Get the uncompressed P-256 point (`0x04 || x || y`) from a DER encoded `SubjectPublicKeyInfo`
+pub fn p256_public_key(public_key_info: &[u8]) -> Result<&[u8], PinError> {
+    let (public_key_info, _) = read_element(public_key_info, TAG_SEQUENCE)?;
+    let (algorithm, rest) = read_element(public_key_info.content, TAG_SEQUENCE)?;
+    if algorithm.content != P256_ALGORITHM {
+        return Err(PinError::UnsupportedKey);
+    }
+
+    // the first byte of a bit string is the number of unused bits, which is 0 for a key
+    let (key, _) = read_element(rest, TAG_BIT_STRING)?;
+    match key.content {
+        [0, point @ ..] if point.len() == 65 && point[0] == 0x04 => Ok(point),
+        _ => Err(PinError::UnsupportedKey),
+    }
+}
+
+/// A DER element
+struct Element<'a> {
+    /// The element including its tag and length
+    encoded: &'a [u8],
+    /// The content of the element
+    content: &'a [u8],
+}
+
+/// Read an element with the expected tag, returning it and the remaining input
+fn read_element(input: &[u8], tag: u8) -> Result<(Element<'_>, &[u8]), PinError> {
+    if input.first() != Some(&tag) {
+        return Err(PinError::Malformed);
+    }
+    read_any(input)
+}
+
+/// Read an element with any tag, returning it and the remaining input
+fn read_any(input: &[u8]) -> Result<(Element<'_>, &[u8]), PinError> {
+    let &first_length_byte = input.get(1).ok_or(PinError::Malformed)?;
+
+    // short form lengths are below 0x80, long form lengths give the number of length bytes that follow
+    let (header_length, content_length) = if first_length_byte < 0x80 {
+        (2, usize::from(first_length_byte))
+    } else {
+        let length_bytes = usize::from(first_length_byte & 0x7F);
+        if length_bytes == 0 || length_bytes > 3 {
+            return Err(PinError::Malformed);
+        }
+        let length = input
+            .get(2..2 + length_bytes)
+            .ok_or(PinError::Malformed)?
+            .iter()
+            .fold(0, |length, &byte| (length << 8) | usize::from(byte));
+        (2 + length_bytes, length)
+    };
+
+    let end = header_length + content_length;
+    let encoded = input.get(..end).ok_or(PinError::Malformed)?;
+    Ok((
+        Element {
+            encoded,
+            content: &encoded[header_length..],
+        },
+        &input[end..],
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A self-signed version 3 certificate with a P-256 key, made with `openssl req -x509`
+    const P256: &[u8] = include_bytes!("../../host-tests/fixtures/tls_pin/p256.der");
+
+    /// A self-signed version 1 certificate with the same key, without the `[0]` version field
+    const P256_V1: &[u8] = include_bytes!("../../host-tests/fixtures/tls_pin/p256_v1.der");
+
+    /// A self-signed certificate with an RSA key
+    const RSA: &[u8] = include_bytes!("../../host-tests/fixtures/tls_pin/rsa.der");
+
+    /// The `SubjectPublicKeyInfo` of the P-256 key with the point in compressed form
+    const P256_COMPRESSED_KEY: &[u8] = include_bytes!("../../host-tests/fixtures/tls_pin/p256_compressed_key.der");
+
+    /// The `SubjectPublicKeyInfo` of the P-256 key, as `openssl pkey -pubin -outform DER` writes it
+    const P256_KEY: &str = "3059301306072a8648ce3d020106082a8648ce3d03010703420004f6d356eb29736156a1743fe77fa88a6f2f086ca7\
+        f15b8786907ac5f0b456349282c364082c27853712fe62603cac15d82e44d7890eba2016627480485c6a1078";
+
+    /// SHA-256 of `p256.der`, as `openssl dgst -sha256` computes it
+    const P256_CERTIFICATE_HASH: &str = "974184b8db264251c4261d84685fa705be838f9b198dc77503622300b1fc562c";
+
+    /// SHA-256 of the `SubjectPublicKeyInfo` of the P-256 key
+    const P256_KEY_HASH: &str = "349aacd24e57353fb325b984680d044854286fd2d87aebe71e02b5f30499708c";
+
+    /// SHA-256 of the `SubjectPublicKeyInfo` of the RSA key
+    const RSA_KEY_HASH: &str = "fc976209f9ead565d0f07224d6a583b713bbfc7adccfe34549150b38cbff759e";
+
+    /// The bytes of a hex string
+    fn bytes(hex: &str) -> std::vec::Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// A SHA-256 hash from a hex string
+    fn hash(hex: &str) -> [u8; 32] {
+        bytes(hex).try_into().unwrap()
+    }
+
+    /// A certificate pin only matches the very certificate, even one with the same key does not
+    #[test]
+    fn checks_certificate_pins() {
+        let pin = Pin::Certificate(hash(P256_CERTIFICATE_HASH));
+        assert_eq!(pin.check(P256), Ok(bytes(P256_KEY).as_slice()));
+        assert_eq!(pin.check(P256_V1), Err(PinError::Mismatch));
+        assert_eq!(pin.check(RSA), Err(PinError::Mismatch));
+    }
+
+    /// A public key pin matches every certificate of the key
+    #[test]
+    fn checks_public_key_pins() {
+        let pin = Pin::PublicKey(hash(P256_KEY_HASH));
+        assert_eq!(pin.check(P256), Ok(bytes(P256_KEY).as_slice()));
+        assert_eq!(pin.check(P256_V1), Ok(bytes(P256_KEY).as_slice()));
+        assert_eq!(pin.check(RSA), Err(PinError::Mismatch));
+        assert!(Pin::PublicKey(hash(RSA_KEY_HASH)).check(RSA).is_ok());
+    }
+
+    /// The public key is found with and without the version field, both certificates have long form lengths
+    #[test]
+    fn finds_the_public_key() {
+        assert_eq!(P256[..2], [TAG_SEQUENCE, 0x82]);
+        assert_eq!(P256_V1[..2], [TAG_SEQUENCE, 0x82]);
+        assert_eq!(subject_public_key_info(P256), Ok(bytes(P256_KEY).as_slice()));
+        assert_eq!(subject_public_key_info(P256_V1), Ok(bytes(P256_KEY).as_slice()));
+        let key = bytes(P256_KEY);
+        let point = p256_public_key(&key).unwrap();
+        assert_eq!(point.len(), 65);
+        assert_eq!(point, &key[26..]);
+    }
+
+    /// Lengths of one to three bytes are read, indefinite and longer lengths are refused
+    #[test]
+    fn reads_long_form_lengths() {
+        let mut one_byte = std::vec![0x04, 0x81, 0x80];
+        one_byte.extend([7; 0x80]);
+        one_byte.push(0xFF);
+        let (element, rest) = read_any(&one_byte).unwrap();
+        assert_eq!((element.content.len(), rest), (0x80, [0xFF].as_slice()));
+
+        let mut three_bytes = std::vec![0x04, 0x83, 0x00, 0x01, 0x00];
+        three_bytes.extend([7; 0x100]);
+        let (element, rest) = read_any(&three_bytes).unwrap();
+        assert_eq!(
+            (element.encoded.len(), element.content.len(), rest.len()),
+            (0x105, 0x100, 0)
+        );
+
+        assert!(read_any(&[0x04, 0x80, 0x00, 0x00]).is_err());
+        assert!(read_any(&[0x04, 0x84, 0x00, 0x00, 0x00, 0x01, 0x07]).is_err());
+    }
+
+    /// Certificates cut short anywhere and lengths beyond the end are refused, never read past the input
+    #[test]
+    fn refuses_truncated_and_oversized_lengths() {
+        for end in 0..P256.len() {
+            assert_eq!(subject_public_key_info(&P256[..end]), Err(PinError::Malformed), "{end}");
+        }
+        let mut oversized = P256.to_vec();
+        oversized[3] += 1;
+        assert_eq!(subject_public_key_info(&oversized), Err(PinError::Malformed));
+        let mut huge = P256.to_vec();
+        huge[2] = 0xFF;
+        assert_eq!(subject_public_key_info(&huge), Err(PinError::Malformed));
+        assert_eq!(p256_public_key(&bytes(P256_KEY)[..90]), Err(PinError::Malformed));
+        assert!(read_any(&[0x04]).is_err());
+        assert!(read_any(&[0x04, 0x82, 0x01]).is_err());
+    }
+
+    /// Only an uncompressed point on P-256 can verify the signatures of the server
+    #[test]
+    fn refuses_unsupported_keys() {
+        let rsa_key = subject_public_key_info(RSA).unwrap();
+        assert_eq!(p256_public_key(rsa_key), Err(PinError::UnsupportedKey));
+        assert_eq!(p256_public_key(P256_COMPRESSED_KEY), Err(PinError::UnsupportedKey));
+        let mut unused_bits = bytes(P256_KEY);
+        unused_bits[24] = 1;
+        assert_eq!(p256_public_key(&unused_bits), Err(PinError::UnsupportedKey));
+    }
+}
@@ -0,0 +1,266 @@
+//! # Weather
+//! This module parses the daily forecast of the Open-Meteo API into what the display shows: today's condition and
+//! its lowest and highest temperature.
+//!
+//! The forecast is requested for a single day with
+//! `daily=weather_code,temperature_2m_max,temperature_2m_min&timezone=auto&forecast_days=1`, so that every array of
+//! the `daily` object holds exactly one value:
+//!```json
+//! {
+//!     "latitude": 52.52, "longitude": 13.419998, "timezone": "Europe/Berlin",
+//!     "daily_units": { "time": "iso8601", "weather_code": "wmo code", "temperature_2m_max": "°C" },
+//!     "daily": { "time": ["2024-05-01"], "weather_code": [3], "temperature_2m_max": [21.4], "temperature_2m_min": [9.8] }
+//! }
+//! ```
+//! All other fields are skipped, values the model does not have are `null`. The condition is the WMO weather
+//! interpretation code, it is reduced to the few conditions we have icons for.
+
+use serde::Deserialize;
+
+/// Errors when parsing a forecast
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ForecastError {
+    /// The response is not the JSON we asked for, or it holds more than one day
+    Json,
+    /// The date of the forecast is not of the form `YYYY-MM-DD`
+    InvalidDate,
+    /// The weather code or a temperature is `null`, the model has no value for it
+    MissingValue,
+    /// The weather code is not a WMO weather interpretation code, the data is the code
+    UnknownCondition(u8),
+}
+
+/// The weather conditions we have icons for
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WeatherCondition {
+    /// Clear sky
+    Clear,
+    /// Mainly clear or partly cloudy
+    PartlyCloudy,
+    /// Overcast
+    Cloudy,
+    /// Fog or depositing rime fog
+    Fog,
+    /// Drizzle, rain or rain showers, also freezing
+    Rain,
+    /// Snow fall, snow grains or snow showers
+    Snow,
+    /// Thunderstorm, also with hail
+    Thunderstorm,
+}
+
+impl WeatherCondition {
+    /// The condition of a WMO weather interpretation code, `None` for codes Open-Meteo does not use
+    pub const fn from_wmo_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(Self::Clear),
+            1 | 2 => Some(Self::PartlyCloudy),
+            3 => Some(Self::Cloudy),
+            45 | 48 => Some(Self::Fog),
+            51..=57 | 61..=67 | 80..=82 => Some(Self::Rain),
+            71..=77 | 85 | 86 => Some(Self::Snow),
+            95..=99 => Some(Self::Thunderstorm),
+            _ => None,
+        }
+    }
+
+    /// The name of the condition, short enough for a line of the display
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Clear => "Clear",
+            Self::PartlyCloudy => "Partly cloudy",
+            Self::Cloudy => "Cloudy",
+            Self::Fog => "Fog",
+            Self::Rain => "Rain",
+            Self::Snow => "Snow",
+            Self::Thunderstorm => "Thunderstorm",
+        }
+    }
+}
+
+/// The forecast of a day
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Forecast {
+    /// The day of the forecast in (year, month, day), local time of the location
+    pub date: (u16, u8, u8),
+    /// The weather condition of the day
+    pub condition: WeatherCondition,
+    /// The lowest temperature of the day in °C, rounded
+    pub min_celsius: i8,
+    /// The highest temperature of the day in °C, rounded
+    pub max_celsius: i8,
+}
+
+/// The part of the response we need, the top level object
+#[derive(Deserialize)]
+struct ForecastResponse<'a> {
+    /// The daily forecast
+    #[serde(borrow)]
+    daily: DailyForecast<'a>,
+}
+
+/// The `daily` object of the response, one value per day
+#[derive(Deserialize)]
+struct DailyForecast<'a> {
+    /// The days as `YYYY-MM-DD`
+    #[serde(borrow)]
+    time: [&'a str; 1],
+    /// The WMO weather interpretation codes
+    weather_code: [Option<u8>; 1],
+    /// The highest temperatures in °C
+    temperature_2m_max: [Option<f32>; 1],
+    /// The lowest temperatures in °C
+    temperature_2m_min: [Option<f32>; 1],
+}
+
+/// Parse the body of a forecast response
+pub fn parse_forecast(body: &[u8]) -> Result<Forecast, ForecastError> {
+    let (response, _) = serde_json_core::from_slice::<ForecastResponse>(body).map_err(|_| ForecastError::Json)?;
+    let DailyForecast {
+        time: [date],
+        weather_code: [code],
+        temperature_2m_max: [max],
+        temperature_2m_min: [min],
+    } = response.daily;
+    let (Some(code), Some(max), Some(min)) = (code, max, min) else {
+        return Err(ForecastError::MissingValue);
+    };
+    Ok(Forecast {
+        date: parse_date(date).ok_or(ForecastError::InvalidDate)?,
+        condition: WeatherCondition::from_wmo_code(code).ok_or(ForecastError::UnknownCondition(code))?,
+        min_celsius: round_celsius(min),
+        max_celsius: round_celsius(max),
+    })
+}
+
+/// Parse a date of the form `YYYY-MM-DD` into (year, month, day)
+fn parse_date(date: &str) -> Option<(u16, u8, u8)> {
+    let mut parts = date.splitn(3, '-');
+    let year = parts.next()?.parse().ok()?;
+    let month = parts.next()?.parse().ok()?;
+    let day = parts.next()?.parse().ok()?;
+    ((1..=12).contains(&month) && (1..=31).contains(&day)).then_some((year, month, day))
+}
+
+/// Round a temperature to whole degrees, half a degree is rounded away from zero
+#[allow(clippy::cast_possible_truncation)]
+fn round_celsius(celsius: f32) -> i8 {
+    // the cast saturates, far beyond any temperature on earth
+    let rounded = if celsius < 0.0 { celsius - 0.5 } else { celsius + 0.5 };
+    rounded as i8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Today's forecast of the recorded responses, rounded to whole degrees
+    #[test]
+    fn parses_recorded_forecasts() {
+        assert_eq!(
+            parse_forecast(include_bytes!("../../host-tests/fixtures/weather/berlin_overcast.json")),
+            Ok(Forecast {
+                date: (2024, 5, 1),
+                condition: WeatherCondition::Cloudy,
+                min_celsius: 10,
+                max_celsius: 21,
+            })
+        );
+        assert_eq!(
+            parse_forecast(include_bytes!("../../host-tests/fixtures/weather/innsbruck_snow.json")),
+            Ok(Forecast {
+                date: (2025, 1, 14),
+                condition: WeatherCondition::Snow,
+                min_celsius: -8,
+                max_celsius: 0,
+            })
+        );
+        assert_eq!(
+            parse_forecast(include_bytes!(
+                "../../host-tests/fixtures/weather/houston_thunderstorm.json"
+            )),
+            Ok(Forecast {
+                date: (2024, 6, 18),
+                condition: WeatherCondition::Thunderstorm,
+                min_celsius: 25,
+                max_celsius: 32,
+            })
+        );
+    }
+
+    /// Errors of the API, missing values and more days than asked for are not a forecast
+    #[test]
+    fn rejects_recorded_responses_without_a_forecast() {
+        assert_eq!(
+            parse_forecast(include_bytes!("../../host-tests/fixtures/weather/no_data.json")),
+            Err(ForecastError::MissingValue)
+        );
+        assert_eq!(
+            parse_forecast(include_bytes!("../../host-tests/fixtures/weather/error_latitude.json")),
+            Err(ForecastError::Json)
+        );
+        assert_eq!(
+            parse_forecast(include_bytes!("../../host-tests/fixtures/weather/three_days.json")),
+            Err(ForecastError::Json)
+        );
+    }
+
+    /// Bodies that are not the JSON of the API, dates that are not dates and codes that are not WMO codes
+    #[test]
+    fn rejects_malformed_bodies() {
+        let daily = |time: &str, code: &str| {
+            format!(
+                r#"{{"daily":{{"time":["{time}"],"weather_code":[{code}],"temperature_2m_max":[1],"temperature_2m_min":[1]}}}}"#
+            )
+        };
+        assert_eq!(parse_forecast(b"<html></html>"), Err(ForecastError::Json));
+        assert_eq!(parse_forecast(b""), Err(ForecastError::Json));
+        assert_eq!(
+            parse_forecast(daily("2024-5", "3").as_bytes()),
+            Err(ForecastError::InvalidDate)
+        );
+        assert_eq!(
+            parse_forecast(daily("2024-13-01", "3").as_bytes()),
+            Err(ForecastError::InvalidDate)
+        );
+        assert_eq!(
+            parse_forecast(daily("2024-05-01", "4").as_bytes()),
+            Err(ForecastError::UnknownCondition(4))
+        );
+        assert_eq!(
+            parse_forecast(daily("2024-05-01", "300").as_bytes()),
+            Err(ForecastError::Json)
+        );
+    }
+
+    /// Every WMO code Open-Meteo uses has a condition
+    #[test]
+    fn maps_wmo_codes() {
+        let codes = [
+            0, 1, 2, 3, 45, 48, 51, 53, 55, 56, 57, 61, 63, 65, 66, 67, 71, 73, 75, 77, 80, 81, 82, 85, 86, 95, 96, 99,
+        ];
+        assert!(
+            codes
+                .iter()
+                .all(|code| WeatherCondition::from_wmo_code(*code).is_some())
+        );
+        assert_eq!(WeatherCondition::from_wmo_code(2), Some(WeatherCondition::PartlyCloudy));
+        assert_eq!(WeatherCondition::from_wmo_code(48), Some(WeatherCondition::Fog));
+        assert_eq!(WeatherCondition::from_wmo_code(66), Some(WeatherCondition::Rain));
+        assert_eq!(WeatherCondition::from_wmo_code(4), None);
+    }
+
+    /// Half a degree is rounded away from zero
+    #[test]
+    fn rounds_temperatures() {
+        assert_eq!(round_celsius(0.4), 0);
+        assert_eq!(round_celsius(0.5), 1);
+        assert_eq!(round_celsius(-0.4), 0);
+        assert_eq!(round_celsius(-0.5), -1);
+        assert_eq!(round_celsius(-7.5), -8);
+        assert_eq!(round_celsius(200.0), i8::MAX);
+    }
+}
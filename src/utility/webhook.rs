@@ -0,0 +1,219 @@
+//! # Webhook
+//! This module contains the events webhooks are fired on and renders the body templates of webhooks.
+//!
+//! A body template is the body sent as is, except for the placeholders, which are replaced by the values of the
+//! moment the webhook fired:
+//! - `{event}`: the name of the event, e.g. `alarm`
+//! - `{hostname}`: the hostname of the clock
+//! - `{time}`: the time of the RTC, e.g. `2024-05-01T06:30:00`
+//! - `{alarm}`: the alarm time, e.g. `06:30`
+//!
+//! Any other brace is copied, so JSON bodies need no escaping: `{"text": "alarm at {alarm}"}` renders as
+//! `{"text": "alarm at 06:30"}`. In a JSON body the values are escaped like in a JSON string, placeholders belong
+//! inside strings there. A plain text body gets the values as they are.
+
+use core::fmt::{self, Write};
+
+/// The events webhooks are fired on
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WebhookEvent {
+    /// The alarm was raised, also after a snooze
+    Alarm,
+    /// The sunrise effect has finished and the alarm sound starts
+    SunriseFinished,
+    /// The ringing alarm was stopped, by the buttons or over the network
+    AlarmStop,
+    /// The ringing alarm was not stopped and expired unattended
+    AlarmExpired,
+}
+
+impl WebhookEvent {
+    /// The name of the event, as used in `webhooks.json` and the `{event}` placeholder
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Alarm => "alarm",
+            Self::SunriseFinished => "sunrise_finished",
+            Self::AlarmStop => "alarm_stop",
+            Self::AlarmExpired => "alarm_expired",
+        }
+    }
+}
+
+/// How the body of a webhook is sent
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BodyFormat {
+    /// A JSON value, sent as `application/json`
+    Json,
+    /// A string of `webhooks.json`, sent as `text/plain`
+    Text,
+}
+
+/// The values the placeholders of a body template are replaced by
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct TemplateValues<'a> {
+    /// The event the webhook fired on
+    pub event: WebhookEvent,
+    /// The hostname of the clock
+    pub hostname: &'a str,
+    /// The time of the RTC, empty if the RTC is not running
+    pub time: &'a str,
+    /// The alarm time
+    pub alarm: &'a str,
+}
+
+impl TemplateValues<'_> {
+    /// The value of a placeholder name, `None` if it is not a placeholder
+    fn get(&self, name: &str) -> Option<&str> {
+        match name {
+            "event" => Some(self.event.name()),
+            "hostname" => Some(self.hostname),
+            "time" => Some(self.time),
+            "alarm" => Some(self.alarm),
+            _ => None,
+        }
+    }
+}
+
+/// Write the body template with its placeholders replaced by the values, escaped for the format of the body
+pub fn render_template<W: Write>(
+    out: &mut W,
+    template: &str,
+    format: BodyFormat,
+    values: &TemplateValues<'_>,
+) -> fmt::Result {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.write_str(&rest[..start])?;
+        rest = &rest[start..];
+        // a placeholder is replaced by its value, any other brace is copied
+        let placeholder = rest
+            .find('}')
+            .and_then(|end| Some((values.get(&rest[1..end])?, end + 1)));
+        if let Some((value, consumed)) = placeholder {
+            match format {
+                BodyFormat::Json => write_json_escaped(out, value)?,
+                BodyFormat::Text => out.write_str(value)?,
+            }
+            rest = &rest[consumed..];
+        } else {
+            out.write_char('{')?;
+            rest = &rest[1..];
+        }
+    }
+    out.write_str(rest)
+}
+
+/// Write the value escaped as the content of a JSON string
+fn write_json_escaped<W: Write>(out: &mut W, value: &str) -> fmt::Result {
+    for c in value.chars() {
+        match c {
+            '"' => out.write_str("\\\"")?,
+            '\\' => out.write_str("\\\\")?,
+            '\n' => out.write_str("\\n")?,
+            '\r' => out.write_str("\\r")?,
+            '\t' => out.write_str("\\t")?,
+            c if c < ' ' => write!(out, "\\u{:04x}", u32::from(c))?,
+            c => out.write_char(c)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use heapless::String;
+
+    use super::*;
+
+    /// The values of an alarm at 06:30
+    const VALUES: TemplateValues<'static> = TemplateValues {
+        event: WebhookEvent::AlarmExpired,
+        hostname: "pico-alarmclock",
+        time: "2024-05-01T06:35:00",
+        alarm: "06:30",
+    };
+
+    /// The rendered template, `None` if it does not fit
+    fn render<const N: usize>(template: &str, format: BodyFormat, values: &TemplateValues<'_>) -> Option<String<N>> {
+        let mut out = String::new();
+        render_template(&mut out, template, format, values).ok()?;
+        Some(out)
+    }
+
+    /// Every placeholder is replaced, in text and in JSON bodies
+    #[test]
+    fn replaces_placeholders() {
+        assert_eq!(
+            render::<128>(
+                "{hostname}: alarm at {alarm} {event} at {time}",
+                BodyFormat::Text,
+                &VALUES
+            )
+            .unwrap(),
+            "pico-alarmclock: alarm at 06:30 alarm_expired at 2024-05-01T06:35:00"
+        );
+        assert_eq!(
+            render::<64>(r#"{"text": "alarm at {alarm}"}"#, BodyFormat::Json, &VALUES).unwrap(),
+            r#"{"text": "alarm at 06:30"}"#
+        );
+        assert_eq!(
+            render::<32>("{event}{alarm}", BodyFormat::Text, &VALUES).unwrap(),
+            "alarm_expired06:30"
+        );
+        let empty_time = TemplateValues { time: "", ..VALUES };
+        assert_eq!(render::<16>("[{time}]", BodyFormat::Text, &empty_time).unwrap(), "[]");
+    }
+
+    /// Braces that are not a placeholder are copied, also unclosed and nested ones
+    #[test]
+    fn copies_literal_braces() {
+        let json = r#"{"on": true, "nested": {"at": "{alarm}"}, "list": [{}]}"#;
+        assert_eq!(
+            render::<128>(json, BodyFormat::Json, &VALUES).unwrap(),
+            r#"{"on": true, "nested": {"at": "06:30"}, "list": [{}]}"#
+        );
+        assert_eq!(
+            render::<32>("{unknown} {", BodyFormat::Text, &VALUES).unwrap(),
+            "{unknown} {"
+        );
+        assert_eq!(
+            render::<32>("{ {alarm} }", BodyFormat::Text, &VALUES).unwrap(),
+            "{ 06:30 }"
+        );
+        assert_eq!(render::<32>("}{alarm", BodyFormat::Text, &VALUES).unwrap(), "}{alarm");
+    }
+
+    /// Values are escaped in JSON bodies only
+    #[test]
+    fn escapes_values_in_json() {
+        let values = TemplateValues {
+            hostname: "a \"clock\"\\\n\u{1}",
+            ..VALUES
+        };
+        assert_eq!(
+            render::<64>(r#"{"host": "{hostname}"}"#, BodyFormat::Json, &values).unwrap(),
+            r#"{"host": "a \"clock\"\\\n\u0001"}"#
+        );
+        assert_eq!(
+            render::<64>("{hostname}", BodyFormat::Text, &values).unwrap(),
+            "a \"clock\"\\\n\u{1}"
+        );
+    }
+
+    /// A body that does not fit the buffer is an error, also when only a value or an escape does not fit
+    #[test]
+    fn reports_overflow() {
+        assert_eq!(render::<5>("06:30", BodyFormat::Text, &VALUES).unwrap(), "06:30");
+        assert_eq!(render::<5>("{alarm}", BodyFormat::Text, &VALUES).unwrap(), "06:30");
+        assert!(render::<4>("{alarm}", BodyFormat::Text, &VALUES).is_none());
+        assert!(render::<4>("alarm!", BodyFormat::Text, &VALUES).is_none());
+        let values = TemplateValues {
+            hostname: "\"",
+            ..VALUES
+        };
+        assert!(render::<1>("{hostname}", BodyFormat::Json, &values).is_none());
+        assert_eq!(render::<2>("{hostname}", BodyFormat::Json, &values).unwrap(), "\\\"");
+    }
+}
@@ -0,0 +1,35 @@
+#!/bin/sh
+
+# Local stand-in for the time API over HTTPS, for testing the pinned TLS connection of the time updater.
+# It serves the current time in the format of worldtimeapi.org with openssl, using TLS 1.3 and a P-256 key
+# like the clock requires. The key is generated on the first run and kept, so the pin stays the same.
+#
+# Point config/time_api.json at this machine, e.g.
+#   "baseurl": "https://192.168.1.20:8443/api", "timezone": "/timezone/Europe/Berlin",
+#   "tls": { "pin": "public_key", "sha256": "<the hash printed below>" }
+#
+# Set PORT to listen on another port than 8443.
+
+PORT="${PORT:-8443}"
+WORKDIR="$(dirname "$0")/target/tls-standin"
+mkdir -p "$WORKDIR/api/timezone/Europe" || exit 1
+cd "$WORKDIR" || exit 1
+
+if [ ! -f key.pem ]; then
+    echo "Generating key and certificate..."
+    openssl req -x509 -newkey ec -pkeyopt ec_paramgen_curve:P-256 -nodes \
+        -keyout key.pem -out cert.pem -days 3650 -subj "/CN=alarmclock-time-standin" || exit 1
+fi
+
+echo "Public key pin (\"pin\": \"public_key\"):"
+openssl x509 -in cert.pem -pubkey -noout | openssl pkey -pubin -outform der | sha256sum | cut -d' ' -f1
+echo "Certificate pin (\"pin\": \"certificate\"):"
+openssl x509 -in cert.pem -outform der | sha256sum | cut -d' ' -f1
+
+# The response is written once at startup, restart the server to serve a fresh time.
+DATETIME="$(date +%Y-%m-%dT%H:%M:%S.000000%:z)"
+echo "{\"datetime\":\"$DATETIME\"}" > api/timezone/Europe/Berlin
+echo "Serving $DATETIME on port $PORT..."
+
+openssl s_server -accept "$PORT" -cert cert.pem -key key.pem \
+    -tls1_3 -ciphersuites TLS_AES_128_GCM_SHA256 -groups P-256 -WWW